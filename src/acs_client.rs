@@ -0,0 +1,133 @@
+use actix_web::{http::StatusCode, web, HttpResponse};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+use crate::cache::AppCaches;
+use crate::card_status::CardStatusList;
+use crate::config::{ComponentsConfig, Settings};
+use crate::error::AppError;
+use crate::handlers::acs;
+use crate::models::ResultsRequest;
+use crate::request_id::{self, REQUEST_ID_HEADER};
+use crate::state_store::StateStore;
+
+/// ACS routes the 3DS Server component calls when the ACS runs behind `acs_base_url`
+pub const ACS_AREQ_PATH: &str = "/acs/areq";
+pub const ACS_RREQ_PATH: &str = "/acs/rreq";
+/// Browser CReq form post, the spec counterpart of trigger-otp
+pub const ACS_CREQ_PATH: &str = "/acs/creq";
+/// threeDSMethodURL advertised in card ranges, and where its page posts the device data
+pub const ACS_METHOD_PATH: &str = "/acs/3ds-method";
+pub const ACS_METHOD_DEVICE_DATA_PATH: &str = "/acs/3ds-method/device-data";
+
+/// The 3DS Server component's link to the ACS component: direct calls into the ACS
+/// handlers, or HTTP requests to a separately running ACS
+pub struct AcsClient {
+    remote: Option<RemoteAcs>,
+}
+
+struct RemoteAcs {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl AcsClient {
+    pub fn new(config: &ComponentsConfig) -> Result<Self, reqwest::Error> {
+        let remote = match &config.acs_base_url {
+            Some(base_url) => Some(RemoteAcs {
+                client: reqwest::Client::builder()
+                    .timeout(Duration::from_millis(config.acs_timeout_ms))
+                    .build()?,
+                base_url: base_url.trim_end_matches('/').to_string(),
+            }),
+            None => None,
+        };
+        Ok(Self { remote })
+    }
+
+    /// Where AReqs and RReqs are sent, for the startup banner
+    pub fn describe(&self) -> String {
+        match &self.remote {
+            Some(remote) => format!("HTTP ({})", remote.base_url),
+            None => "in-process".to_string(),
+        }
+    }
+
+    /// Hand an AReq body to the ACS and return its ARes (or Erro) response
+    pub async fn authenticate(
+        &self,
+        body: web::Bytes,
+        state: web::Data<Arc<Box<dyn StateStore>>>,
+        settings: web::Data<Settings>,
+        caches: web::Data<AppCaches>,
+        card_statuses: web::Data<CardStatusList>,
+    ) -> Result<HttpResponse, AppError> {
+        match &self.remote {
+            Some(remote) => remote.post("AReq", ACS_AREQ_PATH, body).await,
+            None => acs::authenticate_handler(body, state, settings, caches, card_statuses).await,
+        }
+    }
+
+    /// Hand an RReq to the ACS and return its RRes (or Erro) response
+    pub async fn results(
+        &self,
+        req: web::Json<ResultsRequest>,
+        state: web::Data<Arc<Box<dyn StateStore>>>,
+    ) -> Result<HttpResponse, AppError> {
+        match &self.remote {
+            Some(remote) => {
+                let body = serde_json::to_vec(&req.into_inner())?;
+                remote.post("RReq", ACS_RREQ_PATH, body.into()).await
+            }
+            None => acs::results_handler(req, state).await,
+        }
+    }
+}
+
+impl RemoteAcs {
+    /// POST a message to the ACS and relay its response as-is, including Erro responses.
+    /// Connection failures and timeouts become a 403 Erro with HTTP 502/504.
+    async fn post(
+        &self,
+        message_type: &'static str,
+        path: &str,
+        body: web::Bytes,
+    ) -> Result<HttpResponse, AppError> {
+        let url = format!("{}{}", self.base_url, path);
+        debug!(message_type, %url, "forwarding to the ACS");
+
+        let unreachable = |e: reqwest::Error| {
+            warn!(message_type, %url, error = %e, "ACS unreachable");
+            AppError::AcsUnreachable {
+                message_type,
+                detail: format!("ACS unreachable: {}", e),
+                timed_out: e.is_timeout(),
+            }
+        };
+
+        // Pass the request's ID on, so the ACS logs and stores the same one
+        let mut request = self
+            .client
+            .post(&url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json");
+        if let Some(request_id) = request_id::current() {
+            request = request.header(REQUEST_ID_HEADER.as_str(), request_id);
+        }
+        let response = request.body(body).send().await.map_err(&unreachable)?;
+
+        let status =
+            StatusCode::from_u16(response.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("application/json")
+            .to_string();
+        let body = response.bytes().await.map_err(&unreachable)?;
+
+        Ok(HttpResponse::build(status)
+            .content_type(content_type)
+            .body(body))
+    }
+}
@@ -0,0 +1,192 @@
+use moka::sync::Cache;
+use prometheus::{IntCounterVec, Opts, Registry};
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::CacheConfig;
+use crate::models::{CardRange, ChallengeIndicator};
+
+/// Number of leading card digits the card range lookup depends on
+const BIN_LENGTH: usize = 6;
+
+/// The BIN a card number's /3ds/version response is cached under
+pub fn card_bin(card_number: &str) -> &str {
+    card_number.get(..BIN_LENGTH).unwrap_or(card_number)
+}
+
+/// Inputs the frictionless/challenge scenario decision for an AReq depends on
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ChallengeDecisionKey {
+    pub card_suffix: String,
+    pub challenge_indicator: ChallengeIndicator,
+    pub exceeds_instalments: bool,
+}
+
+impl ChallengeDecisionKey {
+    pub fn new(
+        card_number: &str,
+        challenge_indicator: ChallengeIndicator,
+        exceeds_instalments: bool,
+    ) -> Self {
+        let suffix_start = card_number.len().saturating_sub(4);
+        Self {
+            card_suffix: card_number
+                .get(suffix_start..)
+                .unwrap_or(card_number)
+                .to_string(),
+            challenge_indicator,
+            exceeds_instalments,
+        }
+    }
+}
+
+/// In-process caches for values that are static for a given input, each expiring after
+/// its `[cache]` TTL, with hit/miss counters for the metrics endpoint
+pub struct AppCaches {
+    card_ranges: Cache<String, CardRange>,
+    challenge_decisions: Cache<ChallengeDecisionKey, bool>,
+    static_responses: Cache<String, Arc<str>>,
+    requests: IntCounterVec,
+}
+
+impl AppCaches {
+    pub fn new(capacity: u64, ttls: &CacheConfig) -> Self {
+        let requests = IntCounterVec::new(
+            Opts::new(
+                "cache_requests_total",
+                "In-process cache lookups by cache and result",
+            )
+            .namespace("api"),
+            &["cache", "result"],
+        )
+        .expect("valid cache metric");
+
+        Self {
+            card_ranges: Cache::builder()
+                .max_capacity(capacity)
+                .time_to_live(Duration::from_secs(ttls.card_range_ttl_seconds))
+                .build(),
+            challenge_decisions: Cache::builder()
+                .max_capacity(capacity)
+                .time_to_live(Duration::from_secs(ttls.challenge_decision_ttl_seconds))
+                .build(),
+            static_responses: Cache::builder()
+                .max_capacity(capacity)
+                .time_to_live(Duration::from_secs(ttls.static_response_ttl_seconds))
+                .build(),
+            requests,
+        }
+    }
+
+    /// Expose the hit/miss counters through the given registry
+    pub fn register(&self, registry: &Registry) -> prometheus::Result<()> {
+        registry.register(Box::new(self.requests.clone()))
+    }
+
+    /// The /3ds/version card range for this card's BIN, built on a miss
+    pub fn card_range(&self, card_number: &str, build: impl FnOnce() -> CardRange) -> CardRange {
+        let bin = card_bin(card_number).to_string();
+        self.get_or_insert("card_range", &self.card_ranges, bin, build)
+    }
+
+    /// Whether an AReq with these inputs is challenged, decided on a miss
+    pub fn challenge_decision(
+        &self,
+        key: ChallengeDecisionKey,
+        decide: impl FnOnce() -> bool,
+    ) -> bool {
+        self.get_or_insert("challenge_decision", &self.challenge_decisions, key, decide)
+    }
+
+    /// A rendered static response body (e.g. a template with its fixed placeholders
+    /// filled in), rendered on a miss
+    pub fn static_response(&self, key: &str, render: impl FnOnce() -> String) -> Arc<str> {
+        self.get_or_insert(
+            "static_response",
+            &self.static_responses,
+            key.to_string(),
+            || Arc::from(render()),
+        )
+    }
+
+    fn get_or_insert<K, V>(
+        &self,
+        name: &str,
+        cache: &Cache<K, V>,
+        key: K,
+        init: impl FnOnce() -> V,
+    ) -> V
+    where
+        K: Hash + Eq + Send + Sync + 'static,
+        V: Clone + Send + Sync + 'static,
+    {
+        let entry = cache.entry(key).or_insert_with(init);
+        let result = if entry.is_fresh() { "miss" } else { "hit" };
+        self.requests.with_label_values(&[name, result]).inc();
+        entry.into_value()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caches() -> AppCaches {
+        AppCaches::new(
+            10,
+            &CacheConfig {
+                card_range_ttl_seconds: 60,
+                challenge_decision_ttl_seconds: 60,
+                static_response_ttl_seconds: 60,
+            },
+        )
+    }
+
+    fn count(caches: &AppCaches, name: &str, result: &str) -> u64 {
+        caches.requests.with_label_values(&[name, result]).get()
+    }
+
+    fn card_range(start_range: &str) -> CardRange {
+        CardRange {
+            acs_info_ind: vec!["01".to_string()],
+            start_range: start_range.to_string(),
+            acs_end_protocol_version: "2.2.0".to_string(),
+            acs_start_protocol_version: "2.2.0".to_string(),
+            end_range: "5155019999999999".to_string(),
+            three_ds_method_url: None,
+        }
+    }
+
+    #[test]
+    fn test_card_range_cached_per_bin() {
+        let caches = caches();
+
+        let first = caches.card_range("5155016800000000", || card_range("A"));
+        // Same BIN, different card: served from the cache
+        let second = caches.card_range("5155019999999999", || card_range("B"));
+        let other_bin = caches.card_range("4000000000000002", || card_range("C"));
+
+        assert_eq!(first.start_range, "A");
+        assert_eq!(second.start_range, "A");
+        assert_eq!(other_bin.start_range, "C");
+        assert_eq!(count(&caches, "card_range", "hit"), 1);
+        assert_eq!(count(&caches, "card_range", "miss"), 2);
+    }
+
+    #[test]
+    fn test_challenge_decision_keyed_by_scenario_inputs() {
+        let caches = caches();
+        let key = |card: &str, exceeds| {
+            ChallengeDecisionKey::new(card, ChallengeIndicator::NoPreference, exceeds)
+        };
+
+        assert!(caches.challenge_decision(key("5155016800000001", false), || true));
+        // Same card suffix and inputs: the cached decision wins
+        assert!(caches.challenge_decision(key("4000000000000001", false), || false));
+        assert!(!caches.challenge_decision(key("4000000000000001", true), || false));
+
+        assert_eq!(count(&caches, "challenge_decision", "hit"), 1);
+        assert_eq!(count(&caches, "challenge_decision", "miss"), 2);
+    }
+}
@@ -0,0 +1,316 @@
+use config::{Config, ConfigError, File};
+use serde::{Deserialize, Serialize};
+
+use crate::challenge::{is_valid_otp, OTP_MAX_LENGTH, OTP_MIN_LENGTH};
+use crate::models::TransStatus;
+
+/// Authentication outcome a test card is answered with
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CardOutcome {
+    /// Frictionless, fully authenticated (transStatus Y)
+    #[serde(alias = "Y")]
+    Frictionless,
+    /// Challenge, subject to the requestor's challenge indicator (transStatus C)
+    #[serde(alias = "C")]
+    Challenge,
+    #[serde(alias = "N")]
+    NotAuthenticated,
+    #[serde(alias = "U")]
+    Unavailable,
+    #[serde(alias = "R")]
+    Rejected,
+    /// Frictionless with attempts processing (transStatus A)
+    #[serde(alias = "A")]
+    Attempted,
+    /// The ACS never answers the AReq in time
+    Timeout,
+}
+
+impl CardOutcome {
+    /// transStatus and default transStatusReason for outcomes the issuer answers without
+    /// authenticating the cardholder
+    pub fn issuer_answer(&self) -> Option<(TransStatus, &'static str)> {
+        match self {
+            // 01 = Card authentication failed
+            CardOutcome::NotAuthenticated => Some((TransStatus::NotAuthenticated, "01")),
+            // 08 = No card record
+            CardOutcome::Unavailable => Some((TransStatus::Unavailable, "08")),
+            // 11 = Suspected fraud
+            CardOutcome::Rejected => Some((TransStatus::Rejected, "11")),
+            _ => None,
+        }
+    }
+}
+
+/// UI an app challenge of the card opens with, unless the SDK needs HTML or OOB
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AppChallengeUi {
+    /// Text entry of the OTP (acsUiType 01)
+    Otp,
+    /// One-of-several question (acsUiType 02)
+    SingleSelect,
+    /// Pick-all-that-apply question (acsUiType 03)
+    MultiSelect,
+}
+
+/// A registry entry: one of `pan`, `prefix` or `suffix` selects the cards
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CardScenario {
+    #[serde(default)]
+    pub pan: Option<String>,
+    #[serde(default)]
+    pub prefix: Option<String>,
+    #[serde(default)]
+    pub suffix: Option<String>,
+    pub outcome: CardOutcome,
+    /// transStatusReason overriding the outcome's default, for N, U and R
+    #[serde(default)]
+    pub trans_status_reason: Option<String>,
+    /// OTP the card's challenges accept, overriding `acs.otp`
+    #[serde(default)]
+    pub otp: Option<String>,
+    /// UI of the card's app challenges; an OTP form when unset
+    #[serde(default)]
+    pub app_challenge_ui: Option<AppChallengeUi>,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+impl CardScenario {
+    /// How specifically this entry matches a card: None when it doesn't, otherwise exact
+    /// PANs rank above any prefix or suffix, and longer prefixes/suffixes above shorter ones
+    fn match_rank(&self, card_number: &str) -> Option<usize> {
+        if let Some(pan) = &self.pan {
+            return (pan == card_number).then_some(usize::MAX);
+        }
+        if let Some(prefix) = &self.prefix {
+            return card_number
+                .starts_with(prefix.as_str())
+                .then_some(prefix.len());
+        }
+        self.suffix
+            .as_ref()
+            .filter(|suffix| card_number.ends_with(suffix.as_str()))
+            .map(String::len)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let selectors = [&self.pan, &self.prefix, &self.suffix];
+        if selectors
+            .iter()
+            .filter(|selector| selector.is_some())
+            .count()
+            != 1
+        {
+            return Err(
+                "each card scenario needs exactly one of pan, prefix or suffix".to_string(),
+            );
+        }
+        if let Some(selector) = selectors.into_iter().flatten().next() {
+            if selector.is_empty() || !selector.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(format!(
+                    "card scenario selector {:?} must be digits",
+                    selector
+                ));
+            }
+        }
+        if self.trans_status_reason.is_some() && self.outcome.issuer_answer().is_none() {
+            return Err(format!(
+                "trans_status_reason is only used with not_authenticated, unavailable or rejected (card scenario {:?})",
+                self.pan.as_ref().or(self.prefix.as_ref()).or(self.suffix.as_ref())
+            ));
+        }
+        if let Some(otp) = &self.otp {
+            if !is_valid_otp(otp) {
+                return Err(format!(
+                    "card scenario otp {:?} must be {}-{} digits",
+                    otp, OTP_MIN_LENGTH, OTP_MAX_LENGTH
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Test cards mapped to authentication outcomes, from `scenarios.card_registry_file`.
+/// Without a file, cards ending in 4001 are challenged and cards ending in 4009, 4010, 4011
+/// and 4012 are answered N, U, R and A; cards matching no entry are authenticated
+/// frictionlessly.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CardRegistry {
+    #[serde(default)]
+    pub cards: Vec<CardScenario>,
+}
+
+impl Default for CardRegistry {
+    fn default() -> Self {
+        let built_in = |suffix: &str, outcome, description: &str| CardScenario {
+            pan: None,
+            prefix: None,
+            suffix: Some(suffix.to_string()),
+            outcome,
+            trans_status_reason: None,
+            otp: None,
+            app_challenge_ui: None,
+            description: Some(description.to_string()),
+        };
+        Self {
+            cards: vec![
+                built_in("4001", CardOutcome::Challenge, "Built-in challenge card"),
+                built_in(
+                    "4009",
+                    CardOutcome::NotAuthenticated,
+                    "Built-in frictionless decline card",
+                ),
+                built_in(
+                    "4010",
+                    CardOutcome::Unavailable,
+                    "Built-in unavailable card",
+                ),
+                built_in("4011", CardOutcome::Rejected, "Built-in rejected card"),
+                built_in("4012", CardOutcome::Attempted, "Built-in attempts card"),
+            ],
+        }
+    }
+}
+
+impl CardRegistry {
+    /// Load a TOML or JSON registry (chosen by file extension)
+    pub fn load(path: &str) -> Result<Self, ConfigError> {
+        let registry: Self = Config::builder()
+            .add_source(File::with_name(path))
+            .build()?
+            .try_deserialize()?;
+        registry.validate().map_err(ConfigError::Message)?;
+        Ok(registry)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        self.cards.iter().try_for_each(CardScenario::validate)
+    }
+
+    /// The most specific entry for a card
+    pub fn scenario_for(&self, card_number: &str) -> Option<&CardScenario> {
+        self.cards
+            .iter()
+            .filter_map(|scenario| Some((scenario.match_rank(card_number)?, scenario)))
+            .max_by_key(|(rank, _)| *rank)
+            .map(|(_, scenario)| scenario)
+    }
+
+    /// A card's outcome and, for issuer answers, its transStatusReason
+    pub fn outcome_for(&self, card_number: &str) -> (CardOutcome, Option<(TransStatus, &str)>) {
+        match self.scenario_for(card_number) {
+            Some(scenario) => {
+                let answer = scenario.outcome.issuer_answer().map(|(status, reason)| {
+                    (
+                        status,
+                        scenario.trans_status_reason.as_deref().unwrap_or(reason),
+                    )
+                });
+                (scenario.outcome, answer)
+            }
+            None => (CardOutcome::Frictionless, None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scenario(pan: Option<&str>, prefix: Option<&str>, outcome: CardOutcome) -> CardScenario {
+        CardScenario {
+            pan: pan.map(str::to_string),
+            prefix: prefix.map(str::to_string),
+            suffix: None,
+            outcome,
+            trans_status_reason: None,
+            otp: None,
+            app_challenge_ui: None,
+            description: None,
+        }
+    }
+
+    #[test]
+    fn test_most_specific_scenario_wins() {
+        let registry = CardRegistry {
+            cards: vec![
+                scenario(None, Some("4000"), CardOutcome::Challenge),
+                scenario(None, Some("400012"), CardOutcome::Rejected),
+                scenario(Some("4000120000000002"), None, CardOutcome::Attempted),
+            ],
+        };
+        assert!(registry.validate().is_ok());
+
+        assert_eq!(
+            registry.outcome_for("4000000000000002").0,
+            CardOutcome::Challenge
+        );
+        assert_eq!(
+            registry.outcome_for("4000120000000010"),
+            (CardOutcome::Rejected, Some((TransStatus::Rejected, "11")))
+        );
+        assert_eq!(
+            registry.outcome_for("4000120000000002").0,
+            CardOutcome::Attempted
+        );
+        assert_eq!(
+            registry.outcome_for("5100000000000000").0,
+            CardOutcome::Frictionless
+        );
+        assert_eq!(
+            CardRegistry::default().outcome_for("4000000000004001").0,
+            CardOutcome::Challenge
+        );
+        assert_eq!(
+            CardRegistry::default().outcome_for("4000000000004011"),
+            (CardOutcome::Rejected, Some((TransStatus::Rejected, "11")))
+        );
+        assert_eq!(
+            CardRegistry::default().outcome_for("4000000000004012").0,
+            CardOutcome::Attempted
+        );
+        assert_eq!(
+            CardRegistry::default().outcome_for("5200000000004009"),
+            (
+                CardOutcome::NotAuthenticated,
+                Some((TransStatus::NotAuthenticated, "01"))
+            )
+        );
+    }
+
+    #[test]
+    fn test_scenario_validation() {
+        let mut entry = scenario(Some("4000"), Some("4000"), CardOutcome::Challenge);
+        assert!(entry.validate().is_err());
+
+        entry.pan = None;
+        entry.trans_status_reason = Some("11".to_string());
+        assert!(entry.validate().is_err());
+
+        entry.outcome = CardOutcome::NotAuthenticated;
+        assert!(entry.validate().is_ok());
+
+        entry.otp = Some("12".to_string());
+        assert!(entry.validate().is_err());
+
+        entry.otp = Some("246810".to_string());
+        assert!(entry.validate().is_ok());
+
+        entry.prefix = Some("40x0".to_string());
+        assert!(entry.validate().is_err());
+
+        let parsed: CardScenario =
+            serde_json::from_str(r#"{ "suffix": "0002", "outcome": "U" }"#).unwrap();
+        assert_eq!(parsed.outcome, CardOutcome::Unavailable);
+
+        let parsed: CardScenario = serde_json::from_str(
+            r#"{ "pan": "4000", "outcome": "C", "app_challenge_ui": "multi_select" }"#,
+        )
+        .unwrap();
+        assert_eq!(parsed.app_challenge_ui, Some(AppChallengeUi::MultiSelect));
+    }
+}
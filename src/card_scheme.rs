@@ -0,0 +1,126 @@
+use crate::config::SuccessLiability;
+use crate::models::{CardholderAccount, Eci};
+
+/// Card scheme of a transaction, which picks its ECI code set and authenticationValue format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CardScheme {
+    Visa,
+    Mastercard,
+    Amex,
+    Discover,
+}
+
+impl CardScheme {
+    /// The card's scheme: the AReq schemeId when it names one, else the PAN's BIN, else Visa.
+    /// schemeId wins so co-badged cards follow the network the DS routed them to.
+    pub fn of(account: &CardholderAccount) -> Self {
+        Self::from_scheme_id(&account.scheme_id)
+            .or_else(|| Self::from_pan(&account.acct_number))
+            .unwrap_or(CardScheme::Visa)
+    }
+
+    /// Scheme named by a cardholderAccount.schemeId, case-insensitively
+    pub fn from_scheme_id(scheme_id: &str) -> Option<Self> {
+        match scheme_id.to_lowercase().as_str() {
+            "visa" => Some(CardScheme::Visa),
+            "mastercard" => Some(CardScheme::Mastercard),
+            "amex" | "americanexpress" | "american_express" => Some(CardScheme::Amex),
+            "discover" => Some(CardScheme::Discover),
+            _ => None,
+        }
+    }
+
+    /// Scheme of a PAN from its BIN: 4 Visa; 51-55 and 2221-2720 Mastercard; 34 and 37 Amex;
+    /// 6011, 622126-622925, 644-649 and 65 Discover. Only the BIN is read, so masked PANs work.
+    pub fn from_pan(pan: &str) -> Option<Self> {
+        if pan.len() < 6 || !pan.bytes().take(6).all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let prefix = |digits: usize| pan[..digits].parse::<u32>().unwrap_or_default();
+        match (prefix(1), prefix(2), prefix(3), prefix(4), prefix(6)) {
+            (4, ..) => Some(CardScheme::Visa),
+            (_, 51..=55, ..) | (.., 2221..=2720, _) => Some(CardScheme::Mastercard),
+            (_, 34 | 37, ..) => Some(CardScheme::Amex),
+            (_, 65, ..) | (_, _, 644..=649, ..) | (.., 6011, _) | (.., 622126..=622925) => {
+                Some(CardScheme::Discover)
+            }
+            _ => None,
+        }
+    }
+
+    /// ECI for a successful authentication: Mastercard 02/01, the other schemes 05/06
+    pub fn success_eci(self, liability: SuccessLiability) -> Eci {
+        match (self, liability) {
+            (CardScheme::Mastercard, SuccessLiability::Authenticated) => {
+                Eci::MastercardAuthenticated
+            }
+            (CardScheme::Mastercard, SuccessLiability::Attempted) => Eci::MastercardAttempted,
+            (_, SuccessLiability::Authenticated) => Eci::VisaAuthenticated,
+            (_, SuccessLiability::Attempted) => Eci::VisaAttempted,
+        }
+    }
+
+    /// ECI for an authentication that did not succeed: Mastercard 00, the other schemes 07
+    pub fn not_authenticated_eci(self) -> Eci {
+        match self {
+            CardScheme::Mastercard => Eci::MastercardNotAuthenticated,
+            _ => Eci::VisaNotAuthenticated,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::tests::sample_authenticate_request;
+
+    #[test]
+    fn test_scheme_from_bin_and_scheme_id() {
+        assert_eq!(
+            CardScheme::from_pan("4000400040004001"),
+            Some(CardScheme::Visa)
+        );
+        assert_eq!(
+            CardScheme::from_pan("5555555555554444"),
+            Some(CardScheme::Mastercard)
+        );
+        assert_eq!(
+            CardScheme::from_pan("2223003122003222"),
+            Some(CardScheme::Mastercard)
+        );
+        assert_eq!(
+            CardScheme::from_pan("378282246310005"),
+            Some(CardScheme::Amex)
+        );
+        assert_eq!(
+            CardScheme::from_pan("6011111111111117"),
+            Some(CardScheme::Discover)
+        );
+        assert_eq!(
+            CardScheme::from_pan("6221260000000000"),
+            Some(CardScheme::Discover)
+        );
+        assert_eq!(CardScheme::from_pan("1234567890123456"), None);
+        assert_eq!(
+            CardScheme::from_pan("555555******4444"),
+            Some(CardScheme::Mastercard)
+        );
+
+        let mut account = sample_authenticate_request().cardholder_account;
+        account.scheme_id = "MasterCard".to_string();
+        assert_eq!(CardScheme::of(&account), CardScheme::Mastercard);
+
+        // An unknown schemeId falls back to the BIN
+        account.scheme_id = "A000000025".to_string();
+        account.acct_number = "5555555555554444".to_string();
+        assert_eq!(CardScheme::of(&account), CardScheme::Mastercard);
+        assert_eq!(
+            CardScheme::of(&account).success_eci(SuccessLiability::Attempted),
+            Eci::MastercardAttempted
+        );
+        assert_eq!(
+            CardScheme::Amex.not_authenticated_eci(),
+            Eci::VisaNotAuthenticated
+        );
+    }
+}
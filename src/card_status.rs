@@ -0,0 +1,125 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+
+use crate::models::TransStatus;
+
+/// Issuer-side status of a card, consulted before the challenge decision
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CardStatus {
+    #[default]
+    Active,
+    LostStolen,
+    Blocked,
+    NotEnrolled,
+}
+
+impl CardStatus {
+    /// The ARes transStatus and transStatusReason the issuer answers with, or None when the
+    /// card goes through the normal frictionless/challenge decision
+    pub fn outcome(&self) -> Option<(TransStatus, &'static str)> {
+        match self {
+            CardStatus::Active => None,
+            // 10 = Stolen card
+            CardStatus::LostStolen => Some((TransStatus::Rejected, "10")),
+            // 09 = Security failure
+            CardStatus::Blocked => Some((TransStatus::NotAuthenticated, "09")),
+            // 13 = Cardholder not enrolled in service
+            CardStatus::NotEnrolled => Some((TransStatus::Unavailable, "13")),
+        }
+    }
+}
+
+/// Card statuses keyed by PAN, seeded from `[acs.card_statuses]` and managed through the
+/// admin API. Cards without an entry are active. Changes are held in this process only.
+pub struct CardStatusList {
+    statuses: RwLock<HashMap<String, CardStatus>>,
+}
+
+impl CardStatusList {
+    pub fn new(seed: &HashMap<String, CardStatus>) -> Self {
+        Self {
+            statuses: RwLock::new(seed.clone()),
+        }
+    }
+
+    pub fn get(&self, pan: &str) -> CardStatus {
+        self.statuses
+            .read()
+            .expect("card status lock poisoned")
+            .get(pan)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn set(&self, pan: String, status: CardStatus) {
+        self.statuses
+            .write()
+            .expect("card status lock poisoned")
+            .insert(pan, status);
+    }
+
+    /// Remove a card's entry, returning whether it had one
+    pub fn remove(&self, pan: &str) -> bool {
+        self.statuses
+            .write()
+            .expect("card status lock poisoned")
+            .remove(pan)
+            .is_some()
+    }
+
+    /// All entries, ordered by PAN
+    pub fn all(&self) -> BTreeMap<String, CardStatus> {
+        self.statuses
+            .read()
+            .expect("card status lock poisoned")
+            .iter()
+            .map(|(pan, status)| (pan.clone(), *status))
+            .collect()
+    }
+}
+
+/// Whether a string looks like a PAN (12-19 digits)
+pub fn is_valid_pan(pan: &str) -> bool {
+    (12..=19).contains(&pan.len()) && pan.bytes().all(|b| b.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_card_status_list_overrides_seed() {
+        let seed = HashMap::from([("4000000000000119".to_string(), CardStatus::LostStolen)]);
+        let list = CardStatusList::new(&seed);
+
+        assert_eq!(list.get("4000000000000119"), CardStatus::LostStolen);
+        assert_eq!(list.get("4000000000000002"), CardStatus::Active);
+
+        list.set("4000000000000002".to_string(), CardStatus::Blocked);
+        assert_eq!(list.get("4000000000000002"), CardStatus::Blocked);
+        assert!(list.remove("4000000000000119"));
+        assert!(!list.remove("4000000000000119"));
+        assert_eq!(list.all().len(), 1);
+    }
+
+    #[test]
+    fn test_card_status_outcomes() {
+        assert_eq!(CardStatus::Active.outcome(), None);
+        assert_eq!(
+            CardStatus::LostStolen.outcome(),
+            Some((TransStatus::Rejected, "10"))
+        );
+        assert_eq!(
+            CardStatus::Blocked.outcome(),
+            Some((TransStatus::NotAuthenticated, "09"))
+        );
+        assert_eq!(
+            CardStatus::NotEnrolled.outcome(),
+            Some((TransStatus::Unavailable, "13"))
+        );
+        assert!(is_valid_pan("4000000000000002"));
+        assert!(!is_valid_pan("4000-0000"));
+    }
+}
@@ -0,0 +1,117 @@
+use chrono::{DateTime, Utc};
+use prometheus::{IntGauge, Registry};
+use serde::Serialize;
+use std::path::Path;
+use std::sync::atomic::{AtomicI64, Ordering};
+use tracing::warn;
+
+use crate::config::AcsSigningConfig;
+use crate::crypto::certificate_not_after;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Expiry of the ACS signing certificate, as reported by the health endpoint
+#[derive(Debug, Serialize)]
+pub struct CertificateStatus {
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub not_after: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub days_remaining: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Watches the ACS signing certificate's notAfter. The file is re-read on every check, so
+/// a replaced certificate is picked up by the next health check or metrics scrape.
+pub struct AcsCertificateMonitor {
+    cert_path: String,
+    warning_days: i64,
+    expiry: IntGauge,
+    // notAfter last warned about, so repeated checks of the same certificate log once
+    warned_not_after: AtomicI64,
+}
+
+impl AcsCertificateMonitor {
+    pub fn new(signing: &AcsSigningConfig) -> Self {
+        let expiry = IntGauge::new(
+            "acs_cert_expiry_timestamp",
+            "notAfter of the ACS signing certificate as a Unix timestamp (0 when unreadable)",
+        )
+        .expect("valid certificate expiry metric");
+
+        Self {
+            cert_path: signing.cert_path.clone(),
+            warning_days: signing.expiry_warning_days.into(),
+            expiry,
+            warned_not_after: AtomicI64::new(0),
+        }
+    }
+
+    pub fn register(&self, registry: &Registry) -> prometheus::Result<()> {
+        registry.register(Box::new(self.expiry.clone()))
+    }
+
+    /// Re-read the certificate, update the gauge and warn once per certificate nearing expiry
+    pub fn check(&self) -> CertificateStatus {
+        match certificate_not_after(Path::new(&self.cert_path)) {
+            Ok(not_after) => {
+                self.expiry.set(not_after);
+                let days_remaining =
+                    (not_after - Utc::now().timestamp()).div_euclid(SECONDS_PER_DAY);
+
+                if days_remaining < self.warning_days
+                    && self.warned_not_after.swap(not_after, Ordering::Relaxed) != not_after
+                {
+                    if days_remaining < 0 {
+                        warn!(
+                            cert_path = %self.cert_path,
+                            "ACS certificate has expired, mobile challenge signing will fail"
+                        );
+                    } else {
+                        warn!(
+                            cert_path = %self.cert_path,
+                            days_remaining,
+                            "ACS certificate expires soon"
+                        );
+                    }
+                }
+
+                CertificateStatus {
+                    path: self.cert_path.clone(),
+                    not_after: DateTime::from_timestamp(not_after, 0),
+                    days_remaining: Some(days_remaining),
+                    error: None,
+                }
+            }
+            Err(e) => {
+                self.expiry.set(0);
+                CertificateStatus {
+                    path: self.cert_path.clone(),
+                    not_after: None,
+                    days_remaining: None,
+                    error: Some(e.to_string()),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unreadable_certificate_reports_error() {
+        let signing = AcsSigningConfig {
+            cert_path: "certs/does-not-exist.pem".to_string(),
+            ..AcsSigningConfig::default()
+        };
+        let monitor = AcsCertificateMonitor::new(&signing);
+
+        let status = monitor.check();
+        assert!(status.error.is_some());
+        assert_eq!(status.days_remaining, None);
+        assert_eq!(monitor.expiry.get(), 0);
+    }
+}
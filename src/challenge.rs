@@ -0,0 +1,940 @@
+use actix_web::web;
+use base64::{engine::general_purpose, Engine as _};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::auth_value;
+use crate::card_scenarios::AppChallengeUi;
+use crate::card_scheme::CardScheme;
+use crate::config::{OobConfig, OtpConfig, Settings, SuccessLiability};
+use crate::handlers::acs::{protocol_2_3, results_handler};
+use crate::models::{
+    AcsRenderingType, AuthenticateRequest, ChallengeUi, Eci, ResultsRequest, TransStatus,
+};
+use crate::results_delivery;
+use crate::state_store::{StateStore, TransactionData};
+use crate::validation::FieldError;
+use crate::webhook::{self, FinalOutcome, OutcomeFlow};
+
+/// Default authenticationType when nothing could be negotiated (02 = dynamic / OTP)
+pub const DEFAULT_AUTHENTICATION_TYPE: &str = "02";
+
+/// Map an SDK authentication type (deviceRenderOptions.sdkAuthenticationType) to the
+/// authenticationType it can render: 01 = static, 02 = dynamic (OTP), 03 = OOB
+fn authentication_type_for_sdk(sdk_authentication_type: &str) -> Option<&'static str> {
+    match sdk_authentication_type {
+        "01" | "06" => Some("01"),               // Static passcode, KBA
+        "02" | "03" | "04" | "05" => Some("02"), // SMS, key fob, app and other OTPs
+        "07" | "08" | "09" => Some("03"),        // OOB biometrics, login and other
+        _ => None,
+    }
+}
+
+/// Choose the challenge method from the intersection of what the SDK can render and what the
+/// ACS supports, honouring the ACS preference order. Decoupled (04) needs no SDK UI, so it is
+/// always eligible when the ACS supports it. Browser flows (no SDK types) use the first ACS method.
+pub fn negotiate_authentication_type(
+    sdk_authentication_types: &[String],
+    acs_authentication_types: &[String],
+) -> String {
+    if sdk_authentication_types.is_empty() {
+        return acs_authentication_types
+            .first()
+            .cloned()
+            .unwrap_or_else(|| DEFAULT_AUTHENTICATION_TYPE.to_string());
+    }
+
+    acs_authentication_types
+        .iter()
+        .find(|acs_type| {
+            acs_type.as_str() == "04"
+                || sdk_authentication_types.iter().any(|sdk_type| {
+                    authentication_type_for_sdk(sdk_type) == Some(acs_type.as_str())
+                })
+        })
+        .cloned()
+        .unwrap_or_else(|| DEFAULT_AUTHENTICATION_TYPE.to_string())
+}
+
+/// authenticationMethod reported in the RReq for a negotiated authenticationType
+pub fn authentication_method_for(authentication_type: &str) -> &'static str {
+    match authentication_type {
+        "01" => "01", // Static passcode
+        "03" => "07", // OOB biometrics
+        "04" => "10", // Other (decoupled)
+        _ => "02",    // SMS OTP
+    }
+}
+
+/// authenticationMethod for an SPC challenge: Other (FIDO assertion via SPC)
+pub const SPC_AUTHENTICATION_METHOD: &str = "10";
+
+/// Whether `otp` is a numeric OTP of an accepted length
+pub fn is_valid_otp(otp: &str) -> bool {
+    otp.bytes().all(|b| b.is_ascii_digit())
+        && (OTP_MIN_LENGTH..=OTP_MAX_LENGTH).contains(&otp.len())
+}
+
+/// A random numeric OTP of `length` digits (at most 16)
+pub fn generate_otp(length: usize) -> String {
+    Uuid::new_v4()
+        .as_bytes()
+        .iter()
+        .take(length)
+        .map(|byte| char::from(b'0' + byte % 10))
+        .collect()
+}
+
+/// OTP a new challenge expects: the card scenario's, a random one when `acs.otp.random`
+/// is set, otherwise `acs.otp.value`
+pub fn challenge_otp(otp_config: &OtpConfig, scenario_otp: Option<&str>) -> String {
+    match scenario_otp {
+        Some(otp) => otp.to_string(),
+        None if otp_config.random => generate_otp(otp_config.random_length),
+        None => otp_config.value.clone(),
+    }
+}
+
+/// OTP a transaction's challenge accepts; transactions stored without one use `acs.otp.value`
+pub fn expected_otp<'a>(settings: &'a Settings, transaction_data: &'a TransactionData) -> &'a str {
+    transaction_data
+        .otp
+        .as_deref()
+        .unwrap_or(&settings.acs.otp.value)
+}
+
+/// Outcome of an OOB challenge a continuation CReq can complete: the admin API's decision,
+/// otherwise approval once the auto-approve delay passed. None while it is still pending.
+pub fn oob_decision(
+    oob_config: &OobConfig,
+    oob_approved: Option<bool>,
+    oob_started_at_ms: Option<i64>,
+    now_ms: i64,
+) -> Option<bool> {
+    if let Some(approved) = oob_approved {
+        return Some(approved);
+    }
+    let started_at_ms = oob_started_at_ms?;
+    let delay_ms = i64::try_from(oob_config.auto_approve_delay_ms).unwrap_or(i64::MAX);
+    (oob_config.auto_approve && now_ms.saturating_sub(started_at_ms) >= delay_ms).then_some(true)
+}
+
+/// Whether a CReq's oobContinue says the cardholder finished in the banking app: a boolean
+/// true, or the code 01 later message versions use
+pub fn is_oob_continue(oob_continue: Option<&serde_json::Value>) -> bool {
+    match oob_continue {
+        Some(serde_json::Value::String(value)) => value == "01",
+        Some(serde_json::Value::Bool(value)) => *value,
+        _ => false,
+    }
+}
+
+/// UI an app challenge presents first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InitialUi {
+    Otp,
+    SingleSelect,
+    MultiSelect,
+    Oob,
+    Html,
+}
+
+impl InitialUi {
+    /// HTML when the SDK renders only HTML (sdkInterface 02), OOB when that authenticationType
+    /// was negotiated, otherwise the card scenario's question or an OTP form
+    pub fn for_request(
+        settings: &Settings,
+        request: &AuthenticateRequest,
+        authentication_type: &str,
+    ) -> Self {
+        let scenario_ui = settings
+            .scenarios
+            .card_registry
+            .scenario_for(&request.cardholder_account.acct_number)
+            .and_then(|scenario| scenario.app_challenge_ui);
+        let sdk_interface = request
+            .device_render_options
+            .as_ref()
+            .map(|render| render.sdk_interface.as_str());
+        if sdk_interface == Some("02") {
+            InitialUi::Html
+        } else if authentication_type == "03" {
+            InitialUi::Oob
+        } else {
+            match scenario_ui {
+                Some(AppChallengeUi::SingleSelect) => InitialUi::SingleSelect,
+                Some(AppChallengeUi::MultiSelect) => InitialUi::MultiSelect,
+                Some(AppChallengeUi::Otp) | None => InitialUi::Otp,
+            }
+        }
+    }
+
+    /// acsInterface and acsUiTemplate announced in the ARes
+    pub fn rendering(self) -> (&'static str, &'static str) {
+        match self {
+            InitialUi::Otp => ("01", "01"),
+            InitialUi::SingleSelect => ("01", "02"),
+            InitialUi::MultiSelect => ("01", "03"),
+            InitialUi::Oob => ("01", "04"),
+            InitialUi::Html => ("02", "05"),
+        }
+    }
+}
+
+/// The HTML (05) OTP form for SDKs that render webview challenges: acsHTML is the page,
+/// base64url-encoded. Its form posts to HTTPS://EMV3DS/challenge, which the SDK turns into
+/// a CReq carrying the fields as challengeHTMLDataEntry.
+pub fn html_challenge_ui(notice: Option<&str>) -> ChallengeUi {
+    let challenge_info_text = match notice {
+        Some(notice) => format!("{} Enter the OTP sent to your phone.", notice),
+        None => "Enter the OTP sent to your phone.".to_string(),
+    };
+    let html = include_str!("../templates/acs-app-challenge.html")
+        .replace("{{CHALLENGE_INFO_TEXT}}", &challenge_info_text);
+    ChallengeUi::Html {
+        acs_html: general_purpose::URL_SAFE_NO_PAD.encode(html),
+    }
+}
+
+/// Option the single-select question accepts (the billing city, Bengaluru)
+pub const SINGLE_SELECT_ANSWER: &str = "02";
+/// Options the multi-select question accepts, in any order (the recently paid merchants)
+pub const MULTI_SELECT_ANSWER: [&str; 2] = ["01", "03"];
+
+/// The UI a challenge presents again after a wrong answer or a resend, carrying `notice`
+pub fn repeat_ui(presented: Option<&ChallengeUi>, notice: &str) -> ChallengeUi {
+    match presented {
+        Some(ChallengeUi::Html { .. }) => html_challenge_ui(Some(notice)),
+        Some(ChallengeUi::SingleSelect { .. }) => ChallengeUi::single_select_form(Some(notice)),
+        Some(ChallengeUi::MultiSelect { .. }) => ChallengeUi::multi_select_form(Some(notice)),
+        _ => ChallengeUi::otp_form_with_info(notice),
+    }
+}
+
+/// The answer the presented UI accepts: the select option(s) for a select question, the
+/// transaction's OTP otherwise. Multi-select answers are sorted, as `normalized_answer` does.
+pub fn expected_answer(settings: &Settings, transaction_data: &TransactionData) -> String {
+    match &transaction_data.challenge_ui {
+        Some(ChallengeUi::SingleSelect { .. }) => SINGLE_SELECT_ANSWER.to_string(),
+        Some(ChallengeUi::MultiSelect { .. }) => MULTI_SELECT_ANSWER.join(","),
+        _ => expected_otp(settings, transaction_data).to_string(),
+    }
+}
+
+/// A submitted challengeDataEntry in comparable form: multi-select choices in sorted order
+pub fn normalized_answer(presented: Option<&ChallengeUi>, entry: &str) -> String {
+    match presented {
+        Some(ChallengeUi::MultiSelect { .. }) => {
+            let mut selected: Vec<&str> = entry.split(',').collect();
+            selected.sort_unstable();
+            selected.join(",")
+        }
+        _ => entry.to_string(),
+    }
+}
+
+/// The OTP field of an HTML challenge form post; challengeHTMLDataEntry holds the form's
+/// fields URL-encoded (e.g. `otp=1234`)
+pub fn html_form_otp(challenge_html_data_entry: &str) -> Option<String> {
+    challenge_html_data_entry
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(name, _)| *name == "otp")
+        .and_then(|(_, value)| {
+            urlencoding::decode(&value.replace('+', " "))
+                .ok()
+                .map(|value| value.into_owned())
+        })
+}
+
+/// whitelistStatusSource when the cardholder answered the ACS's whitelisting prompt
+pub const WHITELIST_STATUS_SOURCE_ACS: &str = "03";
+
+/// Check a CReq whitelistingDataEntry: Y to whitelist the merchant, N not to
+pub fn validate_whitelisting_data_entry(entry: &serde_json::Value) -> Result<(), FieldError> {
+    match entry.as_str() {
+        Some("Y" | "N") => Ok(()),
+        _ => Err(FieldError::invalid(
+            "whitelistingDataEntry",
+            "whitelistingDataEntry must be Y or N",
+        )),
+    }
+}
+
+/// transStatusReason for a challenge ended by too many wrong OTPs
+pub const EXCEEDED_MAX_CHALLENGES: &str = "19";
+
+/// What a submitted OTP leads to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OtpAttempt {
+    /// Correct OTP: the challenge completes as authenticated
+    Verified,
+    /// Wrong OTP with attempts left: the OTP form is presented again
+    Retry { remaining: u32 },
+    /// Wrong OTP using up the last attempt: the challenge completes with transStatus = N
+    /// and transStatusReason = 19
+    LockedOut,
+}
+
+/// Check a submitted OTP against the expected one, counting a wrong one against `max_attempts`
+pub fn check_otp_attempt(
+    otp: &str,
+    expected_otp: &str,
+    otp_attempts: &mut u32,
+    max_attempts: u32,
+) -> OtpAttempt {
+    if otp == expected_otp {
+        return OtpAttempt::Verified;
+    }
+
+    *otp_attempts += 1;
+    if *otp_attempts >= max_attempts {
+        OtpAttempt::LockedOut
+    } else {
+        OtpAttempt::Retry {
+            remaining: max_attempts - *otp_attempts,
+        }
+    }
+}
+
+/// Record a cardholder interaction that does not end the challenge: a request for a new OTP
+/// or a wrong OTP with attempts left. It shows up in the RReq interactionCounter; failing to
+/// store it is only logged.
+pub async fn record_interaction(
+    state: &web::Data<Arc<Box<dyn StateStore>>>,
+    three_ds_server_trans_id: Uuid,
+    transaction_data: &mut TransactionData,
+) {
+    transaction_data.interaction_count += 1;
+    if let Err(e) = state
+        .update(&three_ds_server_trans_id, transaction_data.clone())
+        .await
+    {
+        warn!(error = %e, "failed to record the challenge interaction");
+    }
+}
+
+/// Two-digit interactionCounter for the number of cardholder challenge interactions
+pub fn format_interaction_counter(interaction_count: u32) -> String {
+    format!("{:02}", interaction_count.min(99))
+}
+
+/// Accepted length of a numeric OTP submitted through a text (01) challenge UI
+pub const OTP_MIN_LENGTH: usize = 4;
+pub const OTP_MAX_LENGTH: usize = 8;
+
+/// Check a CReq challengeDataEntry against the UI the cardholder was shown: a numeric OTP for
+/// text entry (01), one offered option for single-select (02) and a comma-separated list of
+/// distinct offered options for multi-select (03). OOB (04) and HTML (05) don't use the field.
+pub fn validate_challenge_data_entry(ui: &ChallengeUi, entry: &str) -> Result<(), FieldError> {
+    match ui {
+        ChallengeUi::Otp { .. } => {
+            if !is_valid_otp(entry) {
+                return Err(FieldError::invalid(
+                    "challengeDataEntry",
+                    &format!(
+                        "challengeDataEntry must be a {}-{} digit OTP",
+                        OTP_MIN_LENGTH, OTP_MAX_LENGTH
+                    ),
+                ));
+            }
+            Ok(())
+        }
+        ChallengeUi::SingleSelect {
+            challenge_select_info,
+            ..
+        } => {
+            if !challenge_select_info
+                .iter()
+                .any(|option| option.value == entry)
+            {
+                return Err(FieldError::invalid(
+                    "challengeDataEntry",
+                    "challengeDataEntry must be one of the offered challengeSelectInfo options",
+                ));
+            }
+            Ok(())
+        }
+        ChallengeUi::MultiSelect {
+            challenge_select_info,
+            ..
+        } => {
+            let selected: Vec<&str> = entry.split(',').collect();
+            let all_offered = selected.iter().all(|value| {
+                challenge_select_info
+                    .iter()
+                    .any(|option| option.value == *value)
+            });
+            let has_duplicates = selected
+                .iter()
+                .enumerate()
+                .any(|(i, value)| selected[..i].contains(value));
+
+            if entry.is_empty() || !all_offered || has_duplicates {
+                return Err(FieldError::invalid(
+                    "challengeDataEntry",
+                    "challengeDataEntry must list distinct offered challengeSelectInfo options",
+                ));
+            }
+            Ok(())
+        }
+        ChallengeUi::Oob { .. } | ChallengeUi::Html { .. } => Err(FieldError::invalid(
+            "challengeDataEntry",
+            "challengeDataEntry is not used with the presented acsUiType",
+        )),
+    }
+}
+
+/// clientDataJSON type of a Secure Payment Confirmation assertion
+pub const SPC_CLIENT_DATA_TYPE: &str = "payment.secure-payment-confirmation";
+
+/// Fresh base64url challenge for an SPC assertion
+pub fn generate_spc_challenge() -> String {
+    let mut bytes = Vec::with_capacity(32);
+    bytes.extend_from_slice(Uuid::new_v4().as_bytes());
+    bytes.extend_from_slice(Uuid::new_v4().as_bytes());
+    general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Mock SPC assertion check: the base64url clientDataJSON must be an SPC assertion over the
+/// challenge issued in the ARes. Signatures aren't verified as the mock holds no credentials.
+pub fn verify_spc_assertion(client_data_json: &str, expected_challenge: &str) -> bool {
+    let client_data = general_purpose::URL_SAFE_NO_PAD
+        .decode(client_data_json.trim_end_matches('='))
+        .ok()
+        .and_then(|bytes| serde_json::from_slice::<serde_json::Value>(&bytes).ok());
+
+    match client_data {
+        Some(client_data) => {
+            client_data["type"].as_str() == Some(SPC_CLIENT_DATA_TYPE)
+                && client_data["challenge"].as_str() == Some(expected_challenge)
+        }
+        None => false,
+    }
+}
+
+/// How a completed challenge ended, as reported in the RReq
+#[derive(Debug, Clone)]
+pub struct ChallengeOutcome {
+    pub trans_status: TransStatus,
+    pub eci: Eci,
+    /// Present only for Y and A
+    pub authentication_value: Option<String>,
+}
+
+impl ChallengeOutcome {
+    /// The outcome an ACS reports with a transStatus: the scheme's ECI and, for Y and A, its
+    /// authenticationValue. Only the final RReq statuses (Y, A, N, U and R) have one. A
+    /// successful authentication (Y) reports `liability`, so attempts processing turns it
+    /// into A.
+    pub fn for_status(
+        trans_status: TransStatus,
+        req: &AuthenticateRequest,
+        liability: SuccessLiability,
+    ) -> Option<Self> {
+        let scheme = CardScheme::of(&req.cardholder_account);
+        let liability = match trans_status {
+            TransStatus::Authenticated => liability,
+            TransStatus::Attempted => SuccessLiability::Attempted,
+            TransStatus::NotAuthenticated | TransStatus::Unavailable | TransStatus::Rejected => {
+                return Some(Self {
+                    trans_status,
+                    eci: scheme.not_authenticated_eci(),
+                    authentication_value: None,
+                });
+            }
+            _ => return None,
+        };
+        let trans_status = match liability {
+            SuccessLiability::Authenticated => TransStatus::Authenticated,
+            SuccessLiability::Attempted => TransStatus::Attempted,
+        };
+        let eci = scheme.success_eci(liability);
+        let authentication_value = Some(auth_value::for_scheme(scheme).generate(req, eci));
+        Some(Self {
+            trans_status,
+            eci,
+            authentication_value,
+        })
+    }
+}
+
+/// The cardholder's answer to a challenge
+#[derive(Debug, Clone, Copy)]
+pub struct Completion {
+    pub verified: bool,
+    /// authenticationMethod implied by the flow (SPC); otherwise it follows the negotiated
+    /// authenticationType. A scenario's authentication_method overrides both.
+    pub authentication_method: Option<&'static str>,
+    /// transStatusReason reported in the RReq when the challenge fails
+    pub trans_status_reason: Option<&'static str>,
+    /// Reported in the final-outcome webhook
+    pub flow: OutcomeFlow,
+}
+
+/// Complete a challenge for the app, browser and SPC flows alike: decide the outcome, count
+/// the cardholder interaction, record the RReq and notify the merchant. Failing to record it
+/// is logged rather than returned, so the cardholder still gets a response.
+pub async fn complete(
+    state: &web::Data<Arc<Box<dyn StateStore>>>,
+    settings: &Settings,
+    three_ds_server_trans_id: Uuid,
+    transaction_data: &mut TransactionData,
+    completion: Completion,
+) -> ChallengeOutcome {
+    let trans_status = if completion.verified {
+        TransStatus::Authenticated
+    } else {
+        TransStatus::NotAuthenticated
+    };
+    // Decided at AReq time from the full PAN; older transactions fall back to the masked one
+    let liability = transaction_data.success_liability.unwrap_or_else(|| {
+        let req = &transaction_data.authenticate_request;
+        settings
+            .scenarios
+            .success_eci
+            .liability_for(req.message_category, &req.cardholder_account.acct_number)
+    });
+    let outcome = ChallengeOutcome::for_status(
+        trans_status,
+        &transaction_data.authenticate_request,
+        liability,
+    )
+    .expect("Y and N are final statuses");
+
+    // Record this cardholder interaction before reporting it in the RReq
+    transaction_data.interaction_count += 1;
+    if let Err(e) = state
+        .update(&three_ds_server_trans_id, transaction_data.clone())
+        .await
+    {
+        warn!(error = %e, "failed to record the challenge interaction");
+    }
+
+    let results_request = results_request_for(
+        settings,
+        three_ds_server_trans_id,
+        transaction_data,
+        &outcome,
+        completion.authentication_method,
+        completion.trans_status_reason.map(str::to_string),
+    );
+
+    results_delivery::deliver(
+        &settings.results_delivery,
+        &transaction_data
+            .authenticate_request
+            .merchant
+            .results_response_notification_url,
+        &results_request,
+    );
+
+    match results_handler(web::Json(results_request), state.clone()).await {
+        Ok(_) => debug!("transaction updated with the results"),
+        Err(e) => warn!(error = ?e, "failed to record the results"),
+    }
+
+    if let Some(notification_url) = &transaction_data.redirect_url {
+        webhook::notify(
+            &settings.webhook,
+            notification_url,
+            FinalOutcome {
+                three_ds_server_trans_id,
+                trans_status: outcome.trans_status,
+                eci: outcome.eci,
+                authentication_value: outcome.authentication_value.clone(),
+                flow: completion.flow,
+            },
+        );
+    }
+    outcome
+}
+
+/// The RReq reporting a transaction's outcome. `flow_authentication_method` is the
+/// authenticationMethod implied by the flow, if any (see [`Completion`]).
+pub fn results_request_for(
+    settings: &Settings,
+    three_ds_server_trans_id: Uuid,
+    transaction_data: &TransactionData,
+    outcome: &ChallengeOutcome,
+    flow_authentication_method: Option<&'static str>,
+    trans_status_reason: Option<String>,
+) -> ResultsRequest {
+    let authentication_type = transaction_data
+        .authentication_type
+        .clone()
+        .unwrap_or_else(|| DEFAULT_AUTHENTICATION_TYPE.to_string());
+    let authentication_method = settings
+        .scenarios
+        .authentication_method
+        .clone()
+        .unwrap_or_else(|| {
+            flow_authentication_method
+                .unwrap_or_else(|| authentication_method_for(&authentication_type))
+                .to_string()
+        });
+
+    // Decided at AReq time, as the stored request only has the masked PAN
+    let fields_2_3 = transaction_data
+        .protocol_2_3
+        .clone()
+        .unwrap_or_else(|| protocol_2_3(settings, &transaction_data.authenticate_request));
+    let mut results_request = ResultsRequest {
+        acs_trans_id: transaction_data.acs_trans_id,
+        message_category: transaction_data.authenticate_request.message_category,
+        eci: outcome.eci,
+        message_type: "RReq".to_string(),
+        acs_rendering_type: AcsRenderingType::for_ui(transaction_data.challenge_ui.as_ref()),
+        ds_trans_id: transaction_data.ds_trans_id,
+        authentication_method: Some(authentication_method),
+        authentication_type,
+        message_version: transaction_data.message_version().to_string(),
+        sdk_trans_id: transaction_data.sdk_trans_id,
+        interaction_counter: format_interaction_counter(transaction_data.interaction_count),
+        authentication_value: outcome.authentication_value.clone(),
+        trans_status: outcome.trans_status,
+        trans_status_reason,
+        three_ds_server_trans_id,
+        device_binding_status: fields_2_3.device_binding_status,
+        trust_list_status: fields_2_3.trust_list_status,
+        whitelist_status: transaction_data.whitelisting_choice.clone(),
+        whitelist_status_source: transaction_data
+            .whitelisting_choice
+            .as_ref()
+            .map(|_| WHITELIST_STATUS_SOURCE_ACS.to_string()),
+    };
+
+    if crate::protocol::is_2_1(&results_request.message_version) {
+        results_request.restrict_to_2_1_0();
+    }
+    results_request
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth_value::AuthValueGenerator;
+    use crate::config::tests::test_settings;
+    use crate::handlers::tests::{sample_areq, TestState};
+    use crate::models::tests::sample_authenticate_request;
+    use crate::models::ChallengeSelectOption;
+
+    fn types(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn test_negotiation_prefers_acs_order() {
+        let acs = types(&["03", "02"]);
+        assert_eq!(
+            negotiate_authentication_type(&types(&["02", "07"]), &acs),
+            "03"
+        );
+        assert_eq!(negotiate_authentication_type(&types(&["02"]), &acs), "02");
+    }
+
+    #[test]
+    fn test_otp_attempts_lock_out() {
+        let mut attempts = 0;
+        assert_eq!(
+            check_otp_attempt("0000", "1234", &mut attempts, 3),
+            OtpAttempt::Retry { remaining: 2 }
+        );
+        assert_eq!(
+            check_otp_attempt("1111", "1234", &mut attempts, 3),
+            OtpAttempt::Retry { remaining: 1 }
+        );
+        assert_eq!(
+            check_otp_attempt("1234", "1234", &mut attempts, 3),
+            OtpAttempt::Verified
+        );
+        assert_eq!(attempts, 2);
+        assert_eq!(
+            check_otp_attempt("2222", "1234", &mut attempts, 3),
+            OtpAttempt::LockedOut
+        );
+
+        let mut attempts = 0;
+        assert_eq!(
+            check_otp_attempt("1234", "987654", &mut attempts, 1),
+            OtpAttempt::LockedOut
+        );
+    }
+
+    #[test]
+    fn test_challenge_otp_sources() {
+        let mut otp_config = OtpConfig::default();
+        assert_eq!(challenge_otp(&otp_config, None), "1234");
+        assert_eq!(challenge_otp(&otp_config, Some("55555")), "55555");
+
+        otp_config.random = true;
+        let random = challenge_otp(&otp_config, None);
+        assert_eq!(random.len(), otp_config.random_length);
+        assert!(is_valid_otp(&random));
+        assert_eq!(challenge_otp(&otp_config, Some("55555")), "55555");
+    }
+
+    #[test]
+    fn test_oob_decision() {
+        let mut oob_config = OobConfig {
+            auto_approve: true,
+            auto_approve_delay_ms: 5000,
+        };
+        assert_eq!(oob_decision(&oob_config, None, Some(1000), 3000), None);
+        assert_eq!(
+            oob_decision(&oob_config, None, Some(1000), 6000),
+            Some(true)
+        );
+        assert_eq!(
+            oob_decision(&oob_config, Some(false), Some(1000), 6000),
+            Some(false)
+        );
+        assert_eq!(oob_decision(&oob_config, None, None, 6000), None);
+
+        oob_config.auto_approve = false;
+        assert_eq!(oob_decision(&oob_config, None, Some(1000), 60000), None);
+        assert_eq!(
+            oob_decision(&oob_config, Some(true), Some(1000), 2000),
+            Some(true)
+        );
+
+        assert!(is_oob_continue(Some(&serde_json::json!("01"))));
+        assert!(is_oob_continue(Some(&serde_json::json!(true))));
+        assert!(!is_oob_continue(Some(&serde_json::json!("02"))));
+        assert!(!is_oob_continue(None));
+    }
+
+    #[test]
+    fn test_whitelisting_data_entry() {
+        assert!(validate_whitelisting_data_entry(&serde_json::json!("Y")).is_ok());
+        assert!(validate_whitelisting_data_entry(&serde_json::json!("N")).is_ok());
+        let error = validate_whitelisting_data_entry(&serde_json::json!(true)).unwrap_err();
+        assert_eq!(error.field, "whitelistingDataEntry");
+        assert!(validate_whitelisting_data_entry(&serde_json::json!("y")).is_err());
+    }
+
+    #[test]
+    fn test_select_answers() {
+        let multi = ChallengeUi::multi_select_form(None);
+        assert_eq!(normalized_answer(Some(&multi), "03,01"), "01,03");
+        assert_eq!(normalized_answer(None, "03,01"), "03,01");
+
+        let single = ChallengeUi::single_select_form(None);
+        assert!(validate_challenge_data_entry(&single, SINGLE_SELECT_ANSWER).is_ok());
+        assert!(validate_challenge_data_entry(&multi, &MULTI_SELECT_ANSWER.join(",")).is_ok());
+
+        let retry = repeat_ui(Some(&single), "Incorrect answer.");
+        assert_eq!(retry.acs_ui_type(), "02");
+        assert_eq!(repeat_ui(None, "Incorrect OTP.").acs_ui_type(), "01");
+    }
+
+    #[test]
+    fn test_html_challenge_form() {
+        assert_eq!(html_form_otp("otp=1234").as_deref(), Some("1234"));
+        assert_eq!(
+            html_form_otp("submit=1&otp=98%2076").as_deref(),
+            Some("98 76")
+        );
+        assert_eq!(html_form_otp("code=1234"), None);
+
+        let ui = html_challenge_ui(Some("Incorrect OTP."));
+        assert_eq!(ui.acs_ui_type(), "05");
+        let ChallengeUi::Html { acs_html } = ui else {
+            panic!("expected an HTML UI");
+        };
+        let html =
+            String::from_utf8(general_purpose::URL_SAFE_NO_PAD.decode(acs_html).unwrap()).unwrap();
+        assert!(html.contains("HTTPS://EMV3DS/challenge"));
+        assert!(html.contains("Incorrect OTP. Enter the OTP"));
+    }
+
+    #[test]
+    fn test_format_interaction_counter() {
+        assert_eq!(format_interaction_counter(1), "01");
+        assert_eq!(format_interaction_counter(12), "12");
+        assert_eq!(format_interaction_counter(150), "99");
+    }
+
+    #[test]
+    fn test_negotiation_fallbacks() {
+        assert_eq!(
+            negotiate_authentication_type(&types(&["01"]), &types(&["03"])),
+            "02"
+        );
+        assert_eq!(
+            negotiate_authentication_type(&types(&["01"]), &types(&["03", "04"])),
+            "04"
+        );
+        assert_eq!(
+            negotiate_authentication_type(&[], &types(&["01", "02"])),
+            "01"
+        );
+    }
+
+    fn select_options() -> Vec<ChallengeSelectOption> {
+        ["phone", "email"]
+            .iter()
+            .map(|value| ChallengeSelectOption {
+                value: value.to_string(),
+                label: value.to_uppercase(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_otp_entry_validation() {
+        let ui = ChallengeUi::otp_form();
+        assert!(validate_challenge_data_entry(&ui, "1234").is_ok());
+        assert!(validate_challenge_data_entry(&ui, "123").is_err());
+        assert!(validate_challenge_data_entry(&ui, "123456789").is_err());
+
+        let error = validate_challenge_data_entry(&ui, "12a4").unwrap_err();
+        assert_eq!(error.error_code, "203");
+        assert_eq!(error.field, "challengeDataEntry");
+    }
+
+    #[test]
+    fn test_select_entry_validation() {
+        let single = ChallengeUi::SingleSelect {
+            challenge_info_header: "Verify".to_string(),
+            challenge_info_label: "Send code to".to_string(),
+            challenge_info_text: None,
+            challenge_select_info: select_options(),
+            submit_authentication_label: "Next".to_string(),
+        };
+        assert!(validate_challenge_data_entry(&single, "email").is_ok());
+        assert!(validate_challenge_data_entry(&single, "phone,email").is_err());
+
+        let multi = ChallengeUi::MultiSelect {
+            challenge_info_header: "Verify".to_string(),
+            challenge_info_label: "Pick all that apply".to_string(),
+            challenge_info_text: None,
+            challenge_select_info: select_options(),
+            submit_authentication_label: "Next".to_string(),
+        };
+        assert!(validate_challenge_data_entry(&multi, "phone,email").is_ok());
+        assert!(validate_challenge_data_entry(&multi, "phone,phone").is_err());
+        assert!(validate_challenge_data_entry(&multi, "fax").is_err());
+        assert!(validate_challenge_data_entry(&multi, "").is_err());
+    }
+
+    #[test]
+    fn test_spc_assertion_verification() {
+        let challenge = generate_spc_challenge();
+        let encode =
+            |value: serde_json::Value| general_purpose::URL_SAFE_NO_PAD.encode(value.to_string());
+
+        let valid = encode(serde_json::json!({
+            "type": SPC_CLIENT_DATA_TYPE,
+            "challenge": challenge,
+            "origin": "https://merchant.example"
+        }));
+        assert!(verify_spc_assertion(&valid, &challenge));
+
+        let wrong_type =
+            encode(serde_json::json!({ "type": "webauthn.get", "challenge": challenge }));
+        assert!(!verify_spc_assertion(&wrong_type, &challenge));
+
+        let wrong_challenge = encode(serde_json::json!({
+            "type": SPC_CLIENT_DATA_TYPE,
+            "challenge": generate_spc_challenge()
+        }));
+        assert!(!verify_spc_assertion(&wrong_challenge, &challenge));
+        assert!(!verify_spc_assertion("not base64!", &challenge));
+    }
+
+    #[actix_web::test]
+    async fn test_complete_reports_the_outcome_in_the_rreq() {
+        let app = TestState::new(test_settings());
+
+        // The app and browser flows share the outcome, whichever channel the cardholder used
+        let cases = [(true, "Y", "05"), (false, "N", "07")];
+        for (flow, (verified, trans_status, eci)) in [OutcomeFlow::App, OutcomeFlow::Browser]
+            .into_iter()
+            .flat_map(|flow| cases.map(|case| (flow, case)))
+        {
+            let mut areq = sample_areq();
+            let id = Uuid::new_v4();
+            areq["threeDsServerTransId"] = id.to_string().into();
+            app.authenticate(&areq).await.unwrap();
+            let mut transaction = app.state.get(&id).await.unwrap().unwrap();
+
+            let completion = Completion {
+                verified,
+                authentication_method: None,
+                trans_status_reason: None,
+                flow,
+            };
+            let outcome =
+                complete(&app.state, &app.settings, id, &mut transaction, completion).await;
+            assert_eq!(
+                serde_json::to_value(outcome.trans_status).unwrap(),
+                trans_status
+            );
+            assert_eq!(serde_json::to_value(outcome.eci).unwrap(), eci);
+
+            // Recorded for the 3DS Server, counting the cardholder's answer
+            let stored = app.state.get(&id).await.unwrap().unwrap();
+            let rreq = stored.results_request.unwrap();
+            assert_eq!(rreq.interaction_counter, "01");
+            assert_eq!(rreq.authentication_value, outcome.authentication_value);
+            assert_eq!(
+                serde_json::to_value(rreq.trans_status).unwrap(),
+                trans_status
+            );
+        }
+    }
+
+    #[test]
+    fn test_outcome_for_final_statuses() {
+        let mut req = sample_authenticate_request();
+        req.cardholder_account.scheme_id = "mastercard".to_string();
+        let attempted = ChallengeOutcome::for_status(
+            TransStatus::Attempted,
+            &req,
+            SuccessLiability::Authenticated,
+        )
+        .unwrap();
+        assert_eq!(attempted.eci, Eci::MastercardAttempted);
+        assert_eq!(
+            attempted.authentication_value,
+            Some(auth_value::MastercardAav.generate(&req, Eci::MastercardAttempted))
+        );
+
+        // A success under attempts processing is reported as A
+        let verified = ChallengeOutcome::for_status(
+            TransStatus::Authenticated,
+            &req,
+            SuccessLiability::Attempted,
+        )
+        .unwrap();
+        assert_eq!(verified.trans_status, TransStatus::Attempted);
+        assert_eq!(verified.eci, Eci::MastercardAttempted);
+        let verified = ChallengeOutcome::for_status(
+            TransStatus::Authenticated,
+            &req,
+            SuccessLiability::Authenticated,
+        )
+        .unwrap();
+        assert_eq!(verified.trans_status, TransStatus::Authenticated);
+        assert_eq!(verified.eci, Eci::MastercardAuthenticated);
+
+        req.cardholder_account.scheme_id = "visa".to_string();
+        let rejected =
+            ChallengeOutcome::for_status(TransStatus::Rejected, &req, SuccessLiability::Attempted)
+                .unwrap();
+        assert_eq!(rejected.trans_status, TransStatus::Rejected);
+        assert_eq!(rejected.eci, Eci::VisaNotAuthenticated);
+        assert_eq!(rejected.authentication_value, None);
+
+        assert!(ChallengeOutcome::for_status(
+            TransStatus::ChallengeRequired,
+            &req,
+            SuccessLiability::Authenticated
+        )
+        .is_none());
+    }
+}
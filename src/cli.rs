@@ -0,0 +1,113 @@
+use clap::{Parser, ValueEnum};
+
+use crate::config::{Settings, StateBackend};
+
+/// Command-line overrides applied on top of the loaded configuration
+#[derive(Debug, Parser)]
+#[command(version, about = "Mock EMV 3DS Server, Directory Server and ACS")]
+pub struct Cli {
+    /// Configuration file to load instead of config/{RUN_MODE}
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<String>,
+
+    /// Port for the main listener, overriding server.port
+    #[arg(long)]
+    pub port: Option<u16>,
+
+    /// Where transactions are kept, overriding state.backend
+    #[arg(long, value_enum, conflicts_with = "in_memory")]
+    pub store: Option<Store>,
+
+    /// Keep transactions in memory instead of Redis; same as --store memory
+    #[arg(long)]
+    pub in_memory: bool,
+
+    /// Redis URL, overriding redis.url
+    #[arg(long, value_name = "URL")]
+    pub redis_url: Option<String>,
+
+    /// Log level or RUST_LOG-style filter, overriding server.log_level
+    #[arg(long, value_name = "LEVEL")]
+    pub log_level: Option<String>,
+
+    /// Run the startup self-test, overriding monitoring.startup_self_test
+    #[arg(long)]
+    pub self_test: bool,
+
+    /// Print the effective settings as JSON (secrets redacted) and exit
+    #[arg(long)]
+    pub print_config: bool,
+}
+
+/// State store choices for --store
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Store {
+    Memory,
+    Redis,
+    Sql,
+}
+
+impl From<Store> for StateBackend {
+    fn from(store: Store) -> Self {
+        match store {
+            Store::Memory => StateBackend::Memory,
+            Store::Redis => StateBackend::Redis,
+            Store::Sql => StateBackend::Sql,
+        }
+    }
+}
+
+impl Cli {
+    pub fn apply(&self, settings: &mut Settings) {
+        if let Some(port) = self.port {
+            settings.server.port = port;
+        }
+        if let Some(store) = self.store {
+            settings.state.backend = store.into();
+        }
+        if self.in_memory {
+            settings.state.backend = StateBackend::Memory;
+        }
+        if let Some(redis_url) = &self.redis_url {
+            settings.redis.url = redis_url.clone();
+        }
+        if let Some(log_level) = &self.log_level {
+            settings.server.log_level = log_level.clone();
+        }
+        if self.self_test {
+            settings.monitoring.startup_self_test = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags_override_settings() {
+        let cli = Cli::try_parse_from([
+            "mock_three_ds_server",
+            "--port",
+            "9090",
+            "--store",
+            "memory",
+            "--redis-url",
+            "redis://cache:6379",
+            "--log-level",
+            "warn",
+        ])
+        .unwrap();
+        let mut settings = Settings::default();
+        cli.apply(&mut settings);
+
+        assert_eq!(settings.server.port, 9090);
+        assert_eq!(settings.state.backend, StateBackend::Memory);
+        assert_eq!(settings.redis.url, "redis://cache:6379");
+        assert_eq!(settings.server.log_level, "warn");
+
+        assert!(
+            Cli::try_parse_from(["mock_three_ds_server", "--store", "sql", "--in-memory"]).is_err()
+        );
+    }
+}
@@ -1,42 +1,433 @@
 use config::{Config, ConfigError, Environment, File};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::Reverse;
+use std::collections::HashMap;
 use std::env;
+use std::path::Path;
+use uuid::Uuid;
+
+use crate::card_scenarios::CardRegistry;
+use crate::card_status::{is_valid_pan, CardStatus};
+use crate::challenge::{is_valid_otp, OTP_MAX_LENGTH, OTP_MIN_LENGTH};
+use crate::faults::FaultProfile;
+use crate::models::MessageCategory;
+use crate::protocol;
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Settings {
     pub server: ServerConfig,
     pub redis: RedisConfig,
+    #[serde(default)]
+    pub state: StateConfig,
     pub performance: PerformanceConfig,
     pub monitoring: MonitoringConfig,
     pub retry: RetryConfig,
     pub cache: CacheConfig,
+    #[serde(default)]
+    pub scenarios: ScenarioConfig,
+    #[serde(default)]
+    pub validation: ValidationConfig,
+    #[serde(default)]
+    pub payload_limits: PayloadLimitsConfig,
+    #[serde(default)]
+    pub components: ComponentsConfig,
+    #[serde(default)]
+    pub routes: RoutesConfig,
+    #[serde(default)]
+    pub ds: DsConfig,
+    #[serde(default)]
+    pub acs: AcsConfig,
+    #[serde(default)]
+    pub three_ds_server: ThreeDsServerConfig,
+    #[serde(default)]
+    pub debug: DebugConfig,
+    #[serde(default)]
+    pub webhook: WebhookConfig,
+    /// Outbound RReq to the merchant's resultsResponseNotificationUrl; same keys as `webhook`
+    #[serde(default)]
+    pub results_delivery: WebhookConfig,
+    #[serde(default)]
+    pub faults: FaultsConfig,
+}
+
+/// Which 3DS components this process serves
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ComponentRole {
+    /// 3DS Server, DS and ACS routes in one process
+    #[default]
+    All,
+    /// Only the 3DS Server (and DS) routes; AReqs/RReqs go to `acs_base_url`
+    Server,
+    /// Only the ACS routes, including /acs/areq and /acs/rreq for a remote 3DS Server
+    Acs,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct ComponentsConfig {
+    pub role: ComponentRole,
+    /// Base URL of the ACS component. When set, the 3DS Server side sends AReqs and RReqs
+    /// there over HTTP - also with role = "all", to exercise the network path in one
+    /// process. Unset means in-process calls.
+    pub acs_base_url: Option<String>,
+    /// Timeout for 3DS Server -> ACS calls
+    pub acs_timeout_ms: u64,
+}
+
+impl Default for ComponentsConfig {
+    fn default() -> Self {
+        Self {
+            role: ComponentRole::All,
+            acs_base_url: None,
+            acs_timeout_ms: 10_000,
+        }
+    }
+}
+
+impl ComponentsConfig {
+    pub fn serves_server(&self) -> bool {
+        self.role != ComponentRole::Acs
+    }
+
+    pub fn serves_acs(&self) -> bool {
+        self.role != ComponentRole::Server
+    }
+}
+
+/// Where each route group is mounted. Prefixes are prepended to the group's routes
+/// (e.g. `/server/3ds/authenticate`); a group without its own port is served on
+/// `server.port`, and groups sharing a port share a listener.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct RoutesConfig {
+    /// 3DS Server and mock DS routes (/3ds/*, /ds/*)
+    pub server_prefix: String,
+    /// ACS routes (/acs/*, /challenge, /processor/mock/acs/*)
+    pub acs_prefix: String,
+    /// Health and metrics endpoints
+    pub admin_prefix: String,
+    pub server_port: Option<u16>,
+    pub acs_port: Option<u16>,
+    pub admin_port: Option<u16>,
+    /// Serve only the `/v1` paths, without the unversioned aliases
+    pub versioned_only: bool,
+}
+
+/// Current API version; every route group is mounted under `/v1`
+pub const API_VERSION: &str = "v1";
+
+impl RoutesConfig {
+    /// A group prefix under the versioned API path, e.g. `/v1/acs`
+    pub fn versioned(prefix: &str) -> String {
+        format!("/{}{}", API_VERSION, prefix)
+    }
+
+    /// Prefixes a route group is mounted under: the versioned one, then the unversioned
+    /// alias unless `versioned_only` is set
+    pub fn mounts(&self, prefix: &str) -> Vec<String> {
+        let mut mounts = vec![Self::versioned(prefix)];
+        if !self.versioned_only {
+            mounts.push(prefix.to_string());
+        }
+        mounts
+    }
+
+    /// A group prefix as used in URLs handed to clients. The unversioned alias while it is
+    /// served, so existing clients see unchanged URLs.
+    pub fn public_prefix(&self, prefix: &str) -> String {
+        if self.versioned_only {
+            Self::versioned(prefix)
+        } else {
+            prefix.to_string()
+        }
+    }
+
+    /// A request path with its API version and group prefix removed, i.e. the route as
+    /// declared in code
+    pub fn unprefixed<'a>(&self, path: &'a str) -> &'a str {
+        let path = path
+            .strip_prefix('/')
+            .and_then(|rest| rest.strip_prefix(API_VERSION))
+            .filter(|rest| rest.starts_with('/'))
+            .unwrap_or(path);
+        [&self.server_prefix, &self.acs_prefix, &self.admin_prefix]
+            .into_iter()
+            .filter(|prefix| !prefix.is_empty())
+            .filter_map(|prefix| path.strip_prefix(prefix.as_str()))
+            .filter(|rest| rest.starts_with('/'))
+            .min_by_key(|rest| rest.len())
+            .unwrap_or(path)
+    }
+}
+
+/// Maximum request body size per endpoint, in bytes. Bodies over the limit are answered
+/// with an Erro and HTTP 413.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct PayloadLimitsConfig {
+    pub version_bytes: usize,
+    /// AReqs can carry long user agents and accept headers
+    pub authenticate_bytes: usize,
+    pub results_bytes: usize,
+    pub final_bytes: usize,
+    /// Encrypted CReq JWEs are small - keep this tight
+    pub challenge_bytes: usize,
+    /// Form posts to the ACS trigger-otp and verify-otp pages
+    pub acs_form_bytes: usize,
+    pub spc_verify_bytes: usize,
+}
+
+impl Default for PayloadLimitsConfig {
+    fn default() -> Self {
+        Self {
+            version_bytes: 4 * 1024,
+            authenticate_bytes: 256 * 1024,
+            results_bytes: 16 * 1024,
+            final_bytes: 4 * 1024,
+            challenge_bytes: 16 * 1024,
+            acs_form_bytes: 16 * 1024,
+            spc_verify_bytes: 64 * 1024,
+        }
+    }
+}
+
+impl PayloadLimitsConfig {
+    /// The body limit applied to the route serving `path`
+    pub fn limit_for_path(&self, path: &str) -> Option<usize> {
+        match path {
+            "/3ds/version" | "/3ds/preq" => Some(self.version_bytes),
+            "/3ds/authenticate" | "/ds/areq" | "/acs/areq" => Some(self.authenticate_bytes),
+            "/3ds/results" | "/ds/rreq" | "/acs/rreq" => Some(self.results_bytes),
+            "/3ds/final" => Some(self.final_bytes),
+            "/challenge" | "/debug/analyze-jwe" => Some(self.challenge_bytes),
+            path if path.starts_with("/debug/transactions/") => Some(self.challenge_bytes),
+            "/acs/creq"
+            | "/3ds/method"
+            | "/acs/3ds-method"
+            | "/acs/3ds-method/device-data"
+            | "/processor/mock/acs/trigger-otp"
+            | "/processor/mock/acs/verify-otp"
+            | "/processor/mock/acs/resend-otp" => Some(self.acs_form_bytes),
+            "/processor/mock/acs/spc-verify" => Some(self.spc_verify_bytes),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct ServerConfig {
     pub host: String,
     pub port: u16,
+    /// Level or filter directives (e.g. "info,mock_three_ds_server=debug"); RUST_LOG overrides it
     pub log_level: String,
+    #[serde(default)]
+    pub log_format: LogFormat,
     pub workers: Option<usize>,
+    /// Blocking thread pool size per worker (crypto work runs on this pool);
+    /// actix-web's default splits 512 threads across the workers when unset
+    #[serde(default)]
+    pub worker_max_blocking_threads: Option<usize>,
+    /// Pin each worker thread to its own CPU core, round-robin over the available cores
+    #[serde(default)]
+    pub pin_workers: bool,
+    /// Where challenge outcomes are redirected when neither the request nor the stored
+    /// transaction carries a redirect URL
+    #[serde(default = "default_redirect_url")]
+    pub default_redirect_url: String,
+    #[serde(default)]
+    pub tls: TlsConfig,
+}
+
+/// HTTPS on every listener, terminated by the server itself with rustls
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct TlsConfig {
+    pub enabled: bool,
+    /// PEM certificate chain, leaf first
+    pub cert_path: String,
+    /// PEM private key (PKCS#8, PKCS#1 RSA or SEC1 EC)
+    pub key_path: String,
+    /// PEM bundle of CAs that client certificates must chain to; unset, clients need none
+    pub client_ca_path: Option<String>,
+    /// Where a client certificate is required once client_ca_path is set
+    pub client_auth: ClientAuthScope,
+    /// Client certificate subjects accepted where one is required, each matched against the
+    /// full subject (e.g. "CN=ds.example.com, O=Example") or its common name; empty accepts
+    /// any certificate issued by the client CAs
+    pub allowed_client_subjects: Vec<String>,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            cert_path: "certs/server-cert.pem".to_string(),
+            key_path: "certs/server-key.pem".to_string(),
+            client_ca_path: None,
+            client_auth: ClientAuthScope::All,
+            allowed_client_subjects: Vec::new(),
+        }
+    }
+}
+
+/// Routes on which clients must present a certificate
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientAuthScope {
+    /// Every route; connections without a certificate fail the TLS handshake
+    #[default]
+    All,
+    /// Only the 3DS Server's /3ds/* and /ds/* routes, the DS-facing side of a real
+    /// deployment; other routes accept connections without a certificate
+    ServerRoutes,
+}
+
+/// How log events are written to stdout
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per event, with the fields of the spans it happened in
+    Json,
+}
+
+fn default_redirect_url() -> String {
+    "https://juspay.api.in.end".to_string()
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            log_level: "debug".to_string(),
+            log_format: LogFormat::Text,
+            workers: Some(1),
+            worker_max_blocking_threads: Some(32),
+            pin_workers: false,
+            default_redirect_url: default_redirect_url(),
+            tls: TlsConfig::default(),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct PerformanceConfig {
     pub enable_compression: bool,
     pub enable_metrics: bool,
+    /// Apply the rate limits below; switchable at runtime through /admin/performance
+    #[serde(default = "default_enable_rate_limiting")]
+    pub enable_rate_limiting: bool,
     pub cache_size: usize,
+    /// Requests per second per client IP on routes without a rate_limits rule
     pub rate_limit_per_second: u32,
+    /// Per-route limits replacing rate_limit_per_second; the longest matching route wins
+    #[serde(default)]
+    pub rate_limits: Vec<RouteRateLimit>,
+    /// Route prefixes that are never rate limited
+    #[serde(default = "default_rate_limit_exempt")]
+    pub rate_limit_exempt: Vec<String>,
+    /// Maximum concurrent connections per worker
     pub max_connections: usize,
     pub client_timeout_ms: u64,
     pub keep_alive_seconds: u64,
 }
 
+/// Rate limit for the routes under one path prefix
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct RouteRateLimit {
+    /// Path prefix, without `[routes]` prefixes or the /v1 version segment
+    pub route: String,
+    pub per_second: u32,
+    /// Requests a client may burst; twice per_second when unset
+    #[serde(default)]
+    pub burst_size: Option<u32>,
+}
+
+impl RouteRateLimit {
+    pub fn burst_size(&self) -> u32 {
+        self.burst_size.unwrap_or(self.per_second * 2)
+    }
+}
+
+fn default_enable_rate_limiting() -> bool {
+    true
+}
+
+// Health and metrics stay reachable for probes and scrapers under load
+fn default_rate_limit_exempt() -> Vec<String> {
+    vec!["/health".to_string(), "/metrics".to_string()]
+}
+
+impl Default for PerformanceConfig {
+    fn default() -> Self {
+        Self {
+            enable_compression: false,
+            enable_metrics: true,
+            enable_rate_limiting: true,
+            cache_size: 1000,
+            rate_limit_per_second: 100,
+            rate_limits: Vec::new(),
+            rate_limit_exempt: default_rate_limit_exempt(),
+            max_connections: 1000,
+            client_timeout_ms: 120000,
+            keep_alive_seconds: 60,
+        }
+    }
+}
+
+impl PerformanceConfig {
+    /// Requests a client may burst before being rate limited, on routes without a rule
+    pub fn rate_limit_burst_size(&self) -> u32 {
+        self.rate_limit_per_second * 2
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct MonitoringConfig {
     pub metrics_endpoint: String,
     pub health_endpoint: String,
     pub enable_tracing: bool,
     pub request_timeout_seconds: u64,
+    /// Run a synthetic AReq and CReq encryption round trip at startup, reported on /health
+    #[serde(default)]
+    pub startup_self_test: bool,
+    /// Ping the state store on every health check and report its status and latency
+    #[serde(default)]
+    pub deep_health_check: bool,
+    /// State store round trips slower than this report the service as degraded
+    #[serde(default = "default_health_degraded_latency_ms")]
+    pub health_degraded_latency_ms: u64,
+    /// State store round trips taking this long count as the store being down
+    #[serde(default = "default_health_timeout_ms")]
+    pub health_timeout_ms: u64,
+}
+
+fn default_health_degraded_latency_ms() -> u64 {
+    250
+}
+
+fn default_health_timeout_ms() -> u64 {
+    1000
+}
+
+impl Default for MonitoringConfig {
+    fn default() -> Self {
+        Self {
+            metrics_endpoint: "/metrics".to_string(),
+            health_endpoint: "/health".to_string(),
+            enable_tracing: false,
+            request_timeout_seconds: 60,
+            startup_self_test: false,
+            deep_health_check: false,
+            health_degraded_latency_ms: default_health_degraded_latency_ms(),
+            health_timeout_ms: default_health_timeout_ms(),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -47,6 +438,17 @@ pub struct RetryConfig {
     pub multiplier: f64,
 }
 
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 2,
+            initial_delay_ms: 50,
+            max_delay_ms: 2000,
+            multiplier: 1.5,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct CacheConfig {
     pub card_range_ttl_seconds: u64,
@@ -54,6 +456,845 @@ pub struct CacheConfig {
     pub static_response_ttl_seconds: u64,
 }
 
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            card_range_ttl_seconds: 1800,
+            challenge_decision_ttl_seconds: 180,
+            static_response_ttl_seconds: 3600,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct ScenarioConfig {
+    /// Instalment AReqs (threeDSRequestorAuthenticationInd = 03) with more instalments
+    /// than this are always challenged
+    pub instalment_challenge_threshold: Option<u32>,
+    /// authenticationMethod reported in RReqs; derived from the authenticationType when unset
+    pub authentication_method: Option<String>,
+    /// Cards (by number suffix) whose mobile ARes omits broadInfo
+    #[serde(default)]
+    pub no_broad_info_card_suffixes: Vec<String>,
+    /// ECI reported on successful frictionless authentications
+    #[serde(default)]
+    pub success_eci: SuccessEciConfig,
+    /// 3DS 2.3 status fields reported in ARes and RReq messages
+    #[serde(default)]
+    pub protocol_2_3: Protocol23Fields,
+    /// Per-card (by number suffix) overrides of `protocol_2_3`
+    #[serde(default)]
+    pub protocol_2_3_cards: HashMap<String, Protocol23Fields>,
+    /// TOML or JSON file mapping test PANs, prefixes or suffixes to authentication outcomes;
+    /// replaces the built-in registry (cards ending in 4001 are challenged, 4009-4012 are
+    /// answered N, U, R and A)
+    #[serde(default)]
+    pub card_registry_file: Option<String>,
+    /// Loaded from `card_registry_file` by `Settings::new`
+    #[serde(skip)]
+    pub card_registry: CardRegistry,
+}
+
+impl ScenarioConfig {
+    /// 2.3 status fields for a card: its override where set, otherwise the scenario-wide values
+    pub fn protocol_2_3_for(&self, card_number: &str) -> Protocol23Fields {
+        let card = self
+            .protocol_2_3_cards
+            .iter()
+            .find(|(suffix, _)| card_number.ends_with(suffix.as_str()))
+            .map(|(_, fields)| fields.clone())
+            .unwrap_or_default();
+        Protocol23Fields {
+            device_binding_status: card
+                .device_binding_status
+                .or_else(|| self.protocol_2_3.device_binding_status.clone()),
+            trust_list_status: card
+                .trust_list_status
+                .or_else(|| self.protocol_2_3.trust_list_status.clone()),
+        }
+    }
+}
+
+/// 3DS 2.3 status fields; unset values fall back to what the requestor sent in the AReq
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct Protocol23Fields {
+    /// deviceBindingStatus, e.g. 11 (bound) or 12 (not bound)
+    pub device_binding_status: Option<String>,
+    /// trustListStatus: Y, N, E, P, R or U
+    pub trust_list_status: Option<String>,
+}
+
+impl Protocol23Fields {
+    fn validate(&self) -> Result<(), String> {
+        if let Some(status) = &self.device_binding_status {
+            if status.len() != 2 || !status.chars().all(|c| c.is_ascii_digit()) {
+                return Err(format!("Invalid deviceBindingStatus: {}", status));
+            }
+        }
+        if let Some(status) = &self.trust_list_status {
+            if !["Y", "N", "E", "P", "R", "U"].contains(&status.as_str()) {
+                return Err(format!("Invalid trustListStatus: {}", status));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What a successful frictionless authentication reports: full authentication
+/// (transStatus Y, ECI 05 Visa / 02 Mastercard) or attempts processing with the
+/// issuer taking liability (transStatus A, ECI 06 Visa / 01 Mastercard)
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SuccessLiability {
+    #[default]
+    Authenticated,
+    Attempted,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct SuccessEciConfig {
+    /// Payment authentications (messageCategory 01)
+    pub payment: SuccessLiability,
+    /// Non-payment authentications (messageCategory 02)
+    pub non_payment: SuccessLiability,
+    /// Cards (by number suffix) answered with attempts processing whatever the category
+    pub attempted_card_suffixes: Vec<String>,
+}
+
+impl SuccessEciConfig {
+    pub fn liability_for(&self, category: MessageCategory, card_number: &str) -> SuccessLiability {
+        if self
+            .attempted_card_suffixes
+            .iter()
+            .any(|suffix| card_number.ends_with(suffix.as_str()))
+        {
+            return SuccessLiability::Attempted;
+        }
+        match category {
+            MessageCategory::Payment => self.payment,
+            MessageCategory::NonPayment => self.non_payment,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct ValidationConfig {
+    /// Reject AReq/CReq elements the mock does not model (Erro 203 naming them) instead of
+    /// ignoring them, like a DS that validates strictly
+    pub strict_parsing: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AcsConfig {
+    /// authenticationType values the ACS can perform, in order of preference
+    /// (01 = static, 02 = dynamic/OTP, 03 = OOB, 04 = decoupled)
+    #[serde(default = "default_supported_authentication_types")]
+    pub supported_authentication_types: Vec<String>,
+    #[serde(default = "default_acs_operator_id")]
+    pub operator_id: String,
+    #[serde(default = "default_acs_reference_number")]
+    pub reference_number: String,
+    /// Identities presented for specific threeDSRequestorChallengeInd values
+    #[serde(default = "default_challenge_ind_identities")]
+    pub challenge_ind_identities: HashMap<String, AcsIdentity>,
+    /// broadInfo sent to mobile SDKs in the ARes
+    #[serde(default)]
+    pub broad_info: BroadInfoConfig,
+    /// Certificate and key used to sign acsSignedContent for mobile challenges
+    #[serde(default)]
+    pub signing: AcsSigningConfig,
+    /// Secure Payment Confirmation offered to browser requestors that support it
+    #[serde(default)]
+    pub spc: SpcConfig,
+    /// Framing headers and layout of the browser challenge page
+    #[serde(default)]
+    pub challenge_page: ChallengePageConfig,
+    /// Browser device data collection before the AReq
+    #[serde(default)]
+    pub three_ds_method: ThreeDsMethodConfig,
+    /// Wrong-OTP retries in app and browser challenges
+    #[serde(default)]
+    pub otp: OtpConfig,
+    /// Approval of out-of-band (acsUiType 04) app challenges
+    #[serde(default)]
+    pub oob: OobConfig,
+    /// Initial issuer-side card statuses by PAN; managed at runtime through the admin API
+    #[serde(default)]
+    pub card_statuses: HashMap<String, CardStatus>,
+    /// IDs of the critical messageExtensions the ACS accepts; other critical ones get an Erro
+    #[serde(default)]
+    pub recognized_message_extensions: Vec<String>,
+    /// Derive app challenge ephemeral keys from this seed and the acsTransID instead of
+    /// generating them, for reproducible test runs; never set in production
+    #[serde(default)]
+    pub ephemeral_key_seed: Option<String>,
+    /// SDK reference numbers used as ConcatKDF partyVInfo when an AReq carries none
+    #[serde(default)]
+    pub sdk_reference_numbers: SdkReferenceNumbersConfig,
+    /// Key management for app challenge JWEs besides `dir`
+    #[serde(default)]
+    pub jwe: AcsJweConfig,
+    /// Blank cardSecurityCode in stored transactions and message logs, and leave it out of
+    /// the ARes authenticationRequest echo
+    #[serde(default)]
+    pub strip_card_security_code: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct ThreeDsMethodConfig {
+    /// Advertise a threeDSMethodURL in /3ds/version card ranges
+    pub enabled: bool,
+    /// How long a 3DS Method run through /3ds/method takes to fingerprint the device
+    pub duration_ms: u64,
+    /// How long /3ds/method waits for it; a method taking this long or longer times out
+    /// and completes with threeDSCompInd = N
+    pub timeout_ms: u64,
+    /// What an AReq whose threeDSCompInd contradicts the 3DS Method outcome gets
+    pub comp_ind_mismatch: CompIndMismatchPolicy,
+}
+
+impl Default for ThreeDsMethodConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            duration_ms: 0,
+            timeout_ms: 10000,
+            comp_ind_mismatch: CompIndMismatchPolicy::Warn,
+        }
+    }
+}
+
+/// OTP the ACS accepts unless configured otherwise
+pub const DEFAULT_OTP: &str = "1234";
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct OtpConfig {
+    /// OTP challenged cardholders must enter; a card scenario's `otp` overrides it
+    pub value: String,
+    /// Give each challenged transaction its own random OTP instead, readable through
+    /// /admin/transactions/{threeDSServerTransID}/otp
+    pub random: bool,
+    /// Digits in a random OTP
+    pub random_length: usize,
+    /// Wrong OTPs a cardholder may enter; the last one ends the challenge with
+    /// transStatus = N and transStatusReason = 19 (exceeded maximum challenges)
+    pub max_attempts: u32,
+}
+
+impl Default for OtpConfig {
+    fn default() -> Self {
+        Self {
+            value: DEFAULT_OTP.to_string(),
+            random: false,
+            random_length: 6,
+            max_attempts: 3,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct OobConfig {
+    /// Approve OOB challenges without an admin decision once `auto_approve_delay_ms` passed;
+    /// otherwise they wait for POST /admin/transactions/{threeDSServerTransID}/oob
+    pub auto_approve: bool,
+    /// How long after the OOB screen was presented a continuation CReq completes it
+    pub auto_approve_delay_ms: u64,
+}
+
+impl Default for OobConfig {
+    fn default() -> Self {
+        Self {
+            auto_approve: true,
+            auto_approve_delay_ms: 0,
+        }
+    }
+}
+
+/// Handling of an AReq with threeDSCompInd = Y when no 3DS Method completed, or N/U when one did
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CompIndMismatchPolicy {
+    /// Log a warning and authenticate as usual
+    #[default]
+    Warn,
+    /// Answer with an Erro naming threeDSCompInd
+    Reject,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct SpcConfig {
+    pub enabled: bool,
+    /// WebAuthn relying party the mock credentials are registered with
+    pub rp_id: String,
+    pub timeout_ms: u64,
+}
+
+impl Default for SpcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            rp_id: "localhost".to_string(),
+            timeout_ms: 60000,
+        }
+    }
+}
+
+/// How the browser challenge page is laid out
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ChallengePageLayout {
+    /// Compact iframe layout for challengeWindowSize 01-04, full page for 05
+    #[default]
+    Auto,
+    Standalone,
+    Iframe,
+}
+
+/// How a finished browser challenge reaches the notificationURL
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ChallengeCompletion {
+    /// An auto-submitting form posts the base64url-encoded final CRes as `cres` (spec flow)
+    #[default]
+    CresPost,
+    /// A 302 redirect with transStatus, eci and authenticationValue as query parameters
+    Redirect,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct ChallengePageConfig {
+    /// Origins allowed to embed the page, sent as the CSP frame-ancestors directive
+    /// ("*" allows any merchant page)
+    pub frame_ancestors: Vec<String>,
+    /// X-Frame-Options value (DENY or SAMEORIGIN). Unset omits the header, which cannot
+    /// express cross-origin embedding.
+    pub x_frame_options: Option<String>,
+    /// Full Content-Security-Policy value replacing the one built from frame_ancestors
+    pub content_security_policy: Option<String>,
+    pub layout: ChallengePageLayout,
+    /// How a finished challenge hands its outcome to the notificationURL
+    pub completion: ChallengeCompletion,
+}
+
+impl Default for ChallengePageConfig {
+    fn default() -> Self {
+        Self {
+            frame_ancestors: vec!["*".to_string()],
+            x_frame_options: None,
+            content_security_policy: None,
+            layout: ChallengePageLayout::Auto,
+            completion: ChallengeCompletion::CresPost,
+        }
+    }
+}
+
+impl ChallengePageConfig {
+    /// Content-Security-Policy for the page: its inline script and styles, the OTP post
+    /// and merchant redirect, and the configured frame ancestors
+    pub fn content_security_policy(&self) -> String {
+        match &self.content_security_policy {
+            Some(policy) => policy.clone(),
+            None => format!(
+                "default-src 'self'; script-src 'self' 'unsafe-inline'; \
+                 style-src 'self' 'unsafe-inline'; img-src 'self' data:; connect-src *; \
+                 form-action *; frame-ancestors {}",
+                self.frame_ancestors.join(" ")
+            ),
+        }
+    }
+
+    /// Whether the page should use the iframe layout for a CReq challengeWindowSize
+    pub fn iframe_layout(&self, challenge_window_size: &str) -> bool {
+        match self.layout {
+            ChallengePageLayout::Auto => challenge_window_size != "05",
+            ChallengePageLayout::Standalone => false,
+            ChallengePageLayout::Iframe => true,
+        }
+    }
+}
+
+/// What to do when the ACS signing certificate or private key is missing
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingCertPolicy {
+    /// Fail the mobile challenge AReq with an Erro naming the missing paths
+    #[default]
+    Error,
+    /// Generate a self-signed development certificate with rcgen
+    AutoGenerate,
+    /// Send the configured static_signed_content instead of signing
+    Static,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct AcsSigningConfig {
+    pub cert_path: String,
+    pub private_key_path: String,
+    pub missing_cert_policy: MissingCertPolicy,
+    /// Pre-signed acsSignedContent JWS used with missing_cert_policy = "static"
+    pub static_signed_content: Option<String>,
+    /// Warn when the certificate expires within this many days
+    pub expiry_warning_days: u32,
+}
+
+impl Default for AcsSigningConfig {
+    fn default() -> Self {
+        Self {
+            cert_path: "certs/acs-cert.pem".to_string(),
+            private_key_path: "certs/acs-private-key.pem".to_string(),
+            missing_cert_policy: MissingCertPolicy::Error,
+            static_signed_content: None,
+            expiry_warning_days: 30,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct AcsJweConfig {
+    /// RSA private key (PEM) that `alg: RSA-OAEP-256` CReqs are encrypted to; their CRes is
+    /// encrypted to its public half. Unset, such CReqs are answered with an Erro.
+    pub rsa_key_path: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct SdkReferenceNumbersConfig {
+    pub android: String,
+    pub ios: String,
+}
+
+impl Default for SdkReferenceNumbersConfig {
+    fn default() -> Self {
+        Self {
+            android: "3DS_LOA_SDK_JTPL_020200_00788".to_string(),
+            ios: "3DS_LOA_SDK_JTPL_020200_00805".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct BroadInfoConfig {
+    pub enabled: bool,
+    pub category: String,
+    pub severity: String,
+    pub source: String,
+    pub recipients: Vec<String>,
+    pub message: String,
+    pub exp_date: String,
+}
+
+impl Default for BroadInfoConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            category: "01".to_string(),
+            severity: "04".to_string(),
+            source: "03".to_string(),
+            recipients: vec!["02".to_string(), "01".to_string(), "03".to_string()],
+            message: "This is a mock ACS broadcast message".to_string(),
+            exp_date: "20301231".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AcsIdentity {
+    pub operator_id: String,
+    pub reference_number: String,
+}
+
+fn default_supported_authentication_types() -> Vec<String> {
+    vec!["02".to_string()]
+}
+
+fn default_acs_operator_id() -> String {
+    "MOCK_ACS".to_string()
+}
+
+fn default_acs_reference_number() -> String {
+    "issuer1".to_string()
+}
+
+fn default_challenge_ind_identities() -> HashMap<String, AcsIdentity> {
+    // Exemption flow (no challenge requested) is served by a second ACS identity
+    HashMap::from([(
+        "05".to_string(),
+        AcsIdentity {
+            operator_id: "MOCK_ACS_NEW".to_string(),
+            reference_number: "issuer2".to_string(),
+        },
+    )])
+}
+
+impl Default for AcsConfig {
+    fn default() -> Self {
+        Self {
+            supported_authentication_types: default_supported_authentication_types(),
+            operator_id: default_acs_operator_id(),
+            reference_number: default_acs_reference_number(),
+            challenge_ind_identities: default_challenge_ind_identities(),
+            broad_info: BroadInfoConfig::default(),
+            signing: AcsSigningConfig::default(),
+            spc: SpcConfig::default(),
+            challenge_page: ChallengePageConfig::default(),
+            three_ds_method: ThreeDsMethodConfig::default(),
+            otp: OtpConfig::default(),
+            oob: OobConfig::default(),
+            card_statuses: HashMap::new(),
+            recognized_message_extensions: Vec::new(),
+            ephemeral_key_seed: None,
+            sdk_reference_numbers: SdkReferenceNumbersConfig::default(),
+            jwe: AcsJweConfig::default(),
+            strip_card_security_code: false,
+        }
+    }
+}
+
+impl AcsConfig {
+    /// sdkReferenceNumber the challenge key is derived with: the one the AReq carried, else
+    /// the configured one for the SDK platform (android or ios); None for other platforms
+    pub fn sdk_reference_number<'a>(
+        &'a self,
+        platform: &str,
+        areq_sdk_reference_number: Option<&'a str>,
+    ) -> Option<&'a str> {
+        if let Some(sdk_reference_number) = areq_sdk_reference_number {
+            return Some(sdk_reference_number);
+        }
+        match platform.to_lowercase().as_str() {
+            "android" => Some(&self.sdk_reference_numbers.android),
+            "ios" => Some(&self.sdk_reference_numbers.ios),
+            _ => None,
+        }
+    }
+
+    /// ACS operator ID and reference number for a threeDSRequestorChallengeInd on the
+    /// scheme's DS: a challenge indicator identity, else the scheme's, else the default
+    pub fn identity_for_challenge_ind<'a>(
+        &'a self,
+        challenge_ind: &str,
+        ds_profile: &'a DsProfile,
+    ) -> (&'a str, &'a str) {
+        match self.challenge_ind_identities.get(challenge_ind) {
+            Some(identity) => (&identity.operator_id, &identity.reference_number),
+            None => (
+                ds_profile
+                    .acs_operator_id
+                    .as_deref()
+                    .unwrap_or(&self.operator_id),
+                ds_profile
+                    .acs_reference_number
+                    .as_deref()
+                    .unwrap_or(&self.reference_number),
+            ),
+        }
+    }
+}
+
+/// 3DS Server identity echoed in the AReq returned by /3ds/authenticate
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct ThreeDsServerConfig {
+    pub reference_number: String,
+    pub operator_id: String,
+    pub url: String,
+}
+
+impl Default for ThreeDsServerConfig {
+    fn default() -> Self {
+        Self {
+            reference_number: "3DS_LOA_SER_JTPL_020200_00841".to_string(),
+            operator_id: "10073246".to_string(),
+            url: "https://visa.3ds.certification.juspay.in/3ds/results".to_string(),
+        }
+    }
+}
+
+/// Debug endpoints that expose transaction key material
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct DebugConfig {
+    /// Bearer token required by /debug/transactions/{id}/decrypt and /debug/derive-key; the
+    /// endpoints are not mounted without one
+    pub api_token: Option<String>,
+}
+
+/// Final-outcome POST to the AReq notificationURL once a transaction completes
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct WebhookConfig {
+    pub enabled: bool,
+    /// HMAC-SHA256 key for the X-Webhook-Signature header; unsigned when unset
+    pub secret: Option<String>,
+    /// Deliveries including the first; failures and 5xx/429 answers are retried
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for each further one
+    pub retry_delay_ms: u64,
+    pub timeout_ms: u64,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            secret: None,
+            max_attempts: 3,
+            retry_delay_ms: 500,
+            timeout_ms: 5000,
+        }
+    }
+}
+
+/// Fault injection for resilience testing; profiles are switched through /admin/faults
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct FaultsConfig {
+    /// Mounts /admin/faults and applies the active profile; nothing is faulted when false
+    pub enabled: bool,
+    /// Profile active at startup; none until the admin API picks one when unset
+    pub active_profile: Option<String>,
+    pub profiles: HashMap<String, FaultProfile>,
+}
+
+/// Directory Server identity used for a card scheme
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DsProfile {
+    pub ds_reference_number: String,
+    /// When set, dsTransIDs are UUIDv5 values in this namespace so per-scheme routing is visible
+    #[serde(default)]
+    pub ds_trans_id_namespace: Option<Uuid>,
+    /// DS certificate whose public key SDKs use to encrypt sdkEncData for this scheme
+    #[serde(default)]
+    pub ds_certificate_path: Option<String>,
+    #[serde(default = "default_acs_info_ind")]
+    pub acs_info_ind: Vec<String>,
+    /// Card number prefixes routed to this DS by /3ds/version
+    #[serde(default)]
+    pub card_prefixes: Vec<String>,
+    /// Protocol versions this scheme's DS supports (e.g. SafeKey 2.1.0 to 2.2.0), narrowing
+    /// the ACS's own in card ranges and AReq negotiation
+    #[serde(default)]
+    pub acs_start_protocol_version: Option<String>,
+    #[serde(default)]
+    pub acs_end_protocol_version: Option<String>,
+    /// Identities this scheme certified under, replacing `[three_ds_server]` and the `[acs]`
+    /// operator ID and reference number for its AReqs
+    #[serde(default)]
+    pub three_ds_server_reference_number: Option<String>,
+    #[serde(default)]
+    pub three_ds_server_operator_id: Option<String>,
+    #[serde(default)]
+    pub three_ds_server_url: Option<String>,
+    #[serde(default)]
+    pub acs_operator_id: Option<String>,
+    #[serde(default)]
+    pub acs_reference_number: Option<String>,
+}
+
+fn default_acs_info_ind() -> Vec<String> {
+    vec!["01".to_string(), "02".to_string()]
+}
+
+impl Default for DsProfile {
+    fn default() -> Self {
+        Self {
+            ds_reference_number: "MOCK_DS".to_string(),
+            ds_trans_id_namespace: None,
+            ds_certificate_path: None,
+            acs_info_ind: default_acs_info_ind(),
+            card_prefixes: Vec::new(),
+            acs_start_protocol_version: None,
+            acs_end_protocol_version: None,
+            three_ds_server_reference_number: None,
+            three_ds_server_operator_id: None,
+            three_ds_server_url: None,
+            acs_operator_id: None,
+            acs_reference_number: None,
+        }
+    }
+}
+
+impl DsProfile {
+    /// threeDSServerRefNumber, threeDSServerOperatorID and threeDSServerURL echoed for this
+    /// scheme, each falling back to `[three_ds_server]`
+    pub fn three_ds_server<'a>(
+        &'a self,
+        defaults: &'a ThreeDsServerConfig,
+    ) -> (&'a str, &'a str, &'a str) {
+        (
+            self.three_ds_server_reference_number
+                .as_deref()
+                .unwrap_or(&defaults.reference_number),
+            self.three_ds_server_operator_id
+                .as_deref()
+                .unwrap_or(&defaults.operator_id),
+            self.three_ds_server_url.as_deref().unwrap_or(&defaults.url),
+        )
+    }
+
+    /// The ACS protocol versions within this scheme's start and end versions, oldest first
+    pub fn protocol_versions(&self) -> Vec<&'static str> {
+        protocol_versions_between(
+            self.acs_start_protocol_version.as_deref(),
+            self.acs_end_protocol_version.as_deref(),
+        )
+    }
+}
+
+/// The ACS protocol versions from `start` to `end`, each defaulting to the ACS's own bound;
+/// empty when `start` is after `end`
+fn protocol_versions_between(start: Option<&str>, end: Option<&str>) -> Vec<&'static str> {
+    let position = |version: Option<&str>| {
+        version.and_then(|version| {
+            protocol::ACS_PROTOCOL_VERSIONS
+                .iter()
+                .position(|v| *v == version)
+        })
+    };
+    let start = position(start).unwrap_or(0);
+    let end = position(end).unwrap_or(protocol::ACS_PROTOCOL_VERSIONS.len() - 1);
+    protocol::ACS_PROTOCOL_VERSIONS
+        .get(start..=end)
+        .unwrap_or_default()
+        .to_vec()
+}
+
+/// A card range /3ds/version and the PRes return. Unset fields come from the DS profile
+/// routing the range's start and from the ACS.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CardRangeConfig {
+    pub start_range: String,
+    pub end_range: String,
+    #[serde(default)]
+    pub acs_info_ind: Option<Vec<String>>,
+    /// threeDSMethodURL; the mock ACS's 3DS Method when `[acs.three_ds_method]` is enabled
+    #[serde(default)]
+    pub three_ds_method_url: Option<String>,
+    #[serde(default)]
+    pub acs_start_protocol_version: Option<String>,
+    #[serde(default)]
+    pub acs_end_protocol_version: Option<String>,
+}
+
+impl CardRangeConfig {
+    pub fn new(start_range: &str, end_range: &str) -> Self {
+        Self {
+            start_range: start_range.to_string(),
+            end_range: end_range.to_string(),
+            acs_info_ind: None,
+            three_ds_method_url: None,
+            acs_start_protocol_version: None,
+            acs_end_protocol_version: None,
+        }
+    }
+
+    /// Whether a card number falls in this range, compared over the range's digits so
+    /// 19-digit PANs match 16-digit ranges
+    pub fn contains(&self, card_number: &str) -> bool {
+        card_number
+            .get(..self.start_range.len())
+            .is_some_and(|prefix| {
+                (self.start_range.as_str()..=self.end_range.as_str()).contains(&prefix)
+            })
+    }
+
+    /// The range's protocol versions, falling back to those of its DS profile
+    pub fn protocol_versions(&self, profile: &DsProfile) -> Vec<&'static str> {
+        protocol_versions_between(
+            self.acs_start_protocol_version
+                .as_deref()
+                .or(profile.acs_start_protocol_version.as_deref()),
+            self.acs_end_protocol_version
+                .as_deref()
+                .or(profile.acs_end_protocol_version.as_deref()),
+        )
+    }
+}
+
+/// Ranges of the mock issuer, Mastercard, Visa, American Express SafeKey, Discover
+/// ProtectBuy, JCB J/Secure and UnionPay
+fn default_card_ranges() -> Vec<CardRangeConfig> {
+    [
+        ("5155010000000000", "5155019999999999"),
+        ("5100000000000000", "5599999999999999"),
+        ("2221000000000000", "2720999999999999"),
+        ("4000000000000000", "4999999999999999"),
+        ("340000000000000", "349999999999999"),
+        ("370000000000000", "379999999999999"),
+        ("6011000000000000", "6011999999999999"),
+        ("6440000000000000", "6599999999999999"),
+        ("3528000000000000", "3589999999999999"),
+        ("6200000000000000", "6299999999999999"),
+    ]
+    .into_iter()
+    .map(|(start_range, end_range)| CardRangeConfig::new(start_range, end_range))
+    .collect()
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DsConfig {
+    #[serde(default)]
+    pub default_profile: DsProfile,
+    /// Profiles keyed by lowercase schemeId (e.g. "visa", "mastercard")
+    #[serde(default)]
+    pub profiles: HashMap<String, DsProfile>,
+    /// Card ranges /3ds/version and the PRes return, first match wins
+    #[serde(default = "default_card_ranges")]
+    pub card_ranges: Vec<CardRangeConfig>,
+}
+
+impl Default for DsConfig {
+    fn default() -> Self {
+        Self {
+            default_profile: DsProfile::default(),
+            profiles: HashMap::new(),
+            card_ranges: default_card_ranges(),
+        }
+    }
+}
+
+impl DsConfig {
+    pub fn profile_for_scheme(&self, scheme_id: &str) -> &DsProfile {
+        self.profiles
+            .get(&scheme_id.to_lowercase())
+            .unwrap_or(&self.default_profile)
+    }
+
+    /// Profile for an AReq: its schemeId's, else the one routing its card number
+    pub fn profile_for(&self, scheme_id: &str, card_number: &str) -> &DsProfile {
+        self.profiles
+            .get(&scheme_id.to_lowercase())
+            .unwrap_or_else(|| self.profile_for_card(card_number))
+    }
+
+    /// Profile routing a card number: the one with the longest matching card prefix, ties
+    /// going to the first profile name
+    pub fn profile_for_card(&self, card_number: &str) -> &DsProfile {
+        self.profiles
+            .iter()
+            .flat_map(|(name, profile)| {
+                profile
+                    .card_prefixes
+                    .iter()
+                    .filter(|prefix| card_number.starts_with(prefix.as_str()))
+                    .map(move |prefix| (prefix.len(), name, profile))
+            })
+            .max_by_key(|&(length, name, _)| (length, Reverse(name)))
+            .map_or(&self.default_profile, |(_, _, profile)| profile)
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct RedisConfig {
     pub url: String,
@@ -61,6 +1302,118 @@ pub struct RedisConfig {
     pub key_prefix: String,
     pub connection: ConnectionConfig,
     pub pool: PoolConfig,
+    #[serde(default)]
+    pub value_format: ValueFormat,
+    #[serde(default)]
+    pub replica: ReplicaConfig,
+    /// Keys fetched per SCAN/MGET round trip when an acsTransID has no index entry
+    #[serde(default = "default_scan_batch_size")]
+    pub scan_batch_size: usize,
+}
+
+fn default_scan_batch_size() -> usize {
+    500
+}
+
+impl Default for RedisConfig {
+    fn default() -> Self {
+        Self {
+            url: "redis://127.0.0.1:6379".to_string(),
+            ttl_seconds: 1200,
+            key_prefix: "3ds_transaction".to_string(),
+            connection: ConnectionConfig::default(),
+            pool: PoolConfig::default(),
+            value_format: ValueFormat::default(),
+            replica: ReplicaConfig::default(),
+            scan_batch_size: default_scan_batch_size(),
+        }
+    }
+}
+
+/// Read replica for transaction lookups. Writes always go to `redis.url`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct ReplicaConfig {
+    /// Replica serving `get` and acsTransID lookups; unset reads from the primary
+    pub url: Option<String>,
+    /// Wait before re-reading a replica miss from the primary, to ride out replication lag
+    pub miss_retry_delay_ms: u64,
+}
+
+impl Default for ReplicaConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            miss_retry_delay_ms: 20,
+        }
+    }
+}
+
+/// Encoding of transactions stored in Redis. Reads accept both formats, so switching
+/// does not strand transactions written before the change.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ValueFormat {
+    /// Plain JSON, readable with redis-cli
+    #[default]
+    Json,
+    /// MessagePack behind a marker byte - smaller and cheaper to encode
+    Msgpack,
+}
+
+/// Where transactions are kept
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum StateBackend {
+    /// Redis at `redis.url`, shared by all processes
+    #[default]
+    Redis,
+    /// This process's memory, with the same TTL; for local runs without Redis
+    Memory,
+    /// A Postgres or SQLite database at `state.sql.url`; transactions survive restarts
+    Sql,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct StateConfig {
+    pub backend: StateBackend,
+    /// Transactions the memory backend holds at most; past this, the least used are evicted
+    pub memory_max_transactions: u64,
+    /// How long the memory backend keeps a transaction; `redis.ttl_seconds` when unset
+    pub memory_ttl_seconds: Option<u64>,
+    pub sql: SqlStateConfig,
+}
+
+impl Default for StateConfig {
+    fn default() -> Self {
+        Self {
+            backend: StateBackend::default(),
+            memory_max_transactions: 100_000,
+            memory_ttl_seconds: None,
+            sql: SqlStateConfig::default(),
+        }
+    }
+}
+
+/// Database for the `sql` state backend; the schema is migrated on startup
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct SqlStateConfig {
+    /// postgres://..., or sqlite://path?mode=rwc to create the file when missing
+    pub url: String,
+    pub max_connections: u32,
+    pub connection_timeout_seconds: u64,
+}
+
+impl Default for SqlStateConfig {
+    fn default() -> Self {
+        Self {
+            url: "sqlite://mock_three_ds.db?mode=rwc".to_string(),
+            max_connections: 10,
+            connection_timeout_seconds: 5,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -70,6 +1423,16 @@ pub struct ConnectionConfig {
     pub retry_delay_ms: u64,
 }
 
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        Self {
+            timeout_ms: 5000,
+            max_retries: 3,
+            retry_delay_ms: 1000,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct PoolConfig {
     pub max_size: u32,
@@ -78,73 +1441,525 @@ pub struct PoolConfig {
     pub idle_timeout_seconds: u64,
 }
 
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            max_size: 10,
+            min_idle: 2,
+            connection_timeout_seconds: 10,
+            idle_timeout_seconds: 300,
+        }
+    }
+}
+
 impl Settings {
-    pub fn new() -> Result<Self, ConfigError> {
-        let run_mode = env::var("RUN_MODE").unwrap_or_else(|_| "development".into());
+    /// Load `config_file`, or `config/{RUN_MODE}` when none is given. With
+    /// `ALLOW_DEFAULT_CONFIG=1`, a missing file falls back to `Settings::default()`.
+    pub fn new(config_file: Option<&str>) -> Result<Self, ConfigError> {
+        let config_file = match config_file {
+            Some(path) => path.to_string(),
+            None => {
+                let run_mode = env::var("RUN_MODE").unwrap_or_else(|_| "development".into());
+                format!("config/{}", run_mode)
+            }
+        };
 
-        let s = Config::builder()
+        let allow_default = env::var("ALLOW_DEFAULT_CONFIG").as_deref() == Ok("1");
+        let builder = if allow_default && !config_file_exists(&config_file) {
+            tracing::warn!(
+                config_file = %config_file,
+                "configuration file not found, using built-in default settings"
+            );
+            Config::builder().add_source(Config::try_from(&Settings::default())?)
+        } else {
             // Load environment-specific configuration (required)
-            .add_source(File::with_name(&format!("config/{}", run_mode)))
+            Config::builder().add_source(File::with_name(&config_file))
+        };
+
+        let s = builder
             // Add environment variables (with prefix "APP")
             // E.g., `APP_REDIS__URL=redis://custom:6379` would override redis.url
             .add_source(Environment::with_prefix("APP").separator("__"))
             .build()?;
 
-        // Deserialize into Settings struct
-        s.try_deserialize()
-    }
+        // Deserialize into Settings struct, then load the card registry it references
+        let mut settings: Settings = s.try_deserialize()?;
+        if let Some(path) = &settings.scenarios.card_registry_file {
+            settings.scenarios.card_registry = CardRegistry::load(path)?;
+        }
+        Ok(settings)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        tracing_subscriber::EnvFilter::try_new(&self.server.log_level)
+            .map_err(|e| format!("server.log_level is not a valid filter: {}", e))?;
+
+        // Validate Redis URL format
+        if !self.redis.url.starts_with("redis://") && !self.redis.url.starts_with("rediss://") {
+            return Err("Redis URL must start with redis:// or rediss://".to_string());
+        }
+
+        if let Some(url) = &self.redis.replica.url {
+            if !url.starts_with("redis://") && !url.starts_with("rediss://") {
+                return Err("Redis replica URL must start with redis:// or rediss://".to_string());
+            }
+        }
+
+        if self.state.memory_max_transactions == 0 || self.state.memory_ttl_seconds == Some(0) {
+            return Err(
+                "State memory_max_transactions and memory_ttl_seconds must be greater than 0"
+                    .to_string(),
+            );
+        }
+
+        if self.state.backend == StateBackend::Sql {
+            let url = &self.state.sql.url;
+            if !["postgres://", "postgresql://", "sqlite:"]
+                .iter()
+                .any(|scheme| url.starts_with(scheme))
+            {
+                return Err(
+                    "state.sql.url must start with postgres://, postgresql:// or sqlite:"
+                        .to_string(),
+                );
+            }
+            if self.state.sql.max_connections == 0 {
+                return Err("state.sql.max_connections must be greater than 0".to_string());
+            }
+        }
+
+        if self.monitoring.deep_health_check
+            && self.monitoring.health_degraded_latency_ms >= self.monitoring.health_timeout_ms
+        {
+            return Err(
+                "monitoring.health_degraded_latency_ms must be below monitoring.health_timeout_ms"
+                    .to_string(),
+            );
+        }
+
+        // Validate port range
+        if self.server.port == 0 {
+            return Err("Server port must be greater than 0".to_string());
+        }
+
+        // Validate worker tuning
+        if self.server.worker_max_blocking_threads == Some(0) {
+            return Err("Server worker_max_blocking_threads must be greater than 0".to_string());
+        }
+
+        if self.performance.max_connections == 0 {
+            return Err("Performance max_connections must be greater than 0".to_string());
+        }
+
+        // Validate payload limits
+        let limits = &self.payload_limits;
+        if [
+            limits.version_bytes,
+            limits.authenticate_bytes,
+            limits.results_bytes,
+            limits.final_bytes,
+            limits.challenge_bytes,
+            limits.acs_form_bytes,
+            limits.spc_verify_bytes,
+        ]
+        .contains(&0)
+        {
+            return Err("Payload limits must be greater than 0".to_string());
+        }
+
+        let performance = &self.performance;
+        if performance.rate_limit_per_second == 0 {
+            return Err("performance.rate_limit_per_second must be greater than 0".to_string());
+        }
+        if let Some(rule) = performance
+            .rate_limits
+            .iter()
+            .find(|rule| rule.per_second == 0 || rule.burst_size() == 0)
+        {
+            return Err(format!(
+                "performance.rate_limits for route {:?} needs per_second and burst_size above 0",
+                rule.route
+            ));
+        }
+
+        let tls = &self.server.tls;
+        if tls.enabled && (tls.cert_path.is_empty() || tls.key_path.is_empty()) {
+            return Err(
+                "server.tls.cert_path and server.tls.key_path are required when TLS is enabled"
+                    .to_string(),
+            );
+        }
+        if tls.client_ca_path.is_none()
+            && (tls.client_auth != ClientAuthScope::All || !tls.allowed_client_subjects.is_empty())
+        {
+            return Err(
+                "server.tls.client_ca_path is required for client_auth and allowed_client_subjects"
+                    .to_string(),
+            );
+        }
+
+        // Validate component topology
+        match &self.components.acs_base_url {
+            Some(url) if !url.starts_with("http://") && !url.starts_with("https://") => {
+                return Err(
+                    "components.acs_base_url must start with http:// or https://".to_string(),
+                );
+            }
+            None if self.components.role == ComponentRole::Server => {
+                return Err("components.acs_base_url is required when role is server".to_string());
+            }
+            _ => {}
+        }
+
+        if self.webhook.enabled && self.webhook.max_attempts == 0 {
+            return Err("webhook.max_attempts must be greater than 0".to_string());
+        }
+        if self.results_delivery.enabled && self.results_delivery.max_attempts == 0 {
+            return Err("results_delivery.max_attempts must be greater than 0".to_string());
+        }
+
+        for (name, profile) in &self.faults.profiles {
+            profile
+                .validate()
+                .map_err(|e| format!("faults.profiles.{}: {}", name, e))?;
+        }
+        if let Some(active) = &self.faults.active_profile {
+            if !self.faults.profiles.contains_key(active) {
+                return Err(format!(
+                    "faults.active_profile {:?} is not a configured profile",
+                    active
+                ));
+            }
+        }
+
+        // Validate route prefixes and listener ports
+        for (name, prefix) in [
+            ("server_prefix", &self.routes.server_prefix),
+            ("acs_prefix", &self.routes.acs_prefix),
+            ("admin_prefix", &self.routes.admin_prefix),
+        ] {
+            if !prefix.is_empty() && (!prefix.starts_with('/') || prefix.ends_with('/')) {
+                return Err(format!(
+                    "routes.{} must start with / and not end with /",
+                    name
+                ));
+            }
+        }
+
+        if [
+            self.routes.server_port,
+            self.routes.acs_port,
+            self.routes.admin_port,
+        ]
+        .contains(&Some(0))
+        {
+            return Err("Route ports must be greater than 0".to_string());
+        }
+
+        // Validate pool settings
+        if self.redis.pool.max_size == 0 {
+            return Err("Redis pool max_size must be greater than 0".to_string());
+        }
+
+        if self.redis.pool.min_idle > self.redis.pool.max_size {
+            return Err("Redis pool min_idle cannot be greater than max_size".to_string());
+        }
+
+        // Validate TTL
+        if self.redis.ttl_seconds == 0 {
+            return Err("Redis TTL must be greater than 0".to_string());
+        }
+
+        if self.redis.scan_batch_size == 0 {
+            return Err("Redis scan_batch_size must be greater than 0".to_string());
+        }
+
+        if !self.server.default_redirect_url.starts_with("http://")
+            && !self.server.default_redirect_url.starts_with("https://")
+        {
+            return Err(
+                "Server default_redirect_url must start with http:// or https://".to_string(),
+            );
+        }
+
+        // Validate 3DS Server identity, including per-scheme overrides
+        let profiles = std::iter::once(&self.ds.default_profile).chain(self.ds.profiles.values());
+        for profile in profiles {
+            let (_, _, url) = profile.three_ds_server(&self.three_ds_server);
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err("3DS Server URL must start with http:// or https://".to_string());
+            }
+
+            let versions = [
+                &profile.acs_start_protocol_version,
+                &profile.acs_end_protocol_version,
+            ];
+            if let Some(unsupported) = versions
+                .into_iter()
+                .flatten()
+                .find(|version| !protocol::ACS_PROTOCOL_VERSIONS.contains(&version.as_str()))
+            {
+                return Err(format!(
+                    "DS profile protocol version {} is not supported by the ACS (supported: {})",
+                    unsupported,
+                    protocol::ACS_PROTOCOL_VERSIONS.join(", ")
+                ));
+            }
+            if profile.protocol_versions().is_empty() {
+                return Err(
+                    "DS profile acs_start_protocol_version must not be after acs_end_protocol_version"
+                        .to_string(),
+                );
+            }
+        }
+
+        // Validate card ranges
+        for range in &self.ds.card_ranges {
+            let digits =
+                |value: &str| !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit());
+            if !digits(&range.start_range)
+                || range.start_range.len() != range.end_range.len()
+                || !digits(&range.end_range)
+                || range.start_range > range.end_range
+            {
+                return Err(format!(
+                    "Card range {}-{} must be two equally long card numbers, start first",
+                    range.start_range, range.end_range
+                ));
+            }
+            let versions = [
+                &range.acs_start_protocol_version,
+                &range.acs_end_protocol_version,
+            ];
+            let profile = self.ds.profile_for_card(&range.start_range);
+            if versions
+                .into_iter()
+                .flatten()
+                .any(|version| !protocol::ACS_PROTOCOL_VERSIONS.contains(&version.as_str()))
+                || range.protocol_versions(profile).is_empty()
+            {
+                return Err(format!(
+                    "Card range {} protocol versions must be ascending and among {}",
+                    range.start_range,
+                    protocol::ACS_PROTOCOL_VERSIONS.join(", ")
+                ));
+            }
+            if let Some(url) = &range.three_ds_method_url {
+                if !url.starts_with("http://") && !url.starts_with("https://") {
+                    return Err(format!(
+                        "Card range {} three_ds_method_url must start with http:// or https://",
+                        range.start_range
+                    ));
+                }
+            }
+        }
+
+        // Validate ACS capabilities
+        if let Some(invalid) = self
+            .acs
+            .supported_authentication_types
+            .iter()
+            .find(|t| !matches!(t.as_str(), "01" | "02" | "03" | "04"))
+        {
+            return Err(format!("Unsupported ACS authentication type: {}", invalid));
+        }
+
+        if self.acs.broad_info.enabled && !is_yyyymmdd(&self.acs.broad_info.exp_date) {
+            return Err("broadInfo exp_date must be in YYYYMMDD format".to_string());
+        }
+
+        if self.acs.three_ds_method.timeout_ms == 0 {
+            return Err("acs.three_ds_method.timeout_ms must be greater than 0".to_string());
+        }
+
+        if self.acs.otp.max_attempts == 0 {
+            return Err("acs.otp.max_attempts must be at least 1".to_string());
+        }
+
+        if !is_valid_otp(&self.acs.otp.value) {
+            return Err(format!(
+                "acs.otp.value must be {}-{} digits",
+                OTP_MIN_LENGTH, OTP_MAX_LENGTH
+            ));
+        }
+
+        if !(OTP_MIN_LENGTH..=OTP_MAX_LENGTH).contains(&self.acs.otp.random_length) {
+            return Err(format!(
+                "acs.otp.random_length must be between {} and {}",
+                OTP_MIN_LENGTH, OTP_MAX_LENGTH
+            ));
+        }
+
+        if self.acs.sdk_reference_numbers.android.is_empty()
+            || self.acs.sdk_reference_numbers.ios.is_empty()
+        {
+            return Err("acs.sdk_reference_numbers cannot be empty".to_string());
+        }
+
+        if self.acs.signing.missing_cert_policy == MissingCertPolicy::Static
+            && self.acs.signing.static_signed_content.is_none()
+        {
+            return Err(
+                "acs.signing.static_signed_content is required when missing_cert_policy is static"
+                    .to_string(),
+            );
+        }
+
+        if let Some(pan) = self.acs.card_statuses.keys().find(|pan| !is_valid_pan(pan)) {
+            return Err(format!(
+                "acs.card_statuses key {} is not a 12-19 digit PAN",
+                pan
+            ));
+        }
 
-    pub fn validate(&self) -> Result<(), String> {
-        // Validate Redis URL format
-        if !self.redis.url.starts_with("redis://") && !self.redis.url.starts_with("rediss://") {
-            return Err("Redis URL must start with redis:// or rediss://".to_string());
+        let challenge_page = &self.acs.challenge_page;
+        if challenge_page.frame_ancestors.is_empty() {
+            return Err("acs.challenge_page.frame_ancestors must not be empty".to_string());
         }
 
-        // Validate port range
-        if self.server.port == 0 {
-            return Err("Server port must be greater than 0".to_string());
+        if let Some(value) = &challenge_page.x_frame_options {
+            if !matches!(value.to_ascii_uppercase().as_str(), "DENY" | "SAMEORIGIN") {
+                return Err(
+                    "acs.challenge_page.x_frame_options must be DENY or SAMEORIGIN".to_string(),
+                );
+            }
         }
 
-        // Validate pool settings
-        if self.redis.pool.max_size == 0 {
-            return Err("Redis pool max_size must be greater than 0".to_string());
+        // Validate scenario thresholds
+        if self.scenarios.instalment_challenge_threshold == Some(0) {
+            return Err("Instalment challenge threshold must be greater than 0".to_string());
         }
 
-        if self.redis.pool.min_idle > self.redis.pool.max_size {
-            return Err("Redis pool min_idle cannot be greater than max_size".to_string());
+        if let Some(method) = &self.scenarios.authentication_method {
+            if method.len() != 2 || !method.chars().all(|c| c.is_ascii_digit()) {
+                return Err(format!("Invalid authentication method: {}", method));
+            }
         }
 
-        // Validate TTL
-        if self.redis.ttl_seconds == 0 {
-            return Err("Redis TTL must be greater than 0".to_string());
+        self.scenarios.protocol_2_3.validate()?;
+        for fields in self.scenarios.protocol_2_3_cards.values() {
+            fields.validate()?;
         }
 
         Ok(())
     }
 
-    pub fn server_address(&self) -> String {
-        format!("{}:{}", self.server.host, self.server.port)
+    /// Bind address for a route group listener on the given port
+    pub fn listener_address(&self, port: u16) -> String {
+        format!("{}:{}", self.server.host, port)
+    }
+
+    /// URL scheme the listeners serve
+    pub fn scheme(&self) -> &'static str {
+        if self.server.tls.enabled {
+            "https"
+        } else {
+            "http"
+        }
+    }
+
+    /// Base URL the ACS routes are reachable on, used for the acsURL and form actions
+    /// sent to browsers
+    pub fn acs_public_url(&self) -> String {
+        format!(
+            "{}://{}:{}{}",
+            self.scheme(),
+            self.server.host,
+            self.routes.acs_port.unwrap_or(self.server.port),
+            self.routes.public_prefix(&self.routes.acs_prefix)
+        )
+    }
+
+    /// The effective settings as JSON for display, with credentials in URLs and values under
+    /// password, secret or token keys replaced
+    pub fn redacted(&self) -> Result<Value, serde_json::Error> {
+        let mut value = serde_json::to_value(self)?;
+        redact(&mut value);
+        Ok(value)
+    }
+}
+
+const REDACTED: &str = "***";
+
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (key, field) in fields.iter_mut() {
+                let key = key.to_ascii_lowercase();
+                let secret = ["password", "secret", "token"]
+                    .iter()
+                    .any(|marker| key.contains(marker));
+                if secret && !field.is_null() {
+                    *field = Value::String(REDACTED.to_string());
+                } else {
+                    redact(field);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        Value::String(text) => {
+            if let Ok(mut url) = reqwest::Url::parse(text) {
+                if url.password().is_some() && url.set_password(Some(REDACTED)).is_ok() {
+                    *text = url.to_string();
+                }
+            }
+        }
+        _ => {}
     }
 }
 
+fn is_yyyymmdd(value: &str) -> bool {
+    value.len() == 8 && value.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Development settings built into the binary, for running without any config file
 impl Default for Settings {
     fn default() -> Self {
-        Self::new().expect("Failed to load default configuration")
+        Self {
+            server: ServerConfig::default(),
+            redis: RedisConfig::default(),
+            state: StateConfig::default(),
+            performance: PerformanceConfig::default(),
+            monitoring: MonitoringConfig::default(),
+            retry: RetryConfig::default(),
+            cache: CacheConfig::default(),
+            scenarios: ScenarioConfig::default(),
+            validation: ValidationConfig::default(),
+            payload_limits: PayloadLimitsConfig::default(),
+            components: ComponentsConfig::default(),
+            routes: RoutesConfig::default(),
+            ds: DsConfig::default(),
+            acs: AcsConfig::default(),
+            three_ds_server: ThreeDsServerConfig::default(),
+            debug: DebugConfig::default(),
+            webhook: WebhookConfig::default(),
+            results_delivery: WebhookConfig::default(),
+            faults: FaultsConfig::default(),
+        }
     }
 }
 
+/// Whether `File::with_name` would find the file: as given or with a .toml extension
+fn config_file_exists(name: &str) -> bool {
+    Path::new(name).is_file() || Path::new(&format!("{}.toml", name)).is_file()
+}
+
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
 
-    #[test]
-    fn test_settings_validation() {
-        let settings = Settings {
+    pub(crate) fn test_settings() -> Settings {
+        Settings {
             server: ServerConfig {
                 host: "127.0.0.1".to_string(),
                 port: 8080,
                 log_level: "info".to_string(),
+                log_format: LogFormat::Text,
                 workers: Some(1),
+                worker_max_blocking_threads: None,
+                pin_workers: false,
+                default_redirect_url: default_redirect_url(),
+                tls: TlsConfig::default(),
             },
             redis: RedisConfig {
                 url: "redis://127.0.0.1:6379".to_string(),
@@ -161,12 +1976,19 @@ mod tests {
                     connection_timeout_seconds: 10,
                     idle_timeout_seconds: 300,
                 },
+                value_format: ValueFormat::default(),
+                replica: ReplicaConfig::default(),
+                scan_batch_size: 500,
             },
+            state: StateConfig::default(),
             performance: PerformanceConfig {
                 enable_compression: false,
                 enable_metrics: true,
+                enable_rate_limiting: true,
                 cache_size: 1000,
                 rate_limit_per_second: 100,
+                rate_limits: Vec::new(),
+                rate_limit_exempt: default_rate_limit_exempt(),
                 max_connections: 1000,
                 client_timeout_ms: 60000,
                 keep_alive_seconds: 60,
@@ -176,6 +1998,10 @@ mod tests {
                 health_endpoint: "/health".to_string(),
                 enable_tracing: false,
                 request_timeout_seconds: 30,
+                startup_self_test: false,
+                deep_health_check: false,
+                health_degraded_latency_ms: 250,
+                health_timeout_ms: 1000,
             },
             retry: RetryConfig {
                 max_attempts: 3,
@@ -188,119 +2014,489 @@ mod tests {
                 challenge_decision_ttl_seconds: 300,
                 static_response_ttl_seconds: 86400,
             },
-        };
+            scenarios: ScenarioConfig::default(),
+            validation: ValidationConfig::default(),
+            payload_limits: PayloadLimitsConfig::default(),
+            components: ComponentsConfig::default(),
+            routes: RoutesConfig::default(),
+            ds: DsConfig::default(),
+            acs: AcsConfig::default(),
+            three_ds_server: ThreeDsServerConfig::default(),
+            debug: DebugConfig::default(),
+            webhook: WebhookConfig::default(),
+            results_delivery: WebhookConfig::default(),
+            faults: FaultsConfig::default(),
+        }
+    }
+
+    #[test]
+    fn test_default_settings_round_trip_through_config() {
+        let defaults = Settings::default();
+        assert!(defaults.validate().is_ok());
+
+        let loaded: Settings = Config::builder()
+            .add_source(Config::try_from(&defaults).unwrap())
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+        assert_eq!(loaded.server.port, defaults.server.port);
+        assert_eq!(loaded.redis.url, defaults.redis.url);
+        assert_eq!(loaded.components.acs_base_url, None);
+        assert_eq!(
+            loaded
+                .acs
+                .identity_for_challenge_ind("05", &loaded.ds.default_profile),
+            defaults
+                .acs
+                .identity_for_challenge_ind("05", &defaults.ds.default_profile)
+        );
+    }
+
+    #[test]
+    fn test_settings_validation() {
+        let settings = test_settings();
 
         assert!(settings.validate().is_ok());
     }
 
     #[test]
     fn test_invalid_redis_url() {
-        let mut settings = Settings {
-            server: ServerConfig {
-                host: "127.0.0.1".to_string(),
-                port: 8080,
-                log_level: "info".to_string(),
-                workers: Some(1),
+        let mut settings = test_settings();
+        settings.redis.url = "invalid://url".to_string();
+
+        assert!(settings.validate().is_err());
+
+        let mut settings = test_settings();
+        settings.redis.replica.url = Some("127.0.0.1:6380".to_string());
+        assert!(settings.validate().is_err());
+
+        settings.redis.replica.url = Some("redis://127.0.0.1:6380".to_string());
+        assert!(settings.validate().is_ok());
+    }
+
+    #[test]
+    fn test_invalid_default_redirect_url() {
+        let mut settings = test_settings();
+        settings.server.default_redirect_url = "merchant.example.com/return".to_string();
+
+        assert!(settings.validate().is_err());
+    }
+
+    #[test]
+    fn test_listener_address() {
+        let settings = test_settings();
+        assert_eq!(
+            settings.listener_address(settings.server.port),
+            "127.0.0.1:8080"
+        );
+        assert_eq!(settings.listener_address(9090), "127.0.0.1:9090");
+    }
+
+    #[test]
+    fn test_zero_instalment_threshold_rejected() {
+        let mut settings = test_settings();
+        settings.scenarios.instalment_challenge_threshold = Some(0);
+
+        assert!(settings.validate().is_err());
+    }
+
+    #[test]
+    fn test_server_role_requires_acs_url() {
+        let mut settings = test_settings();
+        settings.components.role = ComponentRole::Server;
+        assert!(settings.validate().is_err());
+
+        settings.components.acs_base_url = Some("acs.internal:8081".to_string());
+        assert!(settings.validate().is_err());
+
+        settings.components.acs_base_url = Some("http://acs.internal:8081".to_string());
+        assert!(settings.validate().is_ok());
+    }
+
+    #[test]
+    fn test_route_prefixes() {
+        let mut settings = test_settings();
+        settings.routes.server_prefix = "/server".to_string();
+        settings.routes.acs_prefix = "/acs".to_string();
+        settings.routes.acs_port = Some(8081);
+        assert!(settings.validate().is_ok());
+
+        assert_eq!(
+            settings.routes.unprefixed("/server/3ds/authenticate"),
+            "/3ds/authenticate"
+        );
+        assert_eq!(settings.routes.unprefixed("/acs/challenge"), "/challenge");
+        assert_eq!(settings.routes.unprefixed("/acs/acs/areq"), "/acs/areq");
+        assert_eq!(settings.routes.unprefixed("/health"), "/health");
+        assert_eq!(settings.acs_public_url(), "http://127.0.0.1:8081/acs");
+
+        assert_eq!(
+            settings.routes.unprefixed("/v1/acs/challenge"),
+            "/challenge"
+        );
+        assert_eq!(settings.routes.unprefixed("/v1/health"), "/health");
+        assert_eq!(settings.routes.mounts("/acs"), vec!["/v1/acs", "/acs"]);
+        settings.routes.versioned_only = true;
+        assert_eq!(settings.routes.mounts(""), vec!["/v1"]);
+        assert_eq!(settings.acs_public_url(), "http://127.0.0.1:8081/v1/acs");
+        settings.server.tls.enabled = true;
+        assert_eq!(settings.acs_public_url(), "https://127.0.0.1:8081/v1/acs");
+        settings.server.tls.enabled = false;
+
+        settings.routes.admin_prefix = "admin/".to_string();
+        assert!(settings.validate().is_err());
+    }
+
+    #[test]
+    fn test_challenge_page_framing() {
+        let mut settings = test_settings();
+        let page = &mut settings.acs.challenge_page;
+        assert!(page
+            .content_security_policy()
+            .ends_with("frame-ancestors *"));
+        assert!(page.iframe_layout("02"));
+        assert!(!page.iframe_layout("05"));
+        assert_eq!(page.completion, ChallengeCompletion::CresPost);
+
+        page.frame_ancestors = vec![
+            "'self'".to_string(),
+            "https://checkout.example.com".to_string(),
+        ];
+        page.layout = ChallengePageLayout::Standalone;
+        assert!(page
+            .content_security_policy()
+            .ends_with("frame-ancestors 'self' https://checkout.example.com"));
+        assert!(!page.iframe_layout("02"));
+
+        page.x_frame_options = Some("ALLOW-FROM https://checkout.example.com".to_string());
+        assert!(settings.validate().is_err());
+    }
+
+    #[test]
+    fn test_worker_tuning_validation() {
+        let mut settings = test_settings();
+        settings.server.worker_max_blocking_threads = Some(0);
+        assert!(settings.validate().is_err());
+
+        settings.server.worker_max_blocking_threads = Some(16);
+        settings.performance.max_connections = 0;
+        assert!(settings.validate().is_err());
+    }
+
+    #[test]
+    fn test_static_signing_policy_requires_content() {
+        let mut settings = test_settings();
+        settings.acs.signing.missing_cert_policy = MissingCertPolicy::Static;
+        assert!(settings.validate().is_err());
+
+        settings.acs.signing.static_signed_content = Some("eyJhbGciOiJQUzI1NiJ9..".to_string());
+        assert!(settings.validate().is_ok());
+    }
+
+    #[test]
+    fn test_three_ds_method_settings() {
+        let mut settings = test_settings();
+        assert_eq!(
+            settings.acs.three_ds_method.comp_ind_mismatch,
+            CompIndMismatchPolicy::Warn
+        );
+
+        settings.acs.three_ds_method.timeout_ms = 0;
+        assert!(settings.validate().is_err());
+    }
+
+    #[test]
+    fn test_otp_settings() {
+        let mut settings = test_settings();
+        assert_eq!(settings.acs.otp.max_attempts, 3);
+        assert_eq!(settings.acs.otp.value, DEFAULT_OTP);
+
+        settings.acs.otp.max_attempts = 0;
+        assert!(settings.validate().is_err());
+
+        settings.acs.otp.max_attempts = 3;
+        settings.acs.otp.value = "12a4".to_string();
+        assert!(settings.validate().is_err());
+
+        settings.acs.otp.value = "987654".to_string();
+        settings.acs.otp.random_length = 12;
+        assert!(settings.validate().is_err());
+    }
+
+    #[test]
+    fn test_acs_identity_for_challenge_ind() {
+        let settings = test_settings();
+        let default_profile = DsProfile::default();
+
+        assert_eq!(
+            settings
+                .acs
+                .identity_for_challenge_ind("01", &default_profile),
+            ("MOCK_ACS", "issuer1")
+        );
+        assert_eq!(
+            settings
+                .acs
+                .identity_for_challenge_ind("05", &default_profile),
+            ("MOCK_ACS_NEW", "issuer2")
+        );
+
+        // Scheme identities replace the defaults but not challenge indicator identities
+        let visa = DsProfile {
+            acs_reference_number: Some("3DS_LOA_ACS_VISA_020200_00001".to_string()),
+            three_ds_server_reference_number: Some("3DS_LOA_SER_VISA_020200_00002".to_string()),
+            ..DsProfile::default()
+        };
+        assert_eq!(
+            settings.acs.identity_for_challenge_ind("01", &visa),
+            ("MOCK_ACS", "3DS_LOA_ACS_VISA_020200_00001")
+        );
+        assert_eq!(
+            settings.acs.identity_for_challenge_ind("05", &visa),
+            ("MOCK_ACS_NEW", "issuer2")
+        );
+        assert_eq!(
+            visa.three_ds_server(&settings.three_ds_server),
+            (
+                "3DS_LOA_SER_VISA_020200_00002",
+                settings.three_ds_server.operator_id.as_str(),
+                settings.three_ds_server.url.as_str()
+            )
+        );
+    }
+
+    #[test]
+    fn test_sdk_reference_number_prefers_the_areq() {
+        let mut settings = test_settings();
+        settings.acs.sdk_reference_numbers.ios = "3DS_LOA_SDK_TEST_020200_00001".to_string();
+
+        assert_eq!(
+            settings.acs.sdk_reference_number("iOS", None),
+            Some("3DS_LOA_SDK_TEST_020200_00001")
+        );
+        assert_eq!(
+            settings
+                .acs
+                .sdk_reference_number("android", Some("3DS_LOA_SDK_OTHR_020200_00002")),
+            Some("3DS_LOA_SDK_OTHR_020200_00002")
+        );
+        assert_eq!(settings.acs.sdk_reference_number("web", None), None);
+    }
+
+    #[test]
+    fn test_ds_profile_selection() {
+        let mut settings = test_settings();
+        settings.ds.profiles.insert(
+            "visa".to_string(),
+            DsProfile {
+                ds_reference_number: "VISA_DS".to_string(),
+                card_prefixes: vec!["4".to_string()],
+                ..DsProfile::default()
             },
-            redis: RedisConfig {
-                url: "invalid://url".to_string(),
-                ttl_seconds: 1800,
-                key_prefix: "test".to_string(),
-                connection: ConnectionConfig {
-                    timeout_ms: 5000,
-                    max_retries: 3,
-                    retry_delay_ms: 1000,
-                },
-                pool: PoolConfig {
-                    max_size: 10,
-                    min_idle: 2,
-                    connection_timeout_seconds: 10,
-                    idle_timeout_seconds: 300,
+        );
+
+        assert_eq!(
+            settings.ds.profile_for_scheme("VISA").ds_reference_number,
+            "VISA_DS"
+        );
+        assert_eq!(
+            settings.ds.profile_for_scheme("AMEX").ds_reference_number,
+            "MOCK_DS"
+        );
+        assert_eq!(
+            settings
+                .ds
+                .profile_for_card("4000400040004001")
+                .ds_reference_number,
+            "VISA_DS"
+        );
+        assert_eq!(
+            settings.ds.default_profile.protocol_versions(),
+            protocol::ACS_PROTOCOL_VERSIONS
+        );
+    }
+
+    #[test]
+    fn test_longest_card_prefix_picks_the_profile() {
+        let mut settings = test_settings();
+        for (scheme, prefixes) in [
+            ("mastercard", vec!["5", "2"]),
+            ("maestro", vec!["50", "56", "5018"]),
+            ("other", vec!["2"]),
+        ] {
+            settings.ds.profiles.insert(
+                scheme.to_string(),
+                DsProfile {
+                    ds_reference_number: scheme.to_string(),
+                    card_prefixes: prefixes.into_iter().map(str::to_string).collect(),
+                    ..DsProfile::default()
                 },
-            },
-            performance: PerformanceConfig {
-                enable_compression: false,
-                enable_metrics: true,
-                cache_size: 1000,
-                rate_limit_per_second: 100,
-                max_connections: 1000,
-                client_timeout_ms: 60000,
-                keep_alive_seconds: 60,
-            },
-            monitoring: MonitoringConfig {
-                metrics_endpoint: "/metrics".to_string(),
-                health_endpoint: "/health".to_string(),
-                enable_tracing: false,
-                request_timeout_seconds: 30,
-            },
-            retry: RetryConfig {
-                max_attempts: 3,
-                initial_delay_ms: 100,
-                max_delay_ms: 5000,
-                multiplier: 2.0,
-            },
-            cache: CacheConfig {
-                card_range_ttl_seconds: 3600,
-                challenge_decision_ttl_seconds: 300,
-                static_response_ttl_seconds: 86400,
-            },
+            );
+        }
+        let routed = |card: &str| {
+            settings
+                .ds
+                .profile_for_card(card)
+                .ds_reference_number
+                .clone()
         };
 
+        assert_eq!(routed("5018000000000009"), "maestro");
+        assert_eq!(routed("5600000000000003"), "maestro");
+        assert_eq!(routed("5100000000000008"), "mastercard");
+        // Equally long prefixes go to the first profile name
+        assert_eq!(routed("2221000000000009"), "mastercard");
+        assert_eq!(routed("4000000000001000"), "MOCK_DS");
+    }
+
+    #[test]
+    fn test_card_ranges() {
+        let mut settings = test_settings();
+        let jcb = settings
+            .ds
+            .card_ranges
+            .iter()
+            .find(|range| range.contains("3530111333300000"))
+            .unwrap();
+        assert_eq!(jcb.start_range, "3528000000000000");
+        // 19-digit PANs are compared over the range's 16 digits
+        assert!(jcb.contains("3530111333300000000"));
+        assert!(!jcb.contains("3590000000000000"));
+        assert!(settings.validate().is_ok());
+
+        settings.ds.card_ranges.push(CardRangeConfig {
+            acs_end_protocol_version: Some(protocol::V2_1_0.to_string()),
+            ..CardRangeConfig::new("6200000000000000", "6299999999999999")
+        });
+        assert_eq!(
+            settings
+                .ds
+                .card_ranges
+                .last()
+                .unwrap()
+                .protocol_versions(&DsProfile::default()),
+            [protocol::V2_1_0]
+        );
+        settings
+            .ds
+            .card_ranges
+            .push(CardRangeConfig::new("6299", "62"));
         assert!(settings.validate().is_err());
     }
 
     #[test]
-    fn test_server_address() {
-        let settings = Settings {
-            server: ServerConfig {
-                host: "127.0.0.1".to_string(),
-                port: 8080,
-                log_level: "info".to_string(),
-                workers: Some(1),
-            },
-            redis: RedisConfig {
-                url: "redis://127.0.0.1:6379".to_string(),
-                ttl_seconds: 1800,
-                key_prefix: "test".to_string(),
-                connection: ConnectionConfig {
-                    timeout_ms: 5000,
-                    max_retries: 3,
-                    retry_delay_ms: 1000,
-                },
-                pool: PoolConfig {
-                    max_size: 10,
-                    min_idle: 2,
-                    connection_timeout_seconds: 10,
-                    idle_timeout_seconds: 300,
-                },
-            },
-            performance: PerformanceConfig {
-                enable_compression: false,
-                enable_metrics: true,
-                cache_size: 1000,
-                rate_limit_per_second: 100,
-                max_connections: 1000,
-                client_timeout_ms: 60000,
-                keep_alive_seconds: 60,
-            },
-            monitoring: MonitoringConfig {
-                metrics_endpoint: "/metrics".to_string(),
-                health_endpoint: "/health".to_string(),
-                enable_tracing: false,
-                request_timeout_seconds: 30,
-            },
-            retry: RetryConfig {
-                max_attempts: 3,
-                initial_delay_ms: 100,
-                max_delay_ms: 5000,
-                multiplier: 2.0,
-            },
-            cache: CacheConfig {
-                card_range_ttl_seconds: 3600,
-                challenge_decision_ttl_seconds: 300,
-                static_response_ttl_seconds: 86400,
+    fn test_ds_profile_protocol_versions() {
+        let mut settings = test_settings();
+        settings.ds.profiles.insert(
+            "amex".to_string(),
+            DsProfile {
+                ds_reference_number: "AMEX_DS".to_string(),
+                card_prefixes: vec!["34".to_string(), "37".to_string()],
+                acs_end_protocol_version: Some(protocol::V2_1_0.to_string()),
+                ..DsProfile::default()
             },
+        );
+
+        // Amex cards reach the SafeKey profile by BIN when the schemeId names no profile
+        let amex = settings.ds.profile_for("A000000025", "378282246310005");
+        assert_eq!(amex.ds_reference_number, "AMEX_DS");
+        assert_eq!(amex.protocol_versions(), [protocol::V2_1_0]);
+        assert!(settings.validate().is_ok());
+
+        settings
+            .ds
+            .profiles
+            .get_mut("amex")
+            .unwrap()
+            .acs_start_protocol_version = Some(protocol::V2_2_0.to_string());
+        assert!(settings.validate().is_err());
+        settings
+            .ds
+            .profiles
+            .get_mut("amex")
+            .unwrap()
+            .acs_start_protocol_version = Some("2.3.1".to_string());
+        assert!(settings.validate().is_err());
+    }
+
+    #[test]
+    fn test_three_ds_server_identity() {
+        let identity: ThreeDsServerConfig = serde_json::from_value(serde_json::json!({
+            "operator_id": "OPERATOR-1",
+            "url": "https://3ds.example.com/results"
+        }))
+        .unwrap();
+        assert_eq!(identity.operator_id, "OPERATOR-1");
+        assert_eq!(identity.url, "https://3ds.example.com/results");
+        // Unset fields keep the built-in identity
+        assert_eq!(
+            identity.reference_number,
+            ThreeDsServerConfig::default().reference_number
+        );
+
+        let mut settings = test_settings();
+        settings.three_ds_server.url = "3ds.example.com/results".to_string();
+        assert!(settings.validate().is_err());
+    }
+
+    #[test]
+    fn test_success_liability_selection() {
+        let config = SuccessEciConfig {
+            non_payment: SuccessLiability::Attempted,
+            attempted_card_suffixes: vec!["4006".to_string()],
+            ..SuccessEciConfig::default()
         };
-        assert_eq!(settings.server_address(), "127.0.0.1:8080");
+
+        assert_eq!(
+            config.liability_for(MessageCategory::Payment, "4000000000004000"),
+            SuccessLiability::Authenticated
+        );
+        assert_eq!(
+            config.liability_for(MessageCategory::NonPayment, "4000000000004000"),
+            SuccessLiability::Attempted
+        );
+        assert_eq!(
+            config.liability_for(MessageCategory::Payment, "4000000000004006"),
+            SuccessLiability::Attempted
+        );
+    }
+
+    #[test]
+    fn test_protocol_2_3_fields_per_card() {
+        let mut settings = test_settings();
+        settings.scenarios.protocol_2_3.trust_list_status = Some("N".to_string());
+        settings.scenarios.protocol_2_3_cards.insert(
+            "4003".to_string(),
+            Protocol23Fields {
+                device_binding_status: Some("11".to_string()),
+                trust_list_status: Some("Y".to_string()),
+            },
+        );
+        assert!(settings.validate().is_ok());
+
+        let card = settings.scenarios.protocol_2_3_for("4000000000004003");
+        assert_eq!(card.device_binding_status.as_deref(), Some("11"));
+        assert_eq!(card.trust_list_status.as_deref(), Some("Y"));
+        let other = settings.scenarios.protocol_2_3_for("4000000000004000");
+        assert_eq!(other.device_binding_status, None);
+        assert_eq!(other.trust_list_status.as_deref(), Some("N"));
+
+        settings.scenarios.protocol_2_3.trust_list_status = Some("X".to_string());
+        assert!(settings.validate().is_err());
+    }
+
+    #[test]
+    fn test_redacted_settings_hide_credentials() {
+        let mut settings = test_settings();
+        settings.redis.url = "redis://:hunter2@redis.internal:6379/0".to_string();
+
+        let redacted = settings.redacted().unwrap();
+        assert_eq!(
+            redacted["redis"]["url"],
+            "redis://:***@redis.internal:6379/0"
+        );
+        assert_eq!(redacted["server"]["port"], settings.server.port);
+        assert!(!redacted.to_string().contains("hunter2"));
     }
 }
@@ -1,7 +1,7 @@
 use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, BlockEncryptMut, KeyIvInit};
 use aes::Aes128;
 use aes_gcm::{
-    aead::{Aead, AeadInPlace, KeyInit},
+    aead::{AeadInPlace, KeyInit},
     Aes128Gcm, Key, Nonce,
 };
 use base64::{engine::general_purpose, Engine as _};
@@ -11,16 +11,39 @@ use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
 use p256::elliptic_curve::sec1::ToEncodedPoint;
 use p256::SecretKey;
 use rand_core::OsRng;
+use rsa::{Oaep, RsaPrivateKey, RsaPublicKey};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::fs;
 use std::path::Path;
+use tracing::{debug, info, trace, warn};
 use uuid::Uuid;
 
 type Aes128CbcDec = Decryptor<Aes128>;
 type Aes128CbcEnc = Encryptor<Aes128>;
 type HmacSha256 = Hmac<Sha256>;
 
+/// Failures in the ACS cryptography: ECDH key agreement, JWE handling and content signing
+#[derive(Debug, thiserror::Error)]
+pub enum CryptoError {
+    #[error("Key derivation failed: {0}")]
+    KeyDerivation(String),
+    #[error("Invalid JWE: {0}")]
+    JweFormat(String),
+    /// The JWE authentication tag (HMAC for A128CBC-HS256, GCM tag for A128GCM) did not verify
+    #[error("JWE authentication tag does not match - wrong key or tampered message")]
+    HmacMismatch,
+    /// The AES-CBC plaintext padding did not verify after the tag matched
+    #[error("JWE plaintext padding is invalid: {0}")]
+    Padding(String),
+    #[error("Failed to encrypt JWE: {0}")]
+    Encryption(String),
+    #[error("Certificate error: {0}")]
+    CertLoad(String),
+    #[error("Failed to sign ACS content: {0}")]
+    Signing(String),
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EphemeralKeyPair {
     pub private_key: String, // Base64url encoded d value
@@ -48,9 +71,26 @@ struct AcsSignedContentPayload {
 }
 
 /// Generate ephemeral ECDSA P-256 key pair for 3DS transactions
-pub fn generate_ephemeral_key_pair() -> Result<EphemeralKeyPair, Box<dyn std::error::Error>> {
-    // Generate a new random private key
-    let private_key = SecretKey::random(&mut OsRng);
+pub fn generate_ephemeral_key_pair() -> Result<EphemeralKeyPair, CryptoError> {
+    Ok(ephemeral_key_pair(SecretKey::random(&mut OsRng)))
+}
+
+/// Ephemeral key pair derived from a seed and a transaction ID (the acsTransID for ACS
+/// keys), so test runs with `acs.ephemeral_key_seed` set get the same keys for the same
+/// transaction
+pub fn derive_ephemeral_key_pair(
+    seed: &str,
+    trans_id: Uuid,
+) -> Result<EphemeralKeyPair, CryptoError> {
+    let mut hasher = Sha256::new();
+    hasher.update(seed.as_bytes());
+    hasher.update(trans_id.as_bytes());
+    let private_key = SecretKey::from_slice(&hasher.finalize())
+        .map_err(|e| CryptoError::KeyDerivation(format!("seeded ephemeral key: {}", e)))?;
+    Ok(ephemeral_key_pair(private_key))
+}
+
+fn ephemeral_key_pair(private_key: SecretKey) -> EphemeralKeyPair {
     let public_key = private_key.public_key();
 
     // Get the encoded point for the public key
@@ -69,7 +109,7 @@ pub fn generate_ephemeral_key_pair() -> Result<EphemeralKeyPair, Box<dyn std::er
     let d_bytes = private_key.to_bytes();
     let d = general_purpose::URL_SAFE_NO_PAD.encode(d_bytes.as_slice());
 
-    Ok(EphemeralKeyPair {
+    EphemeralKeyPair {
         private_key: d,
         public_key: AcsEphemPubKey {
             kty: "EC".to_string(),
@@ -77,12 +117,90 @@ pub fn generate_ephemeral_key_pair() -> Result<EphemeralKeyPair, Box<dyn std::er
             x,
             y,
         },
-    })
+    }
+}
+
+/// Generate a self-signed development certificate and RSA key, equivalent to
+/// ./generate-certs.sh but without needing openssl, and write both as PEM
+pub fn generate_self_signed_certificate(
+    cert_path: &Path,
+    key_path: &Path,
+) -> Result<(), CryptoError> {
+    use chrono::Datelike;
+    use rsa::pkcs8::{EncodePrivateKey, LineEnding};
+
+    let generation_failed = |e: &dyn std::fmt::Display| {
+        CryptoError::CertLoad(format!("certificate generation failed: {}", e))
+    };
+
+    // PS256 signing needs an RSA key, which rcgen can't generate itself
+    let private_key =
+        rsa::RsaPrivateKey::new(&mut OsRng, 2048).map_err(|e| generation_failed(&e))?;
+    let key_pem = private_key
+        .to_pkcs8_pem(LineEnding::LF)
+        .map_err(|e| generation_failed(&e))?;
+    let key_pair = rcgen::KeyPair::from_pem(&key_pem).map_err(|e| generation_failed(&e))?;
+
+    let mut params = rcgen::CertificateParams::new(vec!["localhost".to_string()]);
+    params.alg = &rcgen::PKCS_RSA_SHA256;
+    params.key_pair = Some(key_pair);
+    params.distinguished_name = rcgen::DistinguishedName::new();
+    params
+        .distinguished_name
+        .push(rcgen::DnType::OrganizationName, "Mock3DS");
+    params
+        .distinguished_name
+        .push(rcgen::DnType::OrganizationalUnitName, "ACS");
+    params
+        .distinguished_name
+        .push(rcgen::DnType::CommonName, "localhost");
+    let today = chrono::Utc::now().date_naive();
+    let expiry = today + chrono::Duration::days(365);
+    params.not_before = rcgen::date_time_ymd(today.year(), today.month() as u8, today.day() as u8);
+    params.not_after =
+        rcgen::date_time_ymd(expiry.year(), expiry.month() as u8, expiry.day() as u8);
+    let cert_pem = rcgen::Certificate::from_params(params)
+        .and_then(|certificate| certificate.serialize_pem())
+        .map_err(|e| generation_failed(&e))?;
+
+    for parent in [cert_path.parent(), key_path.parent()]
+        .into_iter()
+        .flatten()
+    {
+        fs::create_dir_all(parent).map_err(|e| CryptoError::CertLoad(e.to_string()))?;
+    }
+    write_private_key(key_path, key_pem.as_bytes())
+        .map_err(|e| CryptoError::CertLoad(format!("{}: {}", key_path.display(), e)))?;
+    fs::write(cert_path, cert_pem)
+        .map_err(|e| CryptoError::CertLoad(format!("{}: {}", cert_path.display(), e)))?;
+
+    info!(
+        cert = %cert_path.display(),
+        key = %key_path.display(),
+        "generated self-signed ACS certificate"
+    );
+    Ok(())
+}
+
+// Write a private key readable by its owner only, including over an existing file
+fn write_private_key(path: &Path, pem: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    // The mode above only applies to newly created files
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    file.write_all(pem)
 }
 
 /// Load and format certificate for x5c header
-pub fn load_certificate(cert_path: &Path) -> Result<String, Box<dyn std::error::Error>> {
-    let cert_content = fs::read_to_string(cert_path)?;
+pub fn load_certificate(cert_path: &Path) -> Result<String, CryptoError> {
+    let cert_content = fs::read_to_string(cert_path)
+        .map_err(|e| CryptoError::CertLoad(format!("{}: {}", cert_path.display(), e)))?;
 
     // Remove PEM headers and footers, and all whitespace
     let cert_base64 = cert_content
@@ -94,9 +212,23 @@ pub fn load_certificate(cert_path: &Path) -> Result<String, Box<dyn std::error::
     Ok(cert_base64)
 }
 
+/// notAfter of a PEM certificate, as a Unix timestamp
+pub fn certificate_not_after(cert_path: &Path) -> Result<i64, CryptoError> {
+    let cert_content = fs::read(cert_path)
+        .map_err(|e| CryptoError::CertLoad(format!("{}: {}", cert_path.display(), e)))?;
+    let (_, pem) = x509_parser::pem::parse_x509_pem(&cert_content)
+        .map_err(|e| CryptoError::CertLoad(format!("{}: {}", cert_path.display(), e)))?;
+    let cert = pem
+        .parse_x509()
+        .map_err(|e| CryptoError::CertLoad(format!("{}: {}", cert_path.display(), e)))?;
+
+    Ok(cert.validity().not_after.timestamp())
+}
+
 /// Load private key from PEM file
-pub fn load_private_key(key_path: &Path) -> Result<EncodingKey, Box<dyn std::error::Error>> {
-    let key_content = fs::read(key_path)?;
+pub fn load_private_key(key_path: &Path) -> Result<EncodingKey, CryptoError> {
+    let key_content = fs::read(key_path)
+        .map_err(|e| CryptoError::CertLoad(format!("{}: {}", key_path.display(), e)))?;
 
     // Try to parse as PKCS#8 first
     if let Ok(encoding_key) = EncodingKey::from_rsa_pem(&key_content) {
@@ -110,7 +242,7 @@ pub fn load_private_key(key_path: &Path) -> Result<EncodingKey, Box<dyn std::err
 
     // Try PKCS#1 RSA
     EncodingKey::from_rsa_pem(&key_content)
-        .map_err(|e| format!("Failed to load private key: {}", e).into())
+        .map_err(|e| CryptoError::CertLoad(format!("Failed to load private key: {}", e)))
 }
 
 /// Create ACS signed content JWT for mobile flows
@@ -121,13 +253,8 @@ pub fn create_acs_signed_content(
     ephemeral_keys: &EphemeralKeyPair,
     cert_path: &Path,
     key_path: &Path,
-) -> Result<String, Box<dyn std::error::Error>> {
-    println!("🔐 Creating ACS signed content JWT");
-    println!(
-        "  📋 Input acsTransID: {} (length: {})",
-        acs_trans_id,
-        acs_trans_id.to_string().len()
-    );
+) -> Result<String, CryptoError> {
+    debug!(%acs_trans_id, "creating ACS signed content JWT");
 
     // Load certificate and private key
     let cert_base64 = load_certificate(cert_path)?;
@@ -139,24 +266,18 @@ pub fn create_acs_signed_content(
     header.x5c = Some(vec![cert_base64]);
 
     // Create payload
-    let acs_trans_id_str = acs_trans_id.to_string();
     let payload = AcsSignedContentPayload {
-        acs_trans_id: acs_trans_id_str.clone(),
+        acs_trans_id: acs_trans_id.to_string(),
         acs_ref_number: acs_ref_number.to_string(),
         acs_url: acs_url.to_string(),
         acs_ephem_pub_key: ephemeral_keys.public_key.clone(),
     };
 
-    println!(
-        "  📋 Payload acsTransID: {} (length: {})",
-        acs_trans_id_str,
-        acs_trans_id_str.len()
-    );
-
     // Sign and encode JWT
-    let jwt = encode(&header, &payload, &encoding_key)?;
+    let jwt = encode(&header, &payload, &encoding_key)
+        .map_err(|e| CryptoError::Signing(e.to_string()))?;
 
-    println!("  ✅ Generated JWT length: {} characters", jwt.len());
+    debug!(jwt_length = jwt.len(), "ACS signed content JWT created");
 
     Ok(jwt)
 }
@@ -166,51 +287,120 @@ pub fn create_acs_url(base_url: &str) -> String {
     format!("{}/challenge", base_url.trim_end_matches('/'))
 }
 
+/// Detect an SDK ephemeral key rotation: a CReq whose JWE header carries an `epk` different from
+/// the key stored at AReq time (e.g. after an SDK restart). Returns the new key as a JWK string.
+pub fn rotated_sdk_ephemeral_key(
+    jwe_header: &serde_json::Value,
+    stored_sdk_key_jwk: Option<&str>,
+) -> Option<String> {
+    let epk = jwe_header.get("epk")?;
+    let x = epk["x"].as_str()?;
+    let y = epk["y"].as_str()?;
+
+    let stored: Option<serde_json::Value> =
+        stored_sdk_key_jwk.and_then(|jwk| serde_json::from_str(jwk).ok());
+    if let Some(stored) = stored {
+        if stored["x"].as_str() == Some(x) && stored["y"].as_str() == Some(y) {
+            return None;
+        }
+    }
+
+    Some(
+        serde_json::json!({
+            "kty": epk["kty"].as_str().unwrap_or("EC"),
+            "crv": epk["crv"].as_str().unwrap_or("P-256"),
+            "x": x,
+            "y": y
+        })
+        .to_string(),
+    )
+}
+
 /// Calculate derived key for mobile challenge flow using ECDH
 /// Implements proper ECDH with ConcatKDF following EMVCo 3DS specification
 pub fn calculate_derived_key(
     sdk_public_key_jwk: &str,
     our_private_key: &str,
-    platform: &str, // "android" or "ios"
-) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-    println!("🔐 ECDH: Starting shared secret derivation");
+    sdk_reference_number: &str, // partyVInfo, see AcsConfig::sdk_reference_number
+) -> Result<Vec<u8>, CryptoError> {
+    derive_key_with_trace(sdk_public_key_jwk, our_private_key, sdk_reference_number)
+        .map(|trace| trace.derived_key)
+}
 
+/// Every intermediate value of the ECDH + ConcatKDF derivation, serialized as hex so SDK
+/// developers can compare their own derivation byte for byte
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyDerivationTrace {
+    pub sdk_reference_number: String,
+    #[serde(serialize_with = "serialize_hex")]
+    pub shared_secret: Vec<u8>,
+    #[serde(serialize_with = "serialize_hex")]
+    pub algorithm_id: Vec<u8>,
+    #[serde(serialize_with = "serialize_hex")]
+    pub party_u_info: Vec<u8>,
+    #[serde(serialize_with = "serialize_hex")]
+    pub party_v_info: Vec<u8>,
+    #[serde(serialize_with = "serialize_hex")]
+    pub supp_pub_info: Vec<u8>,
+    #[serde(serialize_with = "serialize_hex")]
+    pub other_info: Vec<u8>,
+    #[serde(serialize_with = "serialize_hex")]
+    pub kdf_input: Vec<u8>,
+    #[serde(serialize_with = "serialize_hex")]
+    pub derived_key: Vec<u8>,
+}
+
+fn serialize_hex<S: serde::Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&hex::encode(bytes))
+}
+
+/// ECDH shared secret (Z) between a P-256 public JWK and our base64url private scalar
+fn ecdh_shared_secret(
+    sdk_public_key_jwk: &str,
+    our_private_key: &str,
+) -> Result<Vec<u8>, CryptoError> {
     // Parse SDK public key from JWK format
-    let sdk_jwk: serde_json::Value = serde_json::from_str(sdk_public_key_jwk)?;
-    println!(
-        "  - Curve: {}",
-        sdk_jwk["crv"].as_str().unwrap_or("unknown")
-    );
+    let sdk_jwk: serde_json::Value = serde_json::from_str(sdk_public_key_jwk)
+        .map_err(|e| CryptoError::KeyDerivation(format!("Invalid SDK public key JWK: {}", e)))?;
 
-    let x_b64 = sdk_jwk["x"]
-        .as_str()
-        .ok_or("Missing x coordinate in SDK public key")?;
-    let y_b64 = sdk_jwk["y"]
-        .as_str()
-        .ok_or("Missing y coordinate in SDK public key")?;
+    let x_b64 = sdk_jwk["x"].as_str().ok_or_else(|| {
+        CryptoError::KeyDerivation("Missing x coordinate in SDK public key".to_string())
+    })?;
+    let y_b64 = sdk_jwk["y"].as_str().ok_or_else(|| {
+        CryptoError::KeyDerivation("Missing y coordinate in SDK public key".to_string())
+    })?;
 
     // Decode x and y coordinates
-    let x_bytes = general_purpose::URL_SAFE_NO_PAD.decode(x_b64)?;
-    let y_bytes = general_purpose::URL_SAFE_NO_PAD.decode(y_b64)?;
-    println!("  - X coordinate length: {} bytes", x_bytes.len());
-    println!("  - Y coordinate length: {} bytes", y_bytes.len());
+    let decode_key_part = |value: &str| {
+        general_purpose::URL_SAFE_NO_PAD
+            .decode(value)
+            .map_err(|e| CryptoError::KeyDerivation(format!("Invalid base64url key data: {}", e)))
+    };
+    let x_bytes = decode_key_part(x_b64)?;
+    let y_bytes = decode_key_part(y_b64)?;
+    debug!(
+        curve = sdk_jwk["crv"].as_str().unwrap_or("unknown"),
+        x_length = x_bytes.len(),
+        y_length = y_bytes.len(),
+        "ECDH: deriving shared secret"
+    );
 
     // Decode our private key from base64url
-    let our_private_key_bytes = general_purpose::URL_SAFE_NO_PAD.decode(our_private_key)?;
+    let our_private_key_bytes = decode_key_part(our_private_key)?;
 
     // Create our private key from the decoded bytes (32-byte array for P-256)
     if our_private_key_bytes.len() != 32 {
-        return Err(format!(
+        return Err(CryptoError::KeyDerivation(format!(
             "Invalid private key length: {} (expected 32)",
             our_private_key_bytes.len()
-        )
-        .into());
+        )));
     }
     let mut key_array = [0u8; 32];
     key_array.copy_from_slice(&our_private_key_bytes);
 
     let our_secret_key = SecretKey::from_bytes(&key_array.into())
-        .map_err(|e| format!("Failed to create private key: {}", e))?;
+        .map_err(|e| CryptoError::KeyDerivation(format!("Failed to create private key: {}", e)))?;
 
     // Build uncompressed public key: 0x04 || x || y
     let mut public_key_bytes = Vec::with_capacity(65);
@@ -219,17 +409,25 @@ pub fn calculate_derived_key(
     public_key_bytes.extend_from_slice(&y_bytes);
 
     // Create SDK public key from the uncompressed bytes
-    let sdk_public_key = p256::PublicKey::from_sec1_bytes(&public_key_bytes)
-        .map_err(|e| format!("Failed to parse SDK public key: {}", e))?;
+    let sdk_public_key = p256::PublicKey::from_sec1_bytes(&public_key_bytes).map_err(|e| {
+        CryptoError::KeyDerivation(format!("Failed to parse SDK public key: {}", e))
+    })?;
 
     // Perform ECDH to get shared secret (Z)
     let shared_secret = p256::ecdh::diffie_hellman(
         our_secret_key.to_nonzero_scalar(),
         sdk_public_key.as_affine(),
     );
-    let shared_secret_bytes = shared_secret.raw_secret_bytes();
+    Ok(shared_secret.raw_secret_bytes().to_vec())
+}
 
-    println!("  - Shared Secret: {}", hex::encode(&shared_secret_bytes));
+/// `calculate_derived_key`, keeping the ConcatKDF inputs alongside the derived key
+pub fn derive_key_with_trace(
+    sdk_public_key_jwk: &str,
+    our_private_key: &str,
+    sdk_reference_number: &str,
+) -> Result<KeyDerivationTrace, CryptoError> {
+    let shared_secret_bytes = ecdh_shared_secret(sdk_public_key_jwk, our_private_key)?;
 
     // Build ConcatKDF OtherInfo per EMVCo spec
     // algorithmID: 4-byte zeros
@@ -238,21 +436,7 @@ pub fn calculate_derived_key(
     // partyUInfo: 4-byte zeros
     let party_u_info = [0u8; 4];
 
-    // partyVInfo: 4-byte big-endian length + sdkReferenceNumber (platform-specific)
-    let sdk_reference_number = match platform.to_lowercase().as_str() {
-        "android" => "3DS_LOA_SDK_JTPL_020200_00788",
-        "ios" => "3DS_LOA_SDK_JTPL_020200_00805",
-        _ => {
-            return Err(format!(
-                "Unsupported platform: {} (supported: android, ios)",
-                platform
-            )
-            .into())
-        }
-    };
-
-    println!("  - Platform: {}", platform);
-    println!("  - SDK Reference Number: {}", sdk_reference_number);
+    // partyVInfo: 4-byte big-endian length + sdkReferenceNumber
     let mut party_v_info = Vec::new();
     party_v_info.extend_from_slice(&(sdk_reference_number.len() as u32).to_be_bytes());
     party_v_info.extend_from_slice(sdk_reference_number.as_bytes());
@@ -267,8 +451,6 @@ pub fn calculate_derived_key(
     other_info.extend_from_slice(&party_v_info);
     other_info.extend_from_slice(&supp_pub_info);
 
-    println!("  - OtherInfo: {}", hex::encode(&other_info));
-
     // ConcatKDF counter: 4-byte big-endian integer with value 1
     let counter = [0u8, 0u8, 0u8, 0x01]; // 1 in big-endian
 
@@ -278,18 +460,97 @@ pub fn calculate_derived_key(
     kdf_input.extend_from_slice(&shared_secret_bytes);
     kdf_input.extend_from_slice(&other_info);
 
-    println!("  - KDF Input: {}", hex::encode(&kdf_input));
-
     // Derive the key by computing SHA-256 hash of the KDF input
     let derived_key_bytes = Sha256::digest(&kdf_input);
 
     // Take first 32 bytes for AES-256 or first 16 bytes for AES-128
     let derived_key = &derived_key_bytes[0..32]; // Use full 32 bytes for more robust key
 
-    println!("  - Derived Key: {}", hex::encode(derived_key));
-    println!("  ✅ Derived key length: {} bytes", derived_key.len());
+    // Key material only at trace level
+    trace!(
+        sdk_reference_number,
+        shared_secret = %hex::encode(&shared_secret_bytes),
+        other_info = %hex::encode(&other_info),
+        kdf_input = %hex::encode(&kdf_input),
+        derived_key = %hex::encode(derived_key),
+        "ECDH: ConcatKDF inputs"
+    );
+    debug!(
+        sdk_reference_number,
+        key_length = derived_key.len(),
+        "ECDH: key derived"
+    );
+
+    Ok(KeyDerivationTrace {
+        sdk_reference_number: sdk_reference_number.to_string(),
+        shared_secret: shared_secret_bytes.to_vec(),
+        algorithm_id: algorithm_id.to_vec(),
+        party_u_info: party_u_info.to_vec(),
+        party_v_info,
+        supp_pub_info: supp_pub_info.to_vec(),
+        other_info,
+        kdf_input,
+        derived_key: derived_key.to_vec(),
+    })
+}
+
+/// Content key for `alg: ECDH-ES` (RFC 7518 section 4.6): ConcatKDF over the ECDH secret
+/// with the enc name as AlgorithmID and the enc key length as SuppPubInfo
+pub fn derive_ecdh_es_key(
+    public_key_jwk: &str,
+    our_private_key: &str,
+    encryption: &str,
+    apu: &[u8],
+    apv: &[u8],
+) -> Result<Vec<u8>, CryptoError> {
+    let key_length = match encryption {
+        "A128CBC-HS256" => 32,
+        "A128GCM" => 16,
+        _ => {
+            return Err(CryptoError::KeyDerivation(format!(
+                "Unsupported encryption algorithm for ECDH-ES: {}",
+                encryption
+            )))
+        }
+    };
+    let shared_secret = ecdh_shared_secret(public_key_jwk, our_private_key)?;
+
+    // One SHA-256 round covers both key lengths: counter || Z || OtherInfo
+    let mut kdf_input = vec![0u8, 0, 0, 1];
+    kdf_input.extend_from_slice(&shared_secret);
+    for info in [encryption.as_bytes(), apu, apv] {
+        kdf_input.extend_from_slice(&(info.len() as u32).to_be_bytes());
+        kdf_input.extend_from_slice(info);
+    }
+    kdf_input.extend_from_slice(&((key_length * 8) as u32).to_be_bytes());
+
+    Ok(Sha256::digest(&kdf_input)[..key_length].to_vec())
+}
 
-    Ok(derived_key.to_vec())
+/// ECDH-ES content key of a received JWE, from the `epk`, `enc`, `apu` and `apv` of its header
+pub fn ecdh_es_content_key(
+    jwe_header: &serde_json::Value,
+    our_private_key: &str,
+) -> Result<Vec<u8>, CryptoError> {
+    let epk = jwe_header
+        .get("epk")
+        .ok_or_else(|| CryptoError::JweFormat("ECDH-ES JWE header has no epk".to_string()))?;
+    let encryption = jwe_header["enc"].as_str().unwrap_or("unknown");
+    let party_info = |name: &str| {
+        jwe_header[name]
+            .as_str()
+            .map(|value| general_purpose::URL_SAFE_NO_PAD.decode(value))
+            .transpose()
+            .map(Option::unwrap_or_default)
+            .map_err(|e| CryptoError::JweFormat(format!("Invalid base64url {}: {}", name, e)))
+    };
+    derive_ecdh_es_key(
+        &epk.to_string(),
+        our_private_key,
+        encryption,
+        &party_info("apu")?,
+        &party_info("apv")?,
+    )
 }
 
 /// Decrypt JWE challenge request from SDK
@@ -297,20 +558,59 @@ pub fn calculate_derived_key(
 pub async fn decrypt_challenge_request(
     jwe_string: &str,
     derived_key_buffer: &[u8],
-) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
-    println!("🔓 Attempting to decrypt challenge request...");
+) -> Result<serde_json::Value, CryptoError> {
+    decrypt_challenge_request_with_rsa(jwe_string, derived_key_buffer, None).await
+}
 
+/// `decrypt_challenge_request`, also accepting `alg: RSA-OAEP-256` JWEs whose content key
+/// the SDK encrypted to the ACS RSA key
+pub async fn decrypt_challenge_request_with_rsa(
+    jwe_string: &str,
+    derived_key_buffer: &[u8],
+    rsa_private_key: Option<&RsaPrivateKey>,
+) -> Result<serde_json::Value, CryptoError> {
     // Extract JWE parts
     let jwe_parts: Vec<&str> = jwe_string.split('.').collect();
     if jwe_parts.len() != 5 {
-        return Err("Invalid JWE structure, expected 5 parts.".into());
+        return Err(CryptoError::JweFormat(
+            "Invalid JWE structure, expected 5 parts.".to_string(),
+        ));
     }
 
+    let decode_part = |name: &str, value: &str| {
+        // Base64 padding is tolerated, as in jwe::analyze
+        general_purpose::URL_SAFE_NO_PAD
+            .decode(value.trim_end_matches('='))
+            .map_err(|e| CryptoError::JweFormat(format!("Invalid base64url {}: {}", name, e)))
+    };
+
     // Get header information for platform detection
-    let header_data = general_purpose::URL_SAFE_NO_PAD.decode(jwe_parts[0])?;
-    let header_json: serde_json::Value = serde_json::from_slice(&header_data)?;
+    let header_data = decode_part("header", jwe_parts[0])?;
+    let header_json: serde_json::Value = serde_json::from_slice(&header_data)
+        .map_err(|e| CryptoError::JweFormat(format!("Invalid header JSON: {}", e)))?;
     let encryption = header_json["enc"].as_str().unwrap_or("unknown");
 
+    // Content encryption key: a random key the SDK encrypted to the ACS RSA key (alg
+    // RSA-OAEP-256), otherwise the derived key itself (alg dir, or whatever the SDK sent)
+    let content_key = match header_json["alg"].as_str() {
+        Some("RSA-OAEP-256") => {
+            let rsa_private_key = rsa_private_key.ok_or_else(|| {
+                CryptoError::KeyDerivation(
+                    "RSA-OAEP-256 JWE received but no RSA key is configured (acs.jwe.rsa_key_path)"
+                        .to_string(),
+                )
+            })?;
+            let encrypted_key = decode_part("encrypted key", jwe_parts[1])?;
+            rsa_private_key
+                .decrypt(Oaep::new::<Sha256>(), &encrypted_key)
+                .map_err(|e| {
+                    CryptoError::KeyDerivation(format!("RSA-OAEP-256 key unwrap failed: {}", e))
+                })?
+        }
+        _ => derived_key_buffer.to_vec(),
+    };
+    let derived_key_buffer = content_key.as_slice();
+
     // Detect platform based on encryption algorithm
     let platform = match encryption {
         "A128CBC-HS256" => "Android",
@@ -318,46 +618,48 @@ pub async fn decrypt_challenge_request(
         _ => "Unknown",
     };
 
-    println!("🔍 Platform Detection:");
-    println!("  - Encryption Algorithm: {}", encryption);
-    println!("  - Detected Platform: {}", platform);
-    println!("  - Derived Key Length: {} bytes", derived_key_buffer.len());
-
     // For logging: decode Base64Url parts
-    let iv = general_purpose::URL_SAFE_NO_PAD.decode(jwe_parts[2])?;
-    let ciphertext = general_purpose::URL_SAFE_NO_PAD.decode(jwe_parts[3])?;
-    let auth_tag = general_purpose::URL_SAFE_NO_PAD.decode(jwe_parts[4])?;
-
-    println!("📋 JWE Components:");
-    println!("  - IV: {}", hex::encode(&iv));
-    println!("  - Ciphertext Length: {} bytes", ciphertext.len());
-    println!("  - Authentication Tag: {}", hex::encode(&auth_tag));
+    let iv = decode_part("IV", jwe_parts[2])?;
+    let ciphertext = decode_part("ciphertext", jwe_parts[3])?;
+    let auth_tag = decode_part("authentication tag", jwe_parts[4])?;
+
+    debug!(
+        enc = encryption,
+        platform,
+        key_length = derived_key_buffer.len(),
+        ciphertext_length = ciphertext.len(),
+        "decrypting challenge request"
+    );
+    trace!(
+        iv = %hex::encode(&iv),
+        auth_tag = %hex::encode(&auth_tag),
+        "JWE components"
+    );
 
     // Perform platform-specific decryption
     let plaintext = match encryption {
         "A128CBC-HS256" => {
-            println!("🤖 Android Decryption: Using A128CBC-HS256");
-
             // Android uses the full 32-byte derived key (16 for HMAC, 16 for AES per JWE spec)
             if derived_key_buffer.len() != 32 {
-                return Err(format!(
+                return Err(CryptoError::KeyDerivation(format!(
                     "Invalid derived key length for Android: {} (expected 32)",
                     derived_key_buffer.len()
-                )
-                .into());
+                )));
             }
 
             let hmac_key = &derived_key_buffer[0..16];
             let aes_key = &derived_key_buffer[16..32];
 
-            println!("  - HMAC Key: {} bytes", hmac_key.len());
-            println!("  - AES Key: {} bytes", aes_key.len());
-            println!("  - Android HMAC Key: {}", hex::encode(hmac_key));
-            println!("  - Android AES Key: {}", hex::encode(aes_key));
+            trace!(
+                hmac_key = %hex::encode(hmac_key),
+                aes_key = %hex::encode(aes_key),
+                "A128CBC-HS256 decryption keys"
+            );
 
             // Verify HMAC tag according to JWE spec (RFC 7516)
-            let mut mac = <HmacSha256 as Mac>::new_from_slice(hmac_key)
-                .map_err(|e| format!("HMAC initialization failed: {}", e))?;
+            let mut mac = <HmacSha256 as Mac>::new_from_slice(hmac_key).map_err(|e| {
+                CryptoError::KeyDerivation(format!("HMAC initialization failed: {}", e))
+            })?;
 
             // The HMAC input for A128CBC-HS256 must follow the JWE specification:
             // HMAC input = AAD || IV || Ciphertext || AAD Length
@@ -383,7 +685,7 @@ pub async fn decrypt_challenge_request(
             // Check if the first 16 bytes of the computed HMAC match the auth tag
             let truncated_hmac = &computed_hmac[0..16];
             if truncated_hmac != auth_tag.as_slice() {
-                return Err("HMAC verification failed - authentication tag does not match".into());
+                return Err(CryptoError::HmacMismatch);
             }
 
             // Decrypt with AES-128-CBC
@@ -392,49 +694,40 @@ pub async fn decrypt_challenge_request(
 
             let plaintext_len = cipher
                 .decrypt_padded_mut::<Pkcs7>(&mut buffer)
-                .map_err(|e| format!("AES-CBC decryption failed: {}", e))?
+                .map_err(|e| CryptoError::Padding(e.to_string()))?
                 .len();
 
             buffer.truncate(plaintext_len);
             buffer
         }
         "A128GCM" => {
-            println!("🍎 iOS Decryption: Using A128GCM");
-
             // iOS uses only the first 16 bytes of the derived key (matching JavaScript implementation)
             if derived_key_buffer.len() < 16 {
-                return Err(format!(
+                return Err(CryptoError::KeyDerivation(format!(
                     "Insufficient key material for iOS: {} bytes (need at least 16)",
                     derived_key_buffer.len()
-                )
-                .into());
+                )));
             }
 
             let ios_key = &derived_key_buffer[0..16];
-            println!(
-                "  - Using key slice: {} bytes (first 16 bytes of derived key)",
-                ios_key.len()
-            );
-            println!("  - iOS Key: {}", hex::encode(ios_key));
+            trace!(key = %hex::encode(ios_key), "A128GCM decryption key (first 16 bytes)");
 
             // For A128GCM in JWE, we need to include AAD (Additional Authenticated Data)
             // AAD is the ASCII bytes of the base64url-encoded JWE Protected Header
             let aad = jwe_parts[0].as_bytes();
-            println!("  - AAD (JWE Header): {}", String::from_utf8_lossy(aad));
-            println!("  - AAD length: {} bytes", aad.len());
 
             // Check IV length - should be 12 bytes for GCM
             if iv.len() != 12 {
-                println!(
-                    "  ⚠️  Warning: IV length is {} bytes, expected 12 for GCM",
-                    iv.len()
-                );
                 if iv.len() > 12 {
-                    println!("  - Truncating IV to first 12 bytes");
-                } else if iv.len() < 12 {
-                    return Err(
-                        format!("IV too short for GCM: {} bytes (need 12)", iv.len()).into(),
+                    warn!(
+                        iv_length = iv.len(),
+                        "GCM IV longer than 12 bytes, truncating"
                     );
+                } else if iv.len() < 12 {
+                    return Err(CryptoError::JweFormat(format!(
+                        "IV too short for GCM: {} bytes (need 12)",
+                        iv.len()
+                    )));
                 }
             }
 
@@ -456,240 +749,265 @@ pub async fn decrypt_challenge_request(
                     &mut ciphertext_buffer,
                     auth_tag.as_slice().into(),
                 )
-                .map_err(|e| format!("iOS A128GCM decryption failed: {}", e))?;
+                .map_err(|_| CryptoError::HmacMismatch)?;
 
             ciphertext_buffer
         }
         _ => {
-            return Err(format!("Unsupported encryption algorithm: {} (supported: A128GCM for iOS, A128CBC-HS256 for Android)", encryption).into());
+            return Err(CryptoError::JweFormat(format!("Unsupported encryption algorithm: {} (supported: A128GCM for iOS, A128CBC-HS256 for Android)", encryption)));
         }
     };
 
     // Parse JSON
-    let decrypted_payload = serde_json::from_slice(&plaintext)?;
-    println!("✅ {} Decryption Successful!", platform);
-    println!(
-        "📋 Decrypted Payload: {}",
-        serde_json::to_string(&decrypted_payload)?
-    );
+    let decrypted_payload: serde_json::Value = serde_json::from_slice(&plaintext)
+        .map_err(|e| CryptoError::JweFormat(format!("Decrypted payload is not JSON: {}", e)))?;
+    debug!(platform, "challenge request decrypted");
+    trace!(payload = %decrypted_payload, "decrypted challenge request");
 
     Ok(decrypted_payload)
 }
 
 /// Encrypt JWE challenge response for SDK
 /// Supports both Android (A128CBC-HS256) and iOS (A128GCM) platforms
-pub async fn encrypt_challenge_response(
-    response_data: &serde_json::Value,
+pub async fn encrypt_challenge_response<T: serde::Serialize + ?Sized>(
+    response_data: &T,
     acs_trans_id: &str,
     derived_key: &[u8],
     platform: &str, // "android" or "ios"
-) -> Result<String, Box<dyn std::error::Error>> {
-    println!("🔒 JWE Encryption: Encrypting challenge response");
-    println!("  - Target Platform: {}", platform);
-
+) -> Result<String, CryptoError> {
     // Serialize response to JSON
-    let plaintext = serde_json::to_vec(response_data)?;
-    println!("  - Response size: {} bytes", plaintext.len());
+    let plaintext =
+        serde_json::to_vec(response_data).map_err(|e| CryptoError::Encryption(e.to_string()))?;
+    debug!(
+        platform,
+        plaintext_length = plaintext.len(),
+        "encrypting challenge response"
+    );
 
     // Platform-specific encryption
     match platform.to_lowercase().as_str() {
         "android" => {
-            println!("🤖 Android Encryption: Using A128CBC-HS256");
-
             // Android uses the full 32-byte derived key (16 for HMAC, 16 for AES per JWE spec)
             if derived_key.len() != 32 {
-                return Err(format!(
+                return Err(CryptoError::KeyDerivation(format!(
                     "Invalid derived key length for Android: {} (expected 32)",
                     derived_key.len()
-                )
-                .into());
+                )));
+            }
+            let header = jwe_header("dir", "A128CBC-HS256", acs_trans_id);
+            seal_jwe(&plaintext, &header, derived_key, &[])
+        }
+        "ios" => {
+            // iOS uses the LAST 16 bytes of the derived key for encryption (matching JavaScript implementation)
+            // JavaScript: Buffer.from(derivedKey.slice(32), 'hex') = last 16 bytes
+            if derived_key.len() < 32 {
+                return Err(CryptoError::KeyDerivation(format!(
+                    "Insufficient key material for iOS: {} bytes (need at least 32)",
+                    derived_key.len()
+                )));
             }
 
-            let hmac_key = &derived_key[0..16]; // First 16 bytes for HMAC (per JWE spec)
-            let aes_key = &derived_key[16..32]; // Last 16 bytes for AES-128
-
-            println!("  🔑 Android AES key: {} bytes", aes_key.len());
-            println!("  🔑 Android HMAC key: {} bytes", hmac_key.len());
-
-            // Generate random IV (16 bytes for CBC)
-            let mut iv = [0u8; 16];
-            use rand_core::RngCore;
-            OsRng.fill_bytes(&mut iv);
+            let ios_key = &derived_key[16..32]; // Last 16 bytes for encryption
+            trace!(key = %hex::encode(ios_key), "A128GCM encryption key (last 16 bytes)");
+            let header = jwe_header("dir", "A128GCM", acs_trans_id);
+            seal_jwe(&plaintext, &header, ios_key, &[])
+        }
+        _ => Err(CryptoError::Encryption(format!(
+            "Unsupported platform: {} (supported: android, ios)",
+            platform
+        ))),
+    }
+}
 
-            // Encrypt with AES-128-CBC
-            let cipher = Aes128CbcEnc::new(aes_key.into(), iv.as_slice().into());
+/// Encrypt JWE challenge response with `alg: RSA-OAEP-256`: a random content key, sent
+/// encrypted to the recipient's RSA public key
+pub async fn encrypt_challenge_response_rsa<T: serde::Serialize + ?Sized>(
+    response_data: &T,
+    acs_trans_id: &str,
+    recipient_key: &RsaPublicKey,
+    platform: &str, // "android" or "ios"
+) -> Result<String, CryptoError> {
+    let plaintext =
+        serde_json::to_vec(response_data).map_err(|e| CryptoError::Encryption(e.to_string()))?;
+    let (encryption, key_length) = match platform.to_lowercase().as_str() {
+        "android" => ("A128CBC-HS256", 32),
+        "ios" => ("A128GCM", 16),
+        _ => {
+            return Err(CryptoError::Encryption(format!(
+                "Unsupported platform: {} (supported: android, ios)",
+                platform
+            )))
+        }
+    };
+    debug!(
+        platform,
+        plaintext_length = plaintext.len(),
+        "encrypting challenge response to an RSA key"
+    );
 
-            // Prepare buffer with space for padding (up to one full block)
-            let mut buffer = plaintext.clone();
-            buffer.resize(plaintext.len() + 16, 0); // Add space for padding
+    let mut content_key = vec![0u8; key_length];
+    rand_core::RngCore::fill_bytes(&mut OsRng, &mut content_key);
+    let encrypted_key = recipient_key
+        .encrypt(&mut OsRng, Oaep::new::<Sha256>(), &content_key)
+        .map_err(|e| CryptoError::Encryption(format!("RSA-OAEP-256 key wrap failed: {}", e)))?;
 
-            // Encrypt with padding
-            let ciphertext_slice = cipher
-                .encrypt_padded_mut::<Pkcs7>(&mut buffer, plaintext.len())
-                .map_err(|e| format!("AES-CBC encryption failed: {}", e))?;
+    let header = jwe_header("RSA-OAEP-256", encryption, acs_trans_id);
+    seal_jwe(&plaintext, &header, &content_key, &encrypted_key)
+}
 
-            let ciphertext = ciphertext_slice.to_vec();
-            println!(
-                "  ✅ Encrypted {} bytes to {} bytes",
-                plaintext.len(),
-                ciphertext.len()
-            );
+/// Encrypt JWE challenge response with `alg: ECDH-ES`: a content key agreed between a fresh
+/// ephemeral key, sent as `epk`, and the recipient's public key
+pub async fn encrypt_challenge_response_ecdh_es<T: serde::Serialize + ?Sized>(
+    response_data: &T,
+    acs_trans_id: &str,
+    recipient_key_jwk: &str,
+    platform: &str, // "android" or "ios"
+) -> Result<String, CryptoError> {
+    let plaintext =
+        serde_json::to_vec(response_data).map_err(|e| CryptoError::Encryption(e.to_string()))?;
+    let encryption = match platform.to_lowercase().as_str() {
+        "android" => "A128CBC-HS256",
+        "ios" => "A128GCM",
+        _ => {
+            return Err(CryptoError::Encryption(format!(
+                "Unsupported platform: {} (supported: android, ios)",
+                platform
+            )))
+        }
+    };
+    debug!(
+        platform,
+        plaintext_length = plaintext.len(),
+        "encrypting challenge response with ECDH-ES"
+    );
 
-            // Create JWE header for Android
-            let header = serde_json::json!({
-                "alg": "dir",
-                "enc": "A128CBC-HS256",
-                "kid": acs_trans_id
-            });
+    let ephemeral_keys = generate_ephemeral_key_pair()?;
+    let content_key = derive_ecdh_es_key(
+        recipient_key_jwk,
+        &ephemeral_keys.private_key,
+        encryption,
+        &[],
+        &[],
+    )?;
+    let mut header = jwe_header("ECDH-ES", encryption, acs_trans_id);
+    header["epk"] = serde_json::json!(ephemeral_keys.public_key);
+    seal_jwe(&plaintext, &header, &content_key, &[])
+}
 
-            let header_json_str = serde_json::to_string(&header)?;
-            println!("  📋 Android JWE header: {}", header_json_str);
+fn jwe_header(alg: &str, encryption: &str, acs_trans_id: &str) -> serde_json::Value {
+    serde_json::json!({
+        "alg": alg,
+        "enc": encryption,
+        "kid": acs_trans_id
+    })
+}
 
-            let header_b64 = general_purpose::URL_SAFE_NO_PAD.encode(&header_json_str);
-            let encrypted_key_b64 = ""; // Empty for direct key agreement
-            let iv_b64 = general_purpose::URL_SAFE_NO_PAD.encode(&iv);
-            let ciphertext_b64 = general_purpose::URL_SAFE_NO_PAD.encode(&ciphertext);
+/// Encrypt a plaintext into a compact JWE with the given content key: 32 bytes (16 for HMAC,
+/// 16 for AES) for A128CBC-HS256, 16 for A128GCM
+fn seal_jwe(
+    plaintext: &[u8],
+    header: &serde_json::Value,
+    content_key: &[u8],
+    encrypted_key: &[u8],
+) -> Result<String, CryptoError> {
+    use rand_core::RngCore;
+
+    // The header is the AAD of both content encryption algorithms
+    let encryption = header["enc"].as_str().unwrap_or("unknown");
+    let header_b64 = general_purpose::URL_SAFE_NO_PAD.encode(header.to_string());
+    let aad = header_b64.as_bytes();
+
+    let (iv, ciphertext, tag) = match encryption {
+        "A128CBC-HS256" => {
+            let hmac_key = &content_key[0..16]; // First 16 bytes for HMAC (per JWE spec)
+            let aes_key = &content_key[16..32]; // Last 16 bytes for AES-128
 
-            // Calculate HMAC according to JWE spec (RFC 7516)
-            let mut mac = <HmacSha256 as Mac>::new_from_slice(hmac_key)
-                .map_err(|e| format!("HMAC initialization failed: {}", e))?;
+            // Generate random IV (16 bytes for CBC)
+            let mut iv = [0u8; 16];
+            OsRng.fill_bytes(&mut iv);
 
-            // The HMAC input for A128CBC-HS256 must follow the JWE specification:
-            // HMAC input = AAD || IV || Ciphertext || AAD Length
-            // where AAD is the JWE Protected Header (base64url encoded)
+            // Encrypt with AES-128-CBC, with space for padding (up to one full block)
+            let cipher = Aes128CbcEnc::new(aes_key.into(), iv.as_slice().into());
+            let mut buffer = plaintext.to_vec();
+            buffer.resize(plaintext.len() + 16, 0);
+            let ciphertext = cipher
+                .encrypt_padded_mut::<Pkcs7>(&mut buffer, plaintext.len())
+                .map_err(|e| CryptoError::Encryption(format!("AES-CBC encryption failed: {}", e)))?
+                .to_vec();
 
-            // 1. AAD (Additional Authenticated Data) - the base64url encoded header
-            let aad = header_b64.as_bytes();
+            // HMAC input per RFC 7516: AAD || IV || Ciphertext || AAD length in bits (64-bit BE)
+            let mut mac = <HmacSha256 as Mac>::new_from_slice(hmac_key).map_err(|e| {
+                CryptoError::Encryption(format!("HMAC initialization failed: {}", e))
+            })?;
             mac.update(aad);
-
-            // 2. IV - raw bytes (not base64 encoded)
             mac.update(&iv);
-
-            // 3. Ciphertext - raw bytes (not base64 encoded)
             mac.update(&ciphertext);
+            mac.update(&((aad.len() * 8) as u64).to_be_bytes());
 
-            // 4. AAD Length - 64-bit big-endian representation of the length of AAD in bits
-            let aad_bits = (aad.len() * 8) as u64;
-            let aad_bits_be = aad_bits.to_be_bytes(); // Convert to big-endian byte array
-            mac.update(&aad_bits_be);
-
-            let hmac_result = mac.finalize().into_bytes();
             // For A128CBC-HS256, use truncated HMAC (first 16 bytes)
-            let truncated_hmac = &hmac_result[0..16];
-            let tag_b64 = general_purpose::URL_SAFE_NO_PAD.encode(truncated_hmac);
-
-            println!(
-                "  📋 Android HMAC tag: {} bytes (truncated from 32)",
-                truncated_hmac.len()
-            );
-
-            // Construct JWE
-            let jwe = format!(
-                "{}.{}.{}.{}.{}",
-                header_b64, encrypted_key_b64, iv_b64, ciphertext_b64, tag_b64
-            );
-
-            println!("  ✅ Android encrypted JWE length: {} bytes", jwe.len());
-            Ok(jwe)
+            let tag = mac.finalize().into_bytes()[0..16].to_vec();
+            (iv.to_vec(), ciphertext, tag)
         }
-        "ios" => {
-            println!("🍎 iOS Encryption: Using A128GCM");
-
-            // iOS uses the LAST 16 bytes of the derived key for encryption (matching JavaScript implementation)
-            // JavaScript: Buffer.from(derivedKey.slice(32), 'hex') = last 16 bytes
-            if derived_key.len() < 32 {
-                return Err(format!(
-                    "Insufficient key material for iOS: {} bytes (need at least 32)",
-                    derived_key.len()
-                )
-                .into());
-            }
-
-            let ios_key = &derived_key[16..32]; // Last 16 bytes for encryption
-            println!(
-                "  🔑 iOS encryption key: {} bytes (last 16 bytes of derived key)",
-                ios_key.len()
-            );
-            println!("  🔑 iOS encryption key: {}", hex::encode(ios_key));
-
+        "A128GCM" => {
             // Generate random IV (12 bytes for GCM)
             let mut iv = [0u8; 12];
-            use rand_core::RngCore;
             OsRng.fill_bytes(&mut iv);
 
-            // Create JWE header for iOS first (needed for AAD)
-            let header = serde_json::json!({
-                "alg": "dir",
-                "enc": "A128GCM",
-                "kid": acs_trans_id
-            });
-
-            let header_json_str = serde_json::to_string(&header)?;
-            println!("  📋 iOS JWE header: {}", header_json_str);
-
-            let header_b64 = general_purpose::URL_SAFE_NO_PAD.encode(&header_json_str);
-
-            // For A128GCM in JWE, we need to include AAD (Additional Authenticated Data)
-            // AAD is the ASCII bytes of the base64url-encoded JWE Protected Header
-            let aad = header_b64.as_bytes();
-            println!("  - AAD (JWE Header): {}", String::from_utf8_lossy(aad));
-            println!("  - AAD length: {} bytes", aad.len());
+            let cipher = Aes128Gcm::new(Key::<Aes128Gcm>::from_slice(content_key));
+            let mut ciphertext = plaintext.to_vec();
+            let tag = cipher
+                .encrypt_in_place_detached(Nonce::from_slice(&iv), aad, &mut ciphertext)
+                .map_err(|e| {
+                    CryptoError::Encryption(format!("iOS A128GCM encryption failed: {}", e))
+                })?;
+            (iv.to_vec(), ciphertext, tag.to_vec())
+        }
+        _ => {
+            return Err(CryptoError::Encryption(format!(
+                "Unsupported encryption algorithm: {}",
+                encryption
+            )))
+        }
+    };
 
-            // Create cipher with iOS key (16 bytes)
-            let key = Key::<Aes128Gcm>::from_slice(ios_key);
-            let cipher = Aes128Gcm::new(key);
-            let nonce = Nonce::from_slice(&iv);
-
-            // Encrypt with A128GCM using AAD
-            let mut plaintext_buffer = plaintext.clone();
-            let auth_tag = cipher
-                .encrypt_in_place_detached(nonce, aad, &mut plaintext_buffer)
-                .map_err(|e| format!("iOS A128GCM encryption failed: {}", e))?;
-
-            let ciphertext = plaintext_buffer;
-            println!(
-                "  ✅ iOS encrypted {} bytes to {} bytes + {} byte tag",
-                plaintext.len(),
-                ciphertext.len(),
-                auth_tag.len()
-            );
+    // Construct JWE; the encrypted key part is empty for direct key agreement
+    let jwe = format!(
+        "{}.{}.{}.{}.{}",
+        header_b64,
+        general_purpose::URL_SAFE_NO_PAD.encode(encrypted_key),
+        general_purpose::URL_SAFE_NO_PAD.encode(&iv),
+        general_purpose::URL_SAFE_NO_PAD.encode(&ciphertext),
+        general_purpose::URL_SAFE_NO_PAD.encode(&tag)
+    );
 
-            let encrypted_key_b64 = ""; // Empty for direct key agreement
-            let iv_b64 = general_purpose::URL_SAFE_NO_PAD.encode(&iv);
-            let ciphertext_b64 = general_purpose::URL_SAFE_NO_PAD.encode(&ciphertext);
-            let tag_b64 = general_purpose::URL_SAFE_NO_PAD.encode(&auth_tag);
+    debug!(
+        alg = header["alg"].as_str(),
+        enc = encryption,
+        jwe_length = jwe.len(),
+        "challenge response encrypted"
+    );
+    Ok(jwe)
+}
 
-            // Construct JWE
-            let jwe = format!(
-                "{}.{}.{}.{}.{}",
-                header_b64, encrypted_key_b64, iv_b64, ciphertext_b64, tag_b64
-            );
+/// Load an RSA private key (PKCS#8 or PKCS#1 PEM) for RSA-OAEP-256 JWEs
+pub fn load_rsa_private_key(key_path: &Path) -> Result<RsaPrivateKey, CryptoError> {
+    use rsa::pkcs1::DecodeRsaPrivateKey;
+    use rsa::pkcs8::DecodePrivateKey;
 
-            println!("  ✅ iOS encrypted JWE length: {} bytes", jwe.len());
-            Ok(jwe)
-        }
-        _ => Err(format!(
-            "Unsupported platform: {} (supported: android, ios)",
-            platform
-        )
-        .into()),
-    }
-}
-/// Encrypt JWE challenge response for SDK (Legacy Android-only function)
-/// This function is kept for backward compatibility and defaults to Android encryption
-/// For new code, use encrypt_challenge_response_for_platform instead
-pub async fn encrypt_challenge_response_legacy(
-    response_data: &serde_json::Value,
-    acs_trans_id: &str,
-    derived_key: &[u8],
-) -> Result<String, Box<dyn std::error::Error>> {
-    encrypt_challenge_response(response_data, acs_trans_id, derived_key, "android").await
+    let key_content = fs::read_to_string(key_path)
+        .map_err(|e| CryptoError::CertLoad(format!("{}: {}", key_path.display(), e)))?;
+    RsaPrivateKey::from_pkcs8_pem(&key_content)
+        .or_else(|_| RsaPrivateKey::from_pkcs1_pem(&key_content))
+        .map_err(|e| CryptoError::CertLoad(format!("{}: {}", key_path.display(), e)))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    const ANDROID_SDK_REFERENCE_NUMBER: &str = "3DS_LOA_SDK_JTPL_020200_00788";
+    const IOS_SDK_REFERENCE_NUMBER: &str = "3DS_LOA_SDK_JTPL_020200_00805";
+
     #[test]
     fn test_generate_ephemeral_key_pair() {
         let result = generate_ephemeral_key_pair();
@@ -703,6 +1021,18 @@ mod tests {
         assert!(!keys.private_key.is_empty());
     }
 
+    #[test]
+    fn test_derived_ephemeral_keys_are_reproducible() {
+        let acs_trans_id = Uuid::new_v4();
+        let keys = derive_ephemeral_key_pair("test-run-1", acs_trans_id).unwrap();
+        let again = derive_ephemeral_key_pair("test-run-1", acs_trans_id).unwrap();
+        assert_eq!(keys.private_key, again.private_key);
+        assert_eq!(keys.public_key.x, again.public_key.x);
+
+        let other = derive_ephemeral_key_pair("test-run-1", Uuid::new_v4()).unwrap();
+        assert_ne!(keys.private_key, other.private_key);
+    }
+
     #[test]
     fn test_create_acs_url() {
         assert_eq!(
@@ -715,6 +1045,44 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_decrypt_error_variants() {
+        let data = serde_json::json!({ "messageType": "CReq" });
+        let key = vec![7u8; 32];
+        let jwe = encrypt_challenge_response(&data, "test-acs-trans-id", &key, "android")
+            .await
+            .expect("Failed to encrypt data");
+
+        // Decrypting with a different key fails tag verification
+        let wrong_key = vec![8u8; 32];
+        assert!(matches!(
+            decrypt_challenge_request(&jwe, &wrong_key).await,
+            Err(CryptoError::HmacMismatch)
+        ));
+
+        assert!(matches!(
+            decrypt_challenge_request("not.a.jwe", &key).await,
+            Err(CryptoError::JweFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_rotated_sdk_ephemeral_key() {
+        let stored = r#"{"kty":"EC","crv":"P-256","x":"old-x","y":"old-y"}"#;
+
+        // No epk in the header, or the same key as stored: nothing rotated
+        let header = serde_json::json!({ "enc": "A128GCM", "kid": "acs-trans-id" });
+        assert!(rotated_sdk_ephemeral_key(&header, Some(stored)).is_none());
+        let header = serde_json::json!({ "epk": { "kty": "EC", "crv": "P-256", "x": "old-x", "y": "old-y" } });
+        assert!(rotated_sdk_ephemeral_key(&header, Some(stored)).is_none());
+
+        let header = serde_json::json!({ "epk": { "kty": "EC", "crv": "P-256", "x": "new-x", "y": "new-y" } });
+        let rotated = rotated_sdk_ephemeral_key(&header, Some(stored)).expect("key should rotate");
+        let rotated: serde_json::Value = serde_json::from_str(&rotated).unwrap();
+        assert_eq!(rotated["x"], "new-x");
+        assert_eq!(rotated["y"], "new-y");
+    }
+
     #[tokio::test]
     async fn test_a128cbc_hs256_round_trip() {
         // Test A128CBC-HS256 encryption/decryption round trip
@@ -736,7 +1104,7 @@ mod tests {
         let derived_key_acs = calculate_derived_key(
             &serde_json::to_string(&sdk_public_jwk).unwrap(),
             &acs_keys.private_key,
-            "android",
+            ANDROID_SDK_REFERENCE_NUMBER,
         )
         .expect("Failed to derive key on ACS side");
 
@@ -800,7 +1168,7 @@ mod tests {
         let derived_key_acs = calculate_derived_key(
             &serde_json::to_string(&sdk_public_jwk).unwrap(),
             &acs_keys.private_key,
-            "ios",
+            IOS_SDK_REFERENCE_NUMBER,
         )
         .expect("Failed to derive key on ACS side");
 
@@ -866,14 +1234,14 @@ mod tests {
         let derived_key_1 = calculate_derived_key(
             &serde_json::to_string(&sdk_public_jwk).unwrap(),
             &acs_keys.private_key,
-            "android",
+            ANDROID_SDK_REFERENCE_NUMBER,
         )
         .expect("Failed to derive key 1");
 
         let derived_key_2 = calculate_derived_key(
             &serde_json::to_string(&acs_public_jwk).unwrap(),
             &sdk_keys.private_key,
-            "android",
+            ANDROID_SDK_REFERENCE_NUMBER,
         )
         .expect("Failed to derive key 2");
 
@@ -888,4 +1256,144 @@ mod tests {
 
         println!("  ✅ ECDH consistency test successful!");
     }
+
+    #[tokio::test]
+    async fn test_ecdh_es_key_agreement_and_round_trip() {
+        // RFC 7518 appendix C: Bob's key and Alice's epk agree on this A128GCM key
+        let header = serde_json::json!({
+            "alg": "ECDH-ES",
+            "enc": "A128GCM",
+            "apu": "QWxpY2U",
+            "apv": "Qm9i",
+            "epk": {
+                "kty": "EC",
+                "crv": "P-256",
+                "x": "gI0GAILBdu7T53akrFmMyGcsF3n5dO7MmwNBHKW5SV0",
+                "y": "SLW_xSffzlPWrHEVI30DHM_4egVwt3NQqeUD7nMFpps"
+            }
+        });
+        let key =
+            ecdh_es_content_key(&header, "VEmDZpDXXK8p8N0Cndsxs924q6nS1RXFASRl6BfUqdw").unwrap();
+        assert_eq!(
+            general_purpose::URL_SAFE_NO_PAD.encode(key),
+            "VqqN6vgjbSBcIijNcacQGg"
+        );
+
+        let acs_keys = generate_ephemeral_key_pair().unwrap();
+        let acs_public_jwk = serde_json::to_string(&acs_keys.public_key).unwrap();
+        let creq = serde_json::json!({ "messageType": "CReq", "sdkCounterStoA": "000" });
+        for platform in ["android", "ios"] {
+            let jwe = encrypt_challenge_response_ecdh_es(
+                &creq,
+                "test-acs-trans-id",
+                &acs_public_jwk,
+                platform,
+            )
+            .await
+            .unwrap();
+            let header: serde_json::Value = serde_json::from_slice(
+                &general_purpose::URL_SAFE_NO_PAD
+                    .decode(jwe.split('.').next().unwrap())
+                    .unwrap(),
+            )
+            .unwrap();
+            let key = ecdh_es_content_key(&header, &acs_keys.private_key).unwrap();
+            assert_eq!(decrypt_challenge_request(&jwe, &key).await.unwrap(), creq);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rsa_oaep_256_round_trip() {
+        let rsa_key = RsaPrivateKey::new(&mut OsRng, 2048).unwrap();
+        let creq = serde_json::json!({ "messageType": "CReq", "sdkCounterStoA": "000" });
+
+        for platform in ["android", "ios"] {
+            let jwe = encrypt_challenge_response_rsa(
+                &creq,
+                "test-acs-trans-id",
+                &rsa_key.to_public_key(),
+                platform,
+            )
+            .await
+            .unwrap();
+            assert!(!jwe.split('.').nth(1).unwrap().is_empty());
+
+            // The derived key is ignored for RSA-OAEP-256 and required to be configured
+            let decrypted = decrypt_challenge_request_with_rsa(&jwe, &[0u8; 32], Some(&rsa_key))
+                .await
+                .unwrap();
+            assert_eq!(decrypted, creq);
+            assert!(matches!(
+                decrypt_challenge_request(&jwe, &[0u8; 32]).await,
+                Err(CryptoError::KeyDerivation(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_generated_certificate_signs_acs_content() {
+        let dir = std::env::temp_dir().join(format!("mock-three-ds-certs-{}", std::process::id()));
+        let (cert_path, key_path) = (dir.join("acs-cert.pem"), dir.join("acs-private-key.pem"));
+        generate_self_signed_certificate(&cert_path, &key_path).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&key_path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let keys = generate_ephemeral_key_pair().unwrap();
+        let signed_content = create_acs_signed_content(
+            Uuid::new_v4(),
+            "issuer1",
+            "https://localhost/challenge",
+            &keys,
+            &cert_path,
+            &key_path,
+        )
+        .unwrap();
+        assert_eq!(signed_content.split('.').count(), 3);
+
+        let cert_der = general_purpose::STANDARD
+            .decode(load_certificate(&cert_path).unwrap())
+            .unwrap();
+        let (_, certificate) = x509_parser::parse_x509_certificate(&cert_der).unwrap();
+        assert!(certificate.subject().to_string().contains("CN=localhost"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_key_derivation_trace_records_concat_kdf_inputs() {
+        let sdk_keys = generate_ephemeral_key_pair().unwrap();
+        let acs_keys = generate_ephemeral_key_pair().unwrap();
+        let sdk_public_jwk = serde_json::to_string(&sdk_keys.public_key).unwrap();
+
+        let trace = derive_key_with_trace(
+            &sdk_public_jwk,
+            &acs_keys.private_key,
+            IOS_SDK_REFERENCE_NUMBER,
+        )
+        .unwrap();
+        assert_eq!(trace.sdk_reference_number, IOS_SDK_REFERENCE_NUMBER);
+        assert_eq!(
+            &trace.party_v_info[4..],
+            trace.sdk_reference_number.as_bytes()
+        );
+        assert_eq!(trace.kdf_input[..4], [0, 0, 0, 1]);
+        assert_eq!(trace.kdf_input[4..36], trace.shared_secret[..]);
+        assert_eq!(trace.kdf_input[36..], trace.other_info[..]);
+        assert_eq!(
+            trace.derived_key,
+            calculate_derived_key(
+                &sdk_public_jwk,
+                &acs_keys.private_key,
+                IOS_SDK_REFERENCE_NUMBER
+            )
+            .unwrap()
+        );
+
+        let json = serde_json::to_value(&trace).unwrap();
+        assert_eq!(json["derivedKey"], hex::encode(&trace.derived_key));
+    }
 }
@@ -0,0 +1,157 @@
+//! Mock Directory Server component.
+//!
+//! `/ds/areq` and `/ds/rreq` let a real 3DS Server talk to this mock as its DS. Messages
+//! are routed by `cardholderAccount.schemeId` to a DS profile and forwarded to the ACS
+//! component (in process or over HTTP, per `[components]`), so three-component
+//! topologies can be tested end to end.
+
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::{debug, info};
+use uuid::Uuid;
+
+use crate::acs_client::AcsClient;
+use crate::cache::AppCaches;
+use crate::card_status::CardStatusList;
+use crate::config::Settings;
+use crate::error::AppError;
+use crate::models::ResultsRequest;
+use crate::state_store::StateStore;
+
+/// The AReq elements the DS routes on; full validation is left to the ACS
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AReqRouting {
+    three_ds_server_trans_id: Option<Uuid>,
+    cardholder_account: Option<CardholderRouting>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CardholderRouting {
+    scheme_id: Option<String>,
+}
+
+/// AReq from a 3DS Server: route by scheme and forward to the ACS
+pub async fn ds_areq_handler(
+    body: web::Bytes,
+    acs: web::Data<AcsClient>,
+    state: web::Data<Arc<Box<dyn StateStore>>>,
+    settings: web::Data<Settings>,
+    caches: web::Data<AppCaches>,
+    card_statuses: web::Data<CardStatusList>,
+) -> Result<HttpResponse, AppError> {
+    // A body the DS cannot route still goes to the ACS, which answers it with an Erro
+    if let Ok(routing) = serde_json::from_slice::<AReqRouting>(&body) {
+        let scheme_id = routing
+            .cardholder_account
+            .and_then(|account| account.scheme_id)
+            .unwrap_or_default();
+        let profile = settings.ds.profile_for_scheme(&scheme_id);
+        info!(
+            three_ds_server_trans_id = %routing
+                .three_ds_server_trans_id
+                .map_or_else(|| "unknown".to_string(), |id| id.to_string()),
+            scheme_id = %scheme_id,
+            ds_reference_number = %profile.ds_reference_number,
+            "routing AReq to the ACS"
+        );
+    } else {
+        debug!("unroutable AReq, forwarding it as-is");
+    }
+
+    acs.authenticate(body, state, settings, caches, card_statuses)
+        .await
+}
+
+/// RReq from the ACS: check it against the transaction the DS routed, then forward it
+pub async fn ds_rreq_handler(
+    req: web::Json<ResultsRequest>,
+    acs: web::Data<AcsClient>,
+    state: web::Data<Arc<Box<dyn StateStore>>>,
+) -> Result<HttpResponse, AppError> {
+    let three_ds_server_trans_id = req.three_ds_server_trans_id;
+    info!(%three_ds_server_trans_id, "routing RReq");
+
+    let transaction_data = state
+        .get(&three_ds_server_trans_id)
+        .await?
+        .ok_or_else(|| AppError::not_found("RReq", three_ds_server_trans_id))?;
+
+    // The DS only relays results for the dsTransID/acsTransID pair it assigned
+    if req.ds_trans_id != transaction_data.ds_trans_id
+        || req.acs_trans_id != transaction_data.acs_trans_id
+    {
+        return Err(AppError::unexpected(
+            "RReq",
+            three_ds_server_trans_id,
+            "dsTransID/acsTransID do not match the authenticated transaction",
+        ));
+    }
+
+    acs.results(req, state).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::test_settings;
+    use crate::handlers::tests::{json_body, sample_areq, TestState};
+    use actix_web::ResponseError;
+    use serde_json::json;
+
+    #[actix_web::test]
+    async fn test_mock_ds_relays_areq_and_rreq_to_the_acs() {
+        let app = TestState::new(test_settings());
+
+        // The sample card is a challenge card
+        let areq = serde_json::to_vec(&sample_areq()).unwrap();
+        let response = ds_areq_handler(
+            areq.into(),
+            app.acs.clone(),
+            app.state.clone(),
+            app.settings.clone(),
+            app.caches.clone(),
+            app.card_statuses.clone(),
+        )
+        .await
+        .unwrap();
+        let ares = json_body(response).await;
+        assert_eq!(ares["transStatus"], "C");
+
+        let mut rreq = json!({
+            "acsTransId": ares["authenticationResponse"]["acsTransId"],
+            "messageCategory": "01",
+            "eci": "07",
+            "messageType": "RReq",
+            "acsRenderingType": { "acsUiTemplate": "01", "acsInterface": "01" },
+            "dsTransId": Uuid::new_v4(),
+            "authenticationMethod": "02",
+            "authenticationType": "02",
+            "messageVersion": "2.2.0",
+            "interactionCounter": "01",
+            "transStatus": "N",
+            "threeDsServerTransId": ares["threeDsServerTransId"]
+        });
+        // The DS only relays results for the dsTransID it assigned
+        let rejected = serde_json::from_value(rreq.clone()).unwrap();
+        let error = ds_rreq_handler(web::Json(rejected), app.acs.clone(), app.state.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.status_code(),
+            actix_web::http::StatusCode::BAD_REQUEST
+        );
+        assert_eq!(error.to_error_message().error_code, "101");
+
+        rreq["dsTransId"] = ares["authenticationResponse"]["dsTransId"].clone();
+        let relayed = serde_json::from_value(rreq).unwrap();
+        let response = ds_rreq_handler(web::Json(relayed), app.acs.clone(), app.state.clone())
+            .await
+            .unwrap();
+        let rres = json_body(response).await;
+        assert_eq!(rres["messageType"], "RRes");
+        assert_eq!(rres["resultsStatus"], "01");
+    }
+}
@@ -0,0 +1,633 @@
+use actix_web::{
+    dev::ServiceResponse,
+    error::{JsonPayloadError, QueryPayloadError, UrlencodedError},
+    http::{header, StatusCode},
+    middleware::ErrorHandlerResponse,
+    web, HttpRequest, HttpResponse, ResponseError,
+};
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use crate::config::Settings;
+use crate::crypto::CryptoError;
+use crate::jwe::JweDiagnostics;
+use crate::models::ErrorMessage;
+use crate::state_store::StateError;
+use crate::validation::FieldError;
+
+/// Handler failure, rendered to the client as a 3DS Erro message
+#[derive(Debug, thiserror::Error)]
+pub enum AppError {
+    /// The received message could not be parsed as the expected 3DS message
+    #[error("invalid {message_type}: {detail}")]
+    InvalidMessage {
+        message_type: &'static str,
+        detail: String,
+    },
+
+    /// A data element of the message is missing or malformed
+    #[error("{message_type} validation failed - {}: {}", .error.field, .error.description)]
+    Validation {
+        message_type: &'static str,
+        three_ds_server_trans_id: Option<Uuid>,
+        error: FieldError,
+    },
+
+    /// The CReq JWE could not be parsed; the diagnostics are returned alongside the Erro
+    #[error("malformed CReq JWE - {}", .0.summary())]
+    MalformedJwe(Box<JweDiagnostics>),
+
+    /// The message references a transaction this ACS does not know
+    #[error("{message_type} references unknown transaction {trans_id}")]
+    TransactionNotFound {
+        message_type: &'static str,
+        trans_id: Uuid,
+    },
+
+    /// The transaction exists but is not in a state that accepts this message
+    #[error("{message_type} not accepted for transaction {three_ds_server_trans_id}: {detail}")]
+    UnexpectedMessage {
+        message_type: &'static str,
+        three_ds_server_trans_id: Uuid,
+        detail: String,
+    },
+
+    /// Key derivation, JWE or signing failure while handling the message
+    #[error("{message_type} crypto failure: {source}")]
+    Crypto {
+        message_type: &'static str,
+        three_ds_server_trans_id: Uuid,
+        acs_trans_id: Option<Uuid>,
+        #[source]
+        source: CryptoError,
+    },
+
+    /// The request body is larger than the route's configured payload limit
+    #[error("{message_type} body exceeds the {limit} byte limit")]
+    PayloadTooLarge {
+        message_type: &'static str,
+        limit: usize,
+    },
+
+    /// The client exceeded the configured request rate
+    #[error("{message_type} rate limited - retry after {retry_after_seconds}s")]
+    RateLimited {
+        message_type: &'static str,
+        limit: u32,
+        retry_after_seconds: u64,
+    },
+
+    /// The route requires a client certificate and none, or one with a subject not on the
+    /// allowlist, was presented
+    #[error("{message_type} refused: {detail}")]
+    ClientCertificate {
+        message_type: &'static str,
+        detail: String,
+    },
+
+    /// The ACS component could not be reached from the 3DS Server component
+    #[error("{message_type} could not be forwarded to the ACS: {detail}")]
+    AcsUnreachable {
+        message_type: &'static str,
+        detail: String,
+        timed_out: bool,
+    },
+
+    /// Transaction state could not be read or written
+    #[error("state store failure: {0}")]
+    State(#[from] StateError),
+
+    /// A response could not be serialized
+    #[error("serialization failure: {0}")]
+    Serialization(#[from] serde_json::Error),
+}
+
+impl AppError {
+    pub fn invalid_message(message_type: &'static str, detail: impl Into<String>) -> Self {
+        Self::InvalidMessage {
+            message_type,
+            detail: detail.into(),
+        }
+    }
+
+    pub fn validation(
+        message_type: &'static str,
+        three_ds_server_trans_id: Uuid,
+        error: FieldError,
+    ) -> Self {
+        Self::Validation {
+            message_type,
+            three_ds_server_trans_id: Some(three_ds_server_trans_id),
+            error,
+        }
+    }
+
+    pub fn not_found(message_type: &'static str, trans_id: Uuid) -> Self {
+        Self::TransactionNotFound {
+            message_type,
+            trans_id,
+        }
+    }
+
+    pub fn unexpected(
+        message_type: &'static str,
+        three_ds_server_trans_id: Uuid,
+        detail: impl Into<String>,
+    ) -> Self {
+        Self::UnexpectedMessage {
+            message_type,
+            three_ds_server_trans_id,
+            detail: detail.into(),
+        }
+    }
+
+    /// A field-level failure found while parsing the body, before the transaction ID is known
+    pub fn rejected_field(message_type: &'static str, error: FieldError) -> Self {
+        Self::Validation {
+            message_type,
+            three_ds_server_trans_id: None,
+            error,
+        }
+    }
+
+    /// A body that failed to deserialize, reported against the offending field where possible
+    pub fn from_json(message_type: &'static str, error: &serde_json::Error) -> Self {
+        match FieldError::from_json_error(error) {
+            Some(field_error) => Self::rejected_field(message_type, field_error),
+            None => Self::invalid_message(message_type, error.to_string()),
+        }
+    }
+
+    /// A body that failed to deserialize at a tracked element path
+    pub fn from_json_path(
+        message_type: &'static str,
+        error: &serde_path_to_error::Error<serde_json::Error>,
+    ) -> Self {
+        match FieldError::from_json_path_error(error) {
+            Some(field_error) => Self::rejected_field(message_type, field_error),
+            None => Self::invalid_message(message_type, error.inner().to_string()),
+        }
+    }
+
+    pub fn crypto(
+        message_type: &'static str,
+        three_ds_server_trans_id: Uuid,
+        source: CryptoError,
+    ) -> Self {
+        Self::Crypto {
+            message_type,
+            three_ds_server_trans_id,
+            acs_trans_id: None,
+            source,
+        }
+    }
+
+    /// Attach the acsTransID to a crypto failure raised after the transaction was identified
+    pub fn with_acs_trans_id(mut self, id: Uuid) -> Self {
+        if let Self::Crypto { acs_trans_id, .. } = &mut self {
+            *acs_trans_id = Some(id);
+        }
+        self
+    }
+
+    /// The spec Erro message describing this failure
+    pub fn to_error_message(&self) -> ErrorMessage {
+        match self {
+            Self::InvalidMessage {
+                message_type,
+                detail,
+            } => {
+                let mut erro = ErrorMessage::new("101", "Message received invalid", detail);
+                erro.error_message_type = Some(message_type.to_string());
+                erro
+            }
+            Self::Validation {
+                message_type,
+                three_ds_server_trans_id,
+                error,
+            } => match three_ds_server_trans_id {
+                Some(trans_id) => error.to_error_message(message_type, *trans_id),
+                // Rejected while extracting the body, before the transaction ID was read
+                None => {
+                    let mut erro =
+                        ErrorMessage::new(error.error_code, &error.description, &error.field);
+                    erro.error_message_type = Some(message_type.to_string());
+                    erro
+                }
+            },
+            Self::MalformedJwe(diagnostics) => {
+                let mut erro =
+                    ErrorMessage::new("101", "Message received invalid", &diagnostics.summary());
+                erro.error_message_type = Some("CReq".to_string());
+                erro
+            }
+            Self::TransactionNotFound {
+                message_type,
+                trans_id,
+            } => ErrorMessage::new(
+                "301",
+                "Transaction ID not recognized",
+                &format!("No transaction found for {}", trans_id),
+            )
+            .for_message(message_type, *trans_id),
+            Self::UnexpectedMessage {
+                message_type,
+                three_ds_server_trans_id,
+                detail,
+            } => ErrorMessage::new("101", "Message received invalid", detail)
+                .for_message(message_type, *three_ds_server_trans_id),
+            Self::Crypto {
+                message_type,
+                three_ds_server_trans_id,
+                acs_trans_id,
+                source,
+            } => {
+                let (error_code, error_description) = match source {
+                    CryptoError::JweFormat(_) => ("101", "Message received invalid"),
+                    CryptoError::KeyDerivation(_) => {
+                        ("203", "Format of one or more data elements is invalid")
+                    }
+                    CryptoError::HmacMismatch | CryptoError::Padding(_) => {
+                        ("302", "Data decryption failure")
+                    }
+                    CryptoError::Signing(_) | CryptoError::Encryption(_) => {
+                        ("403", "Transient system failure")
+                    }
+                    CryptoError::CertLoad(_) => ("404", "Permanent system failure"),
+                };
+                let mut erro =
+                    ErrorMessage::new(error_code, error_description, &source.to_string())
+                        .for_message(message_type, *three_ds_server_trans_id);
+                erro.acs_trans_id = *acs_trans_id;
+                erro
+            }
+            Self::PayloadTooLarge {
+                message_type,
+                limit,
+            } => {
+                let mut erro = ErrorMessage::new(
+                    "101",
+                    "Message received invalid",
+                    &format!("Message exceeds the {} byte limit", limit),
+                );
+                erro.error_message_type = Some(message_type.to_string());
+                erro
+            }
+            Self::RateLimited {
+                message_type,
+                limit,
+                retry_after_seconds,
+            } => {
+                let mut erro = ErrorMessage::new(
+                    "403",
+                    "Transient system failure",
+                    &format!(
+                        "Rate limit of {} requests exceeded, retry after {}s",
+                        limit, retry_after_seconds
+                    ),
+                );
+                erro.error_message_type = Some(message_type.to_string());
+                erro
+            }
+            Self::ClientCertificate {
+                message_type,
+                detail,
+            } => {
+                let mut erro = ErrorMessage::new("303", "Access denied, invalid endpoint", detail);
+                erro.error_message_type = Some(message_type.to_string());
+                erro
+            }
+            Self::AcsUnreachable {
+                message_type,
+                detail,
+                ..
+            } => {
+                let mut erro = ErrorMessage::new("403", "Transient system failure", detail);
+                erro.error_message_type = Some(message_type.to_string());
+                erro
+            }
+            Self::State(StateError::NotFound) => ErrorMessage::new(
+                "301",
+                "Transaction ID not recognized",
+                "Transaction not found",
+            ),
+            Self::State(e) => ErrorMessage::new("403", "Transient system failure", &e.to_string()),
+            Self::Serialization(e) => {
+                ErrorMessage::new("404", "Permanent system failure", &e.to_string())
+            }
+        }
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::TransactionNotFound { .. } | Self::State(StateError::NotFound) => {
+                StatusCode::NOT_FOUND
+            }
+            Self::PayloadTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            Self::ClientCertificate { .. } => StatusCode::FORBIDDEN,
+            Self::AcsUnreachable {
+                timed_out: true, ..
+            } => StatusCode::GATEWAY_TIMEOUT,
+            Self::AcsUnreachable { .. } => StatusCode::BAD_GATEWAY,
+            // ACS-side system failures, as opposed to problems with the received message
+            Self::Crypto {
+                source:
+                    CryptoError::Signing(_) | CryptoError::Encryption(_) | CryptoError::CertLoad(_),
+                ..
+            }
+            | Self::State(_)
+            | Self::Serialization(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        if status.is_server_error() {
+            error!(error = %self, status = status.as_u16(), "request failed");
+        } else {
+            warn!(error = %self, status = status.as_u16(), "request rejected");
+        }
+        let mut response = HttpResponse::build(status);
+        if let Self::RateLimited {
+            limit,
+            retry_after_seconds,
+            ..
+        } = self
+        {
+            response
+                .insert_header(("X-RateLimit-Limit", limit.to_string()))
+                .insert_header(("X-RateLimit-Remaining", "0"))
+                .insert_header((header::RETRY_AFTER, retry_after_seconds.to_string()));
+        }
+        if let Self::MalformedJwe(diagnostics) = self {
+            return response.json(MalformedJweErro {
+                erro: self.to_error_message(),
+                jwe_diagnostics: diagnostics,
+            });
+        }
+        response.json(self.to_error_message())
+    }
+}
+
+/// Erro for a malformed CReq JWE, extended with what was wrong with the token
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MalformedJweErro<'a> {
+    #[serde(flatten)]
+    erro: ErrorMessage,
+    jwe_diagnostics: &'a JweDiagnostics,
+}
+
+/// The 3DS message type received on a route, for errors raised before a handler runs
+fn message_type_for_path(path: &str) -> &'static str {
+    match path {
+        "/3ds/authenticate" | "/ds/areq" | "/acs/areq" => "AReq",
+        "/3ds/results" | "/ds/rreq" | "/acs/rreq" => "RReq",
+        "/3ds/version" | "/3ds/preq" => "PReq",
+        "/3ds/method" | "/acs/3ds-method" | "/acs/3ds-method/device-data" => "3DSMethod",
+        "/challenge"
+        | "/acs/creq"
+        | "/processor/mock/acs/trigger-otp"
+        | "/processor/mock/acs/verify-otp"
+        | "/processor/mock/acs/resend-otp" => "CReq",
+        _ => "Unknown",
+    }
+}
+
+/// The request's route as declared in code, with any configured `[routes]` prefix removed
+fn route_path(req: &HttpRequest) -> &str {
+    match req.app_data::<web::Data<Settings>>() {
+        Some(settings) => settings.routes.unprefixed(req.path()),
+        None => req.path(),
+    }
+}
+
+/// JSON extractor error handler, so malformed request bodies are answered with an Erro too.
+/// Missing elements and rejected protocol codes are reported against the offending field.
+pub fn json_error_handler(err: JsonPayloadError, req: &HttpRequest) -> actix_web::Error {
+    let message_type = message_type_for_path(route_path(req));
+    match &err {
+        JsonPayloadError::Deserialize(e) => AppError::from_json(message_type, e).into(),
+        // Left as a 413 for payload_too_large_handler, which covers every extractor
+        JsonPayloadError::Overflow { .. } | JsonPayloadError::OverflowKnownLength { .. } => {
+            err.into()
+        }
+        _ => AppError::invalid_message(message_type, err.to_string()).into(),
+    }
+}
+
+/// Form extractor error handler answering an undecodable form post as an Erro
+pub fn form_error_handler(err: UrlencodedError, req: &HttpRequest) -> actix_web::Error {
+    let message_type = message_type_for_path(route_path(req));
+    match &err {
+        UrlencodedError::Parse(e) => from_serde_error(message_type, &e.to_string()).into(),
+        UrlencodedError::Overflow { .. } => err.into(),
+        _ => AppError::invalid_message(message_type, err.to_string()).into(),
+    }
+}
+
+/// Query extractor error handler answering bad query parameters as an Erro
+pub fn query_error_handler(err: QueryPayloadError, req: &HttpRequest) -> actix_web::Error {
+    let message_type = message_type_for_path(route_path(req));
+    match &err {
+        QueryPayloadError::Deserialize(e) => from_serde_error(message_type, &e.to_string()).into(),
+        _ => AppError::invalid_message(message_type, err.to_string()).into(),
+    }
+}
+
+fn from_serde_error(message_type: &'static str, message: &str) -> AppError {
+    match FieldError::from_serde_message(None, message) {
+        Some(field_error) => AppError::rejected_field(message_type, field_error),
+        None => AppError::invalid_message(message_type, message),
+    }
+}
+
+/// Error handler middleware hook rewriting extractor 413 responses (JSON, form and raw
+/// body routes alike) as an Erro naming the route's configured payload limit
+pub fn payload_too_large_handler<B>(
+    res: ServiceResponse<B>,
+) -> actix_web::Result<ErrorHandlerResponse<B>> {
+    let (req, _) = res.into_parts();
+    let path = route_path(&req);
+    let limit = req
+        .app_data::<web::Data<Settings>>()
+        .and_then(|settings| settings.payload_limits.limit_for_path(path))
+        .unwrap_or_default();
+
+    let response = AppError::PayloadTooLarge {
+        message_type: message_type_for_path(path),
+        limit,
+    }
+    .error_response();
+
+    Ok(ErrorHandlerResponse::Response(
+        ServiceResponse::new(req, response).map_into_right_body(),
+    ))
+}
+
+/// Erro for a rate limited request, with X-RateLimit-Limit, X-RateLimit-Remaining and
+/// Retry-After headers
+pub fn rate_limited_response(
+    req: &HttpRequest,
+    limit: u32,
+    retry_after_seconds: u64,
+) -> HttpResponse {
+    AppError::RateLimited {
+        message_type: message_type_for_path(route_path(req)),
+        limit,
+        retry_after_seconds,
+    }
+    .error_response()
+}
+
+/// Erro (303, HTTP 403) for a request refused for its client certificate
+pub fn client_certificate_response(req: &HttpRequest, detail: String) -> HttpResponse {
+    AppError::ClientCertificate {
+        message_type: message_type_for_path(route_path(req)),
+        detail,
+    }
+    .error_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_follows_error_code() {
+        let trans_id = Uuid::new_v4();
+
+        let invalid = AppError::invalid_message("CReq", "Invalid JWE format");
+        assert_eq!(invalid.status_code(), StatusCode::BAD_REQUEST);
+        assert_eq!(invalid.to_error_message().error_code, "101");
+
+        let missing = AppError::not_found("RReq", trans_id);
+        assert_eq!(missing.status_code(), StatusCode::NOT_FOUND);
+        assert_eq!(missing.to_error_message().error_code, "301");
+
+        let decrypt = AppError::crypto("CReq", trans_id, CryptoError::HmacMismatch);
+        assert_eq!(decrypt.status_code(), StatusCode::BAD_REQUEST);
+        assert_eq!(decrypt.to_error_message().error_code, "302");
+
+        let too_large = AppError::PayloadTooLarge {
+            message_type: "CReq",
+            limit: 16384,
+        };
+        assert_eq!(too_large.status_code(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(too_large.to_error_message().error_code, "101");
+
+        let state = AppError::from(StateError::Connection("refused".to_string()));
+        assert_eq!(state.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(state.to_error_message().error_code, "403");
+    }
+
+    #[test]
+    fn test_crypto_erro_carries_transaction_ids() {
+        let trans_id = Uuid::new_v4();
+        let acs_trans_id = Uuid::new_v4();
+
+        let erro = AppError::crypto(
+            "CReq",
+            trans_id,
+            CryptoError::CertLoad("missing cert".to_string()),
+        )
+        .with_acs_trans_id(acs_trans_id)
+        .to_error_message();
+
+        assert_eq!(erro.message_type, "Erro");
+        assert_eq!(erro.error_code, "404");
+        assert_eq!(erro.error_message_type.as_deref(), Some("CReq"));
+        assert_eq!(erro.three_ds_server_trans_id, Some(trans_id));
+        assert_eq!(erro.acs_trans_id, Some(acs_trans_id));
+    }
+
+    #[actix_web::test]
+    async fn test_oversized_bodies_are_answered_with_an_erro() {
+        use actix_web::{middleware::ErrorHandlers, test, App};
+        use serde_json::{json, Value};
+
+        let mut settings = crate::config::tests::test_settings();
+        settings.payload_limits.version_bytes = 64;
+        settings.payload_limits.authenticate_bytes = 128;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(settings))
+                .wrap(
+                    ErrorHandlers::new()
+                        .handler(StatusCode::PAYLOAD_TOO_LARGE, payload_too_large_handler),
+                )
+                .service(
+                    web::resource("/3ds/version")
+                        .app_data(web::JsonConfig::default().limit(64))
+                        .route(web::post().to(|_: web::Json<Value>| HttpResponse::Ok())),
+                )
+                .service(
+                    web::resource("/3ds/authenticate")
+                        .app_data(web::PayloadConfig::new(128))
+                        .route(web::post().to(|_: web::Bytes| HttpResponse::Ok())),
+                ),
+        )
+        .await;
+
+        // JSON and raw-body routes alike, each with its own configured limit
+        for (path, message_type, limit) in [
+            ("/3ds/version", "PReq", 64),
+            ("/3ds/authenticate", "AReq", 128),
+        ] {
+            let req = test::TestRequest::post()
+                .uri(path)
+                .set_json(json!({ "padding": "x".repeat(200) }))
+                .to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+            let erro: Value = test::read_body_json(res).await;
+            assert_eq!(erro["errorCode"], "101");
+            assert_eq!(erro["errorMessageType"], message_type);
+            assert_eq!(
+                erro["errorDetail"],
+                format!("Message exceeds the {} byte limit", limit)
+            );
+        }
+
+        let req = test::TestRequest::post()
+            .uri("/3ds/version")
+            .set_json(json!({ "cardNumber": "4000000000001000" }))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    #[test]
+    fn test_form_error_names_missing_field() {
+        let req = actix_web::test::TestRequest::post()
+            .uri("/processor/mock/acs/verify-otp")
+            .to_http_request();
+        // serde_urlencoded reports parse failures as serde's own value error
+        let parse_error = <serde::de::value::Error as serde::de::Error>::missing_field("otp");
+
+        let error = form_error_handler(UrlencodedError::Parse(parse_error), &req);
+        let response = error.error_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let app_error = error.as_error::<AppError>().expect("Erro");
+        let erro = app_error.to_error_message();
+        assert_eq!(erro.error_code, "201");
+        assert_eq!(erro.error_detail, "otp");
+        assert_eq!(erro.error_message_type.as_deref(), Some("CReq"));
+    }
+
+    #[test]
+    fn test_rate_limited_response_carries_backoff_headers() {
+        let req = actix_web::test::TestRequest::post()
+            .uri("/3ds/authenticate")
+            .to_http_request();
+        let response = rate_limited_response(&req, 20, 3);
+        let headers = response.headers();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(headers.get(header::RETRY_AFTER).unwrap(), "3");
+        assert_eq!(headers.get("X-RateLimit-Remaining").unwrap(), "0");
+        assert_eq!(headers.get("X-RateLimit-Limit").unwrap(), "20");
+    }
+}
@@ -0,0 +1,526 @@
+//! Fault injection: per-route chances of a 500, a dropped connection, a malformed JSON body
+//! or a state store error, so integrators can test how their side copes. Profiles come from
+//! `[faults.profiles]` and can be switched or replaced through the admin API without a
+//! restart.
+
+use actix_web::body::{self, BoxBody, MessageBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::error::ErrorInternalServerError;
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use async_trait::async_trait;
+use deadpool_redis::redis::{ErrorKind, RedisError};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::config::{FaultsConfig, API_VERSION};
+use crate::models::{ErrorMessage, MethodDeviceData};
+use crate::recording::RecordedMessage;
+use crate::state_store::{StateError, StateStore, TransactionData};
+
+/// Routes under this path are never faulted, so a profile can always be switched off
+pub const FAULTS_ADMIN_PATH: &str = "/admin/faults";
+
+/// Fault probabilities for the routes under one path prefix
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct FaultRule {
+    /// Path prefix, without the /v1 version segment; empty matches every route
+    pub route: String,
+    /// Answer with HTTP 500 without running the handler
+    pub server_error: f64,
+    /// Close the connection without a complete response
+    pub drop_connection: f64,
+    /// Run the handler, then cut its body short so it no longer parses
+    pub malformed_json: f64,
+    /// Fail the handler's state store reads and writes as if Redis were down
+    pub state_error: f64,
+}
+
+impl FaultRule {
+    fn probabilities(&self) -> [f64; 4] {
+        [
+            self.server_error,
+            self.drop_connection,
+            self.malformed_json,
+            self.state_error,
+        ]
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let probabilities = self.probabilities();
+        if probabilities.iter().any(|p| !(0.0..=1.0).contains(p)) {
+            return Err(format!(
+                "fault probabilities for route {:?} must be between 0 and 1",
+                self.route
+            ));
+        }
+        if probabilities.iter().sum::<f64>() > 1.0 {
+            return Err(format!(
+                "fault probabilities for route {:?} add up to more than 1",
+                self.route
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A named set of rules; the longest matching route wins
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct FaultProfile {
+    pub rules: Vec<FaultRule>,
+}
+
+impl FaultProfile {
+    pub fn validate(&self) -> Result<(), String> {
+        self.rules.iter().try_for_each(FaultRule::validate)
+    }
+
+    fn rule_for(&self, path: &str) -> Option<&FaultRule> {
+        let path = path
+            .strip_prefix(&format!("/{}", API_VERSION))
+            .filter(|rest| rest.starts_with('/'))
+            .unwrap_or(path);
+        self.rules
+            .iter()
+            .filter(|rule| path.starts_with(rule.route.as_str()))
+            .max_by_key(|rule| rule.route.len())
+    }
+}
+
+/// What happens to a faulted request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    ServerError,
+    DropConnection,
+    MalformedJson,
+    StateError,
+}
+
+impl Fault {
+    /// Whether the fault answers without running the handler
+    pub fn short_circuits(&self) -> bool {
+        matches!(self, Fault::ServerError | Fault::DropConnection)
+    }
+
+    // Pick at most one fault, with `roll` uniform in [0, 1)
+    fn pick(rule: &FaultRule, roll: f64) -> Option<Self> {
+        let faults = [
+            Fault::ServerError,
+            Fault::DropConnection,
+            Fault::MalformedJson,
+            Fault::StateError,
+        ];
+        let mut threshold = 0.0;
+        for (fault, probability) in faults.into_iter().zip(rule.probabilities()) {
+            threshold += probability;
+            if roll < threshold {
+                return Some(fault);
+            }
+        }
+        None
+    }
+}
+
+struct FaultState {
+    profiles: HashMap<String, FaultProfile>,
+    active: Option<String>,
+}
+
+/// Fault profiles and the active one, shared by all listeners. Changes made through the
+/// admin API are held in this process only.
+pub struct FaultInjector {
+    enabled: bool,
+    state: RwLock<FaultState>,
+}
+
+impl FaultInjector {
+    pub fn new(config: &FaultsConfig) -> Self {
+        Self {
+            enabled: config.enabled,
+            state: RwLock::new(FaultState {
+                profiles: config.profiles.clone(),
+                active: config.active_profile.clone(),
+            }),
+        }
+    }
+
+    /// The fault, if any, to inject into a request for `path`
+    pub fn decide(&self, path: &str) -> Option<Fault> {
+        if !self.enabled || path.contains(FAULTS_ADMIN_PATH) {
+            return None;
+        }
+        let state = self.state.read().expect("fault state lock poisoned");
+        let profile = state.profiles.get(state.active.as_deref()?)?;
+        let rule = profile.rule_for(path)?;
+        // 53 random bits, the precision of an f64 mantissa
+        let roll = (OsRng.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        Fault::pick(rule, roll)
+    }
+
+    pub fn active(&self) -> Option<String> {
+        self.state
+            .read()
+            .expect("fault state lock poisoned")
+            .active
+            .clone()
+    }
+
+    /// Make a profile the active one, returning whether it exists
+    pub fn activate(&self, name: &str) -> bool {
+        let mut state = self.state.write().expect("fault state lock poisoned");
+        if !state.profiles.contains_key(name) {
+            return false;
+        }
+        state.active = Some(name.to_string());
+        true
+    }
+
+    pub fn deactivate(&self) {
+        self.state
+            .write()
+            .expect("fault state lock poisoned")
+            .active = None;
+    }
+
+    /// Add or replace a profile; replacing the active one takes effect immediately
+    pub fn set_profile(&self, name: String, profile: FaultProfile) {
+        self.state
+            .write()
+            .expect("fault state lock poisoned")
+            .profiles
+            .insert(name, profile);
+    }
+
+    /// All profiles, ordered by name
+    pub fn profiles(&self) -> BTreeMap<String, FaultProfile> {
+        self.state
+            .read()
+            .expect("fault state lock poisoned")
+            .profiles
+            .iter()
+            .map(|(name, profile)| (name.clone(), profile.clone()))
+            .collect()
+    }
+}
+
+tokio::task_local! {
+    // Set while a request picked for a state store fault is being handled
+    static STATE_FAULT: ();
+}
+
+/// The fault middleware, for `App::wrap_fn`: pick the request's fault and apply it around
+/// the rest of the service chain
+pub fn inject<S, B>(
+    injector: &FaultInjector,
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<BoxBody>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody + 'static,
+{
+    let fault = injector.decide(req.path());
+    let path = req.path().to_string();
+    // Routing needs sole ownership of the request, so it is only taken back when the chain
+    // does not run
+    let next = match fault {
+        Some(fault) if fault.short_circuits() => Err(req.into_parts().0),
+        _ => Ok(srv.call(req)),
+    };
+    apply(fault, path, next)
+}
+
+/// Apply a request's fault around the rest of the service chain. `next` is the chain's
+/// response, or the request itself for faults that answer without running the chain.
+async fn apply<B, F>(
+    fault: Option<Fault>,
+    path: String,
+    next: Result<F, HttpRequest>,
+) -> Result<ServiceResponse<BoxBody>, Error>
+where
+    B: MessageBody + 'static,
+    F: Future<Output = Result<ServiceResponse<B>, Error>>,
+{
+    let response = match next {
+        Ok(response) => response,
+        Err(req) => {
+            let fault = fault.unwrap_or(Fault::ServerError);
+            warn!(?fault, %path, "injected fault");
+            return Ok(ServiceResponse::new(req, short_circuit_response(fault)));
+        }
+    };
+
+    match fault {
+        Some(Fault::StateError) => {
+            warn!(fault = ?Fault::StateError, %path, "injected fault");
+            Ok(STATE_FAULT.scope((), response).await?.map_into_boxed_body())
+        }
+        Some(Fault::MalformedJson) => {
+            warn!(fault = ?Fault::MalformedJson, %path, "injected fault");
+            malformed(response.await?).await
+        }
+        _ => Ok(response.await?.map_into_boxed_body()),
+    }
+}
+
+fn short_circuit_response(fault: Fault) -> HttpResponse {
+    match fault {
+        Fault::DropConnection => {
+            // A body that fails before its first chunk makes actix abort the connection
+            HttpResponse::Ok()
+                .content_type("application/json")
+                .streaming(futures_util::stream::once(async {
+                    Err::<web::Bytes, _>(std::io::Error::new(
+                        std::io::ErrorKind::ConnectionAborted,
+                        "injected dropped connection",
+                    ))
+                }))
+        }
+        _ => HttpResponse::InternalServerError().json(ErrorMessage::new(
+            "404",
+            "Permanent system failure",
+            "Injected fault",
+        )),
+    }
+}
+
+// Keep the handler's status and headers, but send only the first half of its body with a
+// stray brace, so JSON parsers fail on it
+async fn malformed<B: MessageBody + 'static>(
+    response: ServiceResponse<B>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let (req, res) = response.into_parts();
+    let (mut res, body) = res.into_parts();
+    let bytes = match body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            let e: Box<dyn std::error::Error> = e.into();
+            return Err(ErrorInternalServerError(e.to_string()));
+        }
+    };
+
+    let mut malformed = bytes[..bytes.len() / 2].to_vec();
+    malformed.push(b'{');
+    res.headers_mut().remove(header::CONTENT_LENGTH);
+    if res.status() == StatusCode::NO_CONTENT {
+        *res.status_mut() = StatusCode::OK;
+    }
+    Ok(ServiceResponse::new(
+        req,
+        res.set_body(malformed).map_into_boxed_body(),
+    ))
+}
+
+/// State store whose calls fail as if Redis were unreachable while a request picked for a
+/// state store fault is being handled
+pub struct FaultInjectingStore {
+    inner: Arc<Box<dyn StateStore>>,
+}
+
+impl FaultInjectingStore {
+    pub fn new(inner: Arc<Box<dyn StateStore>>) -> Self {
+        Self { inner }
+    }
+
+    fn check(&self) -> Result<(), StateError> {
+        if STATE_FAULT.try_with(|_| ()).is_ok() {
+            return Err(StateError::Redis(RedisError::from((
+                ErrorKind::IoError,
+                "injected state store fault",
+            ))));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StateStore for FaultInjectingStore {
+    async fn insert(&self, key: Uuid, data: TransactionData) -> Result<(), StateError> {
+        self.check()?;
+        self.inner.insert(key, data).await
+    }
+
+    async fn get(&self, key: &Uuid) -> Result<Option<TransactionData>, StateError> {
+        self.check()?;
+        self.inner.get(key).await
+    }
+
+    async fn update(&self, key: &Uuid, data: TransactionData) -> Result<(), StateError> {
+        self.check()?;
+        self.inner.update(key, data).await
+    }
+
+    async fn delete(&self, key: &Uuid) -> Result<(), StateError> {
+        self.check()?;
+        self.inner.delete(key).await
+    }
+
+    async fn find_by_acs_trans_id(
+        &self,
+        acs_trans_id: &Uuid,
+    ) -> Result<Option<(Uuid, TransactionData)>, StateError> {
+        self.check()?;
+        self.inner.find_by_acs_trans_id(acs_trans_id).await
+    }
+
+    async fn insert_method_data(
+        &self,
+        key: Uuid,
+        data: MethodDeviceData,
+    ) -> Result<(), StateError> {
+        self.check()?;
+        self.inner.insert_method_data(key, data).await
+    }
+
+    async fn get_method_data(&self, key: &Uuid) -> Result<Option<MethodDeviceData>, StateError> {
+        self.check()?;
+        self.inner.get_method_data(key).await
+    }
+
+    async fn mark_method_completed(&self, key: Uuid) -> Result<(), StateError> {
+        self.check()?;
+        self.inner.mark_method_completed(key).await
+    }
+
+    async fn method_completed(&self, key: &Uuid) -> Result<bool, StateError> {
+        self.check()?;
+        self.inner.method_completed(key).await
+    }
+
+    async fn append_message(&self, key: Uuid, message: RecordedMessage) -> Result<(), StateError> {
+        self.check()?;
+        self.inner.append_message(key, message).await
+    }
+
+    async fn messages(&self, key: &Uuid) -> Result<Vec<RecordedMessage>, StateError> {
+        self.check()?;
+        self.inner.messages(key).await
+    }
+
+    fn backend(&self) -> &'static str {
+        self.inner.backend()
+    }
+
+    async fn ping(&self) -> Result<(), StateError> {
+        self.check()?;
+        self.inner.ping().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(route: &str, server_error: f64, malformed_json: f64) -> FaultRule {
+        FaultRule {
+            route: route.to_string(),
+            server_error,
+            malformed_json,
+            ..FaultRule::default()
+        }
+    }
+
+    #[test]
+    fn test_longest_route_wins_and_rolls_pick_faults() {
+        let profile = FaultProfile {
+            rules: vec![rule("", 0.5, 0.0), rule("/3ds/authenticate", 0.0, 0.25)],
+        };
+        assert!(profile.validate().is_ok());
+
+        let authenticate = profile.rule_for("/v1/3ds/authenticate").unwrap();
+        assert_eq!(authenticate.route, "/3ds/authenticate");
+        assert_eq!(Fault::pick(authenticate, 0.2), Some(Fault::MalformedJson));
+        assert_eq!(Fault::pick(authenticate, 0.3), None);
+
+        let other = profile.rule_for("/3ds/final").unwrap();
+        assert_eq!(Fault::pick(other, 0.4), Some(Fault::ServerError));
+        assert_eq!(Fault::pick(other, 0.6), None);
+
+        assert!(rule("", 0.8, 0.4).validate().is_err());
+        assert!(rule("", -0.1, 0.0).validate().is_err());
+    }
+
+    #[test]
+    fn test_injector_needs_an_active_profile_and_spares_its_admin_routes() {
+        let mut config = FaultsConfig {
+            enabled: true,
+            ..FaultsConfig::default()
+        };
+        config.profiles.insert(
+            "outage".to_string(),
+            FaultProfile {
+                rules: vec![rule("", 1.0, 0.0)],
+            },
+        );
+        let injector = FaultInjector::new(&config);
+        assert_eq!(injector.decide("/3ds/final"), None);
+
+        assert!(!injector.activate("missing"));
+        assert!(injector.activate("outage"));
+        assert_eq!(injector.decide("/3ds/final"), Some(Fault::ServerError));
+        assert_eq!(injector.decide("/v1/admin/faults/active"), None);
+
+        injector.deactivate();
+        assert_eq!(injector.decide("/3ds/final"), None);
+    }
+
+    #[actix_web::test]
+    async fn test_middleware_injects_the_active_profiles_faults() {
+        use actix_web::{test, App};
+
+        let mut config = FaultsConfig {
+            enabled: true,
+            ..FaultsConfig::default()
+        };
+        config.profiles.insert(
+            "outage".to_string(),
+            FaultProfile {
+                rules: vec![rule("", 1.0, 0.0)],
+            },
+        );
+        config.profiles.insert(
+            "garbled".to_string(),
+            FaultProfile {
+                rules: vec![rule("", 0.0, 1.0)],
+            },
+        );
+        let injector = Arc::new(FaultInjector::new(&config));
+        let app = test::init_service(
+            App::new()
+                .wrap_fn({
+                    let injector = injector.clone();
+                    move |req, srv| inject(&injector, req, srv)
+                })
+                .route(
+                    "/3ds/final",
+                    web::post()
+                        .to(|| async { HttpResponse::Ok().json(serde_json::json!({"ok": true})) }),
+                ),
+        )
+        .await;
+        let final_request = || test::TestRequest::post().uri("/3ds/final").to_request();
+
+        let response = test::call_service(&app, final_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(test::read_body(response).await, r#"{"ok":true}"#);
+
+        injector.activate("outage");
+        let response = test::call_service(&app, final_request()).await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["messageType"], "Erro");
+
+        injector.activate("garbled");
+        let response = test::call_service(&app, final_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = test::read_body(response).await;
+        assert!(serde_json::from_slice::<serde_json::Value>(&body).is_err());
+    }
+}
@@ -0,0 +1,723 @@
+//! Scripted end-to-end flows for CI: a short script posted to /test/run-flow runs the AReq,
+//! the challenge and the final result lookup server-side, and comes back as one report with
+//! every message exchanged and a verdict per expectation.
+//!
+//! The AReq goes through the configured ACS (in-process or remote). Challenge steps call this
+//! process's ACS handlers against the shared transaction store.
+//!
+//! /admin/replay runs a flow exported from /admin/transactions/{id}/export the same way,
+//! sending the recorded AReq as a new transaction.
+
+use actix_web::{body::to_bytes, http::StatusCode, web, HttpResponse, ResponseError};
+use base64::{engine::general_purpose, Engine as _};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::acs_client::AcsClient;
+use crate::cache::AppCaches;
+use crate::card_status::CardStatusList;
+use crate::challenge;
+use crate::config::Settings;
+use crate::crypto::{
+    calculate_derived_key, decrypt_challenge_request, encrypt_challenge_response,
+    generate_ephemeral_key_pair,
+};
+use crate::error::AppError;
+use crate::handlers::{acs, server};
+use crate::models::*;
+use crate::pan::MaskedPan;
+use crate::recording::{Direction, FlowCapture};
+use crate::self_test::synthetic_areq;
+use crate::state_store::StateStore;
+
+/// A flow to run: the cardholder, how they authenticate and what the outcome should be
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct FlowScript {
+    pub card_number: String,
+    #[serde(default = "default_device_channel")]
+    pub device_channel: DeviceChannel,
+    /// threeDSRequestorChallengeInd sent in the AReq (default 01)
+    #[serde(default)]
+    pub challenge_ind: Option<ChallengeIndicator>,
+    /// OTP entered if the ACS challenges (default: the OTP the transaction's challenge accepts)
+    #[serde(default)]
+    pub otp: Option<String>,
+    #[serde(default)]
+    pub expect: FlowExpectations,
+    /// Recorded AReq sent instead of the synthetic one when replaying a capture
+    #[serde(skip)]
+    pub base_areq: Option<Value>,
+}
+
+impl FlowScript {
+    /// Script replaying a captured flow: its AReq, the OTP entered last and the outcome it
+    /// had. A flow that ended authenticated enters the OTP of the new transaction instead, as
+    /// random OTPs differ per transaction.
+    ///
+    /// Recordings hold the masked PAN, while the card registry, card statuses and per-card
+    /// OTPs are looked up by the full number, so a masked capture only replays with
+    /// `card_number`: the full PAN, which must mask to the recorded one.
+    pub fn replaying(capture: &FlowCapture, card_number: Option<&str>) -> Result<Self, String> {
+        let areq = capture
+            .first(Direction::Inbound, "AReq")
+            .ok_or("the capture has no AReq")?;
+        let recorded_number = areq["cardholderAccount"]["acctNumber"]
+            .as_str()
+            .ok_or("the recorded AReq has no acctNumber")?;
+        let card_number = match card_number {
+            Some(card_number) if MaskedPan::new(card_number) == MaskedPan::new(recorded_number) => {
+                card_number.to_string()
+            }
+            Some(card_number) => {
+                return Err(format!(
+                    "cardNumber {} is not the recorded card {}",
+                    MaskedPan::new(card_number),
+                    MaskedPan::new(recorded_number)
+                ))
+            }
+            None if recorded_number.contains('*') => {
+                return Err(format!(
+                    "the recorded acctNumber {} is masked, and card lookups need the full PAN: \
+                     pass it as ?cardNumber=",
+                    recorded_number
+                ))
+            }
+            None => recorded_number.to_string(),
+        };
+        let device_channel = serde_json::from_value(areq["deviceChannel"].clone())
+            .map_err(|e| format!("the recorded AReq's deviceChannel: {}", e))?;
+
+        // The final outcome: the Final lookup if one was made, else the RReq, else the ARes
+        let ares = capture
+            .first(Direction::Outbound, "ARes")
+            .map(|ares| &ares["authenticationResponse"]);
+        let outcome = capture
+            .last(Direction::Outbound, "Final")
+            .or_else(|| capture.last(Direction::Inbound, "RReq"))
+            .or(ares);
+        fn field<T: DeserializeOwned>(message: Option<&Value>, name: &str) -> Option<T> {
+            message.and_then(|message| serde_json::from_value(message[name].clone()).ok())
+        }
+        let expect = FlowExpectations {
+            ares_trans_status: field(ares, "transStatus"),
+            trans_status: field(outcome, "transStatus"),
+            eci: field(outcome, "eci"),
+        };
+
+        let otp = match expect.trans_status {
+            Some(TransStatus::Authenticated) => None,
+            _ => capture.last_otp().map(str::to_string),
+        };
+
+        Ok(FlowScript {
+            card_number,
+            device_channel,
+            challenge_ind: None,
+            otp,
+            expect,
+            base_areq: Some(areq.clone()),
+        })
+    }
+}
+
+fn default_device_channel() -> DeviceChannel {
+    DeviceChannel::Browser
+}
+
+/// Expected outcome; unset fields are not checked
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct FlowExpectations {
+    /// transStatus of the ARes
+    pub ares_trans_status: Option<TransStatus>,
+    /// Final transStatus: the RReq's after a challenge, the ARes's when frictionless
+    pub trans_status: Option<TransStatus>,
+    /// Final ECI, taken from the same message as transStatus
+    pub eci: Option<Eci>,
+}
+
+/// One request and the response it got, as JSON (CReqs and CRes decrypted)
+#[derive(Debug, Serialize)]
+pub struct FlowMessage {
+    pub step: &'static str,
+    pub status: u16,
+    pub request: Value,
+    pub response: Value,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FlowAssertion {
+    pub field: &'static str,
+    pub expected: String,
+    pub actual: Option<String>,
+    pub passed: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlowReport {
+    pub passed: bool,
+    pub three_ds_server_trans_id: Uuid,
+    /// Why the flow stopped early, if it did
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub assertions: Vec<FlowAssertion>,
+    pub messages: Vec<FlowMessage>,
+    /// The recorded transaction a replay ran again
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replay_of: Option<Uuid>,
+}
+
+/// Outcome fields observed while the flow ran
+#[derive(Debug, Default)]
+struct Observed {
+    ares_trans_status: Option<String>,
+    trans_status: Option<String>,
+    eci: Option<String>,
+}
+
+/// Handlers and shared state the flow calls into
+struct FlowContext {
+    acs: web::Data<AcsClient>,
+    state: web::Data<Arc<Box<dyn StateStore>>>,
+    settings: web::Data<Settings>,
+    caches: web::Data<AppCaches>,
+    card_statuses: web::Data<CardStatusList>,
+}
+
+/// Run a scripted flow. Answers 200 when every expectation holds, 422 otherwise; the
+/// report is the body either way.
+pub async fn run_flow_handler(
+    script: web::Json<FlowScript>,
+    acs: web::Data<AcsClient>,
+    state: web::Data<Arc<Box<dyn StateStore>>>,
+    settings: web::Data<Settings>,
+    caches: web::Data<AppCaches>,
+    card_statuses: web::Data<CardStatusList>,
+) -> HttpResponse {
+    let context = FlowContext {
+        acs,
+        state,
+        settings,
+        caches,
+        card_statuses,
+    };
+    let report = run_flow(&script, &context).await;
+
+    info!(
+        three_ds_server_trans_id = %report.three_ds_server_trans_id,
+        device_channel = ?script.device_channel,
+        card = %MaskedPan::new(&script.card_number),
+        passed = report.passed,
+        "scripted flow finished"
+    );
+    report_response(report)
+}
+
+/// /admin/replay query: the full PAN of a capture whose recorded acctNumber is masked
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayQuery {
+    pub card_number: Option<String>,
+}
+
+/// Replay a flow exported from /admin/transactions/{id}/export as a new transaction, checking
+/// it ends the way the recorded one did. Answers like /test/run-flow.
+pub async fn replay_handler(
+    capture: web::Json<FlowCapture>,
+    query: web::Query<ReplayQuery>,
+    acs: web::Data<AcsClient>,
+    state: web::Data<Arc<Box<dyn StateStore>>>,
+    settings: web::Data<Settings>,
+    caches: web::Data<AppCaches>,
+    card_statuses: web::Data<CardStatusList>,
+) -> HttpResponse {
+    let script = match FlowScript::replaying(&capture, query.card_number.as_deref()) {
+        Ok(script) => script,
+        Err(e) => return HttpResponse::BadRequest().json(json!({ "error": e })),
+    };
+    let context = FlowContext {
+        acs,
+        state,
+        settings,
+        caches,
+        card_statuses,
+    };
+    let mut report = run_flow(&script, &context).await;
+    report.replay_of = Some(capture.three_ds_server_trans_id);
+
+    info!(
+        replay_of = %capture.three_ds_server_trans_id,
+        three_ds_server_trans_id = %report.three_ds_server_trans_id,
+        passed = report.passed,
+        "replay finished"
+    );
+    report_response(report)
+}
+
+// 200 when every expectation holds, 422 otherwise
+fn report_response(report: FlowReport) -> HttpResponse {
+    if report.passed {
+        HttpResponse::Ok().json(report)
+    } else {
+        HttpResponse::UnprocessableEntity().json(report)
+    }
+}
+
+async fn run_flow(script: &FlowScript, context: &FlowContext) -> FlowReport {
+    let mut report = FlowReport {
+        passed: false,
+        three_ds_server_trans_id: Uuid::new_v4(),
+        error: None,
+        assertions: Vec::new(),
+        messages: Vec::new(),
+        replay_of: None,
+    };
+    let mut observed = Observed::default();
+
+    if let Err(e) = execute(script, context, &mut report, &mut observed).await {
+        report.error = Some(e);
+    }
+
+    let expect = &script.expect;
+    let checks = [
+        (
+            "aresTransStatus",
+            expect.ares_trans_status.map(|s| s.as_str()),
+            &observed.ares_trans_status,
+        ),
+        (
+            "transStatus",
+            expect.trans_status.map(|s| s.as_str()),
+            &observed.trans_status,
+        ),
+        ("eci", expect.eci.map(|eci| eci.as_str()), &observed.eci),
+    ];
+    for (field, expected, actual) in checks {
+        if let Some(expected) = expected {
+            report.assertions.push(FlowAssertion {
+                field,
+                expected: expected.to_string(),
+                actual: actual.clone(),
+                passed: actual.as_deref() == Some(expected),
+            });
+        }
+    }
+
+    report.passed =
+        report.error.is_none() && report.assertions.iter().all(|assertion| assertion.passed);
+    report
+}
+
+async fn execute(
+    script: &FlowScript,
+    context: &FlowContext,
+    report: &mut FlowReport,
+    observed: &mut Observed,
+) -> Result<(), String> {
+    let sdk_keys = match script.device_channel {
+        DeviceChannel::App => Some(generate_ephemeral_key_pair().map_err(|e| e.to_string())?),
+        DeviceChannel::Browser => None,
+        DeviceChannel::ThreeRi => return Err("3RI flows cannot be scripted".to_string()),
+    };
+    let three_ds_server_trans_id = report.three_ds_server_trans_id;
+
+    let synthetic = synthetic_areq(
+        three_ds_server_trans_id,
+        &script.card_number,
+        sdk_keys.as_ref(),
+    );
+    let mut areq = match &script.base_areq {
+        Some(recorded) => replayed_areq(recorded, &synthetic),
+        None => synthetic,
+    };
+    if let Some(challenge_ind) = script.challenge_ind {
+        areq["threeDsRequestor"]["threeDsRequestorChallengeInd"] = json!(challenge_ind);
+    }
+    let result = context
+        .acs
+        .authenticate(
+            web::Bytes::from(areq.to_string()),
+            context.state.clone(),
+            context.settings.clone(),
+            context.caches.clone(),
+            context.card_statuses.clone(),
+        )
+        .await;
+    let (status, body) = json_response(result).await?;
+    let ares = body["authenticationResponse"].clone();
+    let sdk_reference_number = context
+        .settings
+        .acs
+        .sdk_reference_number("android", areq["sdkReferenceNumber"].as_str())
+        .unwrap_or_default()
+        .to_string();
+    report.messages.push(FlowMessage {
+        step: "AReq",
+        status: status.as_u16(),
+        request: areq,
+        response: body,
+    });
+    if !status.is_success() {
+        return Err(format!("AReq answered with HTTP {}", status));
+    }
+
+    observed.ares_trans_status = ares["transStatus"].as_str().map(str::to_string);
+    observed.trans_status = observed.ares_trans_status.clone();
+    observed.eci = ares["eci"].as_str().map(str::to_string);
+    if ares["transStatus"] != TransStatus::ChallengeRequired.as_str() {
+        return Ok(());
+    }
+
+    // Without a scripted OTP, enter the one the transaction's challenge accepts
+    let otp = match &script.otp {
+        Some(otp) => otp.clone(),
+        None => {
+            let transaction_data = context
+                .state
+                .get(&three_ds_server_trans_id)
+                .await
+                .map_err(|e| e.to_string())?
+                .ok_or("the challenged transaction was not stored")?;
+            challenge::expected_otp(&context.settings, &transaction_data).to_string()
+        }
+    };
+    match &sdk_keys {
+        Some(sdk_keys) => {
+            let acs_ephemeral_key = acs_ephemeral_key(&ares)?;
+            let sdk_key = calculate_derived_key(
+                &acs_ephemeral_key,
+                &sdk_keys.private_key,
+                &sdk_reference_number,
+            )
+            .map_err(|e| e.to_string())?;
+            app_challenge(&ares, &sdk_key, &otp, context, report, observed).await?;
+        }
+        None => browser_challenge(three_ds_server_trans_id, &otp, context, report).await?,
+    }
+
+    let final_request = json!({ "threeDsServerTransId": three_ds_server_trans_id });
+    let result = server::final_handler(
+        web::Json(FinalRequest {
+            three_ds_server_trans_id,
+        }),
+        context.state.clone(),
+    )
+    .await;
+    let (status, body) = json_response(result).await?;
+    observed.trans_status = body["transStatus"].as_str().map(str::to_string);
+    observed.eci = body["eci"].as_str().map(str::to_string);
+    report.messages.push(FlowMessage {
+        step: "Final",
+        status: status.as_u16(),
+        request: final_request,
+        response: body,
+    });
+    if !status.is_success() {
+        return Err(format!("Final answered with HTTP {}", status));
+    }
+    Ok(())
+}
+
+/// A recorded AReq as a new transaction: the synthetic AReq's threeDSServerTransID and
+/// acctNumber (the full PAN in place of the masked one) and, for app flows, its SDK
+/// transaction ID and ephemeral key, whose private half this run holds
+fn replayed_areq(recorded: &Value, synthetic: &Value) -> Value {
+    let mut areq = recorded.clone();
+    areq["threeDsServerTransId"] = synthetic["threeDsServerTransId"].clone();
+    areq["cardholderAccount"]["acctNumber"] = synthetic["cardholderAccount"]["acctNumber"].clone();
+    if let (Some(fields), Some(sdk_key)) =
+        (areq.as_object_mut(), synthetic.get("sdkEphemeralPublicKey"))
+    {
+        for field in ["Kty", "Crv", "X", "Y"] {
+            fields.remove(field);
+        }
+        fields.insert("sdkEphemeralPublicKey".to_string(), sdk_key.clone());
+        fields.insert("sdkTransId".to_string(), synthetic["sdkTransId"].clone());
+    }
+    areq
+}
+
+/// The ACS ephemeral public key from the payload of the ARes acsSignedContent JWS, as the
+/// SDK reads it
+fn acs_ephemeral_key(ares: &Value) -> Result<String, String> {
+    let signed_content = ares["acsSignedContent"]
+        .as_str()
+        .ok_or("ARes has no acsSignedContent")?;
+    let payload = signed_content
+        .split('.')
+        .nth(1)
+        .ok_or("acsSignedContent is not a JWS")?;
+    let payload = general_purpose::URL_SAFE_NO_PAD
+        .decode(payload)
+        .map_err(|e| format!("acsSignedContent payload: {}", e))?;
+    let payload: Value =
+        serde_json::from_slice(&payload).map_err(|e| format!("acsSignedContent payload: {}", e))?;
+
+    match payload.get("acsEphemPubKey") {
+        Some(key) => Ok(key.to_string()),
+        None => Err("acsSignedContent has no acsEphemPubKey".to_string()),
+    }
+}
+
+/// App challenge: the initial CReq, then the OTP submission, each encrypted as the SDK would.
+/// A wrong OTP is entered again until the ACS runs out of attempts and ends the challenge.
+async fn app_challenge(
+    ares: &Value,
+    sdk_key: &[u8],
+    otp: &str,
+    context: &FlowContext,
+    report: &mut FlowReport,
+    observed: &mut Observed,
+) -> Result<(), String> {
+    let acs_trans_id = ares["acsTransId"]
+        .as_str()
+        .ok_or("ARes has no acsTransID")?;
+
+    let max_attempts = context.settings.acs.otp.max_attempts;
+    for counter in 0..=max_attempts {
+        let sdk_counter = format!("{:03}", counter);
+        let challenge_data_entry = (counter > 0).then_some(otp);
+        let mut creq = json!({
+            "messageType": "CReq",
+            "messageVersion": ares["messageVersion"],
+            "threeDSServerTransID": ares["threeDsServerTransId"],
+            "acsTransID": acs_trans_id,
+            "sdkTransID": ares["sdkTransId"],
+            "sdkCounterStoA": sdk_counter
+        });
+        if let Some(entry) = challenge_data_entry {
+            creq["challengeDataEntry"] = json!(entry);
+        }
+
+        let jwe = encrypt_challenge_response(&creq, acs_trans_id, sdk_key, "android")
+            .await
+            .map_err(|e| e.to_string())?;
+        let result = acs::challenge_handler(
+            web::Bytes::from(jwe),
+            context.state.clone(),
+            context.settings.clone(),
+        )
+        .await;
+        let (status, cres) = jwe_response(result, sdk_key).await?;
+        report.messages.push(FlowMessage {
+            step: "CReq",
+            status: status.as_u16(),
+            request: creq,
+            response: cres.clone(),
+        });
+        if !status.is_success() || cres["messageType"] != "CRes" {
+            return Err(format!("CReq {} was not answered with a CRes", sdk_counter));
+        }
+        if cres["challengeCompletionInd"] == "Y" {
+            observed.trans_status = cres["transStatus"].as_str().map(str::to_string);
+            return Ok(());
+        }
+    }
+    Err("challenge did not complete after the OTP submission".to_string())
+}
+
+/// Browser challenge: the OTP form post the ACS challenge page makes. The ACS ends the
+/// challenge with the CRes post page (or a redirect); a wrong OTP is answered with the
+/// challenge page again, so it is posted again until the ACS runs out of attempts.
+async fn browser_challenge(
+    three_ds_server_trans_id: Uuid,
+    otp: &str,
+    context: &FlowContext,
+    report: &mut FlowReport,
+) -> Result<(), String> {
+    for _ in 0..context.settings.acs.otp.max_attempts {
+        let form = AcsVerifyOtpRequest {
+            otp: otp.to_string(),
+            three_ds_server_trans_id: three_ds_server_trans_id.to_string(),
+            challenge_window_size: None,
+        };
+        let request = json!({
+            "otp": form.otp,
+            "threeDSServerTransID": form.three_ds_server_trans_id
+        });
+        let result = acs::acs_verify_otp_handler(
+            web::Query(HashMap::new()),
+            web::Form(form),
+            context.state.clone(),
+            context.settings.clone(),
+            context.caches.clone(),
+        )
+        .await;
+        let response = result.unwrap_or_else(|e| e.error_response());
+        let status = response.status();
+        let location = response
+            .headers()
+            .get("Location")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+
+        // Both pages are 200s: the challenge is over once the ACS has recorded its RReq
+        let completed = status == StatusCode::OK
+            && matches!(
+                context.state.get(&three_ds_server_trans_id).await,
+                Ok(Some(transaction)) if transaction.results_request.is_some()
+            );
+
+        report.messages.push(FlowMessage {
+            step: "VerifyOtp",
+            status: status.as_u16(),
+            request,
+            response: json!({ "location": location, "completed": completed }),
+        });
+        match location {
+            Some(location) if status == StatusCode::FOUND && !location.contains("error=") => {
+                return Ok(())
+            }
+            _ if completed => return Ok(()),
+            _ if status == StatusCode::OK => continue,
+            _ => return Err("OTP verification did not redirect with a result".to_string()),
+        }
+    }
+    Err("challenge did not complete after the last OTP attempt".to_string())
+}
+
+/// Status and JSON body of a handler response; errors become their Erro response
+async fn json_response(
+    result: Result<HttpResponse, AppError>,
+) -> Result<(StatusCode, Value), String> {
+    let response = result.unwrap_or_else(|e| e.error_response());
+    let status = response.status();
+    let bytes = to_bytes(response.into_body())
+        .await
+        .map_err(|e| e.to_string())?;
+    let body = serde_json::from_slice(&bytes)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned()));
+    Ok((status, body))
+}
+
+/// Like [`json_response`], decrypting a JWE body with the SDK's key
+async fn jwe_response(
+    result: Result<HttpResponse, AppError>,
+    sdk_key: &[u8],
+) -> Result<(StatusCode, Value), String> {
+    let (status, body) = json_response(result).await?;
+    match body.as_str() {
+        Some(jwe) if status.is_success() => {
+            let cres = decrypt_challenge_request(jwe, sdk_key)
+                .await
+                .map_err(|e| format!("CRes could not be decrypted: {}", e))?;
+            Ok((status, cres))
+        }
+        _ => Ok((status, body)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flow_script_defaults() {
+        let script: FlowScript = serde_json::from_value(json!({
+            "cardNumber": "4000000000004001",
+            "expect": { "transStatus": "Y", "eci": "05" }
+        }))
+        .unwrap();
+
+        assert_eq!(script.device_channel, DeviceChannel::Browser);
+        assert_eq!(script.otp, None);
+        assert_eq!(script.expect.ares_trans_status, None);
+        assert_eq!(script.expect.trans_status, Some(TransStatus::Authenticated));
+        assert_eq!(script.expect.eci, Some(Eci::VisaAuthenticated));
+
+        let typo = json!({ "cardNumber": "4000000000004001", "otpp": "1234" });
+        assert!(serde_json::from_value::<FlowScript>(typo).is_err());
+    }
+
+    #[test]
+    fn test_replaying_a_capture() {
+        let capture: FlowCapture = serde_json::from_value(json!({
+            "threeDSServerTransID": Uuid::new_v4(),
+            "messages": [
+                {
+                    "direction": "inbound",
+                    "messageType": "AReq",
+                    "recordedAt": "2026-01-01T00:00:00Z",
+                    "body": {
+                        "deviceChannel": "02",
+                        "cardholderAccount": { "acctNumber": "4000000000004001" }
+                    }
+                },
+                {
+                    "direction": "outbound",
+                    "messageType": "ARes",
+                    "recordedAt": "2026-01-01T00:00:01Z",
+                    "body": { "authenticationResponse": { "transStatus": "C" } }
+                },
+                {
+                    "direction": "inbound",
+                    "messageType": "ChallengeForm",
+                    "recordedAt": "2026-01-01T00:00:02Z",
+                    "body": { "otp": "0000" }
+                },
+                {
+                    "direction": "inbound",
+                    "messageType": "RReq",
+                    "recordedAt": "2026-01-01T00:00:03Z",
+                    "body": { "transStatus": "N", "eci": "07" }
+                }
+            ]
+        }))
+        .unwrap();
+
+        let script = FlowScript::replaying(&capture, None).unwrap();
+        assert_eq!(script.card_number, "4000000000004001");
+        assert_eq!(script.device_channel, DeviceChannel::Browser);
+        assert_eq!(script.otp.as_deref(), Some("0000"));
+        assert_eq!(
+            script.expect.ares_trans_status,
+            Some(TransStatus::ChallengeRequired)
+        );
+        assert_eq!(
+            script.expect.trans_status,
+            Some(TransStatus::NotAuthenticated)
+        );
+        assert_eq!(script.expect.eci, Some(Eci::VisaNotAuthenticated));
+        assert!(script.base_areq.is_some());
+
+        let empty = FlowCapture {
+            three_ds_server_trans_id: Uuid::new_v4(),
+            messages: Vec::new(),
+        };
+        assert!(FlowScript::replaying(&empty, None).is_err());
+    }
+
+    #[test]
+    fn test_replaying_a_masked_capture_needs_the_full_pan() {
+        let capture: FlowCapture = serde_json::from_value(json!({
+            "threeDSServerTransID": Uuid::new_v4(),
+            "messages": [{
+                "direction": "inbound",
+                "messageType": "AReq",
+                "recordedAt": "2026-01-01T00:00:00Z",
+                "body": {
+                    "deviceChannel": "02",
+                    "cardholderAccount": { "acctNumber": "400000******4001" }
+                }
+            }]
+        }))
+        .unwrap();
+
+        let refused = FlowScript::replaying(&capture, None).unwrap_err();
+        assert!(refused.contains("400000******4001 is masked"));
+        let other_card = FlowScript::replaying(&capture, Some("4000000000004002")).unwrap_err();
+        assert!(other_card.contains("not the recorded card"));
+
+        let script = FlowScript::replaying(&capture, Some("4000000000004001")).unwrap();
+        assert_eq!(script.card_number, "4000000000004001");
+        let synthetic = synthetic_areq(Uuid::new_v4(), &script.card_number, None);
+        let areq = replayed_areq(script.base_areq.as_ref().unwrap(), &synthetic);
+        assert_eq!(areq["cardholderAccount"]["acctNumber"], "4000000000004001");
+    }
+}
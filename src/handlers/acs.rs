@@ -0,0 +1,2332 @@
+//! ACS component: AReq processing, challenges and results recording.
+//!
+//! `authenticate_handler` and `results_handler` are served to the 3DS Server component
+//! on /acs/areq and /acs/rreq when the components run as separate processes.
+
+use actix_web::{
+    http::header::{self, ContentType},
+    web, HttpResponse, HttpResponseBuilder,
+};
+use base64::{engine::general_purpose, Engine as _};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::field::{display, Empty};
+use tracing::Span;
+use tracing::{debug, info, instrument, trace, warn};
+use uuid::Uuid;
+
+use crate::acs_client::ACS_METHOD_DEVICE_DATA_PATH;
+use crate::auth_value;
+use crate::cache::{AppCaches, ChallengeDecisionKey};
+use crate::card_scenarios::CardOutcome;
+use crate::card_scheme::CardScheme;
+use crate::card_status::CardStatusList;
+use crate::challenge::{
+    self, check_otp_attempt, generate_spc_challenge, negotiate_authentication_type,
+    validate_challenge_data_entry, validate_whitelisting_data_entry, verify_spc_assertion,
+    ChallengeOutcome, Completion, InitialUi, OtpAttempt, EXCEEDED_MAX_CHALLENGES,
+    SPC_AUTHENTICATION_METHOD,
+};
+use crate::config::{
+    ChallengeCompletion, ChallengePageConfig, CompIndMismatchPolicy, MissingCertPolicy,
+    Protocol23Fields, Settings, SuccessLiability,
+};
+use crate::crypto::{
+    calculate_derived_key, create_acs_signed_content, create_acs_url,
+    decrypt_challenge_request_with_rsa, derive_ephemeral_key_pair, ecdh_es_content_key,
+    encrypt_challenge_response, encrypt_challenge_response_ecdh_es, encrypt_challenge_response_rsa,
+    generate_ephemeral_key_pair, generate_self_signed_certificate, load_rsa_private_key,
+    rotated_sdk_ephemeral_key, CryptoError, EphemeralKeyPair,
+};
+use crate::error::AppError;
+use crate::handlers::record_transaction;
+use crate::jwe;
+use crate::models::*;
+use crate::pan::{self, MaskedPan};
+use crate::protocol;
+use crate::recording::{self, Direction};
+use crate::request_id;
+use crate::state_store::{StateStore, TransactionData};
+use crate::validation::{
+    decode_browser_creq, decode_three_ds_method_data, decode_trigger_otp_creq,
+    encode_method_notification_data, exceeds_instalment_threshold, is_challenge_window_size,
+    unknown_creq_fields, unknown_fields, validate_authenticate_request, validate_browser_creq,
+    validate_message_extensions, validate_results_request, FieldError,
+};
+use crate::webhook::{self, FinalOutcome, OutcomeFlow};
+
+/// Sign acsSignedContent with the configured ACS certificate, applying the
+/// missing-certificate policy when the cert or key file is absent
+fn acs_signed_content(
+    settings: &Settings,
+    acs_trans_id: Uuid,
+    acs_reference_number: &str,
+    acs_url: &str,
+    keys: &EphemeralKeyPair,
+) -> Result<String, CryptoError> {
+    let signing = &settings.acs.signing;
+    let cert_path = Path::new(&signing.cert_path);
+    let key_path = Path::new(&signing.private_key_path);
+
+    if !cert_path.exists() || !key_path.exists() {
+        let missing = || {
+            CryptoError::CertLoad(format!(
+                "ACS signing certificate or key missing (cert: {}, key: {}); run ./generate-certs.sh or set acs.signing.missing_cert_policy",
+                signing.cert_path, signing.private_key_path
+            ))
+        };
+        match signing.missing_cert_policy {
+            MissingCertPolicy::Error => return Err(missing()),
+            MissingCertPolicy::Static => {
+                warn!("ACS certificates missing, using configured static signed content");
+                return signing.static_signed_content.clone().ok_or_else(missing);
+            }
+            MissingCertPolicy::AutoGenerate => {
+                warn!("ACS certificates missing, generating a self-signed pair");
+                generate_self_signed_certificate(cert_path, key_path)?;
+            }
+        }
+    }
+
+    create_acs_signed_content(
+        acs_trans_id,
+        acs_reference_number,
+        acs_url,
+        keys,
+        cert_path,
+        key_path,
+    )
+}
+
+/// Deserialize a message body into its model. In strict mode, elements the model does not
+/// carry are rejected with an Erro naming them instead of being silently dropped.
+fn parse_message<T>(message_type: &'static str, body: &[u8], strict: bool) -> Result<T, AppError>
+where
+    T: DeserializeOwned + Serialize,
+{
+    let to_app_error = |e: serde_path_to_error::Error<serde_json::Error>| {
+        AppError::from_json_path(message_type, &e)
+    };
+    if !strict {
+        let mut deserializer = serde_json::Deserializer::from_slice(body);
+        let message = serde_path_to_error::deserialize(&mut deserializer).map_err(to_app_error)?;
+        deserializer
+            .end()
+            .map_err(|e| AppError::from_json(message_type, &e))?;
+        return Ok(message);
+    }
+
+    let received: Value =
+        serde_json::from_slice(body).map_err(|e| AppError::from_json(message_type, &e))?;
+    let message = serde_path_to_error::deserialize(&received).map_err(to_app_error)?;
+    let unknown = unknown_fields(&received, &serde_json::to_value(&message)?);
+    if !unknown.is_empty() {
+        return Err(AppError::rejected_field(
+            message_type,
+            FieldError::unrecognized(&unknown),
+        ));
+    }
+
+    Ok(message)
+}
+
+/// deviceBindingStatus and trustListStatus for a transaction's ARes and RReq: the card's
+/// scenario values, falling back to those the requestor sent in the AReq
+pub(crate) fn protocol_2_3(settings: &Settings, req: &AuthenticateRequest) -> Protocol23Fields {
+    let fields = settings
+        .scenarios
+        .protocol_2_3_for(&req.cardholder_account.acct_number);
+    Protocol23Fields {
+        device_binding_status: fields
+            .device_binding_status
+            .or_else(|| req.device_binding_status.clone()),
+        trust_list_status: fields
+            .trust_list_status
+            .or_else(|| req.trust_list_status.clone()),
+    }
+}
+
+/// broadInfo for a mobile ARes, unless disabled globally or for this card's scenario
+fn broad_info_for(settings: &Settings, card_number: &str) -> Option<BroadInfo> {
+    let config = &settings.acs.broad_info;
+    let disabled_for_card = settings
+        .scenarios
+        .no_broad_info_card_suffixes
+        .iter()
+        .any(|suffix| card_number.ends_with(suffix.as_str()));
+
+    if !config.enabled || disabled_for_card {
+        return None;
+    }
+
+    Some(BroadInfo {
+        category: config.category.clone(),
+        severity: config.severity.clone(),
+        source: config.source.clone(),
+        recipients: config.recipients.clone(),
+        description: BroadInfoDescription {
+            message: config.message.clone(),
+        },
+        exp_date: config.exp_date.clone(),
+    })
+}
+
+#[instrument(name = "areq", skip_all, fields(three_ds_server_trans_id = Empty))]
+pub async fn authenticate_handler(
+    body: web::Bytes,
+    state: web::Data<Arc<Box<dyn StateStore>>>,
+    settings: web::Data<Settings>,
+    caches: web::Data<AppCaches>,
+    card_statuses: web::Data<CardStatusList>,
+) -> Result<HttpResponse, AppError> {
+    // Deserialize straight from the raw body - no intermediate Value unless strict
+    let req: AuthenticateRequest =
+        parse_message("AReq", &body, settings.validation.strict_parsing)?;
+    let three_ds_server_trans_id = req.three_ds_server_trans_id;
+    record_transaction(three_ds_server_trans_id);
+    let acs_trans_id = Uuid::new_v4();
+    let sdk_trans_id = req.sdk_trans_id;
+
+    // Route to the DS profile for the card scheme
+    let ds_profile = settings.ds.profile_for(
+        &req.cardholder_account.scheme_id,
+        &req.cardholder_account.acct_number,
+    );
+    let ds_trans_id = match ds_profile.ds_trans_id_namespace {
+        Some(namespace) => Uuid::new_v5(&namespace, acs_trans_id.as_bytes()),
+        None => Uuid::new_v4(),
+    };
+
+    // Enhanced flow decision logic
+    let card_number = &req.cardholder_account.acct_number;
+    let challenge_indicator = req.three_ds_requestor.three_ds_requestor_challenge_ind;
+    let is_mobile = req.device_channel == DeviceChannel::App;
+    let is_browser = req.device_channel == DeviceChannel::Browser;
+    // 3RI: merchant-initiated, with no cardholder present to challenge
+    let is_3ri = req.device_channel == DeviceChannel::ThreeRi;
+
+    info!(
+        device_channel = %req.device_channel,
+        challenge_ind = %challenge_indicator,
+        scheme = %req.cardholder_account.scheme_id,
+        ds = %ds_profile.ds_reference_number,
+        "processing authentication request"
+    );
+    debug!(card = %MaskedPan::new(card_number), "cardholder account");
+
+    // Validate sdk_trans_id presence for mobile flows
+    if is_mobile && sdk_trans_id.is_none() {
+        return Err(AppError::validation(
+            "AReq",
+            three_ds_server_trans_id,
+            FieldError::missing("sdkTransID"),
+        ));
+    }
+
+    // Validate conditional fields (recurring/instalment data) before making a flow decision
+    validate_authenticate_request(&req)
+        .and_then(|()| {
+            validate_message_extensions(
+                req.message_extension.as_deref(),
+                &settings.acs.recognized_message_extensions,
+            )
+        })
+        .map_err(|e| AppError::validation("AReq", three_ds_server_trans_id, e))?;
+
+    // Answer in the requestor's preferred version, or the newest supported one below it
+    let message_version = protocol::negotiate(
+        &req.preferred_protocol_version,
+        req.enforce_preferred_protocol_version,
+        &ds_profile.protocol_versions(),
+    )
+    .map_err(|e| AppError::validation("AReq", three_ds_server_trans_id, e))?;
+    debug!(
+        %message_version,
+        preferred = %req.preferred_protocol_version,
+        "message version negotiated"
+    );
+
+    // Instalment plans above the configured count always require a challenge
+    let exceeds_instalments =
+        exceeds_instalment_threshold(&req, settings.scenarios.instalment_challenge_threshold);
+    if exceeds_instalments {
+        info!("instalment count above threshold, forcing a challenge");
+    }
+
+    // The card's scenario from the registry: its challenge decision, or an outcome of its own
+    let (card_outcome, registry_answer) = settings.scenarios.card_registry.outcome_for(card_number);
+    if card_outcome == CardOutcome::Timeout {
+        // Hold the AReq until the 3DS Server would have given up on the ACS
+        let timeout_ms = settings.components.acs_timeout_ms;
+        info!(timeout_ms, "card scenario timeout, not answering");
+        tokio::time::sleep(Duration::from_millis(timeout_ms)).await;
+        return Err(AppError::AcsUnreachable {
+            message_type: "AReq",
+            detail: format!("ACS did not answer within {} ms", timeout_ms),
+            timed_out: true,
+        });
+    }
+
+    // Lost/stolen, blocked and not-enrolled cards are answered by the issuer without a
+    // challenge, as are registry cards with an N, U or R outcome
+    let card_status = card_statuses.get(card_number);
+    let card_status_outcome = card_status.outcome().or(registry_answer);
+    if let Some((status, reason)) = card_status_outcome {
+        info!(
+            ?card_status,
+            ?card_outcome,
+            trans_status = %status,
+            trans_status_reason = %reason,
+            "answered from the card status or scenario"
+        );
+    }
+
+    // Data-share-only requests are answered for information only, without authenticating
+    let information_only = card_status_outcome.is_none()
+        && challenge_indicator == ChallengeIndicator::NoChallengeDataShareOnly;
+    if information_only {
+        info!("data share only, information-only response");
+    }
+    if is_3ri {
+        debug!("3RI, frictionless only and never challenged");
+    }
+
+    // Determine if challenge is required based on challenge indicator and card number
+    let decision_key =
+        ChallengeDecisionKey::new(card_number, challenge_indicator, exceeds_instalments);
+    let should_challenge = !is_3ri
+        && card_status_outcome.is_none()
+        && !information_only
+        && caches.challenge_decision(decision_key, || {
+            let should_challenge = match challenge_indicator {
+                // Challenge mandated - force challenge even for frictionless cards
+                ChallengeIndicator::ChallengeMandated => true,
+                // No challenge requested - skip challenge even for friction cards
+                ChallengeIndicator::NoChallengeRiskAnalysisPerformed => false,
+                _ => card_outcome == CardOutcome::Challenge, // Card registry
+            };
+            should_challenge || exceeds_instalments
+        });
+
+    // Browser requestors that support SPC are offered it in place of the ACS challenge;
+    // SPC arrived in 2.2.0
+    let use_spc = should_challenge
+        && is_browser
+        && !protocol::is_2_1(message_version)
+        && settings.acs.spc.enabled
+        && req.three_ds_requestor_spc_support.as_deref() == Some("Y");
+    let spc_challenge = if use_spc {
+        Some(generate_spc_challenge())
+    } else {
+        None
+    };
+
+    // The OTP this transaction's challenge will accept
+    let otp = (should_challenge && !use_spc).then(|| {
+        let scenario_otp = settings
+            .scenarios
+            .card_registry
+            .scenario_for(card_number)
+            .and_then(|scenario| scenario.otp.as_deref());
+        challenge::challenge_otp(&settings.acs.otp, scenario_otp)
+    });
+
+    // Frictionless successes and verified challenges report full authentication or attempts
+    // processing per scenario
+    let liability = if card_outcome == CardOutcome::Attempted {
+        SuccessLiability::Attempted
+    } else {
+        settings
+            .scenarios
+            .success_eci
+            .liability_for(req.message_category, card_number)
+    };
+
+    let trans_status = if let Some((status, _)) = card_status_outcome {
+        status
+    } else if information_only {
+        TransStatus::InformationalOnly
+    } else if use_spc {
+        TransStatus::SpcChallenge
+    } else if should_challenge {
+        TransStatus::ChallengeRequired
+    } else if liability == SuccessLiability::Attempted {
+        TransStatus::Attempted
+    } else {
+        TransStatus::Authenticated
+    };
+    let acs_challenge_mandated = if should_challenge { "Y" } else { "N" };
+
+    info!(
+        trans_status = %trans_status,
+        challenge = should_challenge,
+        "flow decision"
+    );
+
+    let scheme = CardScheme::of(&req.cardholder_account);
+    let eci = if card_status_outcome.is_some() || information_only {
+        scheme.not_authenticated_eci()
+    } else {
+        scheme.success_eci(liability)
+    };
+
+    // Frictionless successes carry the scheme's authentication value. Recurring and
+    // instalment set-ups (threeDSRequestorAuthenticationInd 02/03) are cardholder-initiated,
+    // so they get the same ECI and value as any other payment; the requestor keeps them with
+    // the agreement, and the later merchant-initiated payments arrive as 3RI.
+    // A challenged ARes carries neither ECI nor value: the RReq reports them once the
+    // challenge ends.
+    let authentication_value =
+        (!should_challenge && card_status_outcome.is_none() && !information_only)
+            .then(|| auth_value::for_scheme(scheme).generate(&req, eci));
+    let ares_eci = (!should_challenge).then_some(eci);
+
+    let broad_info = if is_mobile {
+        broad_info_for(&settings, card_number)
+    } else {
+        None
+    };
+
+    // Negotiate the challenge method from the SDK's supported types and the ACS capabilities
+    let sdk_authentication_types: &[String] = match &req.device_render_options {
+        Some(render) if is_mobile => &render.sdk_authentication_type,
+        _ => &[],
+    };
+    let authentication_type = negotiate_authentication_type(
+        sdk_authentication_types,
+        &settings.acs.supported_authentication_types,
+    );
+    debug!(%authentication_type, "authentication type negotiated");
+
+    // Determine ACS configuration based on challenge indicator and flow type
+    let (acs_operator_id, acs_reference_number) = settings
+        .acs
+        .identity_for_challenge_ind(challenge_indicator.as_str(), ds_profile);
+
+    // Generate ephemeral keys and ACS signed content for mobile friction flows
+    let (ephemeral_keys, dynamic_acs_signed_content) = if is_mobile && should_challenge {
+        // Generate ephemeral keys for mobile friction flow, reproducibly when seeded
+        let keys = match &settings.acs.ephemeral_key_seed {
+            Some(seed) => derive_ephemeral_key_pair(seed, acs_trans_id),
+            None => generate_ephemeral_key_pair(),
+        }
+        .map_err(|e| AppError::crypto("AReq", three_ds_server_trans_id, e))?;
+
+        // Create ACS URL for mobile challenge - use our server URL
+        let server_url = settings.acs_public_url();
+        let acs_url = create_acs_url(&server_url);
+
+        let signed_content = acs_signed_content(
+            &settings,
+            acs_trans_id,
+            acs_reference_number,
+            &acs_url,
+            &keys,
+        )
+        .map_err(|e| AppError::crypto("AReq", three_ds_server_trans_id, e))?;
+        debug!(
+            %acs_trans_id,
+            acs_reference_number,
+            "ephemeral keys and ACS signed content generated for the app challenge"
+        );
+        (Some(keys), Some(signed_content))
+    } else {
+        (None, None)
+    };
+
+    // Echo the AReq as the DS would have received it, with the scheme's 3DS Server identity
+    let (server_reference_number, server_operator_id, server_url) =
+        ds_profile.three_ds_server(&settings.three_ds_server);
+    let mut authentication_request = AReqEcho::from(&req).with_three_ds_server(
+        server_reference_number,
+        server_operator_id,
+        server_url,
+    );
+    authentication_request.message_version = message_version;
+    if settings.acs.strip_card_security_code {
+        authentication_request.card_security_code = "";
+    }
+
+    let spc_trans_data = spc_challenge.as_ref().map(|challenge| SpcTransData {
+        challenge: challenge.clone(),
+        rp_id: settings.acs.spc.rp_id.clone(),
+        timeout: settings.acs.spc.timeout_ms,
+        payee_name: authentication_request.merchant_name.to_string(),
+        instrument_display_name: format!(
+            "Card ending {}",
+            pan::last_four(authentication_request.acct_number)
+        ),
+        purchase_amount: authentication_request.purchase_amount.to_string(),
+        purchase_currency: authentication_request.purchase_currency.to_string(),
+        purchase_exponent: authentication_request.purchase_exponent.to_string(),
+    });
+
+    // sdkEncData is encrypted to the scheme's DS certificate - make sure this DS has one.
+    // Configured certificates are loaded once at startup.
+    if req.sdk_enc_data.is_some() {
+        match &ds_profile.ds_certificate_path {
+            Some(cert_path) => debug!(%cert_path, "sdkEncData addressed to the DS certificate"),
+            None => warn!(
+                scheme = %req.cardholder_account.scheme_id,
+                "sdkEncData received but no DS certificate is configured for the scheme"
+            ),
+        }
+    }
+
+    // Challenge outcomes are redirected, and final outcomes POSTed, to the notification URL
+    let notification_url = req.merchant.notification_url.clone();
+
+    // Browser device data from a 3DS Method run before this AReq, if any. A method run
+    // through /3ds/method completes without collecting any.
+    let (three_ds_method_data, three_ds_method_completed) = if !is_browser {
+        (None, false)
+    } else {
+        let data = state.get_method_data(&three_ds_server_trans_id).await?;
+        let completed = data.is_some() || state.method_completed(&three_ds_server_trans_id).await?;
+        (data, completed)
+    };
+    if three_ds_method_data.is_some() {
+        debug!("3DS Method device data collected");
+    }
+
+    // threeDSCompInd = Y claims a completed 3DS Method; N and U claim none completed.
+    // App and 3RI AReqs run no 3DS Method, so their threeDSCompInd is not checked.
+    let comp_ind_mismatch = is_browser
+        && match req.three_ds_comp_ind.as_str() {
+            "Y" => !three_ds_method_completed,
+            _ => three_ds_method_completed,
+        };
+    if comp_ind_mismatch {
+        let detail = format!(
+            "threeDSCompInd = {} but the 3DS Method {}",
+            req.three_ds_comp_ind,
+            if three_ds_method_completed {
+                "completed"
+            } else {
+                "did not complete"
+            }
+        );
+        match settings.acs.three_ds_method.comp_ind_mismatch {
+            CompIndMismatchPolicy::Warn => {
+                warn!("{}", detail)
+            }
+            CompIndMismatchPolicy::Reject => {
+                return Err(AppError::validation(
+                    "AReq",
+                    three_ds_server_trans_id,
+                    FieldError::invalid("threeDSCompInd", &detail),
+                ));
+            }
+        }
+    }
+
+    // Extract SDK ephemeral public key if this is a mobile flow - either nested or top-level format
+    let sdk_ephemeral_public_key = if is_mobile {
+        match req.sdk_ephemeral_key() {
+            Some(sdk_key) => Some(serde_json::to_string(&sdk_key).unwrap_or_default()),
+            None => {
+                warn!("app AReq without an SDK ephemeral public key");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // Create challenge request (used when challenge is required)
+    let challenge_request = ChallengeRequest {
+        message_type: "CReq".to_string(),
+        three_ds_server_trans_id,
+        acs_trans_id,
+        challenge_window_size: "01".to_string(),
+        message_version: message_version.to_string(),
+        message_extension: None,
+    };
+
+    // Encode challenge request to base64 - only sent when a challenge is required
+    let base64_encoded_challenge_request = if should_challenge {
+        Some(general_purpose::STANDARD.encode(serde_json::to_vec(&challenge_request)?))
+    } else {
+        None
+    };
+
+    // Build dynamic ACS URL using server configuration
+    let server_url = settings.acs_public_url();
+
+    let trans_status_reason = card_status_outcome.map(|(_, reason)| reason.to_string());
+    // Without a challenge the ARes outcome is final; /3ds/final reports it
+    let frictionless_outcome = (!should_challenge).then(|| FrictionlessOutcome {
+        trans_status,
+        trans_status_reason: trans_status_reason.clone(),
+        eci,
+        authentication_value: authentication_value.clone(),
+    });
+    let fields_2_3 = protocol_2_3(&settings, &req);
+
+    // The UI the app challenge will open with, announced in the ARes
+    let initial_ui = InitialUi::for_request(&settings, &req, &authentication_type);
+    let (acs_interface, acs_ui_template) = initial_ui.rendering();
+
+    // Create authentication response based on flow type (mobile vs browser)
+    let mut authentication_response = if is_mobile {
+        // Mobile flow - includes SDK-specific fields
+        AuthenticationResponse {
+            three_ds_requestor_app_url_ind: Some("N".to_string()),
+            acs_operator_id: acs_operator_id.to_string(),
+            ds_reference_number: ds_profile.ds_reference_number.clone(),
+            eci: ares_eci,
+            acs_signed_content: dynamic_acs_signed_content,
+            ds_trans_id,
+            acs_rendering_type: Some(AcsRenderingTypeResponse {
+                device_user_interface_mode: Some("01".to_string()),
+                acs_interface: acs_interface.to_string(),
+                acs_ui_template: acs_ui_template.to_string(),
+            }),
+            message_type: "ARes".to_string(),
+            three_ds_server_trans_id,
+            acs_trans_id,
+            broad_info,
+            authentication_method: Some("02".to_string()),
+            trans_status_reason: trans_status_reason.or_else(|| Some("15".to_string())),
+            device_info_recognised_version: Some("1.3".to_string()),
+            acs_challenge_mandated: acs_challenge_mandated.to_string(),
+            authentication_type: authentication_type.clone(),
+            sdk_trans_id,
+            authentication_value: authentication_value.clone(),
+            trans_status,
+            device_binding_status: fields_2_3.device_binding_status.clone(),
+            trust_list_status: fields_2_3.trust_list_status.clone(),
+            message_version: message_version.to_string(),
+            acs_reference_number: acs_reference_number.to_string(),
+            acs_url: None, // Mobile flow doesn't use acsURL
+            spc_trans_data: None,
+            message_extension: req.message_extension.clone(),
+        }
+    } else {
+        // Browser flow - traditional response
+        AuthenticationResponse {
+            three_ds_requestor_app_url_ind: None,
+            acs_operator_id: acs_operator_id.to_string(),
+            ds_reference_number: ds_profile.ds_reference_number.clone(),
+            eci: ares_eci,
+            acs_signed_content: None,
+            ds_trans_id,
+            acs_rendering_type: None,
+            message_type: "ARes".to_string(),
+            three_ds_server_trans_id,
+            acs_trans_id,
+            broad_info: None,
+            authentication_method: None,
+            trans_status_reason,
+            device_info_recognised_version: None,
+            acs_challenge_mandated: acs_challenge_mandated.to_string(),
+            authentication_type: authentication_type.clone(),
+            sdk_trans_id: None,
+            authentication_value: authentication_value.clone(),
+            trans_status,
+            device_binding_status: fields_2_3.device_binding_status.clone(),
+            trust_list_status: fields_2_3.trust_list_status.clone(),
+            message_version: message_version.to_string(),
+            acs_reference_number: acs_reference_number.to_string(),
+            acs_url: if should_challenge {
+                Some(format!("{}/processor/mock/acs/trigger-otp", server_url))
+            } else {
+                None
+            },
+            spc_trans_data,
+            message_extension: req.message_extension.clone(),
+        }
+    };
+
+    if protocol::is_2_1(message_version) {
+        authentication_response.restrict_to_2_1_0();
+    }
+
+    // Create response structure
+    let response = AuthenticateResponse {
+        purchase_date: authentication_request.purchase_date,
+        base64_encoded_challenge_request,
+        acs_url: if should_challenge && !is_mobile {
+            Some(format!("{}/processor/mock/acs/trigger-otp", server_url))
+        } else {
+            None
+        },
+        spc_url: if use_spc {
+            Some(format!("{}/processor/mock/acs/spc-verify", server_url))
+        } else {
+            None
+        },
+        three_ds_server_trans_id,
+        authentication_response,
+        challenge_request,
+        acs_challenge_mandated: acs_challenge_mandated.to_string(),
+        trans_status,
+        authentication_request,
+    };
+
+    // Serialize while the response still borrows the request, then hand the request to the store
+    let body = serde_json::to_vec(&response)?;
+    let masked_pan = MaskedPan::new(card_number);
+    let pan_hash = pan::pan_hash(card_number);
+    // Recordings and the store only ever see the masked PAN
+    let mut recorded_response = serde_json::to_value(&response)?;
+    recorded_response["authenticationRequest"]["acctNumber"] = Value::from(masked_pan.as_str());
+    let req = req.for_storage(settings.acs.strip_card_security_code);
+    recording::record(
+        &state,
+        three_ds_server_trans_id,
+        Direction::Inbound,
+        "AReq",
+        &req,
+    )
+    .await;
+    recording::record(
+        &state,
+        three_ds_server_trans_id,
+        Direction::Outbound,
+        "ARes",
+        &recorded_response,
+    )
+    .await;
+
+    trace!(?sdk_ephemeral_public_key, "SDK ephemeral public key");
+    // Store transaction data in state
+    let transaction_data = TransactionData {
+        authenticate_request: req,
+        acs_trans_id,
+        ds_trans_id,
+        sdk_trans_id,
+        results_request: None,
+        ephemeral_keys,
+        redirect_url: Some(notification_url.clone()),
+        sdk_ephemeral_public_key,
+        authentication_type: Some(authentication_type),
+        interaction_count: 0,
+        challenge_ui: None,
+        spc_challenge,
+        three_ds_method_data,
+        frictionless_outcome,
+        message_version: Some(message_version.to_string()),
+        three_ds_method_completed,
+        otp_attempts: 0,
+        otp,
+        oob_started_at_ms: None,
+        oob_approved: None,
+        whitelisting_choice: None,
+        request_id: request_id::current(),
+        masked_pan: Some(masked_pan),
+        pan_hash: Some(pan_hash),
+        initial_ui: Some(initial_ui),
+        protocol_2_3: Some(fields_2_3),
+        three_ds_session_data: None,
+        success_liability: Some(liability),
+    };
+
+    debug!(%acs_trans_id, %ds_trans_id, "storing transaction");
+
+    state
+        .insert(three_ds_server_trans_id, transaction_data)
+        .await?;
+
+    // Without a challenge the ARes already carries the final outcome
+    if !should_challenge {
+        webhook::notify(
+            &settings.webhook,
+            &notification_url,
+            FinalOutcome {
+                three_ds_server_trans_id,
+                trans_status,
+                eci,
+                authentication_value,
+                flow: OutcomeFlow::Frictionless,
+            },
+        );
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .body(body))
+}
+
+/// Debug endpoint explaining what is wrong with a CReq JWE an SDK sent, without decrypting it
+pub async fn analyze_jwe_handler(body: web::Bytes) -> HttpResponse {
+    let diagnostics = jwe::analyze(&String::from_utf8_lossy(&body));
+    info!(
+        valid = diagnostics.valid,
+        "analyzed JWE: {}",
+        if diagnostics.valid {
+            "token is well-formed".to_string()
+        } else {
+            diagnostics.summary()
+        }
+    );
+    HttpResponse::Ok().json(diagnostics)
+}
+
+/// Mobile challenge endpoint - handles encrypted JWE requests from SDK
+#[instrument(
+    name = "app_creq",
+    skip_all,
+    fields(three_ds_server_trans_id = Empty, acs_trans_id = Empty)
+)]
+pub async fn challenge_handler(
+    req: web::Bytes,
+    state: web::Data<Arc<Box<dyn StateStore>>>,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse, AppError> {
+    debug!(body_length = req.len(), "processing app challenge request");
+
+    // Borrow the body as text - the JWE is ASCII, no copy needed
+    let jwe_data = std::str::from_utf8(&req)
+        .map_err(|_| AppError::invalid_message("CReq", "Invalid request body encoding"))?;
+
+    // Classify a malformed token (JSON error report, bad part, truncated kid) for the SDK
+    let jwe = jwe::parse(jwe_data).map_err(|diagnostics| {
+        debug!(?diagnostics, "malformed CReq JWE");
+        AppError::MalformedJwe(diagnostics)
+    })?;
+    let (jwe_data, header_json, acs_trans_id) = (jwe.compact, jwe.header, jwe.acs_trans_id);
+    let acs_trans_id_str = jwe.kid.as_str();
+
+    Span::current().record("acs_trans_id", display(acs_trans_id));
+    debug!(header = %header_json, "CReq JWE header");
+
+    // Find transaction by acsTransID
+    let (three_ds_server_trans_id, mut transaction_data) =
+        match state.find_by_acs_trans_id(&acs_trans_id).await {
+            Ok(Some((trans_id, data))) => {
+                record_transaction(trans_id);
+                (trans_id, data)
+            }
+            Ok(None) => return Err(AppError::not_found("CReq", acs_trans_id)),
+            Err(e) => return Err(e.into()),
+        };
+
+    // An SDK that restarted mid-challenge presents a fresh ephemeral key in the JWE header;
+    // with alg ECDH-ES the epk is a per-message key instead
+    let ecdh_es = header_json["alg"].as_str() == Some("ECDH-ES");
+    let rotated_key = if ecdh_es {
+        None
+    } else {
+        rotated_sdk_ephemeral_key(
+            &header_json,
+            transaction_data.sdk_ephemeral_public_key.as_deref(),
+        )
+    };
+    let sdk_key_rotated = match rotated_key {
+        Some(rotated_key) => {
+            info!("SDK ephemeral key changed since the AReq, re-deriving the shared key");
+            transaction_data.sdk_ephemeral_public_key = Some(rotated_key);
+            true
+        }
+        None => false,
+    };
+
+    // Extract SDK ephemeral public key and our private key for ECDH
+    let (sdk_public_key, our_private_key) = match (
+        &transaction_data.sdk_ephemeral_public_key,
+        &transaction_data.ephemeral_keys,
+    ) {
+        (Some(sdk_key), Some(our_keys)) => (sdk_key.clone(), our_keys.private_key.clone()),
+        _ => {
+            return Err(AppError::unexpected(
+                "CReq",
+                three_ds_server_trans_id,
+                "Missing ephemeral keys for ECDH",
+            ));
+        }
+    };
+
+    // Detect platform from JWE header encryption algorithm; the CRes is encrypted for it too
+    let enc = header_json["enc"].as_str().unwrap_or("unknown");
+    let platform = sdk_platform(enc).ok_or_else(|| {
+        AppError::invalid_message("CReq", format!("Unsupported encryption algorithm: {}", enc))
+    })?;
+
+    // Derive shared secret using ECDH with the AReq's or the platform's SDK reference number
+    let sdk_reference_number = settings
+        .acs
+        .sdk_reference_number(
+            platform,
+            transaction_data
+                .authenticate_request
+                .sdk_reference_number
+                .as_deref(),
+        )
+        .unwrap_or_default();
+    // ECDH-ES CReqs instead agree their content key from the header epk (RFC 7518 ConcatKDF)
+    let derived_key = if ecdh_es {
+        ecdh_es_content_key(&header_json, &our_private_key)
+    } else {
+        calculate_derived_key(&sdk_public_key, &our_private_key, sdk_reference_number)
+    }
+    .map_err(|e| {
+        AppError::crypto("CReq", three_ds_server_trans_id, e).with_acs_trans_id(acs_trans_id)
+    })?;
+
+    // RSA-OAEP-256 CReqs carry a content key encrypted to the configured ACS RSA key; their
+    // CRes is encrypted back to its public half
+    let rsa_key = match (header_json["alg"].as_str(), &settings.acs.jwe.rsa_key_path) {
+        (Some("RSA-OAEP-256"), Some(key_path)) => {
+            Some(load_rsa_private_key(Path::new(key_path)).map_err(|e| {
+                AppError::crypto("CReq", three_ds_server_trans_id, e)
+                    .with_acs_trans_id(acs_trans_id)
+            })?)
+        }
+        _ => None,
+    };
+
+    // Decrypt JWE challenge request
+    let decrypted =
+        decrypt_challenge_request_with_rsa(jwe_data, &derived_key, rsa_key.as_ref()).await;
+    let challenge_request = match decrypted {
+        Ok(request) => request,
+        Err(e) => {
+            if sdk_key_rotated {
+                warn!("CReq undecryptable with the SDK ephemeral key rotated in this CReq");
+            }
+            return Err(AppError::crypto("CReq", three_ds_server_trans_id, e)
+                .with_acs_trans_id(acs_trans_id));
+        }
+    };
+    let cres_key = match (rsa_key, ecdh_es) {
+        (Some(rsa_key), _) => CresKey::Rsa(rsa_key.to_public_key()),
+        (None, true) => CresKey::EcdhEs(sdk_public_key),
+        (None, false) => CresKey::Direct(derived_key),
+    };
+
+    recording::record(
+        &state,
+        three_ds_server_trans_id,
+        Direction::Inbound,
+        "CReq",
+        &challenge_request,
+    )
+    .await;
+
+    // Only persist a rotated SDK key once it has proven able to decrypt the CReq
+    if sdk_key_rotated {
+        if let Err(e) = state
+            .update(&three_ds_server_trans_id, transaction_data.clone())
+            .await
+        {
+            warn!(error = %e, "failed to store the rotated SDK ephemeral key");
+        }
+    }
+
+    let creq_field = |name: &str| {
+        challenge_request
+            .get(name)
+            .and_then(|v| v.as_str())
+            .unwrap_or("missing")
+            .to_string()
+    };
+    debug!(
+        platform,
+        message_type = %creq_field("messageType"),
+        message_version = %creq_field("messageVersion"),
+        sdk_counter = %creq_field("sdkCounterStoA"),
+        challenge_window_size = %creq_field("challengeWindowSize"),
+        challenge_no_entry = %creq_field("challengeNoEntry"),
+        challenge_data_entry = challenge_request.get("challengeDataEntry").is_some(),
+        "CReq decrypted"
+    );
+    trace!(creq = %challenge_request, "decrypted CReq");
+
+    // CRes and Erro answer in the version negotiated for the AReq
+    let message_version = transaction_data.message_version().to_string();
+
+    // Strict mode rejects elements outside the CReq spec; a submission is checked against
+    // the UI the cardholder was shown
+    let unknown = if settings.validation.strict_parsing {
+        unknown_creq_fields(&challenge_request)
+    } else {
+        Vec::new()
+    };
+    let creq_check = if !unknown.is_empty() {
+        Err(FieldError::unrecognized(&unknown))
+    } else if let Some(html_data_entry) = challenge_request.get("challengeHTMLDataEntry") {
+        // The form fields of an HTML (05) UI, posted through the SDK
+        match (&transaction_data.challenge_ui, html_data_entry.as_str()) {
+            (Some(ChallengeUi::Html { .. }), Some(_)) => Ok(()),
+            (Some(ChallengeUi::Html { .. }), None) => Err(FieldError::invalid(
+                "challengeHTMLDataEntry",
+                "challengeHTMLDataEntry must be a string",
+            )),
+            _ => Err(FieldError::invalid(
+                "challengeHTMLDataEntry",
+                "challengeHTMLDataEntry is only used with an HTML (05) acsUiType",
+            )),
+        }
+    } else if let Some(challenge_data_entry) = challenge_request.get("challengeDataEntry") {
+        let challenge_ui = transaction_data
+            .challenge_ui
+            .clone()
+            .unwrap_or_else(ChallengeUi::otp_form);
+        match challenge_data_entry.as_str() {
+            Some(entry) => validate_challenge_data_entry(&challenge_ui, entry),
+            None => Err(FieldError::invalid(
+                "challengeDataEntry",
+                "challengeDataEntry must be a string",
+            )),
+        }
+    } else {
+        Ok(())
+    };
+    // whitelistingDataEntry may accompany any CReq of the challenge
+    let creq_check = creq_check.and_then(|()| {
+        challenge_request
+            .get("whitelistingDataEntry")
+            .map_or(Ok(()), validate_whitelisting_data_entry)
+    });
+    // The CReq's message extensions, echoed in the CRes once every critical one is recognised
+    let message_extensions = match challenge_request.get("messageExtension") {
+        Some(extensions) => serde_json::from_value::<Vec<MessageExtension>>(extensions.clone())
+            .map(Some)
+            .map_err(|_| {
+                FieldError::invalid(
+                    "messageExtension",
+                    "messageExtension must be a list of name, id, criticalityIndicator and data",
+                )
+            }),
+        None => Ok(None),
+    };
+    let creq_check = creq_check.and(message_extensions).and_then(|extensions| {
+        validate_message_extensions(
+            extensions.as_deref(),
+            &settings.acs.recognized_message_extensions,
+        )
+        .map(|()| extensions)
+    });
+    let message_extensions = match creq_check {
+        Ok(extensions) => extensions,
+        Err(field_error) => {
+            warn!(
+                field = %field_error.field,
+                "rejected CReq: {}",
+                field_error.description
+            );
+            let mut erro = field_error.to_error_message("CReq", three_ds_server_trans_id);
+            erro.message_version = message_version.clone();
+            erro.acs_trans_id = Some(acs_trans_id);
+            erro.sdk_trans_id = transaction_data.sdk_trans_id;
+
+            let jwe = encrypt_cres(&erro, acs_trans_id_str, platform, &cres_key)
+                .await
+                .map_err(|e| AppError::crypto("CReq", three_ds_server_trans_id, e))?;
+            return Ok(HttpResponse::Ok()
+                .content_type("application/jose")
+                .body(jwe));
+        }
+    };
+
+    // The cardholder's whitelisting choice, reported in the RReq once the challenge ends
+    if let Some(choice) = challenge_request
+        .get("whitelistingDataEntry")
+        .and_then(|v| v.as_str())
+    {
+        debug!(choice, "whitelisting choice");
+        transaction_data.whitelisting_choice = Some(choice.to_string());
+    }
+
+    // resendChallenge=Y asks for a new OTP instead of submitting one
+    let resend_requested = challenge_request
+        .get("resendChallenge")
+        .and_then(|v| v.as_str())
+        == Some("Y");
+
+    // oobContinue after the OOB screen: the cardholder is back from the banking app
+    let oob_continuation = challenge::is_oob_continue(challenge_request.get("oobContinue"))
+        && matches!(transaction_data.challenge_ui, Some(ChallengeUi::Oob { .. }));
+
+    // The OTP entered: challengeDataEntry for a native UI, the form's otp field for HTML
+    let submitted_otp = match challenge_request.get("challengeDataEntry") {
+        Some(entry) => Some(entry.as_str().unwrap_or("").to_string()),
+        None => challenge_request
+            .get("challengeHTMLDataEntry")
+            .and_then(|v| v.as_str())
+            .map(|entry| challenge::html_form_otp(entry).unwrap_or_default()),
+    };
+
+    // Check if this is an OTP submission, an OOB continuation, a resend or the initial challenge (matching Node.js behavior)
+    let response_data = if let Some(user_otp) = submitted_otp.as_deref() {
+        // Second request: OTP submission
+        let sdk_counter = challenge_request
+            .get("sdkCounterStoA")
+            .and_then(|v| v.as_str())
+            .unwrap_or("unknown");
+        // Select questions (02/03) are answered with options instead of an OTP
+        let is_select = matches!(
+            transaction_data.challenge_ui,
+            Some(ChallengeUi::SingleSelect { .. } | ChallengeUi::MultiSelect { .. })
+        );
+        let expected_answer = challenge::expected_answer(&settings, &transaction_data);
+        let attempt = check_otp_attempt(
+            &challenge::normalized_answer(transaction_data.challenge_ui.as_ref(), user_otp),
+            &expected_answer,
+            &mut transaction_data.otp_attempts,
+            settings.acs.otp.max_attempts,
+        );
+
+        info!(
+            sdk_counter,
+            verified = attempt == OtpAttempt::Verified,
+            "OTP submitted"
+        );
+        trace!(otp = user_otp, "submitted OTP");
+
+        // Validate expected counter for OTP submission
+        if sdk_counter != "001" {
+            warn!(
+                sdk_counter,
+                "unexpected SDK counter for OTP submission (expected 001)"
+            );
+        }
+
+        if let OtpAttempt::Retry { remaining } = attempt {
+            // Wrong OTP with attempts left: present the form again
+            info!(remaining, "wrong OTP, attempts remaining");
+            let notice = format!(
+                "Incorrect {}. {} attempt(s) remaining.",
+                if is_select { "answer" } else { "OTP" },
+                remaining
+            );
+            let challenge_ui =
+                challenge::repeat_ui(transaction_data.challenge_ui.as_ref(), &notice);
+            transaction_data.challenge_ui = Some(challenge_ui.clone());
+            challenge::record_interaction(&state, three_ds_server_trans_id, &mut transaction_data)
+                .await;
+
+            ChallengeResponseMobile::new(
+                &message_version,
+                three_ds_server_trans_id,
+                acs_trans_id,
+                transaction_data.sdk_trans_id,
+                sdk_counter,
+            )
+            .with_ui(challenge_ui)
+        } else {
+            if attempt == OtpAttempt::LockedOut {
+                info!("maximum OTP attempts reached, ending the challenge");
+            }
+            let outcome = challenge::complete(
+                &state,
+                &settings,
+                three_ds_server_trans_id,
+                &mut transaction_data,
+                Completion {
+                    verified: attempt == OtpAttempt::Verified,
+                    authentication_method: None,
+                    trans_status_reason: (attempt == OtpAttempt::LockedOut)
+                        .then_some(EXCEEDED_MAX_CHALLENGES),
+                    flow: OutcomeFlow::App,
+                },
+            )
+            .await;
+
+            // Final response
+            ChallengeResponseMobile::new(
+                &message_version,
+                three_ds_server_trans_id,
+                acs_trans_id,
+                transaction_data.sdk_trans_id,
+                "001",
+            )
+            .completed(outcome.trans_status)
+        }
+    } else if oob_continuation {
+        let sdk_counter = challenge_request
+            .get("sdkCounterStoA")
+            .and_then(|v| v.as_str())
+            .unwrap_or("unknown");
+        debug!(
+            sdk_counter,
+            "OOB continuation, checking the cardholder's approval"
+        );
+
+        let decision = challenge::oob_decision(
+            &settings.acs.oob,
+            transaction_data.oob_approved,
+            transaction_data.oob_started_at_ms,
+            chrono::Utc::now().timestamp_millis(),
+        );
+        match decision {
+            Some(approved) => {
+                info!(approved, "OOB decided");
+                let outcome = challenge::complete(
+                    &state,
+                    &settings,
+                    three_ds_server_trans_id,
+                    &mut transaction_data,
+                    Completion {
+                        verified: approved,
+                        authentication_method: None,
+                        trans_status_reason: None,
+                        flow: OutcomeFlow::App,
+                    },
+                )
+                .await;
+
+                ChallengeResponseMobile::new(
+                    &message_version,
+                    three_ds_server_trans_id,
+                    acs_trans_id,
+                    transaction_data.sdk_trans_id,
+                    sdk_counter,
+                )
+                .completed(outcome.trans_status)
+            }
+            None => {
+                // Not approved yet: present the OOB screen again so the cardholder can retry
+                debug!("OOB approval still pending");
+                let challenge_ui = ChallengeUi::oob_form(
+                    "We have not received your approval yet. Approve the payment in your banking app, then tap Continue.",
+                );
+                transaction_data.challenge_ui = Some(challenge_ui.clone());
+                challenge::record_interaction(
+                    &state,
+                    three_ds_server_trans_id,
+                    &mut transaction_data,
+                )
+                .await;
+
+                ChallengeResponseMobile::new(
+                    &message_version,
+                    three_ds_server_trans_id,
+                    acs_trans_id,
+                    transaction_data.sdk_trans_id,
+                    sdk_counter,
+                )
+                .with_ui(challenge_ui)
+            }
+        }
+    } else if resend_requested {
+        // Resend: count the interaction and present a fresh OTP form
+        let sdk_counter = challenge_request
+            .get("sdkCounterStoA")
+            .and_then(|v| v.as_str())
+            .unwrap_or("000");
+
+        // Stored with the resend so the next challengeDataEntry is checked against it
+        let challenge_ui = if matches!(
+            transaction_data.challenge_ui,
+            Some(ChallengeUi::Html { .. })
+        ) {
+            challenge::html_challenge_ui(Some("A new OTP has been sent."))
+        } else {
+            ChallengeUi::otp_form_with_info("A new OTP has been sent.")
+        };
+        transaction_data.challenge_ui = Some(challenge_ui.clone());
+        challenge::record_interaction(&state, three_ds_server_trans_id, &mut transaction_data)
+            .await;
+
+        info!(
+            sdk_counter,
+            interactions = transaction_data.interaction_count,
+            "OTP resend requested, presenting a fresh OTP form"
+        );
+
+        // acsCounterAtoS answers the CReq's sdkCounterStoA
+        ChallengeResponseMobile::new(
+            &message_version,
+            three_ds_server_trans_id,
+            acs_trans_id,
+            transaction_data.sdk_trans_id,
+            sdk_counter,
+        )
+        .with_ui(challenge_ui)
+    } else {
+        // First request: Initial challenge (matching Node.js behavior - no challengeDataEntry means initial challenge)
+        let sdk_counter = challenge_request
+            .get("sdkCounterStoA")
+            .and_then(|v| v.as_str())
+            .unwrap_or("unknown");
+
+        // HTML for HTML-only SDKs, OOB when that is what was negotiated, otherwise the card's
+        // select question or an OTP form
+        // Decided at AReq time, as the stored request only has the masked PAN
+        let initial_ui = transaction_data.initial_ui.unwrap_or_else(|| {
+            InitialUi::for_request(
+                &settings,
+                &transaction_data.authenticate_request,
+                transaction_data
+                    .authentication_type
+                    .as_deref()
+                    .unwrap_or(challenge::DEFAULT_AUTHENTICATION_TYPE),
+            )
+        });
+        info!(sdk_counter, ui = ?initial_ui, "initial challenge request");
+
+        // Validate expected counter for initial challenge
+        if sdk_counter != "000" {
+            warn!(
+                sdk_counter,
+                "unexpected SDK counter for initial challenge (expected 000)"
+            );
+        }
+
+        // Remember the presented UI so the submitted challengeDataEntry can be checked against it
+        let challenge_ui = match initial_ui {
+            InitialUi::Oob => {
+                transaction_data.oob_started_at_ms = Some(chrono::Utc::now().timestamp_millis());
+                ChallengeUi::oob_form(
+                    "Open your banking app to approve this payment, then tap Continue.",
+                )
+            }
+            InitialUi::Html => challenge::html_challenge_ui(None),
+            InitialUi::SingleSelect => ChallengeUi::single_select_form(None),
+            InitialUi::MultiSelect => ChallengeUi::multi_select_form(None),
+            InitialUi::Otp => ChallengeUi::otp_form(),
+        };
+        transaction_data.challenge_ui = Some(challenge_ui.clone());
+        if let Err(e) = state
+            .update(&three_ds_server_trans_id, transaction_data.clone())
+            .await
+        {
+            warn!(error = %e, "failed to record the presented challenge UI");
+        }
+
+        let cres = ChallengeResponseMobile::new(
+            &message_version,
+            three_ds_server_trans_id,
+            acs_trans_id,
+            transaction_data.sdk_trans_id,
+            "000",
+        )
+        .with_ui(challenge_ui);
+        // A requestor asking for a whitelist prompt (challenge indicator 09) gets one
+        if transaction_data
+            .authenticate_request
+            .three_ds_requestor
+            .three_ds_requestor_challenge_ind
+            == ChallengeIndicator::ChallengeRequestedWhitelistPrompt
+        {
+            cres.with_whitelisting_prompt("Trust this merchant and skip future challenges?")
+        } else {
+            cres
+        }
+    };
+
+    let response_data = response_data.with_message_extensions(message_extensions);
+
+    recording::record(
+        &state,
+        three_ds_server_trans_id,
+        Direction::Outbound,
+        "CRes",
+        &response_data,
+    )
+    .await;
+
+    let encrypted_response = encrypt_cres(&response_data, acs_trans_id_str, platform, &cres_key)
+        .await
+        .map_err(|e| AppError::crypto("CReq", three_ds_server_trans_id, e))?;
+
+    info!(
+        message_type = %response_data.message_type,
+        trans_status = response_data.trans_status.map(|status| status.as_str()),
+        challenge_completion_ind = %response_data.challenge_completion_ind,
+        "challenge response sent"
+    );
+
+    // Return encrypted JWE response
+    Ok(HttpResponse::Ok()
+        .content_type("application/jose")
+        .body(encrypted_response))
+}
+
+/// SDK platform whose JWE content encryption `enc` is: A128CBC-HS256 Android, A128GCM iOS
+fn sdk_platform(enc: &str) -> Option<&'static str> {
+    match enc {
+        "A128CBC-HS256" => Some("android"),
+        "A128GCM" => Some("ios"),
+        _ => None,
+    }
+}
+
+/// What a CRes or Erro is encrypted with, following the CReq's key management
+enum CresKey {
+    /// alg dir: the derived key
+    Direct(Vec<u8>),
+    /// alg RSA-OAEP-256: the public half of the configured RSA key
+    Rsa(rsa::RsaPublicKey),
+    /// alg ECDH-ES: the SDK ephemeral key from the AReq (or rotated since)
+    EcdhEs(String),
+}
+
+async fn encrypt_cres<T: serde::Serialize + ?Sized>(
+    response: &T,
+    acs_trans_id: &str,
+    platform: &str,
+    key: &CresKey,
+) -> Result<String, CryptoError> {
+    match key {
+        CresKey::Direct(derived_key) => {
+            encrypt_challenge_response(response, acs_trans_id, derived_key, platform).await
+        }
+        CresKey::Rsa(public_key) => {
+            encrypt_challenge_response_rsa(response, acs_trans_id, public_key, platform).await
+        }
+        CresKey::EcdhEs(sdk_public_key) => {
+            encrypt_challenge_response_ecdh_es(response, acs_trans_id, sdk_public_key, platform)
+                .await
+        }
+    }
+}
+
+#[instrument(name = "browser_creq", skip_all, fields(three_ds_server_trans_id = Empty))]
+pub async fn acs_trigger_otp_handler(
+    query: web::Query<HashMap<String, String>>,
+    form: web::Form<AcsTriggerOtpRequest>,
+    settings: web::Data<Settings>,
+    state: web::Data<Arc<Box<dyn StateStore>>>,
+    caches: web::Data<AppCaches>,
+) -> Result<HttpResponse, AppError> {
+    // The creq is base64url-encoded per spec, or plain JSON from older clients
+    let creq =
+        decode_trigger_otp_creq(&form.creq).map_err(|e| AppError::rejected_field("CReq", e))?;
+    let challenge_request: ChallengeRequest =
+        parse_message("CReq", &creq, settings.validation.strict_parsing)?;
+
+    // Extract threeDSServerTransID from the challenge request
+    let three_ds_server_trans_id = challenge_request.three_ds_server_trans_id;
+    record_transaction(three_ds_server_trans_id);
+    validate_browser_creq(&challenge_request)
+        .and_then(|()| {
+            validate_message_extensions(
+                challenge_request.message_extension.as_deref(),
+                &settings.acs.recognized_message_extensions,
+            )
+        })
+        .map_err(|e| AppError::validation("CReq", three_ds_server_trans_id, e))?;
+    recording::record(
+        &state,
+        three_ds_server_trans_id,
+        Direction::Inbound,
+        "CReq",
+        &challenge_request,
+    )
+    .await;
+
+    // The transaction must exist and be the one the CReq names
+    let mut transaction_data = state
+        .get(&three_ds_server_trans_id)
+        .await?
+        .ok_or_else(|| AppError::not_found("CReq", three_ds_server_trans_id))?;
+    check_creq_acs_trans_id(&challenge_request, &transaction_data)?;
+    keep_session_data(
+        &state,
+        three_ds_server_trans_id,
+        &mut transaction_data,
+        &form.three_ds_session_data,
+    )
+    .await?;
+
+    // Determine redirect URL: priority is query parameter > stored transaction data > default fallback
+    let redirect_url = if let Some(query_redirect_url) = query.get("redirectUrl") {
+        // Use redirect URL from query parameter if provided
+        debug!(redirect_url = %query_redirect_url, "using the redirect URL from the query");
+        query_redirect_url.clone()
+    } else {
+        // Fall back to stored redirect URL from transaction data
+        match transaction_data.redirect_url {
+            Some(stored_url) => {
+                debug!(redirect_url = %stored_url, "using the stored redirect URL");
+                stored_url
+            }
+            None => default_redirect_url(
+                &settings,
+                "transaction has no redirect URL",
+                Some(three_ds_server_trans_id),
+            ),
+        }
+    };
+
+    Ok(challenge_page(
+        &settings,
+        &caches,
+        &challenge_request,
+        &redirect_url,
+        None,
+    ))
+}
+
+/// Spec browser CReq: the 3DS Requestor's page form-posts `creq` (the base64url-encoded
+/// CReq) to the acsURL, and the ACS answers with its challenge page
+#[instrument(name = "browser_creq", skip_all, fields(three_ds_server_trans_id = Empty))]
+pub async fn acs_creq_handler(
+    form: web::Form<AcsCreqForm>,
+    settings: web::Data<Settings>,
+    state: web::Data<Arc<Box<dyn StateStore>>>,
+    caches: web::Data<AppCaches>,
+) -> Result<HttpResponse, AppError> {
+    let creq = decode_browser_creq(&form.creq).map_err(|e| AppError::rejected_field("CReq", e))?;
+    let challenge_request: ChallengeRequest =
+        parse_message("CReq", &creq, settings.validation.strict_parsing)?;
+    let three_ds_server_trans_id = challenge_request.three_ds_server_trans_id;
+    record_transaction(three_ds_server_trans_id);
+    validate_browser_creq(&challenge_request)
+        .and_then(|()| {
+            validate_message_extensions(
+                challenge_request.message_extension.as_deref(),
+                &settings.acs.recognized_message_extensions,
+            )
+        })
+        .map_err(|e| AppError::validation("CReq", three_ds_server_trans_id, e))?;
+
+    let mut transaction_data = state
+        .get(&three_ds_server_trans_id)
+        .await?
+        .ok_or_else(|| AppError::not_found("CReq", three_ds_server_trans_id))?;
+    check_creq_acs_trans_id(&challenge_request, &transaction_data)?;
+    keep_session_data(
+        &state,
+        three_ds_server_trans_id,
+        &mut transaction_data,
+        &form.three_ds_session_data,
+    )
+    .await?;
+    recording::record(
+        &state,
+        three_ds_server_trans_id,
+        Direction::Inbound,
+        "CReq",
+        &challenge_request,
+    )
+    .await;
+
+    info!(
+        challenge_window_size = %challenge_request.challenge_window_size,
+        session_data_length = form.three_ds_session_data.as_ref().map(String::len),
+        "processing browser challenge request"
+    );
+
+    // The challenge outcome goes back to the notificationURL stored at AReq time
+    let redirect_url = transaction_data.redirect_url.unwrap_or_else(|| {
+        default_redirect_url(
+            &settings,
+            "transaction has no notificationURL",
+            Some(three_ds_server_trans_id),
+        )
+    });
+
+    Ok(challenge_page(
+        &settings,
+        &caches,
+        &challenge_request,
+        &redirect_url,
+        None,
+    ))
+}
+
+/// A browser CReq must carry the acsTransID issued for its transaction; another one is
+/// answered with Erro 301
+fn check_creq_acs_trans_id(
+    challenge_request: &ChallengeRequest,
+    transaction_data: &TransactionData,
+) -> Result<(), AppError> {
+    if transaction_data.acs_trans_id == challenge_request.acs_trans_id {
+        return Ok(());
+    }
+    Err(AppError::validation(
+        "CReq",
+        challenge_request.three_ds_server_trans_id,
+        FieldError::unrecognized_transaction_id("acsTransID"),
+    ))
+}
+
+/// The configured `server.default_redirect_url`, with a warning naming why it was needed:
+/// a challenge outcome sent there never reaches the merchant
+fn default_redirect_url(
+    settings: &Settings,
+    reason: &str,
+    three_ds_server_trans_id: Option<Uuid>,
+) -> String {
+    let redirect_url = &settings.server.default_redirect_url;
+    warn!(
+        url = %redirect_url,
+        reason,
+        three_ds_server_trans_id = %three_ds_server_trans_id
+            .map_or_else(|| "unknown".to_string(), |id| id.to_string()),
+        "using the default redirect URL"
+    );
+    redirect_url.clone()
+}
+
+/// Store the browser CReq's threeDSSessionData on the transaction: the 3DS Requestor gets
+/// it back, unchanged, next to the final CRes
+async fn keep_session_data(
+    state: &web::Data<Arc<Box<dyn StateStore>>>,
+    three_ds_server_trans_id: Uuid,
+    transaction_data: &mut TransactionData,
+    three_ds_session_data: &Option<String>,
+) -> Result<(), AppError> {
+    if three_ds_session_data.is_none()
+        || transaction_data.three_ds_session_data == *three_ds_session_data
+    {
+        return Ok(());
+    }
+    transaction_data.three_ds_session_data = three_ds_session_data.clone();
+    state
+        .update(&three_ds_server_trans_id, transaction_data.clone())
+        .await?;
+    Ok(())
+}
+
+/// Browser "Resend OTP": counts the interaction and serves a fresh challenge page telling
+/// the cardholder a new code was sent
+#[instrument(name = "browser_resend", skip_all, fields(three_ds_server_trans_id = Empty))]
+pub async fn acs_resend_otp_handler(
+    query: web::Query<HashMap<String, String>>,
+    form: web::Form<AcsResendOtpRequest>,
+    settings: web::Data<Settings>,
+    state: web::Data<Arc<Box<dyn StateStore>>>,
+    caches: web::Data<AppCaches>,
+) -> Result<HttpResponse, AppError> {
+    let three_ds_server_trans_id =
+        Uuid::parse_str(&form.three_ds_server_trans_id).map_err(|_| {
+            AppError::rejected_field(
+                "CReq",
+                FieldError::invalid(
+                    "threeDSServerTransID",
+                    "threeDSServerTransID must be a UUID",
+                ),
+            )
+        })?;
+    record_transaction(three_ds_server_trans_id);
+    let mut transaction_data = state
+        .get(&three_ds_server_trans_id)
+        .await?
+        .ok_or_else(|| AppError::not_found("CReq", three_ds_server_trans_id))?;
+
+    // The window size is echoed into the new page, so it is held to the CReq's values
+    let challenge_request = browser_challenge_request(
+        three_ds_server_trans_id,
+        &transaction_data,
+        &form.challenge_window_size,
+    );
+    validate_browser_creq(&challenge_request)
+        .map_err(|e| AppError::validation("CReq", three_ds_server_trans_id, e))?;
+
+    challenge::record_interaction(&state, three_ds_server_trans_id, &mut transaction_data).await;
+    info!(
+        interactions = transaction_data.interaction_count,
+        "OTP resend requested"
+    );
+
+    let redirect_url = query
+        .get("redirectUrl")
+        .cloned()
+        .or_else(|| transaction_data.redirect_url.clone())
+        .unwrap_or_else(|| {
+            default_redirect_url(
+                &settings,
+                "transaction has no redirect URL",
+                Some(three_ds_server_trans_id),
+            )
+        });
+
+    Ok(challenge_page(
+        &settings,
+        &caches,
+        &challenge_request,
+        &redirect_url,
+        Some("A new code has been sent."),
+    ))
+}
+
+/// The CReq a challenge page served again (resend, wrong OTP) stands for; the page carries
+/// only the transaction ID and window size
+fn browser_challenge_request(
+    three_ds_server_trans_id: Uuid,
+    transaction_data: &TransactionData,
+    challenge_window_size: &str,
+) -> ChallengeRequest {
+    ChallengeRequest {
+        message_type: "CReq".to_string(),
+        three_ds_server_trans_id,
+        acs_trans_id: transaction_data.acs_trans_id,
+        challenge_window_size: challenge_window_size.to_string(),
+        message_version: transaction_data.message_version().to_string(),
+        message_extension: None,
+    }
+}
+
+/// The OTP challenge page, posting the entered OTP to verify-otp which then redirects
+/// to `redirect_url` with the outcome; "Resend OTP" posts to resend-otp for a fresh page.
+/// `notice` explains why the page is shown again. Sent with the configured framing headers
+/// so it can be embedded in the merchant's challenge iframe.
+fn challenge_page(
+    settings: &Settings,
+    caches: &AppCaches,
+    challenge_request: &ChallengeRequest,
+    redirect_url: &str,
+    notice: Option<&str>,
+) -> HttpResponse {
+    let page_config = &settings.acs.challenge_page;
+    let layout_class = if page_config.iframe_layout(&challenge_request.challenge_window_size) {
+        "iframe"
+    } else {
+        "standalone"
+    };
+
+    // Build dynamic URLs using server configuration
+    let server_url = settings.acs_public_url();
+    let fallback_redirect_url = server_url.clone();
+    let pay_endpoint = format!(
+        "{}/processor/mock/acs/verify-otp?redirectUrl={}",
+        server_url,
+        urlencoding::encode(redirect_url)
+    );
+    let resend_endpoint = format!(
+        "{}/processor/mock/acs/resend-otp?redirectUrl={}",
+        server_url,
+        urlencoding::encode(redirect_url)
+    );
+    let challenge_info_text = match notice {
+        Some(notice) => format!("{} Please enter your password.", notice),
+        None => "Please enter your password.".to_string(),
+    };
+
+    // Load the HTML template with its per-server placeholders filled in, then the per-request ones
+    let template_content = caches.static_response("acs-challenge.html", || {
+        include_str!("../../templates/acs-challenge.html")
+            .replace("{{FALLBACK_REDIRECT_URL}}", &fallback_redirect_url)
+    });
+    let html_content = template_content
+        .replace(
+            "{{THREE_DS_SERVER_TRANS_ID}}",
+            &challenge_request.three_ds_server_trans_id.to_string(),
+        )
+        .replace("{{PAY_ENDPOINT}}", &pay_endpoint)
+        .replace("{{RESEND_ENDPOINT}}", &resend_endpoint)
+        .replace(
+            "{{CHALLENGE_WINDOW_SIZE}}",
+            &js_string_literal(&challenge_request.challenge_window_size),
+        )
+        .replace("{{CHALLENGE_INFO_TEXT}}", &challenge_info_text)
+        .replace("{{LAYOUT_CLASS}}", layout_class);
+
+    challenge_page_response(page_config).body(html_content)
+}
+
+/// An HTML response in the challenge window, with the page's framing headers
+fn challenge_page_response(page_config: &ChallengePageConfig) -> HttpResponseBuilder {
+    let mut response = HttpResponse::Ok();
+    response
+        .content_type("text/html; charset=utf-8")
+        .insert_header((
+            header::CONTENT_SECURITY_POLICY,
+            page_config.content_security_policy(),
+        ));
+    if let Some(x_frame_options) = &page_config.x_frame_options {
+        response.insert_header((
+            header::X_FRAME_OPTIONS,
+            x_frame_options.to_ascii_uppercase(),
+        ));
+    }
+    response
+}
+
+/// The end of a browser challenge: a page auto-posting the final CRes, base64url-encoded,
+/// as `cres` to the notificationURL, with the CReq's `threeDSSessionData` when it had one
+fn cres_post_page(
+    page_config: &ChallengePageConfig,
+    notification_url: &str,
+    cres: &ChallengeResponseBrowser,
+    three_ds_session_data: Option<&str>,
+) -> Result<HttpResponse, serde_json::Error> {
+    let encoded_cres = general_purpose::URL_SAFE_NO_PAD.encode(serde_json::to_vec(cres)?);
+    let session_data = three_ds_session_data.map_or_else(|| "null".to_string(), js_string_literal);
+    let html_content = include_str!("../../templates/acs-cres-post.html")
+        .replace("{{NOTIFICATION_URL}}", &js_string_literal(notification_url))
+        .replace("{{CRES}}", &js_string_literal(&encoded_cres))
+        .replace("{{THREE_DS_SESSION_DATA}}", &session_data);
+
+    Ok(challenge_page_response(page_config).body(html_content))
+}
+
+#[instrument(name = "browser_otp", skip_all, fields(three_ds_server_trans_id = Empty))]
+pub async fn acs_verify_otp_handler(
+    query: web::Query<HashMap<String, String>>,
+    form: web::Form<AcsVerifyOtpRequest>,
+    state: web::Data<Arc<Box<dyn StateStore>>>,
+    settings: web::Data<Settings>,
+    caches: web::Data<AppCaches>,
+) -> Result<HttpResponse, AppError> {
+    // Extract redirect URL from query parameters
+    let redirect_url = query.get("redirectUrl").cloned().unwrap_or_else(|| {
+        let three_ds_server_trans_id = Uuid::parse_str(&form.three_ds_server_trans_id).ok();
+        default_redirect_url(
+            &settings,
+            "no redirectUrl query parameter",
+            three_ds_server_trans_id,
+        )
+    });
+
+    // Default error response - still redirect as requested
+    let error_redirect = format!("{}?transStatus=U&error=processing_error", redirect_url);
+
+    // Parse transaction ID
+    let three_ds_server_trans_id = match Uuid::parse_str(&form.three_ds_server_trans_id) {
+        Ok(id) => id,
+        Err(_) => {
+            warn!(
+                three_ds_server_trans_id = %form.three_ds_server_trans_id,
+                "invalid transaction ID format"
+            );
+            return Ok(HttpResponse::Found()
+                .append_header(("Location", error_redirect))
+                .finish());
+        }
+    };
+    record_transaction(three_ds_server_trans_id);
+
+    // Get transaction data from state
+    match state.get(&three_ds_server_trans_id).await {
+        Ok(Some(mut transaction_data)) => {
+            recording::record(
+                &state,
+                three_ds_server_trans_id,
+                Direction::Inbound,
+                "ChallengeForm",
+                &serde_json::json!({
+                    "otp": form.otp,
+                    "threeDSServerTransID": three_ds_server_trans_id,
+                }),
+            )
+            .await;
+            let expected_otp = challenge::expected_otp(&settings, &transaction_data).to_string();
+            let attempt = check_otp_attempt(
+                &form.otp,
+                &expected_otp,
+                &mut transaction_data.otp_attempts,
+                settings.acs.otp.max_attempts,
+            );
+
+            // Wrong OTP with attempts left: serve the challenge page again
+            if let OtpAttempt::Retry { remaining } = attempt {
+                info!(remaining, "wrong OTP, attempts remaining");
+                challenge::record_interaction(
+                    &state,
+                    three_ds_server_trans_id,
+                    &mut transaction_data,
+                )
+                .await;
+                // Echoed into the page's script: anything but a CReq value gets full screen
+                let challenge_window_size = form
+                    .challenge_window_size
+                    .as_deref()
+                    .filter(|size| is_challenge_window_size(size))
+                    .unwrap_or("05");
+                let challenge_request = browser_challenge_request(
+                    three_ds_server_trans_id,
+                    &transaction_data,
+                    challenge_window_size,
+                );
+                let notice = format!("Incorrect code, {} attempt(s) remaining.", remaining);
+                return Ok(challenge_page(
+                    &settings,
+                    &caches,
+                    &challenge_request,
+                    &redirect_url,
+                    Some(&notice),
+                ));
+            }
+            let trans_status_reason =
+                (attempt == OtpAttempt::LockedOut).then_some(EXCEEDED_MAX_CHALLENGES);
+            if trans_status_reason.is_some() {
+                info!("maximum OTP attempts reached");
+            }
+
+            // Failures to record the RReq are logged; the cardholder is redirected regardless
+            let ChallengeOutcome {
+                trans_status,
+                eci,
+                authentication_value,
+            } = challenge::complete(
+                &state,
+                &settings,
+                three_ds_server_trans_id,
+                &mut transaction_data,
+                Completion {
+                    verified: attempt == OtpAttempt::Verified,
+                    authentication_method: None,
+                    trans_status_reason,
+                    flow: OutcomeFlow::Browser,
+                },
+            )
+            .await;
+
+            info!(trans_status = %trans_status, eci = %eci, "OTP challenge completed");
+            trace!(otp = %form.otp, "submitted OTP");
+
+            // Spec flow: the final CRes is posted to the notificationURL
+            if settings.acs.challenge_page.completion == ChallengeCompletion::CresPost {
+                let cres = ChallengeResponseBrowser {
+                    three_ds_server_trans_id,
+                    acs_trans_id: transaction_data.acs_trans_id,
+                    message_type: "CRes".to_string(),
+                    message_version: transaction_data.message_version().to_string(),
+                    challenge_completion_ind: "Y".to_string(),
+                    trans_status,
+                };
+                recording::record(
+                    &state,
+                    three_ds_server_trans_id,
+                    Direction::Outbound,
+                    "CRes",
+                    &cres,
+                )
+                .await;
+                debug!(%redirect_url, "posting the final CRes");
+                return cres_post_page(
+                    &settings.acs.challenge_page,
+                    &redirect_url,
+                    &cres,
+                    transaction_data.three_ds_session_data.as_deref(),
+                )
+                .map_err(|e| {
+                    AppError::unexpected("CReq", three_ds_server_trans_id, e.to_string())
+                });
+            }
+
+            // Legacy flow: redirect with the outcome in query parameters with status parameters
+            let mut redirect_with_params = format!(
+                "{}?transStatus={}&threeDSServerTransID={}&eci={}",
+                redirect_url, trans_status, three_ds_server_trans_id, eci
+            );
+            if let Some(authentication_value) = authentication_value {
+                redirect_with_params.push_str(&format!(
+                    "&authenticationValue={}",
+                    urlencoding::encode(&authentication_value)
+                ));
+            }
+            if let Some(reason) = trans_status_reason {
+                redirect_with_params.push_str(&format!("&transStatusReason={}", reason));
+            }
+
+            debug!(location = %redirect_with_params, "redirecting with the outcome");
+
+            Ok(HttpResponse::Found()
+                .append_header(("Location", redirect_with_params))
+                .finish())
+        }
+        Ok(None) => {
+            warn!("transaction not found");
+            Ok(HttpResponse::Found()
+                .append_header(("Location", error_redirect))
+                .finish())
+        }
+        Err(e) => {
+            warn!(error = %e, "failed to read the transaction");
+            Ok(HttpResponse::Found()
+                .append_header(("Location", error_redirect))
+                .finish())
+        }
+    }
+}
+
+/// 3DS Method: the requestor's hidden iframe posts threeDSMethodData here. The page
+/// collects browser device data, posts it to the device-data endpoint, then notifies the
+/// threeDSMethodNotificationURL.
+#[instrument(name = "three_ds_method", skip_all, fields(three_ds_server_trans_id = Empty))]
+pub async fn three_ds_method_handler(
+    form: web::Form<ThreeDsMethodForm>,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse, AppError> {
+    let method_data = decode_three_ds_method_data(&form.three_ds_method_data)
+        .map_err(|e| AppError::rejected_field("3DSMethod", e))?;
+    let three_ds_server_trans_id = method_data.three_ds_server_trans_id;
+    record_transaction(three_ds_server_trans_id);
+
+    info!(
+        notification_url = %method_data.three_ds_method_notification_url,
+        "collecting browser device data"
+    );
+
+    let notification_data = encode_method_notification_data(three_ds_server_trans_id);
+    let device_data_endpoint = format!(
+        "{}{}",
+        settings.acs_public_url(),
+        ACS_METHOD_DEVICE_DATA_PATH
+    );
+
+    let html_content = include_str!("../../templates/three-ds-method.html")
+        .replace(
+            "{{THREE_DS_SERVER_TRANS_ID}}",
+            &js_string_literal(&three_ds_server_trans_id.to_string()),
+        )
+        .replace(
+            "{{DEVICE_DATA_ENDPOINT}}",
+            &js_string_literal(&device_data_endpoint),
+        )
+        .replace(
+            "{{NOTIFICATION_URL}}",
+            &js_string_literal(&method_data.three_ds_method_notification_url),
+        )
+        .replace(
+            "{{NOTIFICATION_DATA}}",
+            &js_string_literal(&notification_data),
+        );
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(html_content))
+}
+
+/// Device data posted by the 3DS Method page, held until the AReq for the transaction
+#[instrument(
+    name = "three_ds_method",
+    skip_all,
+    fields(three_ds_server_trans_id = %req.three_ds_server_trans_id)
+)]
+pub async fn method_device_data_handler(
+    req: web::Json<MethodDeviceDataSubmission>,
+    state: web::Data<Arc<Box<dyn StateStore>>>,
+) -> Result<HttpResponse, AppError> {
+    let submission = req.into_inner();
+    let device_data = submission.device_data;
+
+    info!(
+        screen_width = %device_data.browser_screen_width,
+        screen_height = %device_data.browser_screen_height,
+        tz_offset = %device_data.browser_tz,
+        language = %device_data.browser_language,
+        "3DS Method device data received"
+    );
+
+    state
+        .insert_method_data(submission.three_ds_server_trans_id, device_data)
+        .await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// A value as a JavaScript string literal, safe to embed in an inline script
+fn js_string_literal(value: &str) -> String {
+    Value::from(value).to_string().replace("</", "<\\/")
+}
+
+/// Mock SPC assertion verification - completes a transStatus = S authentication
+#[instrument(
+    name = "spc_verify",
+    skip_all,
+    fields(three_ds_server_trans_id = %req.three_ds_server_trans_id)
+)]
+pub async fn acs_spc_verify_handler(
+    req: web::Json<SpcVerifyRequest>,
+    state: web::Data<Arc<Box<dyn StateStore>>>,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse, AppError> {
+    let three_ds_server_trans_id = req.three_ds_server_trans_id;
+
+    let mut transaction_data = state
+        .get(&three_ds_server_trans_id)
+        .await?
+        .ok_or_else(|| AppError::not_found("SPC", three_ds_server_trans_id))?;
+
+    // Each SPC challenge can only be answered once
+    let expected_challenge = transaction_data.spc_challenge.take().ok_or_else(|| {
+        AppError::unexpected(
+            "SPC",
+            three_ds_server_trans_id,
+            "No pending SPC challenge for this transaction",
+        )
+    })?;
+
+    let verified = !req.signature.is_empty()
+        && verify_spc_assertion(&req.client_data_json, &expected_challenge);
+    let ChallengeOutcome {
+        trans_status,
+        eci,
+        authentication_value,
+    } = challenge::complete(
+        &state,
+        &settings,
+        three_ds_server_trans_id,
+        &mut transaction_data,
+        Completion {
+            verified,
+            authentication_method: Some(SPC_AUTHENTICATION_METHOD),
+            trans_status_reason: None,
+            flow: OutcomeFlow::Spc,
+        },
+    )
+    .await;
+
+    info!(
+        credential_id = %req.credential_id,
+        trans_status = %trans_status,
+        eci = %eci,
+        "SPC assertion verified"
+    );
+
+    Ok(HttpResponse::Ok().json(SpcVerifyResponse {
+        three_ds_server_trans_id,
+        spc_trans_status: trans_status,
+        trans_status,
+        eci,
+        authentication_value,
+    }))
+}
+
+#[instrument(
+    name = "rreq",
+    skip_all,
+    fields(three_ds_server_trans_id = %req.three_ds_server_trans_id)
+)]
+pub async fn results_handler(
+    req: web::Json<ResultsRequest>,
+    state: web::Data<Arc<Box<dyn StateStore>>>,
+) -> Result<HttpResponse, AppError> {
+    let three_ds_server_trans_id = req.three_ds_server_trans_id;
+
+    // Get the existing transaction data
+    let mut transaction_data = state
+        .get(&three_ds_server_trans_id)
+        .await?
+        .ok_or_else(|| AppError::not_found("RReq", three_ds_server_trans_id))?;
+
+    if transaction_data.results_request.is_some() {
+        return Err(AppError::validation(
+            "RReq",
+            three_ds_server_trans_id,
+            FieldError::duplicate_results(),
+        ));
+    }
+    validate_results_request(
+        &req,
+        transaction_data.acs_trans_id,
+        transaction_data.ds_trans_id,
+    )
+    .map_err(|e| AppError::validation("RReq", three_ds_server_trans_id, e))?;
+    recording::record(
+        &state,
+        three_ds_server_trans_id,
+        Direction::Inbound,
+        "RReq",
+        &*req,
+    )
+    .await;
+
+    // The RRes answers in the RReq's messageVersion
+    let message_version = req.message_version.clone();
+
+    // Update the transaction data with results request
+    transaction_data.results_request = Some(req.into_inner());
+
+    // Store the updated transaction data
+    state
+        .update(&three_ds_server_trans_id, transaction_data.clone())
+        .await?;
+
+    let response = ResultsResponse {
+        ds_trans_id: transaction_data.ds_trans_id,
+        message_type: "RRes".to_string(),
+        three_ds_server_trans_id,
+        acs_trans_id: transaction_data.acs_trans_id,
+        sdk_trans_id: transaction_data.sdk_trans_id,
+        results_status: "01".to_string(),
+        message_version,
+    };
+    recording::record(
+        &state,
+        three_ds_server_trans_id,
+        Direction::Outbound,
+        "RRes",
+        &response,
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::test_settings;
+    use crate::handlers::tests::{sample_areq, TestState};
+
+    #[test]
+    fn test_broad_info_follows_the_configuration() {
+        let mut settings = test_settings();
+        settings.acs.broad_info.message = "Scheduled maintenance on 1 May".to_string();
+        settings.scenarios.no_broad_info_card_suffixes = vec!["4012".to_string()];
+
+        let broad_info = broad_info_for(&settings, "4000000000001000").unwrap();
+        assert_eq!(
+            broad_info.description.message,
+            "Scheduled maintenance on 1 May"
+        );
+        assert_eq!(broad_info.exp_date, "20301231");
+        // Cards listed in no_broad_info_card_suffixes get none
+        assert!(broad_info_for(&settings, "4000000000004012").is_none());
+
+        settings.acs.broad_info.enabled = false;
+        assert!(broad_info_for(&settings, "4000000000001000").is_none());
+    }
+
+    #[test]
+    fn test_areq_body_is_parsed_leniently_or_strictly() {
+        let mut areq = sample_areq();
+        let body = serde_json::to_vec(&areq).unwrap();
+        assert!(parse_message::<AuthenticateRequest>("AReq", &body, true).is_ok());
+
+        areq["purchase"]["purchaseColour"] = Value::from("blue");
+        let body = serde_json::to_vec(&areq).unwrap();
+        assert!(parse_message::<AuthenticateRequest>("AReq", &body, false).is_ok());
+        let error = parse_message::<AuthenticateRequest>("AReq", &body, true)
+            .unwrap_err()
+            .to_error_message();
+        assert_eq!(error.error_code, "203");
+        assert_eq!(error.error_detail, "purchase.purchaseColour");
+    }
+
+    #[actix_web::test]
+    async fn test_data_share_only_areq_is_answered_for_information_only() {
+        let app = TestState::new(test_settings());
+
+        // Even a challenge card is not challenged when the requestor only shares data
+        let mut areq = sample_areq();
+        areq["threeDsRequestor"]["threeDsRequestorChallengeInd"] = Value::from("06");
+        let ares = app.authenticate(&areq).await.unwrap();
+        assert_eq!(ares["transStatus"], "I");
+        assert!(ares.get("acsUrl").is_none());
+        let authentication_response = &ares["authenticationResponse"];
+        assert_eq!(authentication_response["transStatus"], "I");
+        assert_eq!(authentication_response["eci"], "07");
+        assert!(authentication_response.get("authenticationValue").is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_not_authenticated_card_is_declined_frictionlessly() {
+        let app = TestState::new(test_settings());
+
+        let mut areq = sample_areq();
+        areq["cardholderAccount"]["acctNumber"] = Value::from("4000000000004009");
+        let ares = app.authenticate(&areq).await.unwrap();
+        assert_eq!(ares["transStatus"], "N");
+        assert_eq!(ares["acsChallengeMandated"], "N");
+        assert!(ares.get("acsUrl").is_none());
+        let authentication_response = &ares["authenticationResponse"];
+        assert_eq!(authentication_response["transStatusReason"], "01");
+        assert_eq!(authentication_response["eci"], "07");
+        // Left out of declines, as the specification requires
+        assert!(authentication_response.get("authenticationValue").is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_resend_otp_counts_the_interaction_and_checks_the_window_size() {
+        let app = TestState::new(test_settings());
+        let mut areq = sample_areq();
+        let id = Uuid::new_v4();
+        areq["threeDsServerTransId"] = id.to_string().into();
+        app.authenticate(&areq).await.unwrap();
+
+        let resend = |challenge_window_size: &str| {
+            acs_resend_otp_handler(
+                web::Query(HashMap::new()),
+                web::Form(AcsResendOtpRequest {
+                    three_ds_server_trans_id: id.to_string(),
+                    challenge_window_size: challenge_window_size.to_string(),
+                }),
+                app.settings.clone(),
+                app.state.clone(),
+                app.caches.clone(),
+            )
+        };
+        let interactions =
+            || async { app.state.get(&id).await.unwrap().unwrap().interaction_count };
+
+        // Echoed into the page's script, so anything but a CReq window size is refused
+        assert!(resend("\";alert(1);//").await.is_err());
+        assert_eq!(interactions().await, 0);
+
+        let page = resend("05").await.unwrap();
+        let page = actix_web::body::to_bytes(page.into_body()).await.unwrap();
+        let page = String::from_utf8(page.to_vec()).unwrap();
+        assert!(page.contains("A new code has been sent."));
+        assert!(page.contains(r#"const challengeWindowSize = "05";"#));
+        assert_eq!(interactions().await, 1);
+    }
+
+    #[actix_web::test]
+    async fn test_wrong_otp_page_keeps_only_a_valid_window_size() {
+        let app = TestState::new(test_settings());
+        let mut areq = sample_areq();
+        let id = Uuid::new_v4();
+        areq["threeDsServerTransId"] = id.to_string().into();
+        app.authenticate(&areq).await.unwrap();
+
+        let page = acs_verify_otp_handler(
+            web::Query(HashMap::new()),
+            web::Form(AcsVerifyOtpRequest {
+                otp: "0000".to_string(),
+                three_ds_server_trans_id: id.to_string(),
+                challenge_window_size: Some("\";alert(1);//".to_string()),
+            }),
+            app.state.clone(),
+            app.settings.clone(),
+            app.caches.clone(),
+        )
+        .await
+        .unwrap();
+        let page = actix_web::body::to_bytes(page.into_body()).await.unwrap();
+        let page = String::from_utf8(page.to_vec()).unwrap();
+        let remaining = app.settings.acs.otp.max_attempts - 1;
+        assert!(page.contains(&format!("Incorrect code, {} attempt(s)", remaining)));
+        assert!(page.contains(r#"const challengeWindowSize = "05";"#));
+        assert!(!page.contains("alert(1)"));
+    }
+
+    #[actix_web::test]
+    async fn test_scheme_profile_overrides_the_identities() {
+        let mut settings = test_settings();
+        settings.ds.profiles.insert(
+            "visa".to_string(),
+            crate::config::DsProfile {
+                three_ds_server_reference_number: Some("3DS_LOA_SER_VISA_020200_00002".to_string()),
+                three_ds_server_operator_id: Some("VISA-OPERATOR".to_string()),
+                three_ds_server_url: Some("https://visa.3ds.example.com/results".to_string()),
+                acs_operator_id: Some("VISA-ACS-OPERATOR".to_string()),
+                acs_reference_number: Some("VISA-ACS-REF".to_string()),
+                ..Default::default()
+            },
+        );
+        let app = TestState::new(settings);
+
+        let mut areq = sample_areq();
+        let response = app.authenticate(&areq).await.unwrap();
+        let echoed = &response["authenticationRequest"];
+        assert_eq!(
+            echoed["threeDSServerRefNumber"],
+            "3DS_LOA_SER_VISA_020200_00002"
+        );
+        assert_eq!(echoed["threeDSServerOperatorID"], "VISA-OPERATOR");
+        assert_eq!(
+            echoed["threeDSServerURL"],
+            "https://visa.3ds.example.com/results"
+        );
+        let ares = &response["authenticationResponse"];
+        assert_eq!(ares["acsOperatorID"], "VISA-ACS-OPERATOR");
+        assert_eq!(ares["acsReferenceNumber"], "VISA-ACS-REF");
+
+        // Schemes without a profile keep the global identities
+        areq["threeDsServerTransId"] = Uuid::new_v4().to_string().into();
+        areq["cardholderAccount"]["schemeId"] = Value::from("MASTERCARD");
+        let response = app.authenticate(&areq).await.unwrap();
+        assert_eq!(
+            response["authenticationRequest"]["threeDSServerRefNumber"],
+            app.settings.three_ds_server.reference_number.as_str()
+        );
+        assert_eq!(
+            response["authenticationResponse"]["acsReferenceNumber"],
+            app.settings.acs.reference_number.as_str()
+        );
+    }
+}
@@ -0,0 +1,439 @@
+//! Admin API: issuer-side simulation data the ACS decision consults, and a snapshot of
+//! what the instance is configured to do.
+
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::card_scheme::CardScheme;
+use crate::card_status::{is_valid_pan, CardStatus, CardStatusList};
+use crate::challenge::{expected_otp, results_request_for, ChallengeOutcome};
+use crate::config::{Settings, SuccessLiability, API_VERSION};
+use crate::error::AppError;
+use crate::faults::{FaultInjector, FaultProfile};
+use crate::handlers::server::configured_card_range;
+use crate::interchange;
+use crate::models::{CardRange, Eci, TransStatus};
+use crate::performance::{PerformanceSwitches, PerformanceUpdate};
+use crate::recording::FlowCapture;
+use crate::state_store::{StateStore, TransactionData};
+use crate::validation::validate_results_request;
+
+/// transStatusReason of an expired transaction (14 = transaction timed out at the ACS)
+const TIMED_OUT_REASON: &str = "14";
+
+#[derive(Debug, Deserialize)]
+pub struct CardStatusUpdate {
+    pub status: CardStatus,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OobDecision {
+    pub approved: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FaultProfileSelection {
+    pub profile: String,
+}
+
+/// Result to record as if the ACS had posted it. eci and authenticationValue default to
+/// what the ACS reports with the transStatus.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ForcedResult {
+    pub trans_status: TransStatus,
+    #[serde(default)]
+    pub eci: Option<Eci>,
+    #[serde(default)]
+    pub authentication_value: Option<String>,
+    #[serde(default)]
+    pub trans_status_reason: Option<String>,
+}
+
+/// All cards with a simulated status, keyed by PAN
+pub async fn list_card_statuses(statuses: web::Data<CardStatusList>) -> HttpResponse {
+    HttpResponse::Ok().json(statuses.all())
+}
+
+/// Set the simulated status of a card
+pub async fn put_card_status(
+    pan: web::Path<String>,
+    update: web::Json<CardStatusUpdate>,
+    statuses: web::Data<CardStatusList>,
+) -> HttpResponse {
+    let pan = pan.into_inner();
+    if !is_valid_pan(&pan) {
+        return HttpResponse::BadRequest().json(json!({
+            "error": "PAN must be 12-19 digits"
+        }));
+    }
+
+    info!(
+        card = %format_args!("***{}", &pan[pan.len() - 4..]),
+        status = ?update.status,
+        "card status set"
+    );
+    statuses.set(pan.clone(), update.status);
+    HttpResponse::Ok().json(json!({ "pan": pan, "status": update.status }))
+}
+
+/// Remove a card's simulated status, making it active again
+pub async fn delete_card_status(
+    pan: web::Path<String>,
+    statuses: web::Data<CardStatusList>,
+) -> HttpResponse {
+    if statuses.remove(&pan) {
+        HttpResponse::NoContent().finish()
+    } else {
+        HttpResponse::NotFound().json(json!({ "error": "No status set for this PAN" }))
+    }
+}
+
+/// The OTP a challenged transaction accepts, so test suites can complete challenges that use
+/// random or per-card OTPs
+pub async fn transaction_otp(
+    three_ds_server_trans_id: web::Path<Uuid>,
+    state: web::Data<Arc<Box<dyn StateStore>>>,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse, AppError> {
+    let three_ds_server_trans_id = three_ds_server_trans_id.into_inner();
+    let transaction_data = state
+        .get(&three_ds_server_trans_id)
+        .await?
+        .ok_or_else(|| AppError::not_found("CReq", three_ds_server_trans_id))?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "threeDSServerTransID": three_ds_server_trans_id,
+        "otp": expected_otp(&settings, &transaction_data),
+        "otpAttempts": transaction_data.otp_attempts,
+        "maxAttempts": settings.acs.otp.max_attempts
+    })))
+}
+
+/// Approve or decline a pending out-of-band challenge as the cardholder's banking app would;
+/// the SDK's next continuation CReq completes it
+pub async fn decide_oob(
+    three_ds_server_trans_id: web::Path<Uuid>,
+    decision: web::Json<OobDecision>,
+    state: web::Data<Arc<Box<dyn StateStore>>>,
+) -> Result<HttpResponse, AppError> {
+    let three_ds_server_trans_id = three_ds_server_trans_id.into_inner();
+    let mut transaction_data = state
+        .get(&three_ds_server_trans_id)
+        .await?
+        .ok_or_else(|| AppError::not_found("CReq", three_ds_server_trans_id))?;
+    if transaction_data.oob_started_at_ms.is_none() {
+        return Ok(HttpResponse::Conflict().json(json!({
+            "error": "No OOB challenge was presented for this transaction"
+        })));
+    }
+
+    info!(
+        %three_ds_server_trans_id,
+        approved = decision.approved,
+        "OOB decided"
+    );
+    transaction_data.oob_approved = Some(decision.approved);
+    state
+        .update(&three_ds_server_trans_id, transaction_data)
+        .await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "threeDSServerTransID": three_ds_server_trans_id,
+        "approved": decision.approved
+    })))
+}
+
+/// Record a synthetic RReq for a transaction, as if the ACS had posted results, so testers
+/// can reach /3ds/final without driving the challenge UI
+pub async fn force_result(
+    three_ds_server_trans_id: web::Path<Uuid>,
+    forced: web::Json<ForcedResult>,
+    state: web::Data<Arc<Box<dyn StateStore>>>,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse, AppError> {
+    let three_ds_server_trans_id = three_ds_server_trans_id.into_inner();
+    let forced = forced.into_inner();
+    let transaction_data = state
+        .get(&three_ds_server_trans_id)
+        .await?
+        .ok_or_else(|| AppError::not_found("RReq", three_ds_server_trans_id))?;
+    if transaction_data.results_request.is_some() || transaction_data.frictionless_outcome.is_some()
+    {
+        return Ok(HttpResponse::Conflict().json(json!({
+            "error": "This transaction already has a result"
+        })));
+    }
+
+    // The forced status is reported as given, whatever the card's success liability
+    let Some(mut outcome) = ChallengeOutcome::for_status(
+        forced.trans_status,
+        &transaction_data.authenticate_request,
+        SuccessLiability::Authenticated,
+    ) else {
+        return Ok(HttpResponse::BadRequest().json(json!({
+            "error": format!("transStatus {} is not a final RReq outcome", forced.trans_status)
+        })));
+    };
+    if let Some(eci) = forced.eci {
+        outcome.eci = eci;
+    }
+    if let Some(authentication_value) = forced.authentication_value {
+        outcome.authentication_value = Some(authentication_value);
+    }
+
+    record_result(
+        &state,
+        &settings,
+        three_ds_server_trans_id,
+        transaction_data,
+        outcome,
+        forced.trans_status_reason,
+    )
+    .await
+}
+
+/// End a transaction as timed out at the ACS: its RReq reports transStatus N with
+/// transStatusReason 14, which /3ds/final then returns
+pub async fn expire_transaction(
+    three_ds_server_trans_id: web::Path<Uuid>,
+    state: web::Data<Arc<Box<dyn StateStore>>>,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse, AppError> {
+    let three_ds_server_trans_id = three_ds_server_trans_id.into_inner();
+    let transaction_data = state
+        .get(&three_ds_server_trans_id)
+        .await?
+        .ok_or_else(|| AppError::not_found("RReq", three_ds_server_trans_id))?;
+    if transaction_data.results_request.is_some() || transaction_data.frictionless_outcome.is_some()
+    {
+        return Ok(HttpResponse::Conflict().json(json!({
+            "error": "This transaction already has a result"
+        })));
+    }
+
+    let outcome = ChallengeOutcome {
+        trans_status: TransStatus::NotAuthenticated,
+        eci: CardScheme::of(&transaction_data.authenticate_request.cardholder_account)
+            .not_authenticated_eci(),
+        authentication_value: None,
+    };
+    record_result(
+        &state,
+        &settings,
+        three_ds_server_trans_id,
+        transaction_data,
+        outcome,
+        Some(TIMED_OUT_REASON.to_string()),
+    )
+    .await
+}
+
+/// Remove a transaction, so later messages for it are answered as for an unknown one
+pub async fn delete_transaction(
+    three_ds_server_trans_id: web::Path<Uuid>,
+    state: web::Data<Arc<Box<dyn StateStore>>>,
+) -> Result<HttpResponse, AppError> {
+    let three_ds_server_trans_id = three_ds_server_trans_id.into_inner();
+    if state.get(&three_ds_server_trans_id).await?.is_none() {
+        return Ok(HttpResponse::NotFound().json(json!({ "error": "Transaction not found" })));
+    }
+
+    state.delete(&three_ds_server_trans_id).await?;
+    info!(%three_ds_server_trans_id, "transaction deleted");
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// The messages a transaction exchanged, in the form /admin/replay accepts. Recorded
+/// messages outlive a deleted transaction until they expire.
+pub async fn export_transaction(
+    three_ds_server_trans_id: web::Path<Uuid>,
+    state: web::Data<Arc<Box<dyn StateStore>>>,
+) -> Result<HttpResponse, AppError> {
+    let three_ds_server_trans_id = three_ds_server_trans_id.into_inner();
+    let messages = state.messages(&three_ds_server_trans_id).await?;
+    if messages.is_empty() && state.get(&three_ds_server_trans_id).await?.is_none() {
+        return Ok(HttpResponse::NotFound().json(json!({ "error": "Transaction not found" })));
+    }
+
+    info!(
+        %three_ds_server_trans_id,
+        messages = messages.len(),
+        "exporting recorded messages"
+    );
+    Ok(HttpResponse::Ok().json(FlowCapture {
+        three_ds_server_trans_id,
+        messages,
+    }))
+}
+
+/// A stored transaction as a protobuf `Transaction` (see proto/transaction.proto), without
+/// its OTP
+pub async fn transaction_protobuf(
+    three_ds_server_trans_id: web::Path<Uuid>,
+    state: web::Data<Arc<Box<dyn StateStore>>>,
+) -> Result<HttpResponse, AppError> {
+    let three_ds_server_trans_id = three_ds_server_trans_id.into_inner();
+    let transaction_data = state
+        .get(&three_ds_server_trans_id)
+        .await?
+        .ok_or_else(|| AppError::not_found("Export", three_ds_server_trans_id))?;
+
+    info!(%three_ds_server_trans_id, "exporting transaction as protobuf");
+    Ok(HttpResponse::Ok()
+        .content_type(interchange::PROTOBUF_CONTENT_TYPE)
+        .body(interchange::encode_transaction(&transaction_data)))
+}
+
+// Validate and store the RReq for an admin-decided outcome, answering with it
+async fn record_result(
+    state: &web::Data<Arc<Box<dyn StateStore>>>,
+    settings: &Settings,
+    three_ds_server_trans_id: Uuid,
+    mut transaction_data: TransactionData,
+    outcome: ChallengeOutcome,
+    trans_status_reason: Option<String>,
+) -> Result<HttpResponse, AppError> {
+    let results_request = results_request_for(
+        settings,
+        three_ds_server_trans_id,
+        &transaction_data,
+        &outcome,
+        None,
+        trans_status_reason,
+    );
+    if let Err(e) = validate_results_request(
+        &results_request,
+        transaction_data.acs_trans_id,
+        transaction_data.ds_trans_id,
+    ) {
+        return Ok(HttpResponse::BadRequest().json(json!({ "error": e.to_string() })));
+    }
+
+    info!(
+        %three_ds_server_trans_id,
+        trans_status = %results_request.trans_status,
+        eci = %results_request.eci,
+        "result recorded"
+    );
+    transaction_data.results_request = Some(results_request.clone());
+    state
+        .update(&three_ds_server_trans_id, transaction_data)
+        .await?;
+    Ok(HttpResponse::Ok().json(results_request))
+}
+
+/// Fault profiles and the active one, if any
+pub async fn list_faults(faults: web::Data<FaultInjector>) -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "active": faults.active(),
+        "profiles": faults.profiles()
+    }))
+}
+
+/// Switch fault injection to a configured profile
+pub async fn activate_fault_profile(
+    selection: web::Json<FaultProfileSelection>,
+    faults: web::Data<FaultInjector>,
+) -> HttpResponse {
+    if !faults.activate(&selection.profile) {
+        return HttpResponse::NotFound().json(json!({ "error": "No such fault profile" }));
+    }
+    info!(profile = %selection.profile, "fault profile activated");
+    HttpResponse::Ok().json(json!({ "active": selection.profile }))
+}
+
+/// Stop injecting faults
+pub async fn deactivate_fault_profile(faults: web::Data<FaultInjector>) -> HttpResponse {
+    faults.deactivate();
+    info!("fault injection deactivated");
+    HttpResponse::NoContent().finish()
+}
+
+/// Add or replace a fault profile
+pub async fn put_fault_profile(
+    name: web::Path<String>,
+    profile: web::Json<FaultProfile>,
+    faults: web::Data<FaultInjector>,
+) -> HttpResponse {
+    let (name, profile) = (name.into_inner(), profile.into_inner());
+    if let Err(e) = profile.validate() {
+        return HttpResponse::BadRequest().json(json!({ "error": e }));
+    }
+    info!(profile = %name, rules = profile.rules.len(), "fault profile set");
+    faults.set_profile(name.clone(), profile.clone());
+    HttpResponse::Ok().json(json!({ "name": name, "profile": profile }))
+}
+
+/// Whether compression and rate limiting are on
+pub async fn performance(switches: web::Data<PerformanceSwitches>) -> HttpResponse {
+    HttpResponse::Ok().json(switches.state())
+}
+
+/// Turn compression or rate limiting on or off, e.g. around a load test
+pub async fn put_performance(
+    update: web::Json<PerformanceUpdate>,
+    switches: web::Data<PerformanceSwitches>,
+) -> HttpResponse {
+    let state = switches.update(&update);
+    info!(
+        compression = state.compression,
+        rate_limiting = state.rate_limiting,
+        "performance switches updated"
+    );
+    HttpResponse::Ok().json(state)
+}
+
+/// API versions this build serves, so client test suites can pick their paths. Always
+/// mounted unversioned.
+pub async fn versions(settings: web::Data<Settings>) -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "current": API_VERSION,
+        "supported": [API_VERSION],
+        "basePath": format!("/{}", API_VERSION),
+        "unversionedAliases": !settings.routes.versioned_only,
+        "serverVersion": env!("CARGO_PKG_VERSION")
+    }))
+}
+
+/// Effective configuration with secrets redacted, the card ranges /3ds/version advertises,
+/// feature toggles and, where the ACS runs, the simulated card statuses
+pub async fn snapshot(
+    settings: web::Data<Settings>,
+    statuses: web::Data<CardStatusList>,
+) -> Result<HttpResponse, AppError> {
+    let card_ranges: Vec<CardRange> = settings
+        .ds
+        .card_ranges
+        .iter()
+        .map(|range| configured_card_range(&settings, range))
+        .collect();
+
+    let mut snapshot = json!({
+        "service": "3ds-mock-server",
+        "version": env!("CARGO_PKG_VERSION"),
+        "generatedAt": chrono::Utc::now().to_rfc3339(),
+        "features": {
+            "role": settings.components.role,
+            "stateBackend": settings.state.backend,
+            "metrics": settings.performance.enable_metrics,
+            "tracing": settings.monitoring.enable_tracing,
+            "startupSelfTest": settings.monitoring.startup_self_test,
+            "strictParsing": settings.validation.strict_parsing,
+            "threeDsMethod": settings.acs.three_ds_method.enabled,
+            "spc": settings.acs.spc.enabled,
+            "broadInfo": settings.acs.broad_info.enabled,
+            "faultInjection": settings.faults.enabled
+        },
+        "cardRanges": card_ranges,
+        "configuration": settings.redacted()?
+    });
+    if settings.components.serves_acs() {
+        snapshot["cardStatuses"] = json!(statuses.all());
+    }
+
+    Ok(HttpResponse::Ok().json(snapshot))
+}
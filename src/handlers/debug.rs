@@ -0,0 +1,296 @@
+//! Debug API: crypto diagnostics for SDK integrators, using a transaction's stored keys or
+//! keys they supply.
+//! Mounted only when `debug.api_token` is set, and every request must present it; the
+//! `/admin/*` routes are held to the same token.
+
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::config::Settings;
+use crate::crypto::{
+    calculate_derived_key, decrypt_challenge_request, derive_key_with_trace,
+    rotated_sdk_ephemeral_key, CryptoError, KeyDerivationTrace,
+};
+use crate::error::AppError;
+use crate::jwe::{self, JweDiagnostics};
+use crate::state_store::{StateStore, TransactionData};
+
+/// Outcome of decrypting a JWE with a transaction's keys. On failure, `failedStage` names the
+/// step that broke: jwe, keys, keyDerivation, authenticationTag, padding or decryption.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DecryptReport {
+    three_ds_server_trans_id: Uuid,
+    acs_trans_id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kid_matches_transaction: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    platform: Option<&'static str>,
+    /// Where the SDK ephemeral key came from: the AReq, or an epk rotated in the JWE header
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sdk_key_source: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    decrypted: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failed_stage: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    jwe_diagnostics: Option<Box<JweDiagnostics>>,
+}
+
+impl DecryptReport {
+    fn fail(mut self, stage: &'static str, error: impl ToString) -> Self {
+        self.failed_stage = Some(stage);
+        self.error = Some(error.to_string());
+        self
+    }
+}
+
+/// Decrypt a JWE with the keys stored for a transaction, reporting the stage that failed
+pub async fn decrypt_jwe(
+    req: HttpRequest,
+    three_ds_server_trans_id: web::Path<Uuid>,
+    body: web::Bytes,
+    state: web::Data<Arc<Box<dyn StateStore>>>,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse, AppError> {
+    if !authorized(&req, &settings) {
+        return Ok(unauthorized());
+    }
+
+    let three_ds_server_trans_id = three_ds_server_trans_id.into_inner();
+    let transaction = state
+        .get(&three_ds_server_trans_id)
+        .await?
+        .ok_or_else(|| AppError::not_found("CReq", three_ds_server_trans_id))?;
+    info!(%three_ds_server_trans_id, "decrypting with the stored keys");
+
+    let report = DecryptReport {
+        three_ds_server_trans_id,
+        acs_trans_id: transaction.acs_trans_id,
+        ..DecryptReport::default()
+    };
+    let report = match String::from_utf8(body.to_vec()) {
+        Ok(token) => decrypt(&token, transaction, &settings, report).await,
+        Err(e) => report.fail("jwe", format!("Body is not UTF-8: {}", e)),
+    };
+
+    Ok(match report.failed_stage {
+        None => HttpResponse::Ok().json(report),
+        Some(_) => HttpResponse::UnprocessableEntity().json(report),
+    })
+}
+
+async fn decrypt(
+    token: &str,
+    transaction: TransactionData,
+    settings: &Settings,
+    mut report: DecryptReport,
+) -> DecryptReport {
+    let parsed = match jwe::parse(token) {
+        Ok(parsed) => parsed,
+        Err(diagnostics) => {
+            let summary = diagnostics.summary();
+            report.jwe_diagnostics = Some(diagnostics);
+            return report.fail("jwe", summary);
+        }
+    };
+    report.kid_matches_transaction = Some(parsed.acs_trans_id == transaction.acs_trans_id);
+    let platform = match parsed.header["enc"].as_str() {
+        Some("A128GCM") => "ios",
+        _ => "android",
+    };
+    report.platform = Some(platform);
+
+    let stored_sdk_key = transaction.sdk_ephemeral_public_key.as_deref();
+    let sdk_key = match rotated_sdk_ephemeral_key(&parsed.header, stored_sdk_key) {
+        Some(rotated_key) => {
+            report.sdk_key_source = Some("jweHeader");
+            rotated_key
+        }
+        None => match stored_sdk_key {
+            Some(stored_key) => {
+                report.sdk_key_source = Some("areq");
+                stored_key.to_string()
+            }
+            None => return report.fail("keys", "Transaction has no SDK ephemeral public key"),
+        },
+    };
+    let Some(acs_keys) = &transaction.ephemeral_keys else {
+        return report.fail("keys", "Transaction has no ACS ephemeral keys");
+    };
+
+    let sdk_reference_number = settings
+        .acs
+        .sdk_reference_number(
+            platform,
+            transaction
+                .authenticate_request
+                .sdk_reference_number
+                .as_deref(),
+        )
+        .unwrap_or_default();
+    let derived_key =
+        match calculate_derived_key(&sdk_key, &acs_keys.private_key, sdk_reference_number) {
+            Ok(derived_key) => derived_key,
+            Err(e) => return report.fail("keyDerivation", e),
+        };
+    match decrypt_challenge_request(parsed.compact, &derived_key).await {
+        Ok(decrypted) => {
+            report.decrypted = Some(decrypted);
+            report
+        }
+        Err(e) => {
+            let stage = match e {
+                CryptoError::KeyDerivation(_) => "keyDerivation",
+                CryptoError::HmacMismatch => "authenticationTag",
+                CryptoError::Padding(_) => "padding",
+                _ => "decryption",
+            };
+            report.fail(stage, e)
+        }
+    }
+}
+
+/// Keys to derive from: a transaction's stored keys, or an SDK public JWK and an ACS private
+/// JWK. With a transaction, `sdkPublicKey` overrides the SDK key stored at AReq time.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct DeriveKeyRequest {
+    /// android or ios, selecting the configured SDK reference number used as partyVInfo
+    pub platform: String,
+    /// Used as partyVInfo instead of the AReq's or the platform's SDK reference number
+    #[serde(default)]
+    pub sdk_reference_number: Option<String>,
+    #[serde(
+        default,
+        rename = "threeDSServerTransID",
+        alias = "threeDsServerTransId"
+    )]
+    pub three_ds_server_trans_id: Option<Uuid>,
+    #[serde(default)]
+    pub sdk_public_key: Option<Value>,
+    #[serde(default)]
+    pub acs_private_key: Option<Value>,
+}
+
+/// Run the ECDH + ConcatKDF derivation and return every input and the derived key as hex
+pub async fn derive_key(
+    req: HttpRequest,
+    request: web::Json<DeriveKeyRequest>,
+    state: web::Data<Arc<Box<dyn StateStore>>>,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse, AppError> {
+    if !authorized(&req, &settings) {
+        return Ok(unauthorized());
+    }
+    let request = request.into_inner();
+
+    let (sdk_key, acs_private_key, areq_sdk_reference_number) =
+        match request.three_ds_server_trans_id {
+            Some(three_ds_server_trans_id) => {
+                let transaction = state
+                    .get(&three_ds_server_trans_id)
+                    .await?
+                    .ok_or_else(|| AppError::not_found("CReq", three_ds_server_trans_id))?;
+                let sdk_key = request
+                    .sdk_public_key
+                    .map(|jwk| jwk.to_string())
+                    .or(transaction.sdk_ephemeral_public_key);
+                let acs_private_key = transaction.ephemeral_keys.map(|keys| keys.private_key);
+                (
+                    sdk_key,
+                    acs_private_key,
+                    transaction.authenticate_request.sdk_reference_number,
+                )
+            }
+            None => (
+                request.sdk_public_key.map(|jwk| jwk.to_string()),
+                request
+                    .acs_private_key
+                    .and_then(|jwk| jwk["d"].as_str().map(str::to_string)),
+                None,
+            ),
+        };
+    let (Some(sdk_key), Some(acs_private_key)) = (sdk_key, acs_private_key) else {
+        return Ok(HttpResponse::BadRequest().json(json!({
+            "error": "Provide threeDSServerTransID of a mobile transaction, or sdkPublicKey and acsPrivateKey (a JWK with d)"
+        })));
+    };
+
+    let Some(sdk_reference_number) = settings.acs.sdk_reference_number(
+        &request.platform,
+        request
+            .sdk_reference_number
+            .as_deref()
+            .or(areq_sdk_reference_number.as_deref()),
+    ) else {
+        return Ok(HttpResponse::UnprocessableEntity().json(json!({
+            "error": format!("Unsupported platform: {} (supported: android, ios)", request.platform)
+        })));
+    };
+
+    info!(platform = %request.platform, sdk_reference_number, "deriving a challenge key");
+    Ok(
+        match derive_key_with_trace(&sdk_key, &acs_private_key, sdk_reference_number) {
+            Ok(trace) => HttpResponse::Ok().json(DeriveKeyResponse {
+                platform: request.platform.to_lowercase(),
+                trace,
+            }),
+            Err(e) => HttpResponse::UnprocessableEntity().json(json!({ "error": e.to_string() })),
+        },
+    )
+}
+
+/// The derivation trace and the platform it was requested for
+#[derive(Debug, Serialize)]
+struct DeriveKeyResponse {
+    platform: String,
+    #[serde(flatten)]
+    trace: KeyDerivationTrace,
+}
+
+pub(crate) fn unauthorized() -> HttpResponse {
+    HttpResponse::Unauthorized()
+        .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+        .json(json!({ "error": "A valid debug API token is required" }))
+}
+
+/// Whether the request carries `Authorization: Bearer <debug.api_token>`
+pub(crate) fn authorized(req: &HttpRequest, settings: &Settings) -> bool {
+    let Some(token) = settings.debug.api_token.as_deref() else {
+        return false;
+    };
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        == Some(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_authorized_requires_configured_bearer_token() {
+        let mut settings = Settings::default();
+        let request = |authorization: &str| {
+            actix_web::test::TestRequest::post()
+                .insert_header((header::AUTHORIZATION, authorization))
+                .to_http_request()
+        };
+
+        assert!(!authorized(&request("Bearer secret"), &settings));
+
+        settings.debug.api_token = Some("secret".to_string());
+        assert!(authorized(&request("Bearer secret"), &settings));
+        assert!(!authorized(&request("Bearer other"), &settings));
+        assert!(!authorized(&request("secret"), &settings));
+    }
+}
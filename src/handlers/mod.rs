@@ -0,0 +1,85 @@
+//! Request handlers, split by the 3DS component that serves them
+
+pub mod acs;
+pub mod admin;
+pub mod debug;
+pub mod server;
+
+use tracing::field::display;
+use tracing::Span;
+use uuid::Uuid;
+
+/// Name the transaction on the current handler span (declared with an empty
+/// `three_ds_server_trans_id` field) once the message carrying it has been parsed, so every
+/// event logged while handling it can be filtered by transaction
+pub(crate) fn record_transaction(three_ds_server_trans_id: Uuid) {
+    Span::current().record(
+        "three_ds_server_trans_id",
+        display(three_ds_server_trans_id),
+    );
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    //! Shared setup for calling handlers directly in unit tests
+
+    use actix_web::{web, HttpResponse};
+    use serde_json::Value;
+    use std::sync::Arc;
+
+    use crate::acs_client::AcsClient;
+    use crate::cache::AppCaches;
+    use crate::card_status::CardStatusList;
+    use crate::config::Settings;
+    use crate::error::AppError;
+    use crate::models::tests::sample_authenticate_request;
+    use crate::state_store::{InMemoryStore, StateStore};
+
+    /// The application data main.rs registers, over an in-process transaction store
+    pub(crate) struct TestState {
+        pub settings: web::Data<Settings>,
+        pub state: web::Data<Arc<Box<dyn StateStore>>>,
+        pub caches: web::Data<AppCaches>,
+        pub card_statuses: web::Data<CardStatusList>,
+        pub acs: web::Data<AcsClient>,
+    }
+
+    impl TestState {
+        pub(crate) fn new(settings: Settings) -> Self {
+            Self {
+                caches: web::Data::new(AppCaches::new(10, &settings.cache)),
+                card_statuses: web::Data::new(CardStatusList::new(&settings.acs.card_statuses)),
+                acs: web::Data::new(AcsClient::new(&settings.components).unwrap()),
+                state: web::Data::new(Arc::new(Box::new(InMemoryStore::new(&settings)))),
+                settings: web::Data::new(settings),
+            }
+        }
+
+        /// Send an AReq to the ACS and return its ARes
+        pub(crate) async fn authenticate(&self, areq: &Value) -> Result<Value, AppError> {
+            let body = serde_json::to_vec(areq).unwrap();
+            let response = super::acs::authenticate_handler(
+                body.into(),
+                self.state.clone(),
+                self.settings.clone(),
+                self.caches.clone(),
+                self.card_statuses.clone(),
+            )
+            .await?;
+            Ok(json_body(response).await)
+        }
+    }
+
+    /// The sample AReq as JSON, for tests to adjust before sending
+    pub(crate) fn sample_areq() -> Value {
+        serde_json::to_value(sample_authenticate_request()).unwrap()
+    }
+
+    /// The JSON body of a handler's response
+    pub(crate) async fn json_body(response: HttpResponse) -> Value {
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+}
@@ -0,0 +1,375 @@
+//! 3DS Server component: the requestor-facing /3ds API.
+//!
+//! AReqs and RReqs are handed to the ACS component through [`AcsClient`], in process or
+//! over HTTP depending on `[components]`; everything else is answered from the shared
+//! transaction store.
+
+use actix_web::{web, HttpResponse};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::field::Empty;
+use tracing::{info, instrument, warn};
+use uuid::Uuid;
+
+use crate::acs_client::{AcsClient, ACS_METHOD_PATH};
+use crate::cache::{card_bin, AppCaches};
+use crate::card_status::CardStatusList;
+use crate::config::{CardRangeConfig, Settings};
+use crate::error::AppError;
+use crate::handlers::record_transaction;
+use crate::models::*;
+use crate::protocol;
+use crate::recording::{self, Direction};
+use crate::state_store::StateStore;
+use crate::validation::{
+    decode_three_ds_method_data, encode_method_notification_data, validate_preparation_request,
+};
+use crate::webhook;
+
+/// The card range /3ds/version returns for a card number: the first configured range
+/// containing it, else one spanning its BIN
+pub fn card_range_for(settings: &Settings, card_number: &str) -> CardRange {
+    if let Some(range) = settings
+        .ds
+        .card_ranges
+        .iter()
+        .find(|range| range.contains(card_number))
+    {
+        return configured_card_range(settings, range);
+    }
+
+    let bin = card_bin(card_number);
+    let padding = card_number.len().saturating_sub(bin.len());
+    let bin_range = CardRangeConfig::new(
+        &format!("{}{}", bin, "0".repeat(padding)),
+        &format!("{}{}", bin, "9".repeat(padding)),
+    );
+    configured_card_range(settings, &bin_range)
+}
+
+/// A configured card range as advertised, unset fields filled in from the DS profile routing
+/// it and the ACS
+pub fn configured_card_range(settings: &Settings, range: &CardRangeConfig) -> CardRange {
+    let ds_profile = settings.ds.profile_for_card(&range.start_range);
+    let protocol_versions = range.protocol_versions(ds_profile);
+    let three_ds_method_url = range.three_ds_method_url.clone().or_else(|| {
+        settings
+            .acs
+            .three_ds_method
+            .enabled
+            .then(|| format!("{}{}", settings.acs_public_url(), ACS_METHOD_PATH))
+    });
+
+    CardRange {
+        acs_info_ind: range
+            .acs_info_ind
+            .clone()
+            .unwrap_or_else(|| ds_profile.acs_info_ind.clone()),
+        start_range: range.start_range.clone(),
+        acs_end_protocol_version: protocol_versions
+            .last()
+            .copied()
+            .unwrap_or_else(protocol::acs_end_protocol_version)
+            .to_string(),
+        acs_start_protocol_version: protocol_versions
+            .first()
+            .copied()
+            .unwrap_or_else(protocol::acs_start_protocol_version)
+            .to_string(),
+        end_range: range.end_range.clone(),
+        three_ds_method_url,
+    }
+}
+
+pub async fn version_handler(
+    req: web::Json<VersionRequest>,
+    settings: web::Data<Settings>,
+    caches: web::Data<AppCaches>,
+) -> Result<HttpResponse, AppError> {
+    // Generate a new transaction ID for this session
+    let trans_id = Uuid::new_v4();
+
+    // Card ranges are static per BIN - only build them on a cache miss
+    let card_range = caches.card_range(&req.card_number, || {
+        card_range_for(&settings, &req.card_number)
+    });
+
+    let response = VersionResponse {
+        three_ds_server_trans_id: trans_id,
+        card_ranges: vec![card_range],
+    };
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// PReq from a 3DS Server preloading card ranges: answered with every range /3ds/version
+/// can return, or none when the PReq serialNum shows the cache is already current
+pub async fn preparation_handler(
+    req: web::Json<PreparationRequest>,
+    settings: web::Data<Settings>,
+    caches: web::Data<AppCaches>,
+) -> Result<HttpResponse, AppError> {
+    let preq = req.into_inner();
+    let three_ds_server_trans_id = preq.three_ds_server_trans_id;
+    validate_preparation_request(&preq)
+        .map_err(|e| AppError::validation("PReq", three_ds_server_trans_id, e))?;
+
+    let card_ranges: Vec<CardRangeData> = settings
+        .ds
+        .card_ranges
+        .iter()
+        .map(|range| {
+            caches
+                .card_range(&range.start_range, || {
+                    configured_card_range(&settings, range)
+                })
+                .into()
+        })
+        .collect();
+    let serial_num = CardRangeData::serial_num(&card_ranges);
+    let up_to_date = preq.serial_num.as_deref() == Some(serial_num.as_str());
+
+    info!(
+        %serial_num,
+        card_ranges = if up_to_date { 0 } else { card_ranges.len() },
+        "answering PReq"
+    );
+
+    let response = PreparationResponse {
+        message_type: "PRes",
+        message_version: preq.message_version,
+        three_ds_server_trans_id,
+        three_ds_server_ref_number: preq.three_ds_server_ref_number,
+        three_ds_server_operator_id: preq.three_ds_server_operator_id,
+        ds_trans_id: Uuid::new_v4(),
+        serial_num,
+        card_range_data: if up_to_date { Vec::new() } else { card_ranges },
+    };
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// 3DS Method run on the requestor's behalf: waits `[acs.three_ds_method] duration_ms`, then
+/// marks the device fingerprint complete and POSTs the completion notification to the
+/// threeDSMethodNotificationURL. A method outlasting `timeout_ms` is abandoned instead, and
+/// the requestor should send threeDSCompInd = N.
+#[instrument(name = "three_ds_method", skip_all, fields(three_ds_server_trans_id = Empty))]
+pub async fn three_ds_method_handler(
+    req: web::Json<ThreeDsMethodForm>,
+    state: web::Data<Arc<Box<dyn StateStore>>>,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse, AppError> {
+    let method_data = decode_three_ds_method_data(&req.three_ds_method_data)
+        .map_err(|e| AppError::rejected_field("3DSMethod", e))?;
+    let three_ds_server_trans_id = method_data.three_ds_server_trans_id;
+    let config = &settings.acs.three_ds_method;
+    record_transaction(three_ds_server_trans_id);
+
+    info!(duration_ms = config.duration_ms, "running 3DS Method");
+
+    let timed_out = config.duration_ms >= config.timeout_ms;
+    tokio::time::sleep(Duration::from_millis(
+        config.duration_ms.min(config.timeout_ms),
+    ))
+    .await;
+    if timed_out {
+        warn!(timeout_ms = config.timeout_ms, "3DS Method timed out");
+        return Ok(HttpResponse::Ok().json(ThreeDsMethodResult {
+            three_ds_server_trans_id,
+            three_ds_comp_ind: "N",
+            notification_delivered: false,
+        }));
+    }
+
+    state
+        .mark_method_completed(three_ds_server_trans_id)
+        .await?;
+
+    let notification = webhook::client()
+        .post(&method_data.three_ds_method_notification_url)
+        .timeout(Duration::from_millis(config.timeout_ms))
+        .form(&[(
+            "threeDSMethodData",
+            encode_method_notification_data(three_ds_server_trans_id),
+        )])
+        .send()
+        .await;
+    let notification_delivered = match notification {
+        Ok(response) if response.status().is_success() => true,
+        Ok(response) => {
+            warn!(
+                url = %method_data.three_ds_method_notification_url,
+                status = %response.status(),
+                "threeDSMethodNotificationURL answered with an error"
+            );
+            false
+        }
+        Err(e) => {
+            warn!(
+                url = %method_data.three_ds_method_notification_url,
+                error = %e,
+                "threeDSMethodNotificationURL failed"
+            );
+            false
+        }
+    };
+
+    Ok(HttpResponse::Ok().json(ThreeDsMethodResult {
+        three_ds_server_trans_id,
+        three_ds_comp_ind: "Y",
+        notification_delivered,
+    }))
+}
+
+/// AReq from the requestor, answered by the ACS component
+pub async fn authenticate_handler(
+    body: web::Bytes,
+    acs: web::Data<AcsClient>,
+    state: web::Data<Arc<Box<dyn StateStore>>>,
+    settings: web::Data<Settings>,
+    caches: web::Data<AppCaches>,
+    card_statuses: web::Data<CardStatusList>,
+) -> Result<HttpResponse, AppError> {
+    acs.authenticate(body, state, settings, caches, card_statuses)
+        .await
+}
+
+/// RReq for a transaction, recorded by the ACS component
+pub async fn results_handler(
+    req: web::Json<ResultsRequest>,
+    acs: web::Data<AcsClient>,
+    state: web::Data<Arc<Box<dyn StateStore>>>,
+) -> Result<HttpResponse, AppError> {
+    acs.results(req, state).await
+}
+
+#[instrument(
+    name = "final",
+    skip_all,
+    fields(three_ds_server_trans_id = %req.three_ds_server_trans_id)
+)]
+pub async fn final_handler(
+    req: web::Json<FinalRequest>,
+    state: web::Data<Arc<Box<dyn StateStore>>>,
+) -> Result<HttpResponse, AppError> {
+    let three_ds_server_trans_id = req.three_ds_server_trans_id;
+
+    let transaction_data = state
+        .get(&three_ds_server_trans_id)
+        .await?
+        .ok_or_else(|| AppError::not_found("Final", three_ds_server_trans_id))?;
+
+    // Frictionless transactions end with the ARes; challenged ones with the RReq
+    if let Some(outcome) = transaction_data.frictionless_outcome {
+        let response = FinalResponse {
+            eci: outcome.eci,
+            authentication_value: outcome.authentication_value,
+            three_ds_server_trans_id,
+            results_response: None,
+            results_request: None,
+            trans_status: outcome.trans_status,
+            trans_status_reason: outcome.trans_status_reason,
+            whitelist_status: None,
+            whitelist_status_source: None,
+        };
+        recording::record(
+            &state,
+            three_ds_server_trans_id,
+            Direction::Outbound,
+            "Final",
+            &response,
+        )
+        .await;
+        return Ok(HttpResponse::Ok().json(response));
+    }
+
+    let results_request = transaction_data.results_request.as_ref().ok_or_else(|| {
+        AppError::unexpected(
+            "Final",
+            three_ds_server_trans_id,
+            "Results not found for this transaction",
+        )
+    })?;
+
+    let results_response = ResultsResponse {
+        ds_trans_id: transaction_data.ds_trans_id,
+        message_type: "RRes".to_string(),
+        three_ds_server_trans_id,
+        acs_trans_id: transaction_data.acs_trans_id,
+        sdk_trans_id: transaction_data.sdk_trans_id,
+        results_status: "01".to_string(),
+        message_version: results_request.message_version.clone(),
+    };
+
+    let response = FinalResponse {
+        eci: results_request.eci,
+        authentication_value: results_request.authentication_value.clone(),
+        three_ds_server_trans_id,
+        results_response: Some(results_response),
+        results_request: Some(results_request.clone()),
+        trans_status: results_request.trans_status,
+        trans_status_reason: results_request.trans_status_reason.clone(),
+        whitelist_status: results_request.whitelist_status.clone(),
+        whitelist_status_source: results_request.whitelist_status_source.clone(),
+    };
+    recording::record(
+        &state,
+        three_ds_server_trans_id,
+        Direction::Outbound,
+        "Final",
+        &response,
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::test_settings;
+    use serde_json::Value;
+
+    #[actix_web::test]
+    async fn test_version_card_ranges_are_cached_per_bin() {
+        use actix_web::{test, App};
+        use prometheus::{Encoder, TextEncoder};
+
+        let settings = test_settings();
+        let caches = web::Data::new(AppCaches::new(10, &settings.cache));
+        let registry = prometheus::Registry::new();
+        caches.register(&registry).unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(settings))
+                .app_data(caches)
+                .route("/3ds/version", web::post().to(version_handler)),
+        )
+        .await;
+
+        // Both cards share a BIN, so the second card range comes from the cache
+        let mut start_ranges = Vec::new();
+        for card in ["4000000000001000", "4000000000001091"] {
+            let req = test::TestRequest::post()
+                .uri("/3ds/version")
+                .set_json(serde_json::json!({ "cardNumber": card }))
+                .to_request();
+            let response: Value = test::call_and_read_body_json(&app, req).await;
+            start_ranges.push(response["cardRanges"][0]["startRange"].clone());
+        }
+        assert_eq!(start_ranges[0], start_ranges[1]);
+
+        let mut metrics = Vec::new();
+        TextEncoder::new()
+            .encode(&registry.gather(), &mut metrics)
+            .unwrap();
+        let metrics = String::from_utf8(metrics).unwrap();
+        for (result, count) in [("hit", 1), ("miss", 1)] {
+            let line = format!(
+                r#"api_cache_requests_total{{cache="card_range",result="{}"}} {}"#,
+                result, count
+            );
+            assert!(metrics.lines().any(|l| l == line), "missing {}", line);
+        }
+    }
+}
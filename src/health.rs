@@ -0,0 +1,225 @@
+//! Dependency checks for /health and its probe endpoints. With `[monitoring]
+//! deep_health_check` on, every health check pings the state store, so probes see the
+//! service go unhealthy when Redis (or the SQL database) does. /health/ready always runs the
+//! readiness checks; /health/live checks nothing, so a store outage takes pods out of
+//! rotation without getting them restarted.
+
+use serde::Serialize;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::config::{MissingCertPolicy, MonitoringConfig, Settings};
+use crate::crypto::{certificate_not_after, load_private_key};
+use crate::self_test::SelfTestReport;
+use crate::state_store::StateStore;
+
+/// Overall service status reported by /health
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    /// Answered with 200
+    Healthy,
+    /// Serving, but a dependency is slow; still answered with 200 so probes keep the pod
+    Degraded,
+    /// Answered with 503
+    Unhealthy,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DependencyStatus {
+    Up,
+    Down,
+}
+
+/// One dependency's entry in the /health `dependencies` object
+#[derive(Debug, Serialize, Clone)]
+pub struct DependencyHealth {
+    pub status: DependencyStatus,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl DependencyHealth {
+    /// How this dependency affects the service status
+    pub fn service_status(&self, degraded_latency_ms: u64) -> HealthStatus {
+        match self.status {
+            DependencyStatus::Down => HealthStatus::Unhealthy,
+            DependencyStatus::Up if self.latency_ms >= degraded_latency_ms => {
+                HealthStatus::Degraded
+            }
+            DependencyStatus::Up => HealthStatus::Healthy,
+        }
+    }
+}
+
+/// Ping the state store once, counting a ping slower than the health timeout as down
+pub async fn check_state_store(
+    state: &dyn StateStore,
+    config: &MonitoringConfig,
+) -> DependencyHealth {
+    let started = Instant::now();
+    let result = tokio::time::timeout(
+        Duration::from_millis(config.health_timeout_ms),
+        state.ping(),
+    )
+    .await;
+    let latency_ms = started.elapsed().as_millis() as u64;
+
+    let error = match result {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(e.to_string()),
+        Err(_) => Some(format!("no answer within {}ms", config.health_timeout_ms)),
+    };
+    if let Some(error) = &error {
+        warn!(backend = state.backend(), %error, "state store health check failed");
+    }
+
+    DependencyHealth {
+        status: if error.is_none() {
+            DependencyStatus::Up
+        } else {
+            DependencyStatus::Down
+        },
+        latency_ms,
+        error,
+    }
+}
+
+/// Outcome of a readiness check that has no latency worth reporting
+#[derive(Debug, Serialize, Clone)]
+pub struct CheckResult {
+    pub status: DependencyStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl CheckResult {
+    fn from_result<E: ToString>(result: Result<(), E>) -> Self {
+        match result {
+            Ok(()) => Self {
+                status: DependencyStatus::Up,
+                error: None,
+            },
+            Err(e) => Self {
+                status: DependencyStatus::Down,
+                error: Some(e.to_string()),
+            },
+        }
+    }
+
+    fn is_up(&self) -> bool {
+        self.status == DependencyStatus::Up
+    }
+}
+
+/// Body of /health/ready
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub state_store: DependencyHealth,
+    pub config: CheckResult,
+    /// Only where the ACS runs and signs mobile challenges with the configured certificate
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acs_signing: Option<CheckResult>,
+    /// Only when the startup self-test ran
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub self_test: Option<CheckResult>,
+}
+
+/// Whether the service can take traffic: the state store answers, the configuration is
+/// valid, the ACS signing certificate and key load, and the startup self-test (if any) passed
+pub async fn readiness(
+    state: &dyn StateStore,
+    settings: &Settings,
+    self_test: Option<&SelfTestReport>,
+) -> Readiness {
+    let state_store = check_state_store(state, &settings.monitoring).await;
+    let config = CheckResult::from_result(settings.validate());
+
+    let signing = &settings.acs.signing;
+    let acs_signing = (settings.components.serves_acs()
+        && signing.missing_cert_policy != MissingCertPolicy::Static)
+        .then(|| {
+            CheckResult::from_result(
+                certificate_not_after(Path::new(&signing.cert_path))
+                    .and_then(|_| load_private_key(Path::new(&signing.private_key_path)))
+                    .map(|_| ()),
+            )
+        });
+    let self_test = self_test.map(|report| {
+        CheckResult::from_result(if report.passed {
+            Ok(())
+        } else {
+            Err("startup self-test failed")
+        })
+    });
+
+    let ready = state_store.status == DependencyStatus::Up
+        && config.is_up()
+        && acs_signing.as_ref().is_none_or(CheckResult::is_up)
+        && self_test.as_ref().is_none_or(CheckResult::is_up);
+    Readiness {
+        ready,
+        state_store,
+        config,
+        acs_signing,
+        self_test,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state_store::InMemoryStore;
+
+    #[tokio::test]
+    async fn test_state_store_check_and_status_mapping() {
+        let settings = Settings::default();
+        let store = InMemoryStore::new(&settings);
+        let health = check_state_store(&store, &settings.monitoring).await;
+        assert_eq!(health.status, DependencyStatus::Up);
+        assert!(health.error.is_none());
+        assert_eq!(
+            serde_json::to_value(&health).unwrap(),
+            serde_json::json!({ "status": "up", "latency_ms": health.latency_ms })
+        );
+
+        let slow = DependencyHealth {
+            status: DependencyStatus::Up,
+            latency_ms: 300,
+            error: None,
+        };
+        assert_eq!(slow.service_status(250), HealthStatus::Degraded);
+        assert_eq!(slow.service_status(500), HealthStatus::Healthy);
+
+        let down = DependencyHealth {
+            status: DependencyStatus::Down,
+            latency_ms: 1000,
+            error: Some("no answer within 1000ms".to_string()),
+        };
+        assert_eq!(down.service_status(250), HealthStatus::Unhealthy);
+    }
+
+    #[tokio::test]
+    async fn test_readiness_needs_a_loadable_signing_certificate() {
+        let mut settings = Settings::default();
+        settings.acs.signing.cert_path = "certs/does-not-exist.pem".to_string();
+        let store = InMemoryStore::new(&settings);
+
+        let report = readiness(&store, &settings, None).await;
+        assert!(!report.ready);
+        assert_eq!(report.acs_signing.unwrap().status, DependencyStatus::Down);
+        assert_eq!(report.state_store.status, DependencyStatus::Up);
+
+        // Static acsSignedContent needs no certificate
+        settings.acs.signing.missing_cert_policy = MissingCertPolicy::Static;
+        settings.acs.signing.static_signed_content = Some("eyJhbGciOiJQUzI1NiJ9..".to_string());
+        let report = readiness(&store, &settings, None).await;
+        assert!(report.ready);
+        assert!(report.acs_signing.is_none());
+        assert_eq!(report.config.status, DependencyStatus::Up);
+    }
+}
@@ -0,0 +1,792 @@
+//! Protobuf representation of stored transactions for tools outside this crate.
+//!
+//! The messages mirror `proto/transaction.proto` field for field (same names and tags),
+//! so Go/Java tools can generate their own types from the schema instead of tracking
+//! the serde model in `state_store.rs`.
+
+use prost::Message;
+
+use crate::config;
+use crate::crypto::AcsEphemPubKey;
+use crate::models::{self, ChallengeUi as ModelChallengeUi, SdkEphemeralPublicKey};
+use crate::state_store::TransactionData;
+
+pub const PROTOBUF_CONTENT_TYPE: &str = "application/x-protobuf";
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Transaction {
+    #[prost(string, tag = "1")]
+    pub three_ds_server_trans_id: String,
+    #[prost(string, tag = "2")]
+    pub acs_trans_id: String,
+    #[prost(string, tag = "3")]
+    pub ds_trans_id: String,
+    #[prost(string, optional, tag = "4")]
+    pub sdk_trans_id: Option<String>,
+    #[prost(message, optional, tag = "5")]
+    pub authenticate_request: Option<AuthenticateRequest>,
+    #[prost(message, optional, tag = "6")]
+    pub results_request: Option<ResultsRequest>,
+    #[prost(message, optional, tag = "7")]
+    pub acs_ephemeral_public_key: Option<EphemeralPublicKey>,
+    #[prost(string, optional, tag = "8")]
+    pub redirect_url: Option<String>,
+    #[prost(string, optional, tag = "9")]
+    pub sdk_ephemeral_public_key: Option<String>,
+    #[prost(string, optional, tag = "10")]
+    pub authentication_type: Option<String>,
+    #[prost(uint32, tag = "11")]
+    pub interaction_count: u32,
+    #[prost(message, optional, tag = "12")]
+    pub challenge_ui: Option<ChallengeUi>,
+    #[prost(string, optional, tag = "13")]
+    pub spc_challenge: Option<String>,
+    #[prost(message, optional, tag = "14")]
+    pub three_ds_method_data: Option<MethodDeviceData>,
+    #[prost(bool, tag = "15")]
+    pub three_ds_method_completed: bool,
+    #[prost(uint32, tag = "16")]
+    pub otp_attempts: u32,
+    #[prost(int64, optional, tag = "18")]
+    pub oob_started_at_ms: Option<i64>,
+    #[prost(bool, optional, tag = "19")]
+    pub oob_approved: Option<bool>,
+    #[prost(string, optional, tag = "20")]
+    pub whitelisting_choice: Option<String>,
+    #[prost(string, optional, tag = "21")]
+    pub request_id: Option<String>,
+    #[prost(string, optional, tag = "22")]
+    pub masked_pan: Option<String>,
+    #[prost(string, optional, tag = "23")]
+    pub pan_hash: Option<String>,
+    #[prost(string, optional, tag = "24")]
+    pub three_ds_session_data: Option<String>,
+    #[prost(string, optional, tag = "25")]
+    pub initial_ui: Option<String>,
+    #[prost(message, optional, tag = "26")]
+    pub protocol_2_3: Option<Protocol23Fields>,
+    #[prost(string, optional, tag = "27")]
+    pub success_liability: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Protocol23Fields {
+    #[prost(string, optional, tag = "1")]
+    pub device_binding_status: Option<String>,
+    #[prost(string, optional, tag = "2")]
+    pub trust_list_status: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct AuthenticateRequest {
+    #[prost(string, tag = "1")]
+    pub three_ds_server_trans_id: String,
+    #[prost(string, optional, tag = "2")]
+    pub sdk_trans_id: Option<String>,
+    #[prost(string, tag = "3")]
+    pub device_channel: String,
+    #[prost(string, tag = "4")]
+    pub message_category: String,
+    #[prost(string, tag = "5")]
+    pub preferred_protocol_version: String,
+    #[prost(bool, tag = "6")]
+    pub enforce_preferred_protocol_version: bool,
+    #[prost(string, tag = "7")]
+    pub three_ds_comp_ind: String,
+    #[prost(message, optional, tag = "8")]
+    pub three_ds_requestor: Option<ThreeDsRequestor>,
+    #[prost(message, optional, tag = "9")]
+    pub cardholder_account: Option<CardholderAccount>,
+    #[prost(message, optional, tag = "10")]
+    pub cardholder: Option<Cardholder>,
+    #[prost(message, optional, tag = "11")]
+    pub purchase: Option<Purchase>,
+    #[prost(message, optional, tag = "12")]
+    pub acquirer: Option<Acquirer>,
+    #[prost(message, optional, tag = "13")]
+    pub merchant: Option<Merchant>,
+    #[prost(message, optional, tag = "14")]
+    pub browser_information: Option<BrowserInformation>,
+    #[prost(message, optional, tag = "15")]
+    pub device_render_options: Option<DeviceRenderOptions>,
+    #[prost(string, optional, tag = "16")]
+    pub sdk_enc_data: Option<String>,
+    #[prost(string, optional, tag = "17")]
+    pub three_ds_requestor_spc_support: Option<String>,
+    #[prost(message, optional, tag = "18")]
+    pub sdk_ephemeral_public_key: Option<EphemeralPublicKey>,
+    #[prost(string, optional, tag = "19")]
+    pub browser_extension: Option<String>,
+    #[prost(string, optional, tag = "20")]
+    pub device_binding_status: Option<String>,
+    #[prost(string, optional, tag = "21")]
+    pub trust_list_status: Option<String>,
+    #[prost(string, optional, tag = "22")]
+    pub message_extension: Option<String>,
+    #[prost(string, optional, tag = "23")]
+    pub sdk_reference_number: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ThreeDsRequestor {
+    #[prost(string, tag = "1")]
+    pub three_ds_requestor_authentication_ind: String,
+    #[prost(string, tag = "2")]
+    pub three_ds_req_auth_method: String,
+    #[prost(string, tag = "3")]
+    pub three_ds_req_auth_timestamp: String,
+    #[prost(string, tag = "4")]
+    pub three_ds_requestor_challenge_ind: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct CardholderAccount {
+    #[prost(string, tag = "1")]
+    pub acct_type: String,
+    #[prost(string, tag = "2")]
+    pub card_expiry_date: String,
+    #[prost(string, tag = "3")]
+    pub scheme_id: String,
+    #[prost(string, tag = "4")]
+    pub acct_number: String,
+    #[prost(string, tag = "5")]
+    pub card_security_code: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Cardholder {
+    #[prost(string, tag = "1")]
+    pub addr_match: String,
+    #[prost(message, optional, tag = "2")]
+    pub bill_addr: Option<Address>,
+    #[prost(string, tag = "3")]
+    pub email: String,
+    #[prost(message, optional, tag = "4")]
+    pub home_phone: Option<Phone>,
+    #[prost(message, optional, tag = "5")]
+    pub mobile_phone: Option<Phone>,
+    #[prost(message, optional, tag = "6")]
+    pub work_phone: Option<Phone>,
+    #[prost(string, tag = "7")]
+    pub cardholder_name: String,
+    #[prost(message, optional, tag = "8")]
+    pub ship_addr: Option<Address>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Address {
+    #[prost(string, tag = "1")]
+    pub city: String,
+    #[prost(string, tag = "2")]
+    pub country: String,
+    #[prost(string, tag = "3")]
+    pub line1: String,
+    #[prost(string, tag = "4")]
+    pub line2: String,
+    #[prost(string, tag = "5")]
+    pub line3: String,
+    #[prost(string, tag = "6")]
+    pub post_code: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Phone {
+    #[prost(string, tag = "1")]
+    pub cc: String,
+    #[prost(string, tag = "2")]
+    pub subscriber: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Purchase {
+    #[prost(uint32, optional, tag = "1")]
+    pub purchase_instal_data: Option<u32>,
+    #[prost(uint64, tag = "2")]
+    pub purchase_amount: u64,
+    #[prost(string, tag = "3")]
+    pub purchase_currency: String,
+    #[prost(uint32, tag = "4")]
+    pub purchase_exponent: u32,
+    #[prost(string, tag = "5")]
+    pub purchase_date: String,
+    #[prost(string, optional, tag = "6")]
+    pub recurring_expiry: Option<String>,
+    #[prost(uint32, optional, tag = "7")]
+    pub recurring_frequency: Option<u32>,
+    #[prost(string, tag = "8")]
+    pub trans_type: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Acquirer {
+    #[prost(string, tag = "1")]
+    pub acquirer_bin: String,
+    #[prost(string, tag = "2")]
+    pub acquirer_merchant_id: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Merchant {
+    #[prost(string, tag = "1")]
+    pub mcc: String,
+    #[prost(string, tag = "2")]
+    pub merchant_country_code: String,
+    #[prost(string, tag = "3")]
+    pub three_ds_requestor_id: String,
+    #[prost(string, tag = "4")]
+    pub three_ds_requestor_name: String,
+    #[prost(string, tag = "5")]
+    pub merchant_name: String,
+    #[prost(string, tag = "6")]
+    pub results_response_notification_url: String,
+    #[prost(string, tag = "7")]
+    pub notification_url: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct BrowserInformation {
+    #[prost(string, tag = "1")]
+    pub browser_accept_header: String,
+    #[prost(string, tag = "2")]
+    pub browser_ip: String,
+    #[prost(string, tag = "3")]
+    pub browser_language: String,
+    #[prost(string, tag = "4")]
+    pub browser_color_depth: String,
+    #[prost(uint32, tag = "5")]
+    pub browser_screen_height: u32,
+    #[prost(uint32, tag = "6")]
+    pub browser_screen_width: u32,
+    #[prost(sint32, tag = "7")]
+    pub browser_tz: i32,
+    #[prost(string, tag = "8")]
+    pub browser_user_agent: String,
+    #[prost(string, tag = "9")]
+    pub challenge_window_size: String,
+    #[prost(bool, tag = "10")]
+    pub browser_java_enabled: bool,
+    #[prost(bool, tag = "11")]
+    pub browser_javascript_enabled: bool,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct DeviceRenderOptions {
+    #[prost(string, tag = "1")]
+    pub sdk_interface: String,
+    #[prost(string, repeated, tag = "2")]
+    pub sdk_ui_type: Vec<String>,
+    #[prost(string, repeated, tag = "3")]
+    pub sdk_authentication_type: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct EphemeralPublicKey {
+    #[prost(string, tag = "1")]
+    pub kty: String,
+    #[prost(string, tag = "2")]
+    pub crv: String,
+    #[prost(string, tag = "3")]
+    pub x: String,
+    #[prost(string, tag = "4")]
+    pub y: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ResultsRequest {
+    #[prost(string, tag = "1")]
+    pub acs_trans_id: String,
+    #[prost(string, tag = "2")]
+    pub message_category: String,
+    #[prost(string, tag = "3")]
+    pub eci: String,
+    #[prost(string, tag = "4")]
+    pub message_type: String,
+    #[prost(string, tag = "5")]
+    pub acs_ui_template: String,
+    #[prost(string, tag = "6")]
+    pub acs_interface: String,
+    #[prost(string, tag = "7")]
+    pub ds_trans_id: String,
+    #[prost(string, tag = "8")]
+    pub authentication_method: String,
+    #[prost(string, tag = "9")]
+    pub authentication_type: String,
+    #[prost(string, tag = "10")]
+    pub message_version: String,
+    #[prost(string, optional, tag = "11")]
+    pub sdk_trans_id: Option<String>,
+    #[prost(string, tag = "12")]
+    pub interaction_counter: String,
+    #[prost(string, optional, tag = "13")]
+    pub authentication_value: Option<String>,
+    #[prost(string, tag = "14")]
+    pub trans_status: String,
+    #[prost(string, tag = "15")]
+    pub three_ds_server_trans_id: String,
+    #[prost(string, optional, tag = "16")]
+    pub device_binding_status: Option<String>,
+    #[prost(string, optional, tag = "17")]
+    pub trust_list_status: Option<String>,
+    #[prost(string, optional, tag = "18")]
+    pub trans_status_reason: Option<String>,
+    #[prost(string, optional, tag = "19")]
+    pub whitelist_status: Option<String>,
+    #[prost(string, optional, tag = "20")]
+    pub whitelist_status_source: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ChallengeUi {
+    #[prost(string, tag = "1")]
+    pub acs_ui_type: String,
+    #[prost(string, optional, tag = "2")]
+    pub challenge_info_header: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    pub challenge_info_label: Option<String>,
+    #[prost(string, optional, tag = "4")]
+    pub challenge_info_text: Option<String>,
+    #[prost(string, optional, tag = "5")]
+    pub submit_authentication_label: Option<String>,
+    #[prost(string, optional, tag = "6")]
+    pub resend_information_label: Option<String>,
+    #[prost(message, repeated, tag = "7")]
+    pub challenge_select_info: Vec<ChallengeSelectOption>,
+    #[prost(string, optional, tag = "8")]
+    pub oob_continue_label: Option<String>,
+    #[prost(string, optional, tag = "9")]
+    pub oob_app_url: Option<String>,
+    #[prost(string, optional, tag = "10")]
+    pub oob_app_label: Option<String>,
+    #[prost(string, optional, tag = "11")]
+    pub acs_html: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct MethodDeviceData {
+    #[prost(string, tag = "1")]
+    pub browser_user_agent: String,
+    #[prost(string, tag = "2")]
+    pub browser_language: String,
+    #[prost(string, tag = "3")]
+    pub browser_color_depth: String,
+    #[prost(uint32, tag = "4")]
+    pub browser_screen_height: u32,
+    #[prost(uint32, tag = "5")]
+    pub browser_screen_width: u32,
+    #[prost(sint32, tag = "6")]
+    pub browser_tz: i32,
+    #[prost(bool, tag = "7")]
+    pub browser_java_enabled: bool,
+    #[prost(string, optional, tag = "8")]
+    pub platform: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ChallengeSelectOption {
+    #[prost(string, tag = "1")]
+    pub value: String,
+    #[prost(string, tag = "2")]
+    pub label: String,
+}
+
+impl From<&TransactionData> for Transaction {
+    fn from(data: &TransactionData) -> Self {
+        let areq = &data.authenticate_request;
+        Self {
+            three_ds_server_trans_id: areq.three_ds_server_trans_id.to_string(),
+            acs_trans_id: data.acs_trans_id.to_string(),
+            ds_trans_id: data.ds_trans_id.to_string(),
+            sdk_trans_id: data.sdk_trans_id.map(|id| id.to_string()),
+            authenticate_request: Some(areq.into()),
+            results_request: data.results_request.as_ref().map(Into::into),
+            // Only the public half - the private key never leaves the store
+            acs_ephemeral_public_key: data
+                .ephemeral_keys
+                .as_ref()
+                .map(|keys| (&keys.public_key).into()),
+            redirect_url: data.redirect_url.clone(),
+            sdk_ephemeral_public_key: data.sdk_ephemeral_public_key.clone(),
+            authentication_type: data.authentication_type.clone(),
+            interaction_count: data.interaction_count,
+            challenge_ui: data.challenge_ui.as_ref().map(Into::into),
+            spc_challenge: data.spc_challenge.clone(),
+            three_ds_method_data: data.three_ds_method_data.as_ref().map(Into::into),
+            three_ds_method_completed: data.three_ds_method_completed,
+            otp_attempts: data.otp_attempts,
+            oob_started_at_ms: data.oob_started_at_ms,
+            oob_approved: data.oob_approved,
+            whitelisting_choice: data.whitelisting_choice.clone(),
+            request_id: data.request_id.clone(),
+            masked_pan: data.masked_pan.as_ref().map(ToString::to_string),
+            pan_hash: data.pan_hash.clone(),
+            three_ds_session_data: data.three_ds_session_data.clone(),
+            // Carried as the acsUiTemplate the ARes announced for it
+            initial_ui: data.initial_ui.map(|ui| ui.rendering().1.to_string()),
+            protocol_2_3: data.protocol_2_3.as_ref().map(Into::into),
+            success_liability: data.success_liability.map(|liability| {
+                match liability {
+                    config::SuccessLiability::Authenticated => "authenticated",
+                    config::SuccessLiability::Attempted => "attempted",
+                }
+                .to_string()
+            }),
+        }
+    }
+}
+
+impl From<&config::Protocol23Fields> for Protocol23Fields {
+    fn from(fields: &config::Protocol23Fields) -> Self {
+        Self {
+            device_binding_status: fields.device_binding_status.clone(),
+            trust_list_status: fields.trust_list_status.clone(),
+        }
+    }
+}
+
+impl From<&models::MethodDeviceData> for MethodDeviceData {
+    fn from(data: &models::MethodDeviceData) -> Self {
+        Self {
+            browser_user_agent: data.browser_user_agent.clone(),
+            browser_language: data.browser_language.clone(),
+            browser_color_depth: data.browser_color_depth.clone(),
+            browser_screen_height: data.browser_screen_height,
+            browser_screen_width: data.browser_screen_width,
+            browser_tz: data.browser_tz,
+            browser_java_enabled: data.browser_java_enabled,
+            platform: data.platform.clone(),
+        }
+    }
+}
+
+impl From<&models::AuthenticateRequest> for AuthenticateRequest {
+    fn from(req: &models::AuthenticateRequest) -> Self {
+        let requestor = &req.three_ds_requestor;
+        let account = &req.cardholder_account;
+        let cardholder = &req.cardholder;
+        let purchase = &req.purchase;
+        let merchant = &req.merchant;
+
+        Self {
+            three_ds_server_trans_id: req.three_ds_server_trans_id.to_string(),
+            sdk_trans_id: req.sdk_trans_id.map(|id| id.to_string()),
+            device_channel: req.device_channel.to_string(),
+            message_category: req.message_category.to_string(),
+            preferred_protocol_version: req.preferred_protocol_version.clone(),
+            enforce_preferred_protocol_version: req.enforce_preferred_protocol_version,
+            three_ds_comp_ind: req.three_ds_comp_ind.clone(),
+            three_ds_requestor: Some(ThreeDsRequestor {
+                three_ds_requestor_authentication_ind: requestor
+                    .three_ds_requestor_authentication_ind
+                    .clone(),
+                three_ds_req_auth_method: requestor
+                    .three_ds_requestor_authentication_info
+                    .three_ds_req_auth_method
+                    .clone(),
+                three_ds_req_auth_timestamp: requestor
+                    .three_ds_requestor_authentication_info
+                    .three_ds_req_auth_timestamp
+                    .clone(),
+                three_ds_requestor_challenge_ind: requestor
+                    .three_ds_requestor_challenge_ind
+                    .to_string(),
+            }),
+            cardholder_account: Some(CardholderAccount {
+                acct_type: account.acct_type.clone(),
+                card_expiry_date: account.card_expiry_date.clone(),
+                scheme_id: account.scheme_id.clone(),
+                acct_number: account.acct_number.clone(),
+                card_security_code: account.card_security_code.clone(),
+            }),
+            cardholder: Some(Cardholder {
+                addr_match: cardholder.addr_match.clone(),
+                bill_addr: Some(Address {
+                    city: cardholder.bill_addr_city.clone(),
+                    country: cardholder.bill_addr_country.clone(),
+                    line1: cardholder.bill_addr_line1.clone(),
+                    line2: cardholder.bill_addr_line2.clone(),
+                    line3: cardholder.bill_addr_line3.clone(),
+                    post_code: cardholder.bill_addr_post_code.clone(),
+                }),
+                email: cardholder.email.clone(),
+                home_phone: Some((&cardholder.home_phone).into()),
+                mobile_phone: Some((&cardholder.mobile_phone).into()),
+                work_phone: Some((&cardholder.work_phone).into()),
+                cardholder_name: cardholder.cardholder_name.clone(),
+                ship_addr: Some(Address {
+                    city: cardholder.ship_addr_city.clone(),
+                    country: cardholder.ship_addr_country.clone(),
+                    line1: cardholder.ship_addr_line1.clone(),
+                    line2: cardholder.ship_addr_line2.clone(),
+                    line3: cardholder.ship_addr_line3.clone(),
+                    post_code: cardholder.ship_addr_post_code.clone(),
+                }),
+            }),
+            purchase: Some(Purchase {
+                purchase_instal_data: purchase.purchase_instal_data,
+                purchase_amount: purchase.purchase_amount,
+                purchase_currency: purchase.purchase_currency.clone(),
+                purchase_exponent: purchase.purchase_exponent,
+                purchase_date: purchase.purchase_date.clone(),
+                recurring_expiry: purchase.recurring_expiry.clone(),
+                recurring_frequency: purchase.recurring_frequency,
+                trans_type: purchase.trans_type.clone(),
+            }),
+            acquirer: Some(Acquirer {
+                acquirer_bin: req.acquirer.acquirer_bin.clone(),
+                acquirer_merchant_id: req.acquirer.acquirer_merchant_id.clone(),
+            }),
+            merchant: Some(Merchant {
+                mcc: merchant.mcc.clone(),
+                merchant_country_code: merchant.merchant_country_code.clone(),
+                three_ds_requestor_id: merchant.three_ds_requestor_id.clone(),
+                three_ds_requestor_name: merchant.three_ds_requestor_name.clone(),
+                merchant_name: merchant.merchant_name.clone(),
+                results_response_notification_url: merchant
+                    .results_response_notification_url
+                    .clone(),
+                notification_url: merchant.notification_url.clone(),
+            }),
+            browser_information: req.browser_information.as_ref().map(|browser| {
+                BrowserInformation {
+                    browser_accept_header: browser.browser_accept_header.clone(),
+                    browser_ip: browser.browser_ip.clone(),
+                    browser_language: browser.browser_language.clone(),
+                    browser_color_depth: browser.browser_color_depth.clone(),
+                    browser_screen_height: browser.browser_screen_height,
+                    browser_screen_width: browser.browser_screen_width,
+                    browser_tz: browser.browser_tz,
+                    browser_user_agent: browser.browser_user_agent.clone(),
+                    challenge_window_size: browser.challenge_window_size.clone(),
+                    browser_java_enabled: browser.browser_java_enabled,
+                    browser_javascript_enabled: browser.browser_javascript_enabled,
+                }
+            }),
+            device_render_options: req.device_render_options.as_ref().map(|render| {
+                DeviceRenderOptions {
+                    sdk_interface: render.sdk_interface.clone(),
+                    sdk_ui_type: render.sdk_ui_type.clone(),
+                    sdk_authentication_type: render.sdk_authentication_type.clone(),
+                }
+            }),
+            sdk_enc_data: req.sdk_enc_data.clone(),
+            three_ds_requestor_spc_support: req.three_ds_requestor_spc_support.clone(),
+            sdk_ephemeral_public_key: req.sdk_ephemeral_key().as_ref().map(Into::into),
+            browser_extension: req.browser_extension.as_ref().map(|data| data.to_string()),
+            device_binding_status: req.device_binding_status.clone(),
+            trust_list_status: req.trust_list_status.clone(),
+            message_extension: req
+                .message_extension
+                .as_ref()
+                .and_then(|extensions| serde_json::to_string(extensions).ok()),
+            sdk_reference_number: req.sdk_reference_number.clone(),
+        }
+    }
+}
+
+impl From<&models::Phone> for Phone {
+    fn from(phone: &models::Phone) -> Self {
+        Self {
+            cc: phone.cc.clone(),
+            subscriber: phone.subscriber.clone(),
+        }
+    }
+}
+
+impl From<&SdkEphemeralPublicKey> for EphemeralPublicKey {
+    fn from(key: &SdkEphemeralPublicKey) -> Self {
+        Self {
+            kty: key.kty.clone(),
+            crv: key.crv.clone(),
+            x: key.x.clone(),
+            y: key.y.clone(),
+        }
+    }
+}
+
+impl From<&AcsEphemPubKey> for EphemeralPublicKey {
+    fn from(key: &AcsEphemPubKey) -> Self {
+        Self {
+            kty: key.kty.clone(),
+            crv: key.crv.clone(),
+            x: key.x.clone(),
+            y: key.y.clone(),
+        }
+    }
+}
+
+impl From<&models::ResultsRequest> for ResultsRequest {
+    fn from(rreq: &models::ResultsRequest) -> Self {
+        Self {
+            acs_trans_id: rreq.acs_trans_id.to_string(),
+            message_category: rreq.message_category.to_string(),
+            eci: rreq.eci.to_string(),
+            message_type: rreq.message_type.clone(),
+            acs_ui_template: rreq.acs_rendering_type.acs_ui_template.clone(),
+            acs_interface: rreq.acs_rendering_type.acs_interface.clone(),
+            ds_trans_id: rreq.ds_trans_id.to_string(),
+            authentication_method: rreq.authentication_method.clone().unwrap_or_default(),
+            authentication_type: rreq.authentication_type.clone(),
+            message_version: rreq.message_version.clone(),
+            sdk_trans_id: rreq.sdk_trans_id.map(|id| id.to_string()),
+            interaction_counter: rreq.interaction_counter.clone(),
+            authentication_value: rreq.authentication_value.clone(),
+            trans_status: rreq.trans_status.to_string(),
+            three_ds_server_trans_id: rreq.three_ds_server_trans_id.to_string(),
+            device_binding_status: rreq.device_binding_status.clone(),
+            trust_list_status: rreq.trust_list_status.clone(),
+            trans_status_reason: rreq.trans_status_reason.clone(),
+            whitelist_status: rreq.whitelist_status.clone(),
+            whitelist_status_source: rreq.whitelist_status_source.clone(),
+        }
+    }
+}
+
+impl From<&ModelChallengeUi> for ChallengeUi {
+    fn from(ui: &ModelChallengeUi) -> Self {
+        let select_info = |options: &[models::ChallengeSelectOption]| {
+            options
+                .iter()
+                .map(|option| ChallengeSelectOption {
+                    value: option.value.clone(),
+                    label: option.label.clone(),
+                })
+                .collect()
+        };
+
+        match ui {
+            ModelChallengeUi::Otp {
+                challenge_info_header,
+                challenge_info_label,
+                challenge_info_text,
+                submit_authentication_label,
+                resend_information_label,
+            } => Self {
+                acs_ui_type: "01".to_string(),
+                challenge_info_header: Some(challenge_info_header.clone()),
+                challenge_info_label: Some(challenge_info_label.clone()),
+                challenge_info_text: challenge_info_text.clone(),
+                submit_authentication_label: Some(submit_authentication_label.clone()),
+                resend_information_label: resend_information_label.clone(),
+                ..Default::default()
+            },
+            ModelChallengeUi::SingleSelect {
+                challenge_info_header,
+                challenge_info_label,
+                challenge_info_text,
+                challenge_select_info,
+                submit_authentication_label,
+            } => Self {
+                acs_ui_type: "02".to_string(),
+                challenge_info_header: Some(challenge_info_header.clone()),
+                challenge_info_label: Some(challenge_info_label.clone()),
+                challenge_info_text: challenge_info_text.clone(),
+                challenge_select_info: select_info(challenge_select_info),
+                submit_authentication_label: Some(submit_authentication_label.clone()),
+                ..Default::default()
+            },
+            ModelChallengeUi::MultiSelect {
+                challenge_info_header,
+                challenge_info_label,
+                challenge_info_text,
+                challenge_select_info,
+                submit_authentication_label,
+            } => Self {
+                acs_ui_type: "03".to_string(),
+                challenge_info_header: Some(challenge_info_header.clone()),
+                challenge_info_label: Some(challenge_info_label.clone()),
+                challenge_info_text: challenge_info_text.clone(),
+                challenge_select_info: select_info(challenge_select_info),
+                submit_authentication_label: Some(submit_authentication_label.clone()),
+                ..Default::default()
+            },
+            ModelChallengeUi::Oob {
+                challenge_info_header,
+                challenge_info_text,
+                oob_continue_label,
+                oob_app_url,
+                oob_app_label,
+            } => Self {
+                acs_ui_type: "04".to_string(),
+                challenge_info_header: Some(challenge_info_header.clone()),
+                challenge_info_text: Some(challenge_info_text.clone()),
+                oob_continue_label: Some(oob_continue_label.clone()),
+                oob_app_url: oob_app_url.clone(),
+                oob_app_label: oob_app_label.clone(),
+                ..Default::default()
+            },
+            ModelChallengeUi::Html { acs_html } => Self {
+                acs_ui_type: "05".to_string(),
+                acs_html: Some(acs_html.clone()),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Encode a stored transaction as a `Transaction` protobuf message
+pub fn encode_transaction(data: &TransactionData) -> Vec<u8> {
+    Transaction::from(data).encode_to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::challenge::InitialUi;
+    use crate::models::tests::sample_authenticate_request;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_transaction_export_decodes_with_schema_fields() {
+        let data = TransactionData {
+            authenticate_request: sample_authenticate_request(),
+            acs_trans_id: Uuid::new_v4(),
+            ds_trans_id: Uuid::new_v4(),
+            sdk_trans_id: None,
+            results_request: None,
+            ephemeral_keys: None,
+            redirect_url: None,
+            sdk_ephemeral_public_key: None,
+            authentication_type: Some("02".to_string()),
+            interaction_count: 2,
+            challenge_ui: Some(ModelChallengeUi::otp_form()),
+            spc_challenge: None,
+            three_ds_method_data: None,
+            frictionless_outcome: None,
+            message_version: None,
+            three_ds_method_completed: false,
+            otp_attempts: 0,
+            otp: Some("918273".to_string()),
+            oob_started_at_ms: None,
+            oob_approved: None,
+            whitelisting_choice: None,
+            request_id: None,
+            masked_pan: None,
+            pan_hash: None,
+            initial_ui: Some(InitialUi::SingleSelect),
+            protocol_2_3: Some(config::Protocol23Fields {
+                device_binding_status: Some("11".to_string()),
+                trust_list_status: None,
+            }),
+            three_ds_session_data: None,
+            success_liability: Some(config::SuccessLiability::Attempted),
+        };
+
+        let encoded = encode_transaction(&data);
+        // The OTP would let anyone holding the export complete the challenge
+        assert!(!encoded.windows(6).any(|bytes| bytes == b"918273"));
+        let decoded = Transaction::decode(encoded.as_slice()).unwrap();
+
+        assert_eq!(decoded.acs_trans_id, data.acs_trans_id.to_string());
+        assert_eq!(decoded.interaction_count, 2);
+        assert_eq!(decoded.acs_ephemeral_public_key, None);
+        let areq = decoded.authenticate_request.unwrap();
+        assert_eq!(areq.device_channel, "02");
+        assert_eq!(areq.purchase.unwrap().purchase_amount, 100);
+        assert_eq!(decoded.challenge_ui.unwrap().acs_ui_type, "01");
+        assert_eq!(decoded.initial_ui.as_deref(), Some("02"));
+        let fields_2_3 = decoded.protocol_2_3.unwrap();
+        assert_eq!(fields_2_3.device_binding_status.as_deref(), Some("11"));
+        assert_eq!(fields_2_3.trust_list_status, None);
+        assert_eq!(decoded.success_liability.as_deref(), Some("attempted"));
+    }
+}
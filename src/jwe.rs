@@ -0,0 +1,353 @@
+//! Lenient parsing of the compact JWE an SDK posts as its CReq. Instead of stopping at the
+//! first problem, every part is inspected so a malformed token can be explained in full.
+
+use base64::{engine::general_purpose, Engine as _};
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+use uuid::Uuid;
+
+/// Names of the five compact serialization parts, in order (RFC 7516 section 7.1)
+const PART_NAMES: [&str; 5] = ["header", "encryptedKey", "iv", "ciphertext", "tag"];
+
+/// Content encryption algorithms the ACS can decrypt
+const SUPPORTED_ENC: &[&str] = &["A128CBC-HS256", "A128GCM"];
+
+/// Length of a hyphenated UUID, the format of the acsTransID carried as kid
+const UUID_LENGTH: usize = 36;
+
+/// A problem found in a compact JWE
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "issue", rename_all = "camelCase")]
+pub enum JweIssue {
+    /// A JSON document was posted instead of a JWE, usually an SDK-side error report
+    JsonInsteadOfJwe,
+    PartCount {
+        found: usize,
+    },
+    InvalidBase64 {
+        part: &'static str,
+        detail: String,
+    },
+    /// Base64 padding on a part; tolerated, but not valid base64url for JWE
+    PaddedBase64 {
+        part: &'static str,
+    },
+    HeaderNotJson {
+        detail: String,
+    },
+    MissingHeaderParameter {
+        name: &'static str,
+    },
+    UnsupportedEnc {
+        enc: String,
+    },
+    /// The kid looks like an acsTransID cut short, e.g. by a fixed-size SDK buffer
+    TruncatedKid {
+        kid: String,
+        length: usize,
+    },
+    InvalidKid {
+        kid: String,
+        detail: String,
+    },
+}
+
+impl JweIssue {
+    /// Whether the token cannot be processed with this issue present
+    pub fn is_fatal(&self) -> bool {
+        !matches!(self, Self::PaddedBase64 { .. })
+    }
+}
+
+impl fmt::Display for JweIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::JsonInsteadOfJwe => write!(f, "received JSON instead of a JWE"),
+            Self::PartCount { found } => write!(f, "expected 5 parts, got {}", found),
+            Self::InvalidBase64 { part, detail } => {
+                write!(f, "{} is not valid base64url ({})", part, detail)
+            }
+            Self::PaddedBase64 { part } => write!(f, "{} carries base64 padding", part),
+            Self::HeaderNotJson { detail } => write!(f, "header is not JSON ({})", detail),
+            Self::MissingHeaderParameter { name } => write!(f, "header has no {}", name),
+            Self::UnsupportedEnc { enc } => write!(
+                f,
+                "unsupported enc {} (supported: {})",
+                enc,
+                SUPPORTED_ENC.join(", ")
+            ),
+            Self::TruncatedKid { kid, length } => write!(
+                f,
+                "kid {} is truncated ({} of {} characters)",
+                kid, length, UUID_LENGTH
+            ),
+            Self::InvalidKid { kid, detail } => {
+                write!(f, "kid {} is not an acsTransID ({})", kid, detail)
+            }
+        }
+    }
+}
+
+/// One part of the compact serialization
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JwePart {
+    pub name: &'static str,
+    pub length: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decoded_bytes: Option<usize>,
+}
+
+/// Everything learned about a token, returned by `/debug/analyze-jwe` and in the Erro
+/// answering a malformed CReq
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JweDiagnostics {
+    pub valid: bool,
+    pub parts: Vec<JwePart>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub header: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enc: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acs_trans_id: Option<Uuid>,
+    pub issues: Vec<JweIssue>,
+}
+
+impl JweDiagnostics {
+    /// The fatal issues as one line, for the Erro errorDetail
+    pub fn summary(&self) -> String {
+        let issues: Vec<String> = self
+            .issues
+            .iter()
+            .filter(|issue| issue.is_fatal())
+            .map(ToString::to_string)
+            .collect();
+        format!("Invalid JWE: {}", issues.join("; "))
+    }
+}
+
+/// A token that passed analysis, ready for key agreement and decryption
+#[derive(Debug)]
+pub struct ParsedJwe<'a> {
+    /// The token without surrounding whitespace or quotes
+    pub compact: &'a str,
+    pub header: Value,
+    /// The kid as sent, echoed back in the CRes header
+    pub kid: String,
+    pub acs_trans_id: Uuid,
+}
+
+/// Parse a CReq JWE, returning the diagnostics when it cannot be processed
+pub fn parse(token: &str) -> Result<ParsedJwe<'_>, Box<JweDiagnostics>> {
+    let diagnostics = analyze(token);
+    match diagnostics {
+        JweDiagnostics {
+            valid: true,
+            header: Some(header),
+            kid: Some(kid),
+            acs_trans_id: Some(acs_trans_id),
+            ..
+        } => Ok(ParsedJwe {
+            compact: normalize(token),
+            header,
+            kid,
+            acs_trans_id,
+        }),
+        diagnostics => Err(Box::new(diagnostics)),
+    }
+}
+
+/// Inspect every part of a token. Surrounding whitespace and quotes (a token posted as a
+/// JSON string) are ignored.
+pub fn analyze(token: &str) -> JweDiagnostics {
+    let token = normalize(token);
+    let mut diagnostics = JweDiagnostics {
+        valid: false,
+        parts: Vec::new(),
+        header: None,
+        enc: None,
+        kid: None,
+        acs_trans_id: None,
+        issues: Vec::new(),
+    };
+
+    if token.starts_with('{') && serde_json::from_str::<serde::de::IgnoredAny>(token).is_ok() {
+        diagnostics.issues.push(JweIssue::JsonInsteadOfJwe);
+        return diagnostics;
+    }
+
+    let parts: Vec<&str> = token.split('.').collect();
+    if parts.len() != PART_NAMES.len() {
+        diagnostics
+            .issues
+            .push(JweIssue::PartCount { found: parts.len() });
+    }
+
+    let mut header_bytes = None;
+    for (index, part) in parts.iter().enumerate() {
+        let name = PART_NAMES.get(index).copied().unwrap_or("extra");
+        let unpadded = part.trim_end_matches('=');
+        if unpadded.len() != part.len() {
+            diagnostics
+                .issues
+                .push(JweIssue::PaddedBase64 { part: name });
+        }
+        let decoded = match general_purpose::URL_SAFE_NO_PAD.decode(unpadded) {
+            Ok(decoded) => Some(decoded),
+            Err(e) => {
+                diagnostics.issues.push(JweIssue::InvalidBase64 {
+                    part: name,
+                    detail: e.to_string(),
+                });
+                None
+            }
+        };
+        diagnostics.parts.push(JwePart {
+            name,
+            length: part.len(),
+            decoded_bytes: decoded.as_ref().map(Vec::len),
+        });
+        if index == 0 {
+            header_bytes = decoded;
+        }
+    }
+
+    if let Some(header_bytes) = header_bytes {
+        match serde_json::from_slice::<Value>(&header_bytes) {
+            Ok(header) => {
+                inspect_header(&header, &mut diagnostics);
+                diagnostics.header = Some(header);
+            }
+            Err(e) => diagnostics.issues.push(JweIssue::HeaderNotJson {
+                detail: e.to_string(),
+            }),
+        }
+    }
+
+    diagnostics.valid = !diagnostics.issues.iter().any(JweIssue::is_fatal);
+    diagnostics
+}
+
+fn normalize(token: &str) -> &str {
+    let token = token.trim();
+    token
+        .strip_prefix('"')
+        .and_then(|token| token.strip_suffix('"'))
+        .unwrap_or(token)
+        .trim()
+}
+
+fn inspect_header(header: &Value, diagnostics: &mut JweDiagnostics) {
+    match header["enc"].as_str() {
+        Some(enc) => {
+            if !SUPPORTED_ENC.contains(&enc) {
+                diagnostics.issues.push(JweIssue::UnsupportedEnc {
+                    enc: enc.to_string(),
+                });
+            }
+            diagnostics.enc = Some(enc.to_string());
+        }
+        None => diagnostics
+            .issues
+            .push(JweIssue::MissingHeaderParameter { name: "enc" }),
+    }
+
+    let Some(kid) = header["kid"].as_str() else {
+        diagnostics
+            .issues
+            .push(JweIssue::MissingHeaderParameter { name: "kid" });
+        return;
+    };
+    diagnostics.kid = Some(kid.to_string());
+    match Uuid::parse_str(kid) {
+        Ok(acs_trans_id) => diagnostics.acs_trans_id = Some(acs_trans_id),
+        Err(_) if is_truncated_uuid(kid) => diagnostics.issues.push(JweIssue::TruncatedKid {
+            kid: kid.to_string(),
+            length: kid.len(),
+        }),
+        Err(e) => diagnostics.issues.push(JweIssue::InvalidKid {
+            kid: kid.to_string(),
+            detail: e.to_string(),
+        }),
+    }
+}
+
+/// A proper prefix of a hyphenated UUID: hex digits with hyphens only where a UUID has them
+fn is_truncated_uuid(kid: &str) -> bool {
+    !kid.is_empty()
+        && kid.len() < UUID_LENGTH
+        && kid.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(header: &Value) -> String {
+        let header = general_purpose::URL_SAFE_NO_PAD.encode(header.to_string());
+        format!("{}..aXY.Y2lwaGVydGV4dA.dGFn", header)
+    }
+
+    #[test]
+    fn test_parse_accepts_quoted_token() {
+        let acs_trans_id = Uuid::new_v4();
+        let jwe = token(&serde_json::json!({ "enc": "A128GCM", "kid": acs_trans_id }));
+        let quoted = format!(" \"{}\"\n", jwe);
+
+        let parsed = parse(&quoted).expect("valid JWE");
+        assert_eq!(parsed.compact, jwe);
+        assert_eq!(parsed.acs_trans_id, acs_trans_id);
+    }
+
+    #[test]
+    fn test_analyze_classifies_issues() {
+        let diagnostics = analyze("abc.def");
+        assert!(!diagnostics.valid);
+        assert_eq!(diagnostics.issues[0], JweIssue::PartCount { found: 2 });
+
+        let diagnostics = analyze("not+base64..aXY.Y2lwaGVydGV4dA.dGFn");
+        assert!(matches!(
+            diagnostics.issues[0],
+            JweIssue::InvalidBase64 { part: "header", .. }
+        ));
+
+        let header = general_purpose::URL_SAFE_NO_PAD.encode("not json");
+        let diagnostics = analyze(&format!("{}..aXY.Y2lwaGVydGV4dA.dGFn", header));
+        assert!(matches!(
+            diagnostics.issues[0],
+            JweIssue::HeaderNotJson { .. }
+        ));
+
+        let kid = &Uuid::new_v4().to_string()[..35];
+        let diagnostics = analyze(&token(&serde_json::json!({ "enc": "A128GCM", "kid": kid })));
+        assert_eq!(
+            diagnostics.issues,
+            vec![JweIssue::TruncatedKid {
+                kid: kid.to_string(),
+                length: 35
+            }]
+        );
+        assert!(diagnostics.summary().contains("truncated"));
+
+        let diagnostics = analyze(r#"{"errorCode":"402"}"#);
+        assert_eq!(diagnostics.issues, vec![JweIssue::JsonInsteadOfJwe]);
+    }
+
+    #[test]
+    fn test_padding_is_tolerated() {
+        let jwe = token(&serde_json::json!({ "enc": "A128GCM", "kid": Uuid::new_v4() }));
+        let diagnostics = analyze(&format!("{}==", jwe));
+        assert!(diagnostics.valid);
+        assert_eq!(
+            diagnostics.issues,
+            vec![JweIssue::PaddedBase64 { part: "tag" }]
+        );
+    }
+}
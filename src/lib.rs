@@ -0,0 +1,1263 @@
+//! Mock EMV 3DS Server, Directory Server and ACS. The binary runs it from its configuration
+//! files; other crates can start it in-process with [`MockThreeDsServer::builder`], e.g. to
+//! run their integration tests against it without shelling out to the binary.
+
+#![recursion_limit = "256"]
+
+mod acs_client;
+mod auth_value;
+mod cache;
+mod card_scenarios;
+mod card_scheme;
+mod card_status;
+mod cert_expiry;
+mod challenge;
+pub mod cli;
+pub mod config;
+mod crypto;
+mod ds;
+mod error;
+mod faults;
+mod flow_runner;
+mod handlers;
+mod health;
+mod interchange;
+mod jwe;
+mod models;
+mod pan;
+mod performance;
+mod protocol;
+mod rate_limit;
+mod recording;
+mod request_id;
+mod results_delivery;
+mod self_test;
+mod state_store;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+mod tls;
+mod validation;
+mod webhook;
+
+use crate::config::{
+    MissingCertPolicy, PayloadLimitsConfig, RoutesConfig, Settings, StateBackend, API_VERSION,
+};
+use acs_client::{
+    AcsClient, ACS_AREQ_PATH, ACS_CREQ_PATH, ACS_METHOD_DEVICE_DATA_PATH, ACS_METHOD_PATH,
+    ACS_RREQ_PATH,
+};
+use actix_web::{
+    dev::{ServerHandle, Service, ServiceResponse},
+    http::{header, StatusCode},
+    middleware, web, App, HttpResponse, HttpServer, Result,
+};
+use actix_web_prom::PrometheusMetricsBuilder;
+use cache::AppCaches;
+use card_status::CardStatusList;
+use cert_expiry::AcsCertificateMonitor;
+use crypto::CryptoError;
+use faults::{FaultInjectingStore, FaultInjector};
+use futures_util::future::{join_all, ready, try_join_all, Either};
+use health::HealthStatus;
+use performance::PerformanceSwitches;
+use prometheus::{Encoder, TextEncoder};
+use rate_limit::{Decision, RateLimits};
+use self_test::SelfTestReport;
+use state_store::{create_redis_store, InMemoryStore, SqlStore, StateError, StateStore};
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tls::{PeerCertificates, TlsError};
+use tokio::task::JoinHandle;
+
+// actix's default access log line, plus the request's X-Request-ID
+const ACCESS_LOG_FORMAT: &str =
+    r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T request_id=%{x-request-id}o"#;
+
+// Pin the calling worker thread to the next core in round-robin order
+fn pin_worker_to_core(core_ids: &[core_affinity::CoreId], next_core: &AtomicUsize) {
+    if core_ids.is_empty() {
+        return;
+    }
+    let core_id = core_ids[next_core.fetch_add(1, Ordering::Relaxed) % core_ids.len()];
+    if core_affinity::set_for_current(core_id) {
+        tracing::debug!(core = core_id.id, "worker pinned to core");
+    } else {
+        tracing::warn!(core = core_id.id, "failed to pin worker to core");
+    }
+}
+
+// JSON extractor config enforcing a route's payload limit, with bad bodies answered as Erro
+fn json_config(limit: usize) -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(limit)
+        .error_handler(error::json_error_handler)
+}
+
+// Form extractor config for the ACS browser posts, answering bad forms as Erro
+fn form_config(limit: usize) -> web::FormConfig {
+    web::FormConfig::default()
+        .limit(limit)
+        .error_handler(error::form_error_handler)
+}
+
+// 3DS Server routes, plus the mock DS routes that sit alongside it
+fn configure_server_routes(
+    cfg: &mut web::ServiceConfig,
+    limits: &PayloadLimitsConfig,
+    prefix: &str,
+) {
+    let path = |route: &str| format!("{}{}", prefix, route);
+    cfg.service(
+        web::resource(path("/3ds/version"))
+            .app_data(json_config(limits.version_bytes))
+            .route(web::post().to(handlers::server::version_handler)),
+    )
+    // PReqs are as small as version calls
+    .service(
+        web::resource(path("/3ds/preq"))
+            .app_data(json_config(limits.version_bytes))
+            .route(web::post().to(handlers::server::preparation_handler)),
+    )
+    .service(
+        web::resource(path("/3ds/method"))
+            .app_data(json_config(limits.acs_form_bytes))
+            .route(web::post().to(handlers::server::three_ds_method_handler)),
+    )
+    .service(
+        web::resource(path("/3ds/authenticate"))
+            .app_data(web::PayloadConfig::new(limits.authenticate_bytes))
+            .route(web::post().to(handlers::server::authenticate_handler)),
+    )
+    .service(
+        web::resource(path("/3ds/results"))
+            .app_data(json_config(limits.results_bytes))
+            .route(web::post().to(handlers::server::results_handler)),
+    )
+    .service(
+        web::resource(path("/3ds/final"))
+            .app_data(json_config(limits.final_bytes))
+            .route(web::post().to(handlers::server::final_handler)),
+    )
+    .service(
+        web::resource(path("/ds/areq"))
+            .app_data(web::PayloadConfig::new(limits.authenticate_bytes))
+            .route(web::post().to(ds::ds_areq_handler)),
+    )
+    .service(
+        web::resource(path("/ds/rreq"))
+            .app_data(json_config(limits.results_bytes))
+            .route(web::post().to(ds::ds_rreq_handler)),
+    )
+    // Scripted end-to-end flows for CI; scripts are small, like RReqs
+    .service(
+        web::resource(path("/test/run-flow"))
+            .app_data(json_config(limits.results_bytes))
+            .route(web::post().to(flow_runner::run_flow_handler)),
+    );
+}
+
+// ACS routes: cardholder-facing challenge endpoints and the AReq/RReq entry points
+// the 3DS Server component calls when it runs in another process
+fn configure_acs_routes(cfg: &mut web::ServiceConfig, limits: &PayloadLimitsConfig, prefix: &str) {
+    let path = |route: &str| format!("{}{}", prefix, route);
+    cfg.service(
+        web::resource(path(ACS_AREQ_PATH))
+            .app_data(web::PayloadConfig::new(limits.authenticate_bytes))
+            .route(web::post().to(handlers::acs::authenticate_handler)),
+    )
+    .service(
+        web::resource(path(ACS_RREQ_PATH))
+            .app_data(json_config(limits.results_bytes))
+            .route(web::post().to(handlers::acs::results_handler)),
+    )
+    .service(
+        web::resource(path(ACS_CREQ_PATH))
+            .app_data(form_config(limits.acs_form_bytes))
+            .route(web::post().to(handlers::acs::acs_creq_handler)),
+    )
+    .service(
+        web::resource(path(ACS_METHOD_PATH))
+            .app_data(form_config(limits.acs_form_bytes))
+            .route(web::post().to(handlers::acs::three_ds_method_handler)),
+    )
+    .service(
+        web::resource(path(ACS_METHOD_DEVICE_DATA_PATH))
+            .app_data(json_config(limits.acs_form_bytes))
+            .route(web::post().to(handlers::acs::method_device_data_handler)),
+    )
+    .service(
+        web::resource(path("/processor/mock/acs/trigger-otp"))
+            .app_data(form_config(limits.acs_form_bytes))
+            .route(web::post().to(handlers::acs::acs_trigger_otp_handler)),
+    )
+    .service(
+        web::resource(path("/processor/mock/acs/verify-otp"))
+            .app_data(form_config(limits.acs_form_bytes))
+            .route(web::post().to(handlers::acs::acs_verify_otp_handler)),
+    )
+    .service(
+        web::resource(path("/processor/mock/acs/resend-otp"))
+            .app_data(form_config(limits.acs_form_bytes))
+            .route(web::post().to(handlers::acs::acs_resend_otp_handler)),
+    )
+    .service(
+        web::resource(path("/processor/mock/acs/spc-verify"))
+            .app_data(json_config(limits.spc_verify_bytes))
+            .route(web::post().to(handlers::acs::acs_spc_verify_handler)),
+    )
+    .service(
+        web::resource(path("/challenge"))
+            .app_data(web::PayloadConfig::new(limits.challenge_bytes))
+            .route(web::post().to(handlers::acs::challenge_handler)),
+    )
+    .service(
+        web::resource(path("/debug/analyze-jwe"))
+            .app_data(web::PayloadConfig::new(limits.challenge_bytes))
+            .route(web::post().to(handlers::acs::analyze_jwe_handler)),
+    );
+}
+
+// Health and metrics endpoints
+fn configure_admin_routes(cfg: &mut web::ServiceConfig, settings: &Settings, prefix: &str) {
+    let path = |route: &str| format!("{}{}", prefix, route);
+    cfg.route(
+        &path(&settings.monitoring.health_endpoint),
+        web::get().to(health_check),
+    );
+    cfg.route(
+        &path(&format!("{}/live", settings.monitoring.health_endpoint)),
+        web::get().to(liveness_check),
+    );
+    cfg.route(
+        &path(&format!("{}/ready", settings.monitoring.health_endpoint)),
+        web::get().to(readiness_check),
+    );
+    if settings.performance.enable_metrics {
+        cfg.route(
+            &path(&settings.monitoring.metrics_endpoint),
+            web::get().to(metrics_handler),
+        );
+    }
+    cfg.route(
+        &path("/admin/snapshot"),
+        web::get().to(handlers::admin::snapshot),
+    )
+    .service(
+        web::resource(path("/admin/performance"))
+            .route(web::get().to(handlers::admin::performance))
+            .route(web::put().to(handlers::admin::put_performance)),
+    )
+    .route(
+        &path("/admin/transactions/{three_ds_server_trans_id}/protobuf"),
+        web::get().to(handlers::admin::transaction_protobuf),
+    );
+    // Stored keys decrypt transaction contents, so these debug routes need the debug token
+    if settings.debug.api_token.is_some() {
+        cfg.service(
+            web::resource(path(
+                "/debug/transactions/{three_ds_server_trans_id}/decrypt",
+            ))
+            .app_data(web::PayloadConfig::new(
+                settings.payload_limits.challenge_bytes,
+            ))
+            .route(web::post().to(handlers::debug::decrypt_jwe)),
+        )
+        .service(
+            web::resource(path("/debug/derive-key"))
+                .app_data(json_config(settings.payload_limits.results_bytes))
+                .route(web::post().to(handlers::debug::derive_key)),
+        );
+    }
+    if settings.faults.enabled {
+        cfg.route(
+            &path(faults::FAULTS_ADMIN_PATH),
+            web::get().to(handlers::admin::list_faults),
+        )
+        .service(
+            web::resource(path("/admin/faults/active"))
+                .route(web::put().to(handlers::admin::activate_fault_profile))
+                .route(web::delete().to(handlers::admin::deactivate_fault_profile)),
+        )
+        .route(
+            &path("/admin/faults/profiles/{name}"),
+            web::put().to(handlers::admin::put_fault_profile),
+        );
+    }
+    // Card statuses only affect the ACS, so they are managed where it runs
+    if settings.components.serves_acs() {
+        cfg.route(
+            &path("/admin/card-statuses"),
+            web::get().to(handlers::admin::list_card_statuses),
+        )
+        .service(
+            web::resource(path("/admin/card-statuses/{pan}"))
+                .route(web::put().to(handlers::admin::put_card_status))
+                .route(web::delete().to(handlers::admin::delete_card_status)),
+        )
+        .route(
+            &path("/admin/transactions/{three_ds_server_trans_id}/otp"),
+            web::get().to(handlers::admin::transaction_otp),
+        )
+        .route(
+            &path("/admin/transactions/{three_ds_server_trans_id}/oob"),
+            web::post().to(handlers::admin::decide_oob),
+        )
+        .route(
+            &path("/admin/transactions/{three_ds_server_trans_id}/force-result"),
+            web::post().to(handlers::admin::force_result),
+        )
+        .route(
+            &path("/admin/transactions/{three_ds_server_trans_id}/expire"),
+            web::post().to(handlers::admin::expire_transaction),
+        )
+        .route(
+            &path("/admin/transactions/{three_ds_server_trans_id}"),
+            web::delete().to(handlers::admin::delete_transaction),
+        )
+        .route(
+            &path("/admin/transactions/{three_ds_server_trans_id}/export"),
+            web::get().to(handlers::admin::export_transaction),
+        )
+        .service(
+            web::resource(path("/admin/replay"))
+                .app_data(json_config(recording::MAX_CAPTURE_BYTES))
+                .route(web::post().to(flow_runner::replay_handler)),
+        );
+    }
+}
+
+// Route groups served by one listener
+#[derive(Debug, Clone, Copy, Default)]
+struct RouteGroups {
+    server: bool,
+    acs: bool,
+    admin: bool,
+}
+
+// Listener ports and the route groups each serves; groups without their own port share server.port
+fn listeners(settings: &Settings) -> BTreeMap<u16, RouteGroups> {
+    let port = |group_port: Option<u16>| group_port.unwrap_or(settings.server.port);
+    let mut listeners = BTreeMap::<u16, RouteGroups>::new();
+    if settings.components.serves_server() {
+        listeners
+            .entry(port(settings.routes.server_port))
+            .or_default()
+            .server = true;
+    }
+    if settings.components.serves_acs() {
+        listeners
+            .entry(port(settings.routes.acs_port))
+            .or_default()
+            .acs = true;
+    }
+    listeners
+        .entry(port(settings.routes.admin_port))
+        .or_default()
+        .admin = true;
+    listeners
+}
+
+// Health check endpoint, with the ACS signing certificate's expiry where the ACS runs, the
+// startup self-test result when it ran and, for deep checks, the state store's status. A
+// failed self-test or an unreachable store reports the service as unhealthy (503); a slow
+// store as degraded (200).
+async fn health_check(
+    state: web::Data<Arc<Box<dyn StateStore>>>,
+    settings: web::Data<Settings>,
+    cert_monitor: web::Data<AcsCertificateMonitor>,
+    self_test: web::Data<Option<SelfTestReport>>,
+) -> Result<HttpResponse> {
+    let mut status = match self_test.as_ref() {
+        Some(report) if !report.passed => HealthStatus::Unhealthy,
+        _ => HealthStatus::Healthy,
+    };
+    let mut dependencies = BTreeMap::new();
+    if settings.monitoring.deep_health_check {
+        let store =
+            health::check_state_store(state.get_ref().as_ref().as_ref(), &settings.monitoring)
+                .await;
+        status = status.max(store.service_status(settings.monitoring.health_degraded_latency_ms));
+        dependencies.insert(state.backend(), store);
+    }
+
+    let mut health = serde_json::json!({
+        "status": status,
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+        "service": "3ds-mock-server"
+    });
+    if !dependencies.is_empty() {
+        health["dependencies"] = serde_json::to_value(dependencies)?;
+    }
+    if settings.components.serves_acs() {
+        health["acs_certificate"] = serde_json::to_value(cert_monitor.check())?;
+    }
+    if let Some(report) = self_test.as_ref() {
+        health["self_test"] = serde_json::to_value(report)?;
+    }
+
+    match status {
+        HealthStatus::Unhealthy => Ok(HttpResponse::ServiceUnavailable().json(health)),
+        HealthStatus::Healthy | HealthStatus::Degraded => Ok(HttpResponse::Ok().json(health)),
+    }
+}
+
+// Liveness probe: answers as long as the workers do, whatever the state of the dependencies
+async fn liveness_check() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "status": "alive" }))
+}
+
+// Readiness probe: 503 until the state store, configuration, signing certificate and
+// startup self-test all check out
+async fn readiness_check(
+    state: web::Data<Arc<Box<dyn StateStore>>>,
+    settings: web::Data<Settings>,
+    self_test: web::Data<Option<SelfTestReport>>,
+) -> HttpResponse {
+    let readiness = health::readiness(
+        state.get_ref().as_ref().as_ref(),
+        &settings,
+        self_test.as_ref().as_ref(),
+    )
+    .await;
+    if readiness.ready {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
+
+// Prometheus metrics endpoint: request metrics from every listener plus the cache counters
+async fn metrics_handler(
+    registry: web::Data<prometheus::Registry>,
+    settings: web::Data<Settings>,
+    cert_monitor: web::Data<AcsCertificateMonitor>,
+) -> HttpResponse {
+    if settings.components.serves_acs() {
+        cert_monitor.check();
+    }
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    if let Err(e) = encoder.encode(&registry.gather(), &mut buffer) {
+        tracing::error!(error = %e, "failed to encode metrics");
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok()
+        .content_type(encoder.format_type())
+        .body(buffer)
+}
+
+// Startup banner: performance settings, components and the endpoints on each listener
+fn print_banner(
+    settings: &Settings,
+    listeners: &BTreeMap<u16, RouteGroups>,
+    acs_client: &AcsClient,
+) {
+    println!("⚡ Performance features:");
+    println!(
+        "   🗜️  Compression: {}",
+        if settings.performance.enable_compression {
+            "enabled"
+        } else {
+            "disabled"
+        }
+    );
+    println!(
+        "   📊 Metrics: {}",
+        if settings.performance.enable_metrics {
+            "enabled"
+        } else {
+            "disabled"
+        }
+    );
+    println!(
+        "   🚦 Rate limiting: {} ({} req/s, {} route rules, exempt: {})",
+        if settings.performance.enable_rate_limiting {
+            "enabled"
+        } else {
+            "disabled"
+        },
+        settings.performance.rate_limit_per_second,
+        settings.performance.rate_limits.len(),
+        settings.performance.rate_limit_exempt.join(", ")
+    );
+    println!(
+        "   🔌 Max connections per worker: {}",
+        settings.performance.max_connections
+    );
+    if let Some(blocking_threads) = settings.server.worker_max_blocking_threads {
+        println!("   🧵 Blocking threads per worker: {}", blocking_threads);
+    }
+    if settings.faults.enabled {
+        println!(
+            "   💥 Fault injection: enabled (active profile: {})",
+            settings.faults.active_profile.as_deref().unwrap_or("none")
+        );
+    }
+
+    let components = &settings.components;
+    let routes = &settings.routes;
+    println!("🧩 Components: {:?}", components.role);
+    if components.serves_server() {
+        println!("   🔀 3DS Server -> ACS: {}", acs_client.describe());
+    }
+    for (port, groups) in listeners {
+        println!(
+            "🌐 Server starting on: {}://{}",
+            settings.scheme(),
+            settings.listener_address(*port)
+        );
+        if routes.versioned_only {
+            println!("📋 Available endpoints:");
+        } else {
+            println!(
+                "📋 Available endpoints (also served without the /{} prefix):",
+                API_VERSION
+            );
+        }
+        if groups.server {
+            let prefix = &RoutesConfig::versioned(&routes.server_prefix);
+            println!("   POST {}/3ds/version", prefix);
+            println!("   POST {}/3ds/preq", prefix);
+            println!("   POST {}/3ds/method", prefix);
+            println!("   POST {}/3ds/authenticate", prefix);
+            println!("   POST {}/3ds/results", prefix);
+            println!("   POST {}/3ds/final", prefix);
+            println!("   POST {}/ds/areq (Mock DS -> ACS)", prefix);
+            println!("   POST {}/ds/rreq (Mock DS -> ACS results)", prefix);
+        }
+        if groups.acs {
+            let prefix = &RoutesConfig::versioned(&routes.acs_prefix);
+            println!(
+                "   POST {}{} (ACS AReq from a remote 3DS Server)",
+                prefix, ACS_AREQ_PATH
+            );
+            println!(
+                "   POST {}{} (ACS RReq from a remote 3DS Server)",
+                prefix, ACS_RREQ_PATH
+            );
+            println!(
+                "   POST {}{} (Browser CReq, base64url form post)",
+                prefix, ACS_CREQ_PATH
+            );
+            println!("   POST {}{} (3DS Method page)", prefix, ACS_METHOD_PATH);
+            println!(
+                "   POST {}{} (3DS Method device data)",
+                prefix, ACS_METHOD_DEVICE_DATA_PATH
+            );
+            println!(
+                "   POST {}/processor/mock/acs/trigger-otp (ACS Challenge)",
+                prefix
+            );
+            println!(
+                "   POST {}/processor/mock/acs/verify-otp (OTP Verification)",
+                prefix
+            );
+            println!(
+                "   POST {}/processor/mock/acs/resend-otp (OTP Resend)",
+                prefix
+            );
+            println!(
+                "   POST {}/processor/mock/acs/spc-verify (SPC Assertion)",
+                prefix
+            );
+            println!("   POST {}/challenge (Mobile Challenge)", prefix);
+            println!(
+                "   POST {}/debug/analyze-jwe (CReq JWE diagnostics)",
+                prefix
+            );
+        }
+        if groups.admin {
+            let prefix = &RoutesConfig::versioned(&routes.admin_prefix);
+            if settings.performance.enable_metrics {
+                println!(
+                    "   GET  {}{} (metrics)",
+                    prefix, settings.monitoring.metrics_endpoint
+                );
+            }
+            println!(
+                "   GET  {}{} (health)",
+                prefix, settings.monitoring.health_endpoint
+            );
+            println!(
+                "   GET  {}{}/live, {}{}/ready (liveness and readiness probes)",
+                prefix,
+                settings.monitoring.health_endpoint,
+                prefix,
+                settings.monitoring.health_endpoint
+            );
+            println!(
+                "   GET|PUT {}/admin/performance (compression and rate limiting switches)",
+                prefix
+            );
+            println!(
+                "   GET  {}/admin/transactions/{{threeDSServerTransID}}/protobuf (Protobuf export)",
+                prefix
+            );
+            println!(
+                "   GET  {}/versions (API version discovery)",
+                routes.admin_prefix
+            );
+            if settings.debug.api_token.is_some() {
+                println!(
+                    "   POST {}/debug/transactions/{{id}}/decrypt (decrypt a JWE with stored keys)",
+                    prefix
+                );
+                println!(
+                    "   POST {}/debug/derive-key (ECDH derived key and ConcatKDF inputs)",
+                    prefix
+                );
+            }
+            if settings.faults.enabled {
+                println!("   GET  {}/admin/faults (fault profiles)", prefix);
+                println!(
+                    "   PUT|DELETE {}/admin/faults/active (switch fault profile)",
+                    prefix
+                );
+                println!(
+                    "   PUT  {}/admin/faults/profiles/{{name}} (set fault profile)",
+                    prefix
+                );
+            }
+            if components.serves_acs() {
+                println!("   GET  {}/admin/card-statuses (card status list)", prefix);
+                println!(
+                    "   PUT|DELETE {}/admin/card-statuses/{{pan}} (set/clear card status)",
+                    prefix
+                );
+                println!(
+                    "   GET  {}/admin/transactions/{{threeDSServerTransID}}/otp (challenge OTP)",
+                    prefix
+                );
+                println!(
+                    "   POST {}/admin/transactions/{{threeDSServerTransID}}/oob (approve/decline OOB)",
+                    prefix
+                );
+                println!(
+                    "   POST {}/admin/transactions/{{threeDSServerTransID}}/force-result (record a synthetic RReq)",
+                    prefix
+                );
+                println!(
+                    "   POST {}/admin/transactions/{{threeDSServerTransID}}/expire (time the transaction out)",
+                    prefix
+                );
+                println!(
+                    "   DELETE {}/admin/transactions/{{threeDSServerTransID}} (delete the transaction)",
+                    prefix
+                );
+                println!(
+                    "   GET  {}/admin/transactions/{{threeDSServerTransID}}/export (recorded messages)",
+                    prefix
+                );
+                println!("   POST {}/admin/replay (replay an exported flow)", prefix);
+            }
+        }
+    }
+}
+
+/// Why the mock server could not start
+#[derive(Debug, thiserror::Error)]
+pub enum StartError {
+    #[error("Configuration validation failed: {0}")]
+    Config(String),
+    #[error(transparent)]
+    Certificate(#[from] CryptoError),
+    #[error("Failed to initialize Redis store: {0}")]
+    Redis(#[source] StateError),
+    #[error("Failed to initialize SQL store: {0}")]
+    Sql(#[source] StateError),
+    #[error("Failed to create ACS client: {0}")]
+    AcsClient(#[from] reqwest::Error),
+    #[error("Failed to load TLS configuration: {0}")]
+    Tls(#[from] TlsError),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Start the mock server from `settings`, printing the startup banner, and serve until it
+/// is stopped (Ctrl-C)
+pub async fn run_server(settings: Settings) -> std::result::Result<(), StartError> {
+    let server = MockThreeDsServer::builder()
+        .settings(settings)
+        .banner(true)
+        .start()
+        .await?;
+    Ok(server.wait().await?)
+}
+
+/// Settings for a mock server started in-process. Must be started on an actix runtime, e.g.
+/// in an `#[actix_web::test]`.
+#[derive(Debug, Clone)]
+pub struct MockThreeDsServerBuilder {
+    settings: Settings,
+    banner: bool,
+}
+
+impl Default for MockThreeDsServerBuilder {
+    // Built-in defaults, with an in-memory store on a free port
+    fn default() -> Self {
+        let mut settings = Settings::default();
+        settings.state.backend = StateBackend::Memory;
+        settings.server.port = 0;
+        Self {
+            settings,
+            banner: false,
+        }
+    }
+}
+
+impl MockThreeDsServerBuilder {
+    /// Use these settings instead of the built-in defaults
+    pub fn settings(mut self, settings: Settings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// Port for the route groups without their own; 0 picks a free one
+    pub fn port(mut self, port: u16) -> Self {
+        self.settings.server.port = port;
+        self
+    }
+
+    /// Print the startup banner listing the endpoints, as the binary does
+    pub fn banner(mut self, banner: bool) -> Self {
+        self.banner = banner;
+        self
+    }
+
+    /// Bind the listeners and start serving in the background
+    pub async fn start(self) -> std::result::Result<MockThreeDsServer, StartError> {
+        let Self {
+            mut settings,
+            banner,
+        } = self;
+
+        // A free port is bound before anything reads server.port, so the acsURL sent to
+        // browsers carries the real one
+        let mut ephemeral = None;
+        if settings.server.port == 0 {
+            let listener = TcpListener::bind(settings.listener_address(0))?;
+            settings.server.port = listener.local_addr()?.port();
+            ephemeral = Some(listener);
+        }
+        settings.validate().map_err(StartError::Config)?;
+
+        // Make sure mobile challenges can be signed, per acs.signing.missing_cert_policy
+        let signing = &settings.acs.signing;
+        if !Path::new(&signing.cert_path).exists() || !Path::new(&signing.private_key_path).exists()
+        {
+            match signing.missing_cert_policy {
+                MissingCertPolicy::AutoGenerate => {
+                    crypto::generate_self_signed_certificate(
+                        Path::new(&signing.cert_path),
+                        Path::new(&signing.private_key_path),
+                    )?;
+                }
+                MissingCertPolicy::Error => tracing::warn!(
+                    cert_path = %signing.cert_path,
+                    private_key_path = %signing.private_key_path,
+                    "ACS certificate or key not found, mobile challenges will fail until ./generate-certs.sh is run"
+                ),
+                MissingCertPolicy::Static => tracing::warn!(
+                    cert_path = %signing.cert_path,
+                    private_key_path = %signing.private_key_path,
+                    "ACS certificate or key not found, using the static acsSignedContent"
+                ),
+            }
+        }
+
+        // DS certificates are read here rather than for every AReq carrying sdkEncData, so a
+        // missing or unreadable one stops startup
+        let ds_profiles =
+            std::iter::once(&settings.ds.default_profile).chain(settings.ds.profiles.values());
+        for cert_path in ds_profiles.filter_map(|profile| profile.ds_certificate_path.as_ref()) {
+            crypto::load_certificate(Path::new(cert_path))?;
+        }
+
+        // Create the state store: Redis (no fallback) unless running in memory or on SQL
+        let app_state: Arc<Box<dyn StateStore>> = match settings.state.backend {
+            StateBackend::Redis => Arc::new(Box::new(
+                create_redis_store(&settings)
+                    .await
+                    .map_err(StartError::Redis)?,
+            )),
+            StateBackend::Memory => Arc::new(Box::new(InMemoryStore::new(&settings))),
+            StateBackend::Sql => Arc::new(Box::new(
+                SqlStore::new(&settings).await.map_err(StartError::Sql)?,
+            )),
+        };
+        // State store faults fail the calls made while handling a request picked for one
+        let app_state: Arc<Box<dyn StateStore>> = if settings.faults.enabled {
+            Arc::new(Box::new(FaultInjectingStore::new(app_state)))
+        } else {
+            app_state
+        };
+
+        // Card ranges, challenge decisions and rendered templates, shared by all workers
+        let caches = web::Data::new(AppCaches::new(
+            settings.performance.cache_size as u64,
+            &settings.cache,
+        ));
+
+        // Issuer card statuses, seeded from config and shared by all listeners
+        let card_statuses = web::Data::new(CardStatusList::new(&settings.acs.card_statuses));
+
+        // Fault profiles, switched through /admin/faults and shared by all listeners
+        let fault_injector = web::Data::new(FaultInjector::new(&settings.faults));
+
+        // Setup Prometheus metrics; the registry is served on the admin listener when enabled
+        let registry = prometheus::Registry::new();
+        caches
+            .register(&registry)
+            .expect("Failed to register cache metrics");
+
+        // Report the ACS signing certificate's expiry, warning when it is close
+        let cert_monitor = web::Data::new(AcsCertificateMonitor::new(&settings.acs.signing));
+        if settings.components.serves_acs() {
+            cert_monitor
+                .register(&registry)
+                .expect("Failed to register certificate metrics");
+            let status = cert_monitor.check();
+            match (status.not_after, status.days_remaining) {
+                (Some(not_after), Some(days_remaining)) => tracing::info!(
+                    not_after = %not_after.to_rfc3339(),
+                    days_remaining,
+                    "ACS certificate valid"
+                ),
+                _ => tracing::warn!(
+                    error = %status.error.unwrap_or_default(),
+                    "ACS certificate expiry unknown"
+                ),
+            }
+        }
+        let prometheus = PrometheusMetricsBuilder::new("api")
+            .registry(registry.clone())
+            .build()
+            .unwrap();
+        let registry = web::Data::new(registry);
+
+        // Per-route rate limits per client IP, shared by every listener
+        let rate_limits = Arc::new(RateLimits::new(&settings.performance));
+        // Compression and rate limiting switches, flipped through /admin/performance
+        let switches = web::Data::new(PerformanceSwitches::new(&settings.performance));
+        let pruning = tokio::spawn({
+            let rate_limits = rate_limits.clone();
+            async move {
+                let mut interval = tokio::time::interval(Duration::from_secs(60));
+                loop {
+                    interval.tick().await;
+                    rate_limits.retain_recent();
+                }
+            }
+        });
+
+        // 3DS Server -> ACS link, in-process unless an ACS base URL is configured
+        let acs_client = web::Data::new(AcsClient::new(&settings.components)?);
+
+        // Certificates for HTTPS listeners, loaded once and shared by every listener
+        let tls_config = if settings.server.tls.enabled {
+            Some(tls::server_config(&settings.server.tls)?)
+        } else {
+            None
+        };
+
+        // Optional synthetic flow through the real handlers before accepting traffic
+        let self_test = web::Data::new(if settings.monitoring.startup_self_test {
+            Some(
+                self_test::run(
+                    app_state.clone(),
+                    &settings,
+                    caches.clone(),
+                    card_statuses.clone(),
+                    &acs_client,
+                )
+                .await,
+            )
+        } else {
+            None
+        });
+
+        let listeners = listeners(&settings);
+        if banner {
+            print_banner(&settings, &listeners, &acs_client);
+        }
+
+        // The app factory runs once on each worker thread as it starts, so pinning happens there
+        let core_ids = if settings.server.pin_workers {
+            let core_ids = core_affinity::get_core_ids().unwrap_or_default();
+            if core_ids.is_empty() {
+                tracing::warn!("worker pinning requested but CPU cores could not be enumerated");
+            } else {
+                tracing::info!(cores = core_ids.len(), "pinning workers across cores");
+            }
+            core_ids
+        } else {
+            Vec::new()
+        };
+        let next_core = Arc::new(AtomicUsize::new(0));
+
+        // One HTTP server per listener port, each with its own workers
+        let mut servers = Vec::with_capacity(listeners.len());
+        let mut addresses = Vec::new();
+        let mut address_of_main_port = None;
+        for (port, groups) in listeners {
+            let app_state = app_state.clone();
+            let settings_clone = settings.clone();
+            let caches = caches.clone();
+            let card_statuses = card_statuses.clone();
+            let fault_injector = fault_injector.clone();
+            let cert_monitor = cert_monitor.clone();
+            let self_test = self_test.clone();
+            let acs_client = acs_client.clone();
+            let registry = registry.clone();
+            let switches = switches.clone();
+            let prometheus = prometheus.clone();
+            let rate_limits = rate_limits.clone();
+            let core_ids = core_ids.clone();
+            let next_core = next_core.clone();
+
+            let mut server = HttpServer::new(move || {
+                pin_worker_to_core(&core_ids, &next_core);
+                let limits = &settings_clone.payload_limits;
+                let routes = &settings_clone.routes;
+
+                App::new()
+                    .app_data(web::Data::new(app_state.clone()))
+                    .app_data(web::Data::new(settings_clone.clone()))
+                    .app_data(caches.clone())
+                    .app_data(card_statuses.clone())
+                    .app_data(fault_injector.clone())
+                    .app_data(cert_monitor.clone())
+                    .app_data(self_test.clone())
+                    .app_data(acs_client.clone())
+                    .app_data(registry.clone())
+                    .app_data(switches.clone())
+                    .app_data(web::JsonConfig::default().error_handler(error::json_error_handler))
+                    .app_data(web::QueryConfig::default().error_handler(error::query_error_handler))
+                    .wrap(middleware::ErrorHandlers::new().handler(
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                        error::payload_too_large_handler,
+                    ))
+                    // Injected faults replace or wrap the handler, inside logging and metrics
+                    .wrap_fn({
+                        let fault_injector = fault_injector.clone();
+                        move |req, srv| faults::inject(&fault_injector, req, srv)
+                    })
+                    // Admin routes reveal OTPs and decide outcomes, so like the debug routes they
+                    // need the debug API token; without one configured they are refused
+                    .wrap_fn({
+                        let settings = settings_clone.clone();
+                        move |req, srv| {
+                            let path = settings.routes.unprefixed(req.path());
+                            if path.starts_with("/admin/")
+                                && !handlers::debug::authorized(req.request(), &settings)
+                            {
+                                let (http_req, _) = req.into_parts();
+                                let response = handlers::debug::unauthorized();
+                                Either::Left(ready(Ok(ServiceResponse::new(http_req, response))))
+                            } else {
+                                let response = srv.call(req);
+                                Either::Right(async move {
+                                    Ok::<_, actix_web::Error>(response.await?.map_into_boxed_body())
+                                })
+                            }
+                        }
+                    })
+                    // Client certificate requirement and subject allowlist for mutual TLS
+                    .wrap_fn({
+                        let settings = settings_clone.clone();
+                        move |req, srv| {
+                            let rejection = tls::client_certificate_rejection(
+                                &settings.server.tls,
+                                settings.routes.unprefixed(req.path()),
+                                req.conn_data::<PeerCertificates>(),
+                            );
+                            match rejection {
+                                Some(detail) => {
+                                    let (http_req, _) = req.into_parts();
+                                    let erro =
+                                        error::client_certificate_response(&http_req, detail);
+                                    Either::Left(ready(Ok(ServiceResponse::new(http_req, erro))))
+                                }
+                                None => {
+                                    let response = srv.call(req);
+                                    Either::Right(async move {
+                                        Ok::<_, actix_web::Error>(
+                                            response.await?.map_into_boxed_body(),
+                                        )
+                                    })
+                                }
+                            }
+                        }
+                    })
+                    .wrap(middleware::Logger::new(ACCESS_LOG_FORMAT))
+                    .wrap(middleware::Condition::new(
+                        settings_clone.performance.enable_metrics,
+                        prometheus.clone(),
+                    ))
+                    // Rate limited requests are answered as an Erro with backoff headers; allowed
+                    // ones report their remaining quota in x-ratelimit-* headers
+                    .wrap_fn({
+                        let rate_limits = rate_limits.clone();
+                        let switches = switches.clone();
+                        let settings = settings_clone.clone();
+                        move |req, srv| {
+                            let decision = match req.peer_addr() {
+                                Some(peer) if switches.rate_limiting() => rate_limits
+                                    .check(settings.routes.unprefixed(req.path()), peer.ip()),
+                                _ => Decision::Exempt,
+                            };
+                            match decision {
+                                Decision::Limited {
+                                    limit,
+                                    retry_after_seconds,
+                                } => {
+                                    let (http_req, _) = req.into_parts();
+                                    let erro = error::rate_limited_response(
+                                        &http_req,
+                                        limit,
+                                        retry_after_seconds,
+                                    );
+                                    Either::Left(ready(Ok(ServiceResponse::new(http_req, erro))))
+                                }
+                                _ => {
+                                    let response = srv.call(req);
+                                    Either::Right(async move {
+                                        let mut response = response.await?;
+                                        decision.add_headers(response.headers_mut());
+                                        Ok::<_, actix_web::Error>(response.map_into_boxed_body())
+                                    })
+                                }
+                            }
+                        }
+                    })
+                    .wrap(middleware::Compress::default())
+                    // Compress only encodes for clients accepting an encoding, so hiding their
+                    // Accept-Encoding turns it off while the compression switch is off
+                    .wrap_fn({
+                        let switches = switches.clone();
+                        move |mut req, srv| {
+                            if !switches.compression() {
+                                req.headers_mut().remove(header::ACCEPT_ENCODING);
+                            }
+                            srv.call(req)
+                        }
+                    })
+                    // Outermost, so every response carries X-Request-ID and every log line its span
+                    .wrap_fn(|req, srv| request_id::correlate(req, srv))
+                    .configure(|cfg| {
+                        // Each group under /v1, plus its unversioned alias unless versioned_only
+                        if groups.server {
+                            for prefix in routes.mounts(&routes.server_prefix) {
+                                configure_server_routes(cfg, limits, &prefix);
+                            }
+                        }
+                        if groups.acs {
+                            for prefix in routes.mounts(&routes.acs_prefix) {
+                                configure_acs_routes(cfg, limits, &prefix);
+                            }
+                        }
+                        if groups.admin {
+                            for prefix in routes.mounts(&routes.admin_prefix) {
+                                configure_admin_routes(cfg, &settings_clone, &prefix);
+                            }
+                            cfg.route(
+                                &format!("{}/versions", routes.admin_prefix),
+                                web::get().to(handlers::admin::versions),
+                            );
+                        }
+                    })
+            })
+            .workers(settings.server.workers.unwrap_or(0)) // 0 = use all CPU cores
+            .max_connections(settings.performance.max_connections)
+            .client_request_timeout(Duration::from_millis(
+                settings.performance.client_timeout_ms,
+            ))
+            .keep_alive(Duration::from_secs(settings.performance.keep_alive_seconds))
+            .on_connect(tls::capture_peer_certificates);
+
+            if let Some(blocking_threads) = settings.server.worker_max_blocking_threads {
+                server = server.worker_max_blocking_threads(blocking_threads);
+            }
+
+            let address = settings.listener_address(port);
+            let listener = if port == settings.server.port {
+                ephemeral.take()
+            } else {
+                None
+            };
+            let server = match (listener, &tls_config) {
+                (Some(listener), Some(tls_config)) => {
+                    server.listen_rustls_0_21(listener, tls_config.clone())?
+                }
+                (Some(listener), None) => server.listen(listener)?,
+                (None, Some(tls_config)) => server.bind_rustls_021(address, tls_config.clone())?,
+                (None, None) => server.bind(address)?,
+            };
+            if port == settings.server.port {
+                address_of_main_port = server.addrs().first().copied();
+            }
+            addresses.extend(server.addrs());
+            servers.push(server.run());
+        }
+
+        let shutdown = ShutdownHandle(servers.iter().map(|server| server.handle()).collect());
+        let servers = actix_web::rt::spawn(async move {
+            let result = try_join_all(servers).await;
+            pruning.abort();
+            result.map(|_| ())
+        });
+        Ok(MockThreeDsServer {
+            address: address_of_main_port.unwrap_or(addresses[0]),
+            addresses,
+            settings,
+            shutdown,
+            servers,
+        })
+    }
+}
+
+/// A mock server running in the background of the current actix runtime
+#[derive(Debug)]
+pub struct MockThreeDsServer {
+    settings: Settings,
+    address: SocketAddr,
+    addresses: Vec<SocketAddr>,
+    shutdown: ShutdownHandle,
+    servers: JoinHandle<io::Result<()>>,
+}
+
+impl MockThreeDsServer {
+    pub fn builder() -> MockThreeDsServerBuilder {
+        MockThreeDsServerBuilder::default()
+    }
+
+    /// Address of the `server.port` listener, or of the first listener when every route
+    /// group has its own port
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Addresses of every listener
+    pub fn addresses(&self) -> &[SocketAddr] {
+        &self.addresses
+    }
+
+    /// e.g. `http://127.0.0.1:41234`, to build request URLs from
+    pub fn base_url(&self) -> String {
+        format!("{}://{}", self.settings.scheme(), self.address)
+    }
+
+    /// Settings in effect, with a free port picked for port 0 filled in
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// Handle for stopping the server from elsewhere, e.g. a test's cleanup
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Stop every listener and wait for their workers to exit
+    pub async fn stop(self, graceful: bool) -> io::Result<()> {
+        self.shutdown.stop(graceful).await;
+        self.wait().await
+    }
+
+    /// Serve until the listeners stop, on Ctrl-C or through a shutdown handle
+    pub async fn wait(self) -> io::Result<()> {
+        self.servers.await.map_err(io::Error::other)?
+    }
+}
+
+/// Stops a running mock server
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle(Vec<ServerHandle>);
+
+impl ShutdownHandle {
+    /// Stop every listener; a graceful stop lets requests in flight finish first
+    pub async fn stop(&self, graceful: bool) {
+        join_all(self.0.iter().map(|handle| handle.stop(graceful))).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::test_settings;
+    use actix_web::{test, App};
+    use serde_json::{json, Value};
+
+    #[actix_web::test]
+    async fn test_builder_serves_on_a_free_port_until_stopped() {
+        let server = MockThreeDsServer::builder().start().await.unwrap();
+        assert_ne!(server.address().port(), 0);
+        assert_eq!(server.settings().server.port, server.address().port());
+        assert!(server
+            .settings()
+            .acs_public_url()
+            .contains(&server.address().port().to_string()));
+
+        let url = format!("{}/health/live", server.base_url());
+        let response = reqwest::get(&url).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+        server.stop(false).await.unwrap();
+        assert!(reqwest::get(&url).await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_unreadable_ds_certificate_stops_startup() {
+        let mut settings = Settings::default();
+        settings.state.backend = StateBackend::Memory;
+        settings.server.port = 0;
+        settings.ds.default_profile.ds_certificate_path = Some("certs/missing-ds.pem".to_string());
+
+        let error = MockThreeDsServer::builder()
+            .settings(settings)
+            .start()
+            .await
+            .unwrap_err();
+        assert!(matches!(error, StartError::Certificate(_)), "{}", error);
+    }
+
+    #[actix_web::test]
+    async fn test_routes_are_served_under_v1_and_as_aliases() {
+        for versioned_only in [false, true] {
+            let mut settings = test_settings();
+            settings.routes.versioned_only = versioned_only;
+            let (routes, limits) = (settings.routes.clone(), settings.payload_limits.clone());
+            let app = test::init_service(
+                App::new()
+                    .app_data(web::Data::new(AppCaches::new(10, &settings.cache)))
+                    .app_data(web::Data::new(settings))
+                    .configure(|cfg| {
+                        for prefix in routes.mounts(&routes.server_prefix) {
+                            configure_server_routes(cfg, &limits, &prefix);
+                        }
+                        cfg.route("/versions", web::get().to(handlers::admin::versions));
+                    }),
+            )
+            .await;
+
+            let req = test::TestRequest::get().uri("/versions").to_request();
+            let versions: Value = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(versions["current"], "v1");
+            assert_eq!(versions["unversionedAliases"], !versioned_only);
+
+            // versioned_only drops the unversioned aliases
+            for (path, served) in [("/v1/3ds/version", true), ("/3ds/version", !versioned_only)] {
+                let req = test::TestRequest::post()
+                    .uri(path)
+                    .set_json(json!({ "cardNumber": "4000000000001000" }))
+                    .to_request();
+                let status = test::call_service(&app, req).await.status();
+                assert_eq!(status.is_success(), served, "{}", path);
+            }
+        }
+    }
+}
@@ -1,37 +1,41 @@
-#![recursion_limit = "256"]
-
-mod config;
-mod crypto;
-mod handlers;
-mod models;
-mod state_store;
-
-use actix_governor::{Governor, GovernorConfigBuilder};
-use actix_web::{middleware, web, App, HttpResponse, HttpServer, Result};
-use actix_web_prom::PrometheusMetricsBuilder;
-use state_store::{create_redis_store, StateStore};
-use std::sync::Arc;
-use std::time::Duration;
-
-// Health check endpoint
-async fn health_check() -> Result<HttpResponse> {
-    Ok(HttpResponse::Ok().json(serde_json::json!({
-        "status": "healthy",
-        "timestamp": chrono::Utc::now().to_rfc3339(),
-        "service": "3ds-mock-server"
-    })))
-}
+use clap::Parser;
+use mock_three_ds_server::cli::Cli;
+use mock_three_ds_server::config::{self, LogFormat};
+use mock_three_ds_server::{run_server, StartError};
+use tracing_subscriber::EnvFilter;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    // Load configuration
-    let settings = config::Settings::new().unwrap_or_else(|e| {
+    let cli = Cli::parse();
+
+    // Load configuration, then apply command-line overrides. Logging is configured from the
+    // settings, so warnings while loading go to a plain subscriber.
+    let loaded = tracing::subscriber::with_default(tracing_subscriber::fmt().finish(), || {
+        config::Settings::new(cli.config.as_deref())
+    });
+    let mut settings = loaded.unwrap_or_else(|e| {
         eprintln!("❌ Failed to load configuration: {}", e);
         eprintln!(
             "Make sure config/development.toml or config/production.toml exists and is valid."
         );
         std::process::exit(1);
     });
+    cli.apply(&mut settings);
+
+    // Dump the merged file, environment and flag settings, before validation so bad ones show
+    if cli.print_config {
+        match settings
+            .redacted()
+            .and_then(|settings| serde_json::to_string_pretty(&settings))
+        {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("❌ Failed to print configuration: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
 
     // Validate configuration
     if let Err(e) = settings.validate() {
@@ -39,117 +43,38 @@ async fn main() -> std::io::Result<()> {
         std::process::exit(1);
     }
 
-    // Initialize logger with configured level
-    env_logger::init_from_env(env_logger::Env::new().default_filter_or(&settings.server.log_level));
+    // Initialize logging with the configured level and format; RUST_LOG takes precedence
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(&settings.server.log_level));
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+    match settings.server.log_format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().with_current_span(true).init(),
+    }
 
     let run_mode = std::env::var("RUN_MODE").unwrap_or_else(|_| "development".into());
     println!("🚀 Starting 3DS Mock Server (Production Optimized)");
-    println!("📁 Configuration mode: {}", run_mode);
-    println!("⚡ Performance features:");
-    println!(
-        "   🗜️  Compression: {}",
-        if settings.performance.enable_compression {
-            "enabled"
-        } else {
-            "disabled"
-        }
-    );
-    println!(
-        "   📊 Metrics: {}",
-        if settings.performance.enable_metrics {
-            "enabled"
-        } else {
-            "disabled"
-        }
-    );
-    println!(
-        "   🚦 Rate limiting: {} req/s",
-        settings.performance.rate_limit_per_second
-    );
-
-    // Create Redis store (Redis-only, no fallback)
-    let redis_store = create_redis_store(&settings).await.unwrap_or_else(|e| {
-        eprintln!("❌ Failed to initialize Redis store: {}", e);
-        eprintln!("🔧 Redis is required for this application to run.");
-        eprintln!(
-            "   Please ensure Redis is running at: {}",
-            settings.redis.url
-        );
-        std::process::exit(1);
-    });
-
-    let app_state: Arc<Box<dyn StateStore>> = Arc::new(Box::new(redis_store));
-    let server_addr = settings.server_address();
-
-    // Setup Prometheus metrics if enabled
-    let prometheus = if settings.performance.enable_metrics {
-        Some(
-            PrometheusMetricsBuilder::new("api")
-                .endpoint(&settings.monitoring.metrics_endpoint)
-                .build()
-                .unwrap(),
-        )
-    } else {
-        None
-    };
-
-    // Setup rate limiting
-    let governor_conf = GovernorConfigBuilder::default()
-        .per_second(settings.performance.rate_limit_per_second as u64)
-        .burst_size(settings.performance.rate_limit_per_second * 2) // Allow bursts up to 2x the rate
-        .finish()
-        .unwrap();
-
-    println!("🌐 Server starting on: http://{}", server_addr);
-    println!("📋 Available endpoints:");
-    println!("   POST /3ds/version");
-    println!("   POST /3ds/authenticate");
-    println!("   POST /3ds/results");
-    println!("   POST /3ds/final");
-    println!("   POST /processor/mock/acs/trigger-otp (ACS Challenge)");
-    println!("   POST /processor/mock/acs/verify-otp (OTP Verification)");
-    println!("   POST /challenge (Mobile Challenge)");
-    if settings.performance.enable_metrics {
-        println!("   GET  {} (metrics)", settings.monitoring.metrics_endpoint);
+    match &cli.config {
+        Some(path) => println!("📁 Configuration file: {}", path),
+        None => println!("📁 Configuration mode: {}", run_mode),
     }
-    println!("   GET  {} (health)", settings.monitoring.health_endpoint);
 
-    let settings_clone = settings.clone();
-
-    HttpServer::new(move || {
-        App::new()
-            .app_data(web::Data::new(app_state.clone()))
-            .app_data(web::Data::new(settings_clone.clone()))
-            .wrap(middleware::Logger::default())
-            .wrap(Governor::new(&governor_conf))
-            .wrap(middleware::Compress::default())
-            .route(
-                &settings_clone.monitoring.health_endpoint,
-                web::get().to(health_check),
-            )
-            .route("/3ds/version", web::post().to(handlers::version_handler))
-            .route(
-                "/3ds/authenticate",
-                web::post().to(handlers::authenticate_handler),
-            )
-            .route("/3ds/results", web::post().to(handlers::results_handler))
-            .route("/3ds/final", web::post().to(handlers::final_handler))
-            .route(
-                "/processor/mock/acs/trigger-otp",
-                web::post().to(handlers::acs_trigger_otp_handler),
-            )
-            .route(
-                "/processor/mock/acs/verify-otp",
-                web::post().to(handlers::acs_verify_otp_handler),
-            )
-            .route("/challenge", web::post().to(handlers::challenge_handler))
-    })
-    .workers(settings.server.workers.unwrap_or(0)) // 0 = use all CPU cores
-    .client_request_timeout(Duration::from_millis(
-        settings.performance.client_timeout_ms,
-    ))
-    .keep_alive(Duration::from_secs(settings.performance.keep_alive_seconds))
-    .bind(&server_addr)?
-    .run()
-    .await
+    let redis_url = settings.redis.url.clone();
+    let sql_url = settings.state.sql.url.clone();
+    match run_server(settings).await {
+        Ok(()) => Ok(()),
+        Err(StartError::Io(e)) => Err(e),
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            match e {
+                StartError::Redis(_) => {
+                    eprintln!("🔧 Redis is required unless started with --in-memory.");
+                    eprintln!("   Please ensure Redis is running at: {}", redis_url);
+                }
+                StartError::Sql(_) => eprintln!("   Please check state.sql.url: {}", sql_url),
+                _ => {}
+            }
+            std::process::exit(1);
+        }
+    }
 }
@@ -1,6 +1,157 @@
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
 use uuid::Uuid;
 
+use crate::pan::MaskedPan;
+use crate::validation::FieldError;
+
+/// Declare a 3DS code-list field as an enum that (de)serializes as its spec code. Unknown
+/// codes are rejected with a field-level error naming the data element.
+macro_rules! protocol_code {
+    (
+        $(#[$meta:meta])*
+        $name:ident($field:literal) {
+            $($(#[$variant_meta:meta])* $variant:ident = $code:literal,)+
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+        #[serde(try_from = "String", into = "String")]
+        pub enum $name {
+            $($(#[$variant_meta])* $variant,)+
+        }
+
+        impl $name {
+            /// Spec name of the data element carrying this code
+            pub const FIELD: &'static str = $field;
+
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    $(Self::$variant => $code,)+
+                }
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = FieldError;
+
+            fn from_str(code: &str) -> Result<Self, Self::Err> {
+                match code {
+                    $($code => Ok(Self::$variant),)+
+                    _ => Err(FieldError::invalid(
+                        $field,
+                        &format!("'{}' is not a valid {} value", code, $field),
+                    )),
+                }
+            }
+        }
+
+        impl TryFrom<String> for $name {
+            type Error = FieldError;
+
+            fn try_from(code: String) -> Result<Self, Self::Error> {
+                code.parse()
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(value: $name) -> Self {
+                value.as_str().to_string()
+            }
+        }
+    };
+}
+
+protocol_code! {
+    /// transStatus - outcome of the authentication
+    TransStatus("transStatus") {
+        Authenticated = "Y",
+        NotAuthenticated = "N",
+        Unavailable = "U",
+        Attempted = "A",
+        ChallengeRequired = "C",
+        DecoupledChallenge = "D",
+        Rejected = "R",
+        InformationalOnly = "I",
+        /// Secure Payment Confirmation challenge (3DS 2.3)
+        SpcChallenge = "S",
+    }
+}
+
+protocol_code! {
+    /// deviceChannel - where the authentication was initiated
+    DeviceChannel("deviceChannel") {
+        App = "01",
+        Browser = "02",
+        ThreeRi = "03",
+    }
+}
+
+protocol_code! {
+    /// messageCategory
+    MessageCategory("messageCategory") {
+        Payment = "01",
+        NonPayment = "02",
+    }
+}
+
+protocol_code! {
+    /// eci - Electronic Commerce Indicator reported to the authorisation system
+    Eci("eci") {
+        MastercardNotAuthenticated = "00",
+        MastercardAttempted = "01",
+        MastercardAuthenticated = "02",
+        VisaAuthenticated = "05",
+        VisaAttempted = "06",
+        VisaNotAuthenticated = "07",
+    }
+}
+
+protocol_code! {
+    /// threeDSRequestorChallengeInd - the requestor's challenge preference
+    ChallengeIndicator("threeDSRequestorChallengeInd") {
+        NoPreference = "01",
+        NoChallengeRequested = "02",
+        ChallengeRequested = "03",
+        ChallengeMandated = "04",
+        NoChallengeRiskAnalysisPerformed = "05",
+        NoChallengeDataShareOnly = "06",
+        NoChallengeScaPerformed = "07",
+        NoChallengeWhitelistExemption = "08",
+        ChallengeRequestedWhitelistPrompt = "09",
+    }
+}
+
+/// Deserialize a numeric field sent either as a JSON number or as a numeric string, since
+/// clients disagree on how to encode amounts, timezone offsets and screen dimensions
+fn number_or_string<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr + Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum NumberOrString<T> {
+        Number(T),
+        String(String),
+    }
+
+    match NumberOrString::<T>::deserialize(deserializer)? {
+        NumberOrString::Number(value) => Ok(value),
+        // Reported like serde's own type errors, e.g. `expected u64`, naming the element type
+        NumberOrString::String(value) => value.trim().parse().map_err(|_| {
+            de::Error::invalid_value(de::Unexpected::Str(&value), &std::any::type_name::<T>())
+        }),
+    }
+}
+
 // Version API Models
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -15,7 +166,7 @@ pub struct VersionResponse {
     pub card_ranges: Vec<CardRange>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct CardRange {
     pub acs_info_ind: Vec<String>,
@@ -23,6 +174,93 @@ pub struct CardRange {
     pub acs_end_protocol_version: String,
     pub acs_start_protocol_version: String,
     pub end_range: String,
+    /// Where the requestor's hidden iframe posts threeDSMethodData
+    #[serde(rename = "threeDSMethodURL", skip_serializing_if = "Option::is_none")]
+    pub three_ds_method_url: Option<String>,
+}
+
+// Preparation API Models
+/// PReq from a 3DS Server refreshing its card range cache
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PreparationRequest {
+    pub message_type: String,
+    pub message_version: String,
+    #[serde(rename = "threeDSServerTransID")]
+    pub three_ds_server_trans_id: Uuid,
+    #[serde(rename = "threeDSServerRefNumber")]
+    pub three_ds_server_ref_number: String,
+    #[serde(default, rename = "threeDSServerOperatorID")]
+    pub three_ds_server_operator_id: Option<String>,
+    /// serialNum of the last PRes the 3DS Server applied; absent for a full refresh
+    #[serde(default)]
+    pub serial_num: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PreparationResponse {
+    pub message_type: &'static str,
+    pub message_version: String,
+    #[serde(rename = "threeDSServerTransID")]
+    pub three_ds_server_trans_id: Uuid,
+    #[serde(rename = "threeDSServerRefNumber")]
+    pub three_ds_server_ref_number: String,
+    #[serde(
+        rename = "threeDSServerOperatorID",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub three_ds_server_operator_id: Option<String>,
+    #[serde(rename = "dsTransID")]
+    pub ds_trans_id: Uuid,
+    pub serial_num: String,
+    /// Empty when the PReq serialNum is already current
+    pub card_range_data: Vec<CardRangeData>,
+}
+
+/// One cardRangeData entry of a PRes
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CardRangeData {
+    pub start_range: String,
+    pub end_range: String,
+    /// A = add the range; the mock always sends its full cache
+    pub action_ind: &'static str,
+    pub acs_start_protocol_version: String,
+    pub acs_end_protocol_version: String,
+    pub ds_start_protocol_version: String,
+    pub ds_end_protocol_version: String,
+    #[serde(rename = "threeDSMethodURL", skip_serializing_if = "Option::is_none")]
+    pub three_ds_method_url: Option<String>,
+    pub acs_info_ind: Vec<String>,
+}
+
+impl From<CardRange> for CardRangeData {
+    fn from(range: CardRange) -> Self {
+        Self {
+            start_range: range.start_range,
+            end_range: range.end_range,
+            action_ind: "A",
+            acs_start_protocol_version: range.acs_start_protocol_version,
+            acs_end_protocol_version: range.acs_end_protocol_version,
+            // The mock DS relays every version the ACS answers in
+            ds_start_protocol_version: crate::protocol::acs_start_protocol_version().to_string(),
+            ds_end_protocol_version: crate::protocol::acs_end_protocol_version().to_string(),
+            three_ds_method_url: range.three_ds_method_url,
+            acs_info_ind: range.acs_info_ind,
+        }
+    }
+}
+
+impl CardRangeData {
+    /// serialNum identifying a set of card ranges: the first 20 hex digits of the SHA-256 of
+    /// their JSON, so it changes whenever a range, method URL or version does
+    pub fn serial_num(ranges: &[CardRangeData]) -> String {
+        use sha2::{Digest, Sha256};
+
+        let json = serde_json::to_vec(ranges).unwrap_or_default();
+        hex::encode(Sha256::digest(&json))[..20].to_string()
+    }
 }
 
 // Authenticate API Models
@@ -31,10 +269,12 @@ pub struct CardRange {
 pub struct AuthenticateRequest {
     pub three_ds_server_trans_id: Uuid,
     pub sdk_trans_id: Option<Uuid>,
-    pub device_channel: String,
-    pub message_category: String,
+    pub device_channel: DeviceChannel,
+    pub message_category: MessageCategory,
     pub preferred_protocol_version: String,
     pub enforce_preferred_protocol_version: bool,
+    /// Required for browser AReqs; 3RI AReqs run no 3DS Method and may omit it
+    #[serde(default)]
     pub three_ds_comp_ind: String,
     pub three_ds_requestor: ThreeDSRequestor,
     pub cardholder_account: CardholderAccount,
@@ -44,12 +284,39 @@ pub struct AuthenticateRequest {
     pub merchant: Merchant,
     #[serde(rename = "browserInformation", skip_serializing_if = "Option::is_none")]
     pub browser_information: Option<BrowserInformation>,
-    pub device_render_options: DeviceRenderOptions,
+    /// Absent from 3RI AReqs, which carry no SDK data
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_render_options: Option<DeviceRenderOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sdk_enc_data: Option<String>,
+    /// Reference number of the 3DS SDK (app AReqs); replaces the configured one in the
+    /// CReq key derivation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sdk_reference_number: Option<String>,
+    /// Y when the requestor can run Secure Payment Confirmation (3DS 2.3)
+    #[serde(
+        default,
+        alias = "threeDSRequestorSpcSupport",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub three_ds_requestor_spc_support: Option<String>,
+    /// Browser extension data (3DS 2.3), kept as received
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub browser_extension: Option<serde_json::Value>,
+    /// Device binding status known to the requestor (3DS 2.3)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_binding_status: Option<String>,
+    /// Trust list status known to the requestor (3DS 2.3)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trust_list_status: Option<String>,
     #[serde(
         rename = "sdkEphemeralPublicKey",
         skip_serializing_if = "Option::is_none"
     )]
     pub sdk_ephemeral_public_key: Option<SdkEphemeralPublicKey>,
+    /// Scheme or issuer specific data, echoed in the ARes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_extension: Option<Vec<MessageExtension>>,
     // SDK ephemeral public key fields at top level (new format)
     #[serde(rename = "Kty", skip_serializing_if = "Option::is_none")]
     pub kty: Option<String>,
@@ -61,12 +328,52 @@ pub struct AuthenticateRequest {
     pub y: Option<String>,
 }
 
+impl AuthenticateRequest {
+    /// SDK ephemeral public key from either the nested object or the top-level Kty/Crv/X/Y fields
+    pub fn sdk_ephemeral_key(&self) -> Option<SdkEphemeralPublicKey> {
+        if let Some(sdk_key) = &self.sdk_ephemeral_public_key {
+            return Some(sdk_key.clone());
+        }
+
+        match (&self.kty, &self.crv, &self.x, &self.y) {
+            (Some(kty), Some(crv), Some(x), Some(y)) => Some(SdkEphemeralPublicKey {
+                kty: kty.clone(),
+                crv: crv.clone(),
+                x: x.clone(),
+                y: y.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// This AReq as kept once it is answered: the PAN masked and, with
+    /// `strip_card_security_code`, the cardSecurityCode blanked
+    pub fn for_storage(mut self, strip_card_security_code: bool) -> Self {
+        let account = &mut self.cardholder_account;
+        account.acct_number = MaskedPan::new(&account.acct_number).to_string();
+        if strip_card_security_code {
+            account.card_security_code.clear();
+        }
+        self
+    }
+}
+
+/// One messageExtension entry. The ACS rejects a critical one it does not recognise.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageExtension {
+    pub name: String,
+    pub id: String,
+    pub criticality_indicator: bool,
+    pub data: serde_json::Value,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct ThreeDSRequestor {
     pub three_ds_requestor_authentication_ind: String,
     pub three_ds_requestor_authentication_info: ThreeDSRequestorAuthenticationInfo,
-    pub three_ds_requestor_challenge_ind: String,
+    pub three_ds_requestor_challenge_ind: ChallengeIndicator,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -76,7 +383,7 @@ pub struct ThreeDSRequestorAuthenticationInfo {
     pub three_ds_req_auth_timestamp: String,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Deserialize, Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct CardholderAccount {
     pub acct_type: String,
@@ -86,6 +393,19 @@ pub struct CardholderAccount {
     pub card_security_code: String,
 }
 
+/// Logs the PAN masked and never the card security code
+impl fmt::Debug for CardholderAccount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CardholderAccount")
+            .field("acct_type", &self.acct_type)
+            .field("card_expiry_date", &self.card_expiry_date)
+            .field("scheme_id", &self.scheme_id)
+            .field("acct_number", &MaskedPan::new(&self.acct_number))
+            .field("card_security_code", &"***")
+            .finish()
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Cardholder {
@@ -118,13 +438,18 @@ pub struct Phone {
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Purchase {
-    pub purchase_instal_data: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub purchase_instal_data: Option<u32>,
+    #[serde(deserialize_with = "number_or_string")]
     pub purchase_amount: u64,
     pub purchase_currency: String,
+    #[serde(deserialize_with = "number_or_string")]
     pub purchase_exponent: u32,
     pub purchase_date: String,
-    pub recurring_expiry: String,
-    pub recurring_frequency: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recurring_expiry: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recurring_frequency: Option<u32>,
     pub trans_type: String,
 }
 
@@ -155,10 +480,13 @@ pub struct BrowserInformation {
     pub browser_ip: String,
     pub browser_language: String,
     pub browser_color_depth: String,
+    #[serde(deserialize_with = "number_or_string")]
     pub browser_screen_height: u32,
+    #[serde(deserialize_with = "number_or_string")]
     pub browser_screen_width: u32,
-    #[serde(rename = "browserTZ")]
-    pub browser_tz: u32,
+    /// Minutes between UTC and the cardholder's local time - negative east of UTC
+    #[serde(rename = "browserTZ", deserialize_with = "number_or_string")]
+    pub browser_tz: i32,
     pub browser_user_agent: String,
     pub challenge_window_size: String,
     pub browser_java_enabled: bool,
@@ -181,20 +509,306 @@ pub struct SdkEphemeralPublicKey {
     pub y: String,
 }
 
+/// Serialize a numeric field as a string without allocating an intermediate String
+fn display_as_string<S, T>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    T: fmt::Display,
+{
+    serializer.collect_str(value)
+}
+
+fn option_display_as_string<S, T>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    T: fmt::Display,
+{
+    match value {
+        Some(value) => serializer.collect_str(value),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// The AReq as the DS would have received it, echoed back in the authenticate response.
+/// Borrows from the parsed request so the echo costs no per-field copies.
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AReqEcho<'a> {
+    pub message_type: &'static str,
+    pub message_version: &'static str,
+    pub message_category: MessageCategory,
+    pub device_channel: DeviceChannel,
+    #[serde(rename = "threeDSServerTransID")]
+    pub three_ds_server_trans_id: Uuid,
+    #[serde(
+        rename = "threeDSServerRefNumber",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub three_ds_server_ref_number: Option<&'a str>,
+    #[serde(
+        rename = "threeDSServerOperatorID",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub three_ds_server_operator_id: Option<&'a str>,
+    #[serde(rename = "threeDSServerURL", skip_serializing_if = "Option::is_none")]
+    pub three_ds_server_url: Option<&'a str>,
+    #[serde(rename = "threeDSCompInd")]
+    pub three_ds_comp_ind: &'a str,
+    #[serde(rename = "threeDSRequestorAuthenticationInd")]
+    pub three_ds_requestor_authentication_ind: &'a str,
+    #[serde(rename = "threeDSRequestorAuthenticationInfo")]
+    pub three_ds_requestor_authentication_info: AReqEchoAuthenticationInfo<'a>,
+    #[serde(rename = "threeDSRequestorChallengeInd")]
+    pub three_ds_requestor_challenge_ind: ChallengeIndicator,
+    #[serde(
+        rename = "threeDSRequestorSpcSupport",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub three_ds_requestor_spc_support: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub browser_extension: Option<&'a serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_binding_status: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trust_list_status: Option<&'a str>,
+    #[serde(rename = "threeDSRequestorID")]
+    pub three_ds_requestor_id: &'a str,
+    #[serde(rename = "threeDSRequestorName")]
+    pub three_ds_requestor_name: &'a str,
+    #[serde(rename = "threeDSRequestorURL")]
+    pub three_ds_requestor_url: &'a str,
+    #[serde(rename = "notificationURL")]
+    pub notification_url: &'a str,
+    #[serde(rename = "acquirerBIN")]
+    pub acquirer_bin: &'a str,
+    #[serde(rename = "acquirerMerchantID")]
+    pub acquirer_merchant_id: &'a str,
+    pub merchant_name: &'a str,
+    pub merchant_country_code: &'a str,
+    pub mcc: &'a str,
+    pub acct_type: &'a str,
+    pub acct_number: &'a str,
+    pub card_expiry_date: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    pub card_security_code: &'a str,
+    pub cardholder_name: &'a str,
+    pub email: &'a str,
+    pub home_phone: &'a Phone,
+    pub mobile_phone: &'a Phone,
+    pub work_phone: &'a Phone,
+    pub addr_match: &'a str,
+    pub bill_addr_city: &'a str,
+    pub bill_addr_country: &'a str,
+    pub bill_addr_line1: &'a str,
+    pub bill_addr_line2: &'a str,
+    pub bill_addr_line3: &'a str,
+    pub bill_addr_post_code: &'a str,
+    pub ship_addr_city: &'a str,
+    pub ship_addr_country: &'a str,
+    pub ship_addr_line1: &'a str,
+    pub ship_addr_line2: &'a str,
+    pub ship_addr_line3: &'a str,
+    pub ship_addr_post_code: &'a str,
+    #[serde(serialize_with = "display_as_string")]
+    pub purchase_amount: u64,
+    pub purchase_currency: &'a str,
+    #[serde(serialize_with = "display_as_string")]
+    pub purchase_exponent: u32,
+    pub purchase_date: &'a str,
+    pub trans_type: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recurring_expiry: Option<&'a str>,
+    #[serde(
+        serialize_with = "option_display_as_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub recurring_frequency: Option<u32>,
+    #[serde(
+        serialize_with = "option_display_as_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub purchase_instal_data: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_render_options: Option<AReqEchoDeviceRenderOptions<'a>>,
+    #[serde(flatten)]
+    pub browser_information: Option<AReqEchoBrowserInformation<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sdk_ephemeral_public_key: Option<SdkEphemeralPublicKey>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sdk_enc_data: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sdk_reference_number: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_extension: Option<&'a [MessageExtension]>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct AReqEchoAuthenticationInfo<'a> {
+    #[serde(rename = "threeDSReqAuthMethod")]
+    pub three_ds_req_auth_method: &'a str,
+    #[serde(rename = "threeDSReqAuthTimestamp")]
+    pub three_ds_req_auth_timestamp: &'a str,
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AReqEchoDeviceRenderOptions<'a> {
+    pub sdk_interface: &'a str,
+    pub sdk_ui_type: &'a [String],
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AReqEchoBrowserInformation<'a> {
+    pub browser_accept_header: &'a str,
+    #[serde(rename = "browserIP")]
+    pub browser_ip: &'a str,
+    pub browser_language: &'a str,
+    pub browser_color_depth: &'a str,
+    #[serde(serialize_with = "display_as_string")]
+    pub browser_screen_height: u32,
+    #[serde(serialize_with = "display_as_string")]
+    pub browser_screen_width: u32,
+    #[serde(rename = "browserTZ", serialize_with = "display_as_string")]
+    pub browser_tz: i32,
+    pub browser_user_agent: &'a str,
+    pub browser_java_enabled: bool,
+    pub browser_javascript_enabled: bool,
+}
+
+impl<'a> From<&'a BrowserInformation> for AReqEchoBrowserInformation<'a> {
+    fn from(browser: &'a BrowserInformation) -> Self {
+        Self {
+            browser_accept_header: &browser.browser_accept_header,
+            browser_ip: &browser.browser_ip,
+            browser_language: &browser.browser_language,
+            browser_color_depth: &browser.browser_color_depth,
+            browser_screen_height: browser.browser_screen_height,
+            browser_screen_width: browser.browser_screen_width,
+            browser_tz: browser.browser_tz,
+            browser_user_agent: &browser.browser_user_agent,
+            browser_java_enabled: browser.browser_java_enabled,
+            browser_javascript_enabled: browser.browser_javascript_enabled,
+        }
+    }
+}
+
+impl<'a> From<&'a AuthenticateRequest> for AReqEcho<'a> {
+    fn from(req: &'a AuthenticateRequest) -> Self {
+        let requestor = &req.three_ds_requestor;
+        let account = &req.cardholder_account;
+        let cardholder = &req.cardholder;
+        let purchase = &req.purchase;
+
+        Self {
+            message_type: "AReq",
+            message_version: "2.2.0",
+            message_category: req.message_category,
+            device_channel: req.device_channel,
+            three_ds_server_trans_id: req.three_ds_server_trans_id,
+            three_ds_server_ref_number: None,
+            three_ds_server_operator_id: None,
+            three_ds_server_url: None,
+            three_ds_comp_ind: &req.three_ds_comp_ind,
+            three_ds_requestor_authentication_ind: &requestor.three_ds_requestor_authentication_ind,
+            three_ds_requestor_authentication_info: AReqEchoAuthenticationInfo {
+                three_ds_req_auth_method: &requestor
+                    .three_ds_requestor_authentication_info
+                    .three_ds_req_auth_method,
+                three_ds_req_auth_timestamp: &requestor
+                    .three_ds_requestor_authentication_info
+                    .three_ds_req_auth_timestamp,
+            },
+            three_ds_requestor_challenge_ind: requestor.three_ds_requestor_challenge_ind,
+            three_ds_requestor_spc_support: req.three_ds_requestor_spc_support.as_deref(),
+            browser_extension: req.browser_extension.as_ref(),
+            device_binding_status: req.device_binding_status.as_deref(),
+            trust_list_status: req.trust_list_status.as_deref(),
+            three_ds_requestor_id: &req.merchant.three_ds_requestor_id,
+            three_ds_requestor_name: &req.merchant.three_ds_requestor_name,
+            three_ds_requestor_url: &req.merchant.notification_url,
+            notification_url: &req.merchant.notification_url,
+            acquirer_bin: &req.acquirer.acquirer_bin,
+            acquirer_merchant_id: &req.acquirer.acquirer_merchant_id,
+            merchant_name: &req.merchant.merchant_name,
+            merchant_country_code: &req.merchant.merchant_country_code,
+            mcc: &req.merchant.mcc,
+            acct_type: &account.acct_type,
+            acct_number: &account.acct_number,
+            card_expiry_date: &account.card_expiry_date,
+            card_security_code: &account.card_security_code,
+            cardholder_name: &cardholder.cardholder_name,
+            email: &cardholder.email,
+            home_phone: &cardholder.home_phone,
+            mobile_phone: &cardholder.mobile_phone,
+            work_phone: &cardholder.work_phone,
+            addr_match: &cardholder.addr_match,
+            bill_addr_city: &cardholder.bill_addr_city,
+            bill_addr_country: &cardholder.bill_addr_country,
+            bill_addr_line1: &cardholder.bill_addr_line1,
+            bill_addr_line2: &cardholder.bill_addr_line2,
+            bill_addr_line3: &cardholder.bill_addr_line3,
+            bill_addr_post_code: &cardholder.bill_addr_post_code,
+            ship_addr_city: &cardholder.ship_addr_city,
+            ship_addr_country: &cardholder.ship_addr_country,
+            ship_addr_line1: &cardholder.ship_addr_line1,
+            ship_addr_line2: &cardholder.ship_addr_line2,
+            ship_addr_line3: &cardholder.ship_addr_line3,
+            ship_addr_post_code: &cardholder.ship_addr_post_code,
+            purchase_amount: purchase.purchase_amount,
+            purchase_currency: &purchase.purchase_currency,
+            purchase_exponent: purchase.purchase_exponent,
+            purchase_date: &purchase.purchase_date,
+            trans_type: &purchase.trans_type,
+            recurring_expiry: purchase.recurring_expiry.as_deref(),
+            recurring_frequency: purchase.recurring_frequency,
+            purchase_instal_data: purchase.purchase_instal_data,
+            device_render_options: req.device_render_options.as_ref().map(|render| {
+                AReqEchoDeviceRenderOptions {
+                    sdk_interface: &render.sdk_interface,
+                    sdk_ui_type: &render.sdk_ui_type,
+                }
+            }),
+            browser_information: req.browser_information.as_ref().map(Into::into),
+            sdk_ephemeral_public_key: req.sdk_ephemeral_key(),
+            sdk_enc_data: req.sdk_enc_data.as_deref(),
+            sdk_reference_number: req.sdk_reference_number.as_deref(),
+            message_extension: req.message_extension.as_deref(),
+        }
+    }
+}
+
+impl<'a> AReqEcho<'a> {
+    pub fn with_three_ds_server(
+        mut self,
+        ref_number: &'a str,
+        operator_id: &'a str,
+        url: &'a str,
+    ) -> Self {
+        self.three_ds_server_ref_number = Some(ref_number);
+        self.three_ds_server_operator_id = Some(operator_id);
+        self.three_ds_server_url = Some(url);
+        self
+    }
+}
+
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
-pub struct AuthenticateResponse {
-    pub purchase_date: String,
+pub struct AuthenticateResponse<'a> {
+    pub purchase_date: &'a str,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub base64_encoded_challenge_request: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub acs_url: Option<String>,
+    /// Where the requestor posts the SPC assertion when transStatus = S
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spc_url: Option<String>,
     pub three_ds_server_trans_id: Uuid,
     pub authentication_response: AuthenticationResponse,
     pub challenge_request: ChallengeRequest,
     pub acs_challenge_mandated: String,
-    pub trans_status: String,
-    pub authentication_request: serde_json::Value, // Will be dynamically created
+    pub trans_status: TransStatus,
+    pub authentication_request: AReqEcho<'a>,
 }
 
 #[derive(Debug, Serialize)]
@@ -205,7 +819,9 @@ pub struct AuthenticationResponse {
     #[serde(rename = "acsOperatorID")]
     pub acs_operator_id: String,
     pub ds_reference_number: String,
-    pub eci: String,
+    /// Absent on challenge (transStatus = C or S) responses; the RReq reports it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eci: Option<Eci>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub acs_signed_content: Option<String>,
     pub ds_trans_id: Uuid,
@@ -226,18 +842,62 @@ pub struct AuthenticationResponse {
     pub authentication_type: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sdk_trans_id: Option<Uuid>,
-    pub authentication_value: String,
-    pub trans_status: String,
+    /// Present only on frictionless successes (transStatus = Y or A)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authentication_value: Option<String>,
+    pub trans_status: TransStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_binding_status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trust_list_status: Option<String>,
     pub message_version: String,
     pub acs_reference_number: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub acs_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spc_trans_data: Option<SpcTransData>,
+    /// The AReq's extensions, echoed back
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_extension: Option<Vec<MessageExtension>>,
+}
+
+impl AuthenticationResponse {
+    /// Drop the elements 2.1.0 does not define, for an ARes answering in 2.1.0
+    pub fn restrict_to_2_1_0(&mut self) {
+        self.three_ds_requestor_app_url_ind = None;
+        self.broad_info = None;
+        self.authentication_method = None;
+        self.device_info_recognised_version = None;
+        self.device_binding_status = None;
+        self.trust_list_status = None;
+        self.spc_trans_data = None;
+        if let Some(rendering_type) = &mut self.acs_rendering_type {
+            rendering_type.device_user_interface_mode = None;
+        }
+    }
+}
+
+/// Inputs for the requestor's Secure Payment Confirmation call, sent when transStatus = S
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpcTransData {
+    /// Base64url challenge the SPC assertion must sign
+    pub challenge: String,
+    pub rp_id: String,
+    pub timeout: u64,
+    pub payee_name: String,
+    pub instrument_display_name: String,
+    pub purchase_amount: String,
+    pub purchase_currency: String,
+    pub purchase_exponent: String,
 }
 
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AcsRenderingTypeResponse {
-    pub device_user_interface_mode: String,
+    /// Added in 2.2.0, so absent from 2.1.0 ARes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_user_interface_mode: Option<String>,
     pub acs_interface: String,
     pub acs_ui_template: String,
 }
@@ -263,10 +923,295 @@ pub struct BroadInfoDescription {
 #[serde(rename_all = "camelCase")]
 pub struct ChallengeRequest {
     pub message_type: String,
+    /// Spec element names accepted alongside the camelCase ones echoed in the ARes
+    #[serde(alias = "threeDSServerTransID")]
     pub three_ds_server_trans_id: Uuid,
+    #[serde(alias = "acsTransID")]
     pub acs_trans_id: Uuid,
     pub challenge_window_size: String,
     pub message_version: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_extension: Option<Vec<MessageExtension>>,
+}
+
+// App-based Challenge Response (CRes) Models
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ChallengeResponseMobile {
+    pub message_type: String,
+    pub message_version: String,
+    #[serde(rename = "threeDSServerTransID")]
+    pub three_ds_server_trans_id: Uuid,
+    #[serde(rename = "acsTransID")]
+    pub acs_trans_id: Uuid,
+    #[serde(rename = "sdkTransID", skip_serializing_if = "Option::is_none")]
+    pub sdk_trans_id: Option<Uuid>,
+    #[serde(rename = "acsCounterAtoS")]
+    pub acs_counter_a_to_s: String,
+    pub challenge_completion_ind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trans_status: Option<TransStatus>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub ui: Option<ChallengeUi>,
+    /// Prompt for the SDK's whitelisting checkbox, answered by whitelistingDataEntry
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub whitelisting_info_text: Option<String>,
+    /// The CReq's extensions, echoed back
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_extension: Option<Vec<MessageExtension>>,
+}
+
+impl ChallengeResponseMobile {
+    /// Build an in-progress CRes (challengeCompletionInd = N) with no UI attached
+    pub fn new(
+        message_version: &str,
+        three_ds_server_trans_id: Uuid,
+        acs_trans_id: Uuid,
+        sdk_trans_id: Option<Uuid>,
+        acs_counter_a_to_s: &str,
+    ) -> Self {
+        Self {
+            message_type: "CRes".to_string(),
+            message_version: message_version.to_string(),
+            three_ds_server_trans_id,
+            acs_trans_id,
+            sdk_trans_id,
+            acs_counter_a_to_s: acs_counter_a_to_s.to_string(),
+            challenge_completion_ind: "N".to_string(),
+            trans_status: None,
+            ui: None,
+            whitelisting_info_text: None,
+            message_extension: None,
+        }
+    }
+
+    /// Attach the UI, dropping the OOB app link (added in 2.2.0) from a 2.1.0 CRes
+    pub fn with_ui(mut self, mut ui: ChallengeUi) -> Self {
+        if crate::protocol::is_2_1(&self.message_version) {
+            if let ChallengeUi::Oob {
+                oob_app_url,
+                oob_app_label,
+                ..
+            } = &mut ui
+            {
+                *oob_app_url = None;
+                *oob_app_label = None;
+            }
+        }
+        self.ui = Some(ui);
+        self
+    }
+
+    /// Echo the CReq's message extensions
+    pub fn with_message_extensions(mut self, extensions: Option<Vec<MessageExtension>>) -> Self {
+        self.message_extension = extensions;
+        self
+    }
+
+    /// Offer the cardholder to whitelist the merchant; 2.1.0 has no whitelisting
+    pub fn with_whitelisting_prompt(mut self, whitelisting_info_text: &str) -> Self {
+        if !crate::protocol::is_2_1(&self.message_version) {
+            self.whitelisting_info_text = Some(whitelisting_info_text.to_string());
+        }
+        self
+    }
+
+    /// Mark the challenge as finished with the final transStatus; a completed CRes carries no UI
+    pub fn completed(mut self, trans_status: TransStatus) -> Self {
+        self.challenge_completion_ind = "Y".to_string();
+        self.trans_status = Some(trans_status);
+        self.ui = None;
+        self
+    }
+}
+
+/// Final CRes of a browser challenge, posted base64url-encoded as `cres` to the notificationURL
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ChallengeResponseBrowser {
+    #[serde(rename = "threeDSServerTransID")]
+    pub three_ds_server_trans_id: Uuid,
+    #[serde(rename = "acsTransID")]
+    pub acs_trans_id: Uuid,
+    pub message_type: String,
+    pub message_version: String,
+    pub challenge_completion_ind: String,
+    pub trans_status: TransStatus,
+}
+
+/// Native UI the SDK should render, tagged by acsUiType
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "acsUiType")]
+pub enum ChallengeUi {
+    #[serde(rename = "01", rename_all = "camelCase")]
+    Otp {
+        challenge_info_header: String,
+        challenge_info_label: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        challenge_info_text: Option<String>,
+        submit_authentication_label: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        resend_information_label: Option<String>,
+    },
+    #[serde(rename = "02", rename_all = "camelCase")]
+    SingleSelect {
+        challenge_info_header: String,
+        challenge_info_label: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        challenge_info_text: Option<String>,
+        challenge_select_info: Vec<ChallengeSelectOption>,
+        submit_authentication_label: String,
+    },
+    #[serde(rename = "03", rename_all = "camelCase")]
+    MultiSelect {
+        challenge_info_header: String,
+        challenge_info_label: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        challenge_info_text: Option<String>,
+        challenge_select_info: Vec<ChallengeSelectOption>,
+        submit_authentication_label: String,
+    },
+    #[serde(rename = "04", rename_all = "camelCase")]
+    Oob {
+        challenge_info_header: String,
+        challenge_info_text: String,
+        oob_continue_label: String,
+        #[serde(rename = "oobAppURL", skip_serializing_if = "Option::is_none")]
+        oob_app_url: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        oob_app_label: Option<String>,
+    },
+    #[serde(rename = "05", rename_all = "camelCase")]
+    Html {
+        /// Base64url-encoded HTML document rendered in the SDK web view
+        #[serde(rename = "acsHTML")]
+        acs_html: String,
+    },
+}
+
+impl ChallengeUi {
+    /// acsUiType code of the UI
+    pub fn acs_ui_type(&self) -> &'static str {
+        match self {
+            ChallengeUi::Otp { .. } => "01",
+            ChallengeUi::SingleSelect { .. } => "02",
+            ChallengeUi::MultiSelect { .. } => "03",
+            ChallengeUi::Oob { .. } => "04",
+            ChallengeUi::Html { .. } => "05",
+        }
+    }
+
+    /// The OTP entry form presented by the mock ACS
+    pub fn otp_form() -> Self {
+        ChallengeUi::Otp {
+            challenge_info_header: "Authentication Required".to_string(),
+            challenge_info_label: "Enter OTP:".to_string(),
+            challenge_info_text: None,
+            submit_authentication_label: "Submit".to_string(),
+            resend_information_label: Some("Resend OTP".to_string()),
+        }
+    }
+
+    /// The single-select (02) question: the city of the card's billing address. The accepted
+    /// answer is `challenge::SINGLE_SELECT_ANSWER`.
+    pub fn single_select_form(challenge_info_text: Option<&str>) -> Self {
+        ChallengeUi::SingleSelect {
+            challenge_info_header: "Verify it's you".to_string(),
+            challenge_info_label: "Which city is your billing address in?".to_string(),
+            challenge_info_text: challenge_info_text.map(str::to_string),
+            challenge_select_info: ChallengeSelectOption::list(&[
+                ("01", "Mumbai"),
+                ("02", "Bengaluru"),
+                ("03", "Delhi"),
+                ("04", "Chennai"),
+            ]),
+            submit_authentication_label: "Submit".to_string(),
+        }
+    }
+
+    /// The multi-select (03) question: the merchants recently paid with the card. The accepted
+    /// answer is `challenge::MULTI_SELECT_ANSWER`, in any order.
+    pub fn multi_select_form(challenge_info_text: Option<&str>) -> Self {
+        ChallengeUi::MultiSelect {
+            challenge_info_header: "Verify it's you".to_string(),
+            challenge_info_label: "Which of these merchants did you pay in the last 30 days?"
+                .to_string(),
+            challenge_info_text: challenge_info_text.map(str::to_string),
+            challenge_select_info: ChallengeSelectOption::list(&[
+                ("01", "Fresh Mart Groceries"),
+                ("02", "Skyline Airways"),
+                ("03", "Corner Book Store"),
+                ("04", "City Fuel Station"),
+            ]),
+            submit_authentication_label: "Submit".to_string(),
+        }
+    }
+
+    /// The out-of-band screen: the cardholder approves in their banking app, then continues
+    pub fn oob_form(challenge_info_text: &str) -> Self {
+        ChallengeUi::Oob {
+            challenge_info_header: "Approve in your banking app".to_string(),
+            challenge_info_text: challenge_info_text.to_string(),
+            oob_continue_label: "Continue".to_string(),
+            oob_app_url: None,
+            oob_app_label: None,
+        }
+    }
+
+    /// The OTP entry form presented again, telling the cardholder why (a resent code or a
+    /// wrong OTP)
+    pub fn otp_form_with_info(challenge_info_text: &str) -> Self {
+        ChallengeUi::Otp {
+            challenge_info_header: "Authentication Required".to_string(),
+            challenge_info_label: "Enter OTP:".to_string(),
+            challenge_info_text: Some(challenge_info_text.to_string()),
+            submit_authentication_label: "Submit".to_string(),
+            resend_information_label: Some("Resend OTP".to_string()),
+        }
+    }
+}
+
+/// One challengeSelectInfo entry, serialized as a single-pair object `{ "<value>": "<label>" }`
+#[derive(Debug, Clone)]
+pub struct ChallengeSelectOption {
+    pub value: String,
+    pub label: String,
+}
+
+impl ChallengeSelectOption {
+    fn list(options: &[(&str, &str)]) -> Vec<Self> {
+        options
+            .iter()
+            .map(|(value, label)| Self {
+                value: value.to_string(),
+                label: label.to_string(),
+            })
+            .collect()
+    }
+}
+
+impl Serialize for ChallengeSelectOption {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        let mut map = serializer.serialize_map(Some(1))?;
+        map.serialize_entry(&self.value, &self.label)?;
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for ChallengeSelectOption {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let entry = std::collections::BTreeMap::<String, String>::deserialize(deserializer)?;
+        let mut pairs = entry.into_iter();
+
+        match (pairs.next(), pairs.next()) {
+            (Some((value, label)), None) => Ok(Self { value, label }),
+            _ => Err(serde::de::Error::custom(
+                "challengeSelectInfo entries must contain exactly one key",
+            )),
+        }
+    }
 }
 
 // Results API Models
@@ -274,19 +1219,47 @@ pub struct ChallengeRequest {
 #[serde(rename_all = "camelCase")]
 pub struct ResultsRequest {
     pub acs_trans_id: Uuid,
-    pub message_category: String,
-    pub eci: String,
+    pub message_category: MessageCategory,
+    pub eci: Eci,
     pub message_type: String,
     pub acs_rendering_type: AcsRenderingType,
     pub ds_trans_id: Uuid,
-    pub authentication_method: String,
+    /// Added in 2.2.0, so absent from 2.1.0 RReqs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authentication_method: Option<String>,
     pub authentication_type: String,
     pub message_version: String,
     pub sdk_trans_id: Option<Uuid>,
     pub interaction_counter: String,
-    pub authentication_value: String,
-    pub trans_status: String,
+    /// The CAVV/AAV, present only for transStatus Y and A
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authentication_value: Option<String>,
+    pub trans_status: TransStatus,
+    /// Why the challenge failed (19 = exceeded maximum challenges)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trans_status_reason: Option<String>,
     pub three_ds_server_trans_id: Uuid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_binding_status: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trust_list_status: Option<String>,
+    /// Cardholder's whitelisting choice from the CReq (Y/N), added in 2.2.0
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub whitelist_status: Option<String>,
+    /// Who set whitelistStatus (03 = ACS)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub whitelist_status_source: Option<String>,
+}
+
+impl ResultsRequest {
+    /// Drop the elements 2.1.0 does not define
+    pub fn restrict_to_2_1_0(&mut self) {
+        self.authentication_method = None;
+        self.device_binding_status = None;
+        self.trust_list_status = None;
+        self.whitelist_status = None;
+        self.whitelist_status_source = None;
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -296,6 +1269,22 @@ pub struct AcsRenderingType {
     pub acs_interface: String,
 }
 
+impl AcsRenderingType {
+    /// Rendering of the UI a challenge presented: acsInterface 02 for HTML, otherwise native
+    /// (01) with the UI's acsUiType as template. Browser challenges present no SDK UI.
+    pub fn for_ui(ui: Option<&ChallengeUi>) -> Self {
+        let (acs_interface, acs_ui_template) = match ui {
+            Some(ui @ ChallengeUi::Html { .. }) => ("02", ui.acs_ui_type()),
+            Some(ui) => ("01", ui.acs_ui_type()),
+            None => ("01", "01"),
+        };
+        Self {
+            acs_ui_template: acs_ui_template.to_string(),
+            acs_interface: acs_interface.to_string(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ResultsResponse {
@@ -312,6 +1301,97 @@ pub struct ResultsResponse {
 #[derive(Debug, Deserialize)]
 pub struct AcsTriggerOtpRequest {
     pub creq: String,
+    #[serde(rename = "threeDSSessionData")]
+    pub three_ds_session_data: Option<String>,
+}
+
+/// 3DS Method form post from the requestor's hidden iframe to the threeDSMethodURL
+#[derive(Debug, Deserialize)]
+pub struct ThreeDsMethodForm {
+    #[serde(rename = "threeDSMethodData")]
+    pub three_ds_method_data: String,
+}
+
+/// Outcome of a 3DS Method run through /3ds/method: the threeDSCompInd to send in the AReq
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ThreeDsMethodResult {
+    #[serde(rename = "threeDSServerTransID")]
+    pub three_ds_server_trans_id: Uuid,
+    #[serde(rename = "threeDSCompInd")]
+    pub three_ds_comp_ind: &'static str,
+    /// Whether the threeDSMethodNotificationURL accepted the completion notification
+    pub notification_delivered: bool,
+}
+
+/// Decoded threeDSMethodData
+#[derive(Debug, Deserialize)]
+pub struct ThreeDsMethodData {
+    #[serde(rename = "threeDSServerTransID")]
+    pub three_ds_server_trans_id: Uuid,
+    #[serde(rename = "threeDSMethodNotificationURL")]
+    pub three_ds_method_notification_url: String,
+}
+
+/// Browser device data collected by the 3DS Method page, kept with the transaction
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MethodDeviceData {
+    pub browser_user_agent: String,
+    pub browser_language: String,
+    pub browser_color_depth: String,
+    pub browser_screen_height: u32,
+    pub browser_screen_width: u32,
+    /// Minutes between UTC and the cardholder's local time - negative east of UTC
+    #[serde(rename = "browserTZ")]
+    pub browser_tz: i32,
+    pub browser_java_enabled: bool,
+    #[serde(default)]
+    pub platform: Option<String>,
+}
+
+/// Device data posted back by the 3DS Method page
+#[derive(Debug, Deserialize)]
+pub struct MethodDeviceDataSubmission {
+    #[serde(rename = "threeDSServerTransID")]
+    pub three_ds_server_trans_id: Uuid,
+    #[serde(flatten)]
+    pub device_data: MethodDeviceData,
+}
+
+/// Browser CReq form post to the acsURL: `creq` is the base64url-encoded CReq
+#[derive(Debug, Deserialize)]
+pub struct AcsCreqForm {
+    pub creq: String,
+    #[serde(rename = "threeDSSessionData")]
+    pub three_ds_session_data: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpcVerifyRequest {
+    #[serde(rename = "threeDSServerTransID")]
+    pub three_ds_server_trans_id: Uuid,
+    pub credential_id: String,
+    /// Base64url-encoded clientDataJSON from the PaymentResponse assertion
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    /// Required in the assertion, but the mock only checks clientDataJSON's challenge
+    #[allow(dead_code)]
+    pub authenticator_data: String,
+    pub signature: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpcVerifyResponse {
+    #[serde(rename = "threeDSServerTransID")]
+    pub three_ds_server_trans_id: Uuid,
+    pub spc_trans_status: TransStatus,
+    pub trans_status: TransStatus,
+    pub eci: Eci,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authentication_value: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -319,6 +1399,68 @@ pub struct AcsVerifyOtpRequest {
     pub otp: String,
     #[serde(rename = "threeDSServerTransID")]
     pub three_ds_server_trans_id: String,
+    /// Keeps the page layout when a wrong OTP is answered with the page again
+    #[serde(rename = "challengeWindowSize", default)]
+    pub challenge_window_size: Option<String>,
+}
+
+/// "Resend OTP" form post from the browser challenge page
+#[derive(Debug, Deserialize)]
+pub struct AcsResendOtpRequest {
+    #[serde(rename = "threeDSServerTransID")]
+    pub three_ds_server_trans_id: String,
+    #[serde(rename = "challengeWindowSize")]
+    pub challenge_window_size: String,
+}
+
+// Error Message (Erro) Models
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorMessage {
+    pub message_type: String,
+    pub message_version: String,
+    #[serde(
+        rename = "threeDSServerTransID",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub three_ds_server_trans_id: Option<Uuid>,
+    #[serde(rename = "acsTransID", skip_serializing_if = "Option::is_none")]
+    pub acs_trans_id: Option<Uuid>,
+    #[serde(rename = "dsTransID", skip_serializing_if = "Option::is_none")]
+    pub ds_trans_id: Option<Uuid>,
+    #[serde(rename = "sdkTransID", skip_serializing_if = "Option::is_none")]
+    pub sdk_trans_id: Option<Uuid>,
+    pub error_code: String,
+    pub error_component: String,
+    pub error_description: String,
+    pub error_detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_message_type: Option<String>,
+}
+
+impl ErrorMessage {
+    /// Build an ACS-originated Erro message for the given 3DS error code
+    pub fn new(error_code: &str, error_description: &str, error_detail: &str) -> Self {
+        Self {
+            message_type: "Erro".to_string(),
+            message_version: "2.2.0".to_string(),
+            three_ds_server_trans_id: None,
+            acs_trans_id: None,
+            ds_trans_id: None,
+            sdk_trans_id: None,
+            error_code: error_code.to_string(),
+            error_component: "A".to_string(),
+            error_description: error_description.to_string(),
+            error_detail: error_detail.to_string(),
+            error_message_type: None,
+        }
+    }
+
+    pub fn for_message(mut self, message_type: &str, three_ds_server_trans_id: Uuid) -> Self {
+        self.error_message_type = Some(message_type.to_string());
+        self.three_ds_server_trans_id = Some(three_ds_server_trans_id);
+        self
+    }
 }
 
 // Final API Models
@@ -328,13 +1470,301 @@ pub struct FinalRequest {
     pub three_ds_server_trans_id: Uuid,
 }
 
+/// Outcome the ARes answered with when no challenge followed, reported by /3ds/final in
+/// place of an RReq
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FrictionlessOutcome {
+    pub trans_status: TransStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trans_status_reason: Option<String>,
+    pub eci: Eci,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authentication_value: Option<String>,
+}
+
+/// The transaction's final outcome: from the RReq after a challenge, otherwise from the ARes
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct FinalResponse {
-    pub eci: String,
-    pub authentication_value: String,
+    pub eci: Eci,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authentication_value: Option<String>,
     pub three_ds_server_trans_id: Uuid,
-    pub results_response: ResultsResponse,
-    pub results_request: ResultsRequest,
-    pub trans_status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub results_response: Option<ResultsResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub results_request: Option<ResultsRequest>,
+    pub trans_status: TransStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trans_status_reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub whitelist_status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub whitelist_status_source: Option<String>,
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn sample_authenticate_request() -> AuthenticateRequest {
+        serde_json::from_value(serde_json::json!({
+            "threeDsServerTransId": "29bf9634-b810-420d-bd8e-25072ce602f5",
+            "deviceChannel": "02",
+            "messageCategory": "01",
+            "preferredProtocolVersion": "2.2.0",
+            "enforcePreferredProtocolVersion": true,
+            "threeDsCompInd": "Y",
+            "threeDsRequestor": {
+                "threeDsRequestorAuthenticationInd": "01",
+                "threeDsRequestorAuthenticationInfo": {
+                    "threeDsReqAuthMethod": "04",
+                    "threeDsReqAuthTimestamp": "202409190344"
+                },
+                "threeDsRequestorChallengeInd": "01"
+            },
+            "cardholderAccount": {
+                "acctType": "02",
+                "cardExpiryDate": "3107",
+                "schemeId": "VISA",
+                "acctNumber": "4000400040004001",
+                "cardSecurityCode": "166"
+            },
+            "cardholder": {
+                "addrMatch": "N",
+                "billAddrCity": "Zurich",
+                "billAddrCountry": "756",
+                "billAddrLine1": "Zypressenstrasse 71",
+                "billAddrLine2": "P.O. Box",
+                "billAddrLine3": "8040 Zürich",
+                "billAddrPostCode": "8000",
+                "email": "netcetera@example.com",
+                "homePhone": { "cc": "1", "subscriber": "123" },
+                "mobilePhone": { "cc": "1", "subscriber": "123" },
+                "workPhone": { "cc": "1", "subscriber": "123" },
+                "cardholderName": "John Doe",
+                "shipAddrCity": "Zurich",
+                "shipAddrCountry": "756",
+                "shipAddrLine1": "Zypressenstrasse 98",
+                "shipAddrLine2": "P.O. Box",
+                "shipAddrLine3": "8040 Zürich",
+                "shipAddrPostCode": "8000"
+            },
+            "purchase": {
+                "purchaseAmount": 100,
+                "purchaseCurrency": "356",
+                "purchaseExponent": 2,
+                "purchaseDate": "20240919034416",
+                "transType": "01"
+            },
+            "acquirer": {
+                "acquirerBin": "271989",
+                "acquirerMerchantId": "JuspayTest1"
+            },
+            "merchant": {
+                "mcc": "1520",
+                "merchantCountryCode": "356",
+                "threeDsRequestorId": "juspay-prev",
+                "threeDsRequestorName": "juspay-prev",
+                "merchantName": "testMerchant",
+                "resultsResponseNotificationUrl": "https://example.com/3ds/results",
+                "notificationUrl": "https://example.com/notify"
+            },
+            "browserInformation": {
+                "browserAcceptHeader": "application/json",
+                "browserIP": "192.168.1.11",
+                "browserLanguage": "en",
+                "browserColorDepth": "8",
+                "browserScreenHeight": 1,
+                "browserScreenWidth": 1,
+                "browserTZ": 1,
+                "browserUserAgent": "Mozilla/5.0",
+                "challengeWindowSize": "01",
+                "browserJavaEnabled": false,
+                "browserJavascriptEnabled": true
+            },
+            "deviceRenderOptions": {
+                "sdkInterface": "01",
+                "sdkUiType": ["01"],
+                "sdkAuthenticationType": ["02"]
+            }
+        }))
+        .expect("sample AReq should deserialize")
+    }
+
+    #[test]
+    fn test_areq_echo_uses_spec_field_names() {
+        let req = sample_authenticate_request();
+        let echo = AReqEcho::from(&req).with_three_ds_server("REF", "OP", "https://example.com");
+        let json = serde_json::to_value(&echo).unwrap();
+
+        assert_eq!(json["messageType"], "AReq");
+        assert_eq!(
+            json["threeDSServerTransID"],
+            "29bf9634-b810-420d-bd8e-25072ce602f5"
+        );
+        assert_eq!(json["threeDSServerRefNumber"], "REF");
+        assert_eq!(json["acquirerBIN"], "271989");
+        assert_eq!(json["purchaseAmount"], "100");
+        assert_eq!(json["browserTZ"], "1");
+        assert_eq!(json["browserIP"], "192.168.1.11");
+        assert_eq!(
+            json["threeDSRequestorAuthenticationInfo"]["threeDSReqAuthMethod"],
+            "04"
+        );
+        assert!(json.get("recurringExpiry").is_none());
+        assert!(json.get("sdkEphemeralPublicKey").is_none());
+    }
+
+    #[test]
+    fn test_stored_request_masks_card_data() {
+        let req = sample_authenticate_request();
+        let pan = req.cardholder_account.acct_number.clone();
+
+        let stored = req.clone().for_storage(false);
+        assert_eq!(
+            stored.cardholder_account.acct_number,
+            MaskedPan::new(&pan).as_str()
+        );
+        assert_eq!(
+            stored.cardholder_account.card_security_code,
+            req.cardholder_account.card_security_code
+        );
+        assert!(!format!("{:?}", req.cardholder_account).contains(&pan));
+
+        let stripped = req.for_storage(true);
+        assert!(stripped.cardholder_account.card_security_code.is_empty());
+        let echo = AReqEcho::from(&stripped);
+        assert!(serde_json::to_value(&echo)
+            .unwrap()
+            .get("cardSecurityCode")
+            .is_none());
+    }
+
+    #[test]
+    fn test_protocol_codes_round_trip_and_reject_unknown() {
+        let req = sample_authenticate_request();
+        assert_eq!(req.device_channel, DeviceChannel::Browser);
+        assert_eq!(req.message_category, MessageCategory::Payment);
+        assert_eq!(
+            req.three_ds_requestor.three_ds_requestor_challenge_ind,
+            ChallengeIndicator::NoPreference
+        );
+
+        assert_eq!(
+            serde_json::to_value(TransStatus::SpcChallenge).unwrap(),
+            "S"
+        );
+        assert_eq!("07".parse::<Eci>().unwrap(), Eci::VisaNotAuthenticated);
+
+        let error = "X".parse::<TransStatus>().unwrap_err();
+        assert_eq!(error.error_code, "203");
+        assert_eq!(error.field, "transStatus");
+        assert!(serde_json::from_str::<DeviceChannel>("\"04\"").is_err());
+    }
+
+    #[test]
+    fn test_numeric_fields_accept_strings_and_negative_tz() {
+        let purchase: Purchase = serde_json::from_value(serde_json::json!({
+            "purchaseAmount": "12550",
+            "purchaseCurrency": "356",
+            "purchaseExponent": 2,
+            "purchaseDate": "20240919034416",
+            "transType": "01"
+        }))
+        .unwrap();
+        assert_eq!(purchase.purchase_amount, 12550);
+        assert_eq!(purchase.purchase_exponent, 2);
+
+        let mut browser = serde_json::json!({
+            "browserAcceptHeader": "application/json",
+            "browserIP": "192.168.1.11",
+            "browserLanguage": "en",
+            "browserColorDepth": "24",
+            "browserScreenHeight": "1080",
+            "browserScreenWidth": 1920,
+            "browserTZ": "-330",
+            "browserUserAgent": "Mozilla/5.0",
+            "challengeWindowSize": "01",
+            "browserJavaEnabled": false,
+            "browserJavascriptEnabled": true
+        });
+        let info: BrowserInformation = serde_json::from_value(browser.clone()).unwrap();
+        assert_eq!(info.browser_screen_height, 1080);
+        assert_eq!(info.browser_screen_width, 1920);
+        assert_eq!(info.browser_tz, -330);
+
+        browser["browserTZ"] = serde_json::json!(-60);
+        let info: BrowserInformation = serde_json::from_value(browser.clone()).unwrap();
+        assert_eq!(info.browser_tz, -60);
+
+        browser["browserTZ"] = serde_json::json!("UTC+1");
+        assert!(serde_json::from_value::<BrowserInformation>(browser).is_err());
+    }
+
+    #[test]
+    fn test_sdk_ephemeral_key_from_top_level_fields() {
+        let mut req = sample_authenticate_request();
+        assert!(req.sdk_ephemeral_key().is_none());
+
+        req.kty = Some("EC".to_string());
+        req.crv = Some("P-256".to_string());
+        req.x = Some("x".to_string());
+        req.y = Some("y".to_string());
+
+        let key = req
+            .sdk_ephemeral_key()
+            .expect("top-level key should be used");
+        assert_eq!(key.crv, "P-256");
+    }
+
+    #[test]
+    fn test_cres_otp_form_serialization() {
+        let trans_id = Uuid::new_v4();
+        let cres = ChallengeResponseMobile::new("2.2.0", trans_id, Uuid::new_v4(), None, "000")
+            .with_ui(ChallengeUi::otp_form());
+        let json = serde_json::to_value(&cres).unwrap();
+
+        assert_eq!(json["messageType"], "CRes");
+        assert_eq!(json["threeDSServerTransID"], trans_id.to_string());
+        assert_eq!(json["acsCounterAtoS"], "000");
+        assert_eq!(json["acsUiType"], "01");
+        assert_eq!(json["challengeCompletionInd"], "N");
+        assert_eq!(json["challengeInfoLabel"], "Enter OTP:");
+        assert_eq!(json["resendInformationLabel"], "Resend OTP");
+        assert!(json.get("challengeInfoText").is_none());
+        assert!(json.get("transStatus").is_none());
+        assert!(json.get("sdkTransID").is_none());
+    }
+
+    #[test]
+    fn test_cres_select_and_completed_serialization() {
+        let select = ChallengeUi::MultiSelect {
+            challenge_info_header: "Verify".to_string(),
+            challenge_info_label: "Pick all that apply".to_string(),
+            challenge_info_text: None,
+            challenge_select_info: vec![ChallengeSelectOption {
+                value: "phone".to_string(),
+                label: "Mobile **** 1234".to_string(),
+            }],
+            submit_authentication_label: "Next".to_string(),
+        };
+        let cres = ChallengeResponseMobile::new(
+            "2.2.0",
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Some(Uuid::new_v4()),
+            "001",
+        )
+        .with_ui(select);
+        let json = serde_json::to_value(&cres).unwrap();
+        assert_eq!(json["acsUiType"], "03");
+        assert_eq!(json["challengeSelectInfo"][0]["phone"], "Mobile **** 1234");
+
+        let json = serde_json::to_value(cres.completed(TransStatus::Authenticated)).unwrap();
+        assert_eq!(json["challengeCompletionInd"], "Y");
+        assert_eq!(json["transStatus"], "Y");
+        assert!(json.get("acsUiType").is_none());
+    }
 }
@@ -0,0 +1,112 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+/// Digits kept at the start of a masked PAN: the BIN, which routes and selects scenarios
+const VISIBLE_PREFIX: usize = 6;
+/// Digits kept at the end of a masked PAN, as printed on receipts
+const VISIBLE_SUFFIX: usize = 4;
+
+/// A PAN safe to store and log: the BIN and last four digits, the rest replaced with `*`.
+/// Numbers too short to keep both only show their last four digits.
+#[derive(Clone, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct MaskedPan(String);
+
+impl MaskedPan {
+    pub fn new(pan: &str) -> Self {
+        let suffix_start = pan.len().saturating_sub(VISIBLE_SUFFIX);
+        let prefix_end = if pan.len() > VISIBLE_PREFIX + VISIBLE_SUFFIX {
+            VISIBLE_PREFIX
+        } else {
+            0
+        };
+        let masked = pan
+            .char_indices()
+            .map(|(i, c)| {
+                if i < prefix_end || i >= suffix_start {
+                    c
+                } else {
+                    '*'
+                }
+            })
+            .collect();
+        Self(masked)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+// Stored values are masked again on the way in: masking is idempotent, and a full PAN that
+// reached the store some other way never comes back out unmasked
+impl<'de> Deserialize<'de> for MaskedPan {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(|pan| Self::new(&pan))
+    }
+}
+
+impl fmt::Display for MaskedPan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl fmt::Debug for MaskedPan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Hex SHA-256 of a PAN, stored in place of the number so a transaction can still be matched
+/// to a card
+pub fn pan_hash(pan: &str) -> String {
+    hex::encode(Sha256::digest(pan.as_bytes()))
+}
+
+/// The last four characters of a card number, as shown to the cardholder. Counted in
+/// characters, since an AReq's acctNumber is not known to be digits.
+pub fn last_four(pan: &str) -> &str {
+    let start = pan
+        .char_indices()
+        .rev()
+        .nth(VISIBLE_SUFFIX - 1)
+        .map_or(0, |(i, _)| i);
+    &pan[start..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_masked_pan() {
+        assert_eq!(
+            MaskedPan::new("4000400040004001").as_str(),
+            "400040******4001"
+        );
+        assert_eq!(
+            format!("{:?}", MaskedPan::new("378282246310005")),
+            "378282*****0005"
+        );
+        assert_eq!(MaskedPan::new("4000400001").as_str(), "******0001");
+        assert_eq!(pan_hash("4000400040004001").len(), 64);
+        assert_ne!(pan_hash("4000400040004001"), pan_hash("4000400040004002"));
+    }
+
+    #[test]
+    fn test_last_four() {
+        assert_eq!(last_four("4000400040004001"), "4001");
+        assert_eq!(last_four("401"), "401");
+        assert_eq!(last_four("4000400040€€€€"), "€€€€");
+    }
+
+    #[test]
+    fn test_deserialized_pans_are_masked() {
+        let stored: MaskedPan = serde_json::from_str(r#""400040******4001""#).unwrap();
+        assert_eq!(stored, MaskedPan::new("4000400040004001"));
+        let full: MaskedPan = serde_json::from_str(r#""4000400040004001""#).unwrap();
+        assert_eq!(full.as_str(), "400040******4001");
+    }
+}
@@ -0,0 +1,92 @@
+//! Runtime switches for the compression and rate limiting middlewares. They start from
+//! `[performance] enable_compression` and `enable_rate_limiting` and can be flipped through
+//! /admin/performance, so a load test can lift the rate limits without a restart.
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::config::PerformanceConfig;
+
+/// Which of the switchable middlewares are on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct PerformanceState {
+    pub compression: bool,
+    pub rate_limiting: bool,
+}
+
+/// Switches to change; absent ones keep their state
+#[derive(Debug, Default, Deserialize)]
+pub struct PerformanceUpdate {
+    pub compression: Option<bool>,
+    pub rate_limiting: Option<bool>,
+}
+
+/// The switches, shared by all workers of all listeners
+pub struct PerformanceSwitches {
+    compression: AtomicBool,
+    rate_limiting: AtomicBool,
+}
+
+impl PerformanceSwitches {
+    pub fn new(config: &PerformanceConfig) -> Self {
+        Self {
+            compression: AtomicBool::new(config.enable_compression),
+            rate_limiting: AtomicBool::new(config.enable_rate_limiting),
+        }
+    }
+
+    pub fn compression(&self) -> bool {
+        self.compression.load(Ordering::Relaxed)
+    }
+
+    pub fn rate_limiting(&self) -> bool {
+        self.rate_limiting.load(Ordering::Relaxed)
+    }
+
+    pub fn state(&self) -> PerformanceState {
+        PerformanceState {
+            compression: self.compression(),
+            rate_limiting: self.rate_limiting(),
+        }
+    }
+
+    /// Apply an update and report the resulting state
+    pub fn update(&self, update: &PerformanceUpdate) -> PerformanceState {
+        if let Some(compression) = update.compression {
+            self.compression.store(compression, Ordering::Relaxed);
+        }
+        if let Some(rate_limiting) = update.rate_limiting {
+            self.rate_limiting.store(rate_limiting, Ordering::Relaxed);
+        }
+        self.state()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_switches_start_from_config_and_keep_unset_ones() {
+        let config = PerformanceConfig {
+            enable_compression: true,
+            enable_rate_limiting: false,
+            ..PerformanceConfig::default()
+        };
+        let switches = PerformanceSwitches::new(&config);
+        assert_eq!(
+            switches.state(),
+            PerformanceState {
+                compression: true,
+                rate_limiting: false
+            }
+        );
+
+        let state = switches.update(&PerformanceUpdate {
+            rate_limiting: Some(true),
+            ..PerformanceUpdate::default()
+        });
+        assert!(state.compression);
+        assert!(state.rate_limiting);
+    }
+}
@@ -0,0 +1,93 @@
+//! messageVersion negotiation: the ACS answers in the AReq's preferredProtocolVersion when it
+//! supports it, otherwise in the newest version it supports below it.
+
+use crate::validation::FieldError;
+
+pub const V2_1_0: &str = "2.1.0";
+pub const V2_2_0: &str = "2.2.0";
+
+/// Versions the ACS answers in, oldest first; advertised in /3ds/version card ranges as
+/// acsStartProtocolVersion to acsEndProtocolVersion
+pub const ACS_PROTOCOL_VERSIONS: [&str; 2] = [V2_1_0, V2_2_0];
+
+/// messageVersion of transactions stored before versions were negotiated
+pub const DEFAULT_MESSAGE_VERSION: &str = V2_2_0;
+
+pub fn acs_start_protocol_version() -> &'static str {
+    ACS_PROTOCOL_VERSIONS[0]
+}
+
+pub fn acs_end_protocol_version() -> &'static str {
+    ACS_PROTOCOL_VERSIONS[ACS_PROTOCOL_VERSIONS.len() - 1]
+}
+
+/// Whether messages must be 2.1.0-shaped, without the elements later versions added
+pub fn is_2_1(message_version: &str) -> bool {
+    message_version == V2_1_0
+}
+
+fn parse(version: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = version.split('.').map(|part| part.parse::<u32>().ok());
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(Some(major)), Some(Some(minor)), Some(Some(patch)), None) => {
+            Some((major, minor, patch))
+        }
+        _ => None,
+    }
+}
+
+/// The messageVersion to answer an AReq in, from the versions the card's scheme supports. With
+/// enforcePreferredProtocolVersion, a preferred version outside them is an error (Erro 102)
+/// instead of a fallback.
+pub fn negotiate(
+    preferred: &str,
+    enforce: bool,
+    supported: &[&'static str],
+) -> Result<&'static str, FieldError> {
+    if let Some(version) = supported.iter().copied().find(|v| *v == preferred) {
+        return Ok(version);
+    }
+
+    let fallback = parse(preferred).and_then(|preferred| {
+        supported
+            .iter()
+            .copied()
+            .rev()
+            .find(|version| parse(version).is_some_and(|version| version <= preferred))
+    });
+    match fallback {
+        Some(version) if !enforce => Ok(version),
+        _ => Err(FieldError::unsupported_version(
+            "preferredProtocolVersion",
+            &format!(
+                "Protocol version {} is not supported (supported: {})",
+                preferred,
+                supported.join(", ")
+            ),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiation() {
+        let all = &ACS_PROTOCOL_VERSIONS;
+        assert_eq!(negotiate("2.1.0", true, all).unwrap(), "2.1.0");
+        assert_eq!(negotiate("2.2.0", false, all).unwrap(), "2.2.0");
+        assert_eq!(negotiate("2.3.1", false, all).unwrap(), "2.2.0");
+
+        let error = negotiate("2.3.1", true, all).unwrap_err();
+        assert_eq!(error.error_code, "102");
+        assert_eq!(error.field, "preferredProtocolVersion");
+
+        assert!(negotiate("2.0.9", false, all).is_err());
+        assert!(negotiate("two", false, all).is_err());
+
+        // A scheme supporting only 2.1.0 answers newer requests in it
+        assert_eq!(negotiate("2.2.0", false, &[V2_1_0]).unwrap(), "2.1.0");
+        assert!(negotiate("2.2.0", true, &[V2_1_0]).is_err());
+    }
+}
@@ -0,0 +1,230 @@
+//! Request rate limits per client IP, set per route: `[performance] rate_limit_per_second`
+//! for every route, overridden by `rate_limits` rules (the longest matching route wins) and
+//! lifted for the `rate_limit_exempt` routes. Each rule keeps its own quota, so a burst of
+//! challenge traffic does not eat into a client's AReq allowance.
+
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+use governor::clock::{Clock, DefaultClock};
+use governor::middleware::StateInformationMiddleware;
+use governor::state::keyed::DefaultKeyedStateStore;
+use governor::{Quota, RateLimiter};
+use std::net::IpAddr;
+use std::num::NonZeroU32;
+
+use crate::config::PerformanceConfig;
+
+type KeyedLimiter =
+    RateLimiter<IpAddr, DefaultKeyedStateStore<IpAddr>, DefaultClock, StateInformationMiddleware>;
+
+struct RouteLimiter {
+    route: String,
+    burst_size: u32,
+    limiter: KeyedLimiter,
+}
+
+impl RouteLimiter {
+    // Zero rates are rejected by Settings::validate
+    fn new(route: &str, per_second: u32, burst_size: u32) -> Self {
+        let per_second = NonZeroU32::new(per_second).expect("validated rate limit");
+        let burst_size = NonZeroU32::new(burst_size).expect("validated burst size");
+        Self {
+            route: route.to_string(),
+            burst_size: burst_size.get(),
+            limiter: RateLimiter::keyed(Quota::per_second(per_second).allow_burst(burst_size))
+                .with_middleware::<StateInformationMiddleware>(),
+        }
+    }
+}
+
+/// Outcome of a request's rate limit check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// The route is not rate limited
+    Exempt,
+    Allowed {
+        limit: u32,
+        remaining: u32,
+    },
+    Limited {
+        limit: u32,
+        retry_after_seconds: u64,
+    },
+}
+
+impl Decision {
+    /// Report the remaining quota on an allowed request's response
+    pub fn add_headers(&self, headers: &mut HeaderMap) {
+        if let Decision::Allowed { limit, remaining } = self {
+            headers.insert(
+                HeaderName::from_static("x-ratelimit-limit"),
+                HeaderValue::from(*limit),
+            );
+            headers.insert(
+                HeaderName::from_static("x-ratelimit-remaining"),
+                HeaderValue::from(*remaining),
+            );
+        }
+    }
+}
+
+/// The route limiters, shared by all workers of all listeners
+pub struct RateLimits {
+    default: RouteLimiter,
+    routes: Vec<RouteLimiter>,
+    exempt: Vec<String>,
+}
+
+impl RateLimits {
+    pub fn new(config: &PerformanceConfig) -> Self {
+        Self {
+            default: RouteLimiter::new(
+                "",
+                config.rate_limit_per_second,
+                config.rate_limit_burst_size(),
+            ),
+            routes: config
+                .rate_limits
+                .iter()
+                .map(|rule| RouteLimiter::new(&rule.route, rule.per_second, rule.burst_size()))
+                .collect(),
+            exempt: config.rate_limit_exempt.clone(),
+        }
+    }
+
+    fn limiter_for(&self, path: &str) -> Option<&RouteLimiter> {
+        if self.exempt.iter().any(|route| covers(route, path)) {
+            return None;
+        }
+        self.routes
+            .iter()
+            .filter(|limiter| covers(&limiter.route, path))
+            .max_by_key(|limiter| limiter.route.len())
+            .or(Some(&self.default))
+    }
+
+    /// Count a request from `client` to `path` (with any route prefix removed)
+    pub fn check(&self, path: &str, client: IpAddr) -> Decision {
+        let Some(route) = self.limiter_for(path) else {
+            return Decision::Exempt;
+        };
+        match route.limiter.check_key(&client) {
+            Ok(snapshot) => Decision::Allowed {
+                limit: route.burst_size,
+                remaining: snapshot.remaining_burst_capacity(),
+            },
+            Err(not_until) => Decision::Limited {
+                limit: route.burst_size,
+                retry_after_seconds: not_until
+                    .wait_time_from(DefaultClock::default().now())
+                    .as_secs_f64()
+                    .ceil()
+                    .max(1.0) as u64,
+            },
+        }
+    }
+
+    /// Forget clients whose quota has fully recovered, so idle clients don't pile up
+    pub fn retain_recent(&self) {
+        self.default.limiter.retain_recent();
+        for route in &self.routes {
+            route.limiter.retain_recent();
+        }
+    }
+}
+
+// Whether `route` is `path` or one of its parent segments: `/health` covers `/health` and
+// `/health/ready` but not `/healthz`
+fn covers(route: &str, path: &str) -> bool {
+    let route = route.trim_end_matches('/');
+    path.strip_prefix(route)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RouteRateLimit;
+
+    #[test]
+    fn test_routes_have_their_own_quota_and_exemptions() {
+        let config = PerformanceConfig {
+            rate_limit_per_second: 1,
+            rate_limits: vec![RouteRateLimit {
+                route: "/challenge".to_string(),
+                per_second: 5,
+                burst_size: Some(3),
+            }],
+            ..PerformanceConfig::default()
+        };
+        let limits = RateLimits::new(&config);
+        let client: IpAddr = "192.0.2.10".parse().unwrap();
+
+        // Default burst of 2 for routes without a rule
+        assert_eq!(
+            limits.check("/3ds/authenticate", client),
+            Decision::Allowed {
+                limit: 2,
+                remaining: 1
+            }
+        );
+        limits.check("/3ds/authenticate", client);
+        assert!(matches!(
+            limits.check("/3ds/results", client),
+            Decision::Limited { limit: 2, .. }
+        ));
+
+        // The challenge rule and the exempt health endpoints are unaffected
+        for remaining in [2, 1, 0] {
+            assert_eq!(
+                limits.check("/challenge", client),
+                Decision::Allowed {
+                    limit: 3,
+                    remaining
+                }
+            );
+        }
+        assert_eq!(limits.check("/health/ready", client), Decision::Exempt);
+        assert_eq!(limits.check("/health", client), Decision::Exempt);
+
+        // Other clients have their own quota
+        let other: IpAddr = "192.0.2.11".parse().unwrap();
+        assert!(matches!(
+            limits.check("/3ds/authenticate", other),
+            Decision::Allowed { .. }
+        ));
+    }
+
+    #[test]
+    fn test_routes_match_whole_path_segments() {
+        let config = PerformanceConfig {
+            rate_limit_per_second: 1,
+            rate_limits: vec![RouteRateLimit {
+                route: "/admin/".to_string(),
+                per_second: 5,
+                burst_size: Some(3),
+            }],
+            ..PerformanceConfig::default()
+        };
+        let limits = RateLimits::new(&config);
+        let client: IpAddr = "192.0.2.10".parse().unwrap();
+
+        // Look-alike paths fall under the default quota, not the rule or the exemption
+        assert!(matches!(
+            limits.check("/healthz", client),
+            Decision::Allowed { limit: 2, .. }
+        ));
+        assert!(matches!(
+            limits.check("/administrator", client),
+            Decision::Allowed { limit: 2, .. }
+        ));
+        assert!(matches!(
+            limits.check("/admin", client),
+            Decision::Allowed { limit: 3, .. }
+        ));
+        assert!(matches!(
+            limits.check("/admin/transactions", client),
+            Decision::Allowed { limit: 3, .. }
+        ));
+        assert_eq!(limits.check("/metrics", client), Decision::Exempt);
+    }
+}
@@ -0,0 +1,170 @@
+//! Per-transaction message log: the messages each transaction exchanged, kept in the state
+//! store next to it. /admin/transactions/{id}/export returns the log, and /admin/replay runs
+//! an exported flow again through the flow runner, turning a captured bug report into a
+//! reproducible scenario.
+
+use actix_web::web;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::state_store::StateStore;
+
+/// Body limit for /admin/replay: a capture holds every message of a flow, ARes included
+pub const MAX_CAPTURE_BYTES: usize = 1024 * 1024;
+
+/// Which way a message went, seen from the mock
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+/// One message as exchanged; CReqs and CRes are kept decrypted
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordedMessage {
+    pub direction: Direction,
+    /// AReq, ARes, CReq, CRes, ChallengeForm, RReq, RRes or Final
+    pub message_type: String,
+    pub recorded_at: DateTime<Utc>,
+    pub body: Value,
+}
+
+/// A transaction's exported message log, also the body /admin/replay accepts
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FlowCapture {
+    #[serde(rename = "threeDSServerTransID")]
+    pub three_ds_server_trans_id: Uuid,
+    pub messages: Vec<RecordedMessage>,
+}
+
+impl FlowCapture {
+    /// Body of the first message of a type and direction
+    pub fn first(&self, direction: Direction, message_type: &str) -> Option<&Value> {
+        self.messages
+            .iter()
+            .find(|message| message.direction == direction && message.message_type == message_type)
+            .map(|message| &message.body)
+    }
+
+    /// Body of the last message of a type and direction
+    pub fn last(&self, direction: Direction, message_type: &str) -> Option<&Value> {
+        self.messages
+            .iter()
+            .rev()
+            .find(|message| message.direction == direction && message.message_type == message_type)
+            .map(|message| &message.body)
+    }
+
+    /// The last OTP the cardholder entered, in an app CReq or the browser challenge form
+    pub fn last_otp(&self) -> Option<&str> {
+        self.messages
+            .iter()
+            .rev()
+            .filter(|message| message.direction == Direction::Inbound)
+            .find_map(|message| match message.message_type.as_str() {
+                "CReq" => message.body["challengeDataEntry"].as_str(),
+                "ChallengeForm" => message.body["otp"].as_str(),
+                _ => None,
+            })
+    }
+}
+
+/// Append a message to a transaction's log. Failures are logged rather than returned, so
+/// recording never changes how a message is answered.
+pub async fn record<T: Serialize>(
+    state: &web::Data<Arc<Box<dyn StateStore>>>,
+    three_ds_server_trans_id: Uuid,
+    direction: Direction,
+    message_type: &str,
+    body: &T,
+) {
+    let body = match serde_json::to_value(body) {
+        Ok(body) => body,
+        Err(e) => {
+            warn!(
+                message_type,
+                %three_ds_server_trans_id,
+                error = %e,
+                "could not record message"
+            );
+            return;
+        }
+    };
+    let message = RecordedMessage {
+        direction,
+        message_type: message_type.to_string(),
+        recorded_at: Utc::now(),
+        body,
+    };
+    if let Err(e) = state
+        .append_message(three_ds_server_trans_id, message)
+        .await
+    {
+        warn!(
+            message_type,
+            %three_ds_server_trans_id,
+            error = %e,
+            "could not record message"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn message(direction: Direction, message_type: &str, body: Value) -> RecordedMessage {
+        RecordedMessage {
+            direction,
+            message_type: message_type.to_string(),
+            recorded_at: Utc::now(),
+            body,
+        }
+    }
+
+    #[test]
+    fn test_capture_lookups() {
+        let capture = FlowCapture {
+            three_ds_server_trans_id: Uuid::new_v4(),
+            messages: vec![
+                message(Direction::Inbound, "AReq", json!({ "deviceChannel": "02" })),
+                message(Direction::Outbound, "ARes", json!({ "transStatus": "C" })),
+                message(
+                    Direction::Inbound,
+                    "ChallengeForm",
+                    json!({ "otp": "0000" }),
+                ),
+                message(
+                    Direction::Inbound,
+                    "ChallengeForm",
+                    json!({ "otp": "1234" }),
+                ),
+                message(Direction::Inbound, "RReq", json!({ "transStatus": "Y" })),
+            ],
+        };
+
+        assert_eq!(
+            capture.first(Direction::Inbound, "AReq").unwrap()["deviceChannel"],
+            "02"
+        );
+        assert!(capture.first(Direction::Outbound, "AReq").is_none());
+        assert_eq!(
+            capture.last(Direction::Inbound, "RReq").unwrap()["transStatus"],
+            "Y"
+        );
+        assert_eq!(capture.last_otp(), Some("1234"));
+
+        let exported = serde_json::to_value(&capture).unwrap();
+        assert!(exported["threeDSServerTransID"].is_string());
+        assert_eq!(exported["messages"][1]["direction"], "outbound");
+        assert_eq!(exported["messages"][1]["messageType"], "ARes");
+    }
+}
@@ -0,0 +1,112 @@
+//! Request correlation: every request gets an X-Request-ID, taken from the caller when it
+//! sends a usable one and generated otherwise. The ID is echoed in the response, carried by
+//! a `request` span around the handler (so every log line for the request has it), and
+//! stored on transactions created by an AReq, so a /3ds/authenticate call can be matched
+//! to the challenge calls that follow it.
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+use actix_web::Error;
+use std::future::Future;
+use tracing::{info_span, Instrument};
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest caller-supplied ID kept; longer ones are replaced
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+tokio::task_local! {
+    // ID of the request being handled
+    static REQUEST_ID: String;
+}
+
+/// ID of the request being handled, if called while handling one
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok()
+}
+
+/// The caller's X-Request-ID when it is printable ASCII of a sane length
+fn incoming(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(REQUEST_ID_HEADER)?.to_str().ok()?.trim();
+    let usable = !value.is_empty()
+        && value.len() <= MAX_REQUEST_ID_LENGTH
+        && value.bytes().all(|byte| byte.is_ascii_graphic());
+    usable.then(|| value.to_string())
+}
+
+/// Run the rest of the service chain under the request's ID and span, then echo the ID
+pub fn correlate<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    let request_id = incoming(req.headers()).unwrap_or_else(|| Uuid::new_v4().to_string());
+    let span = info_span!(
+        "request",
+        request_id = %request_id,
+        method = %req.method(),
+        path = %req.path(),
+    );
+    // Only printable ASCII gets here, so the header value is always valid
+    let header_value = HeaderValue::from_str(&request_id).ok();
+
+    let response = REQUEST_ID.sync_scope(request_id.clone(), || span.in_scope(|| srv.call(req)));
+    REQUEST_ID.scope(
+        request_id,
+        async move {
+            let mut response = response.await?;
+            if let Some(value) = header_value {
+                response.headers_mut().insert(REQUEST_ID_HEADER, value);
+            }
+            Ok(response)
+        }
+        .instrument(span),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App, HttpResponse};
+
+    #[actix_web::test]
+    async fn test_request_id_is_propagated_or_generated() {
+        let app = test::init_service(App::new().wrap_fn(correlate).route(
+            "/",
+            web::get().to(|| async { HttpResponse::Ok().body(current().unwrap()) }),
+        ))
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/")
+            .insert_header((REQUEST_ID_HEADER, "merchant-req-42"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(
+            res.headers().get(REQUEST_ID_HEADER).unwrap(),
+            "merchant-req-42"
+        );
+        assert_eq!(test::read_body(res).await, "merchant-req-42");
+
+        // Unusable IDs are replaced with a fresh UUID
+        let req = test::TestRequest::get()
+            .uri("/")
+            .insert_header((REQUEST_ID_HEADER, "has spaces"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        let generated = res
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(Uuid::parse_str(&generated).is_ok());
+        assert_eq!(test::read_body(res).await, generated.as_str());
+
+        assert!(current().is_none());
+    }
+}
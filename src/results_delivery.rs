@@ -0,0 +1,102 @@
+//! Outbound RReq: once a challenge ends, the RReq is POSTed to the AReq merchant's
+//! resultsResponseNotificationUrl, as the DS would forward it, so 3DS Server integrators can
+//! exercise their inbound RReq endpoint.
+
+use tracing::warn;
+
+use crate::config::WebhookConfig;
+use crate::models::ResultsRequest;
+use crate::webhook::post_with_retries;
+
+/// Send the RReq in the background, with the retries and signing of `config`. Does nothing
+/// while `results_delivery.enabled` is off or when the AReq named no results URL.
+pub fn deliver(config: &WebhookConfig, results_url: &str, results_request: &ResultsRequest) {
+    if !config.enabled || results_url.is_empty() {
+        return;
+    }
+    let body = match serde_json::to_vec(results_request) {
+        Ok(body) => body,
+        Err(e) => {
+            warn!(error = %e, "RReq not sent");
+            return;
+        }
+    };
+    let config = config.clone();
+    let url = results_url.to_string();
+    let three_ds_server_trans_id = results_request.three_ds_server_trans_id;
+    tokio::spawn(async move {
+        post_with_retries(&config, &url, body, "rreq", three_ds_server_trans_id).await
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use uuid::Uuid;
+
+    use crate::challenge::{self, Completion};
+    use crate::config::tests::test_settings;
+    use crate::handlers::tests::{sample_areq, TestState};
+    use crate::webhook::OutcomeFlow;
+
+    // Accept one HTTP POST on `listener`, answer 200 and return its JSON body
+    async fn receive_post(listener: TcpListener) -> Value {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buffer = [0u8; 4096];
+        let (head_end, content_length) = loop {
+            let read = stream.read(&mut buffer).await.unwrap();
+            request.extend_from_slice(&buffer[..read]);
+            if let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                let head = String::from_utf8_lossy(&request[..end]).to_ascii_lowercase();
+                let length = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length:"))
+                    .map_or(0, |value| value.trim().parse().unwrap());
+                break (end + 4, length);
+            }
+        };
+        while request.len() < head_end + content_length {
+            let read = stream.read(&mut buffer).await.unwrap();
+            request.extend_from_slice(&buffer[..read]);
+        }
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+            .await
+            .unwrap();
+        serde_json::from_slice(&request[head_end..]).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn test_rreq_is_delivered_to_the_results_url() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let results_url = format!("http://{}/3ds/results", listener.local_addr().unwrap());
+        let mut settings = test_settings();
+        settings.results_delivery.enabled = true;
+        let app = TestState::new(settings);
+
+        let mut areq = sample_areq();
+        let id = Uuid::new_v4();
+        areq["threeDsServerTransId"] = id.to_string().into();
+        areq["merchant"]["resultsResponseNotificationUrl"] = results_url.into();
+        app.authenticate(&areq).await.unwrap();
+        let mut transaction = app.state.get(&id).await.unwrap().unwrap();
+        let completion = Completion {
+            verified: true,
+            authentication_method: None,
+            trans_status_reason: None,
+            flow: OutcomeFlow::Browser,
+        };
+        challenge::complete(&app.state, &app.settings, id, &mut transaction, completion).await;
+
+        let rreq = tokio::time::timeout(Duration::from_secs(5), receive_post(listener))
+            .await
+            .expect("no RReq was delivered");
+        assert_eq!(rreq["messageType"], "RReq");
+        assert_eq!(rreq["transStatus"], "Y");
+        assert_eq!(rreq["threeDsServerTransId"], id.to_string());
+    }
+}
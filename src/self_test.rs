@@ -0,0 +1,332 @@
+//! Startup self-test: one synthetic mobile challenge flow through the real handlers, so a
+//! broken certificate, state store or ACS link shows up at boot instead of on the first
+//! external test.
+
+use actix_web::{body::to_bytes, web};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::acs_client::AcsClient;
+use crate::cache::AppCaches;
+use crate::card_status::CardStatusList;
+use crate::config::Settings;
+use crate::crypto::{
+    calculate_derived_key, decrypt_challenge_request, encrypt_challenge_response,
+    generate_ephemeral_key_pair, EphemeralKeyPair,
+};
+use crate::state_store::{StateStore, TransactionData};
+
+/// Card that always takes the challenge path, so the ACS signs acsSignedContent
+const SELF_TEST_CARD: &str = "4000000000004001";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SelfTestStep {
+    pub name: &'static str,
+    pub passed: bool,
+    pub detail: String,
+}
+
+/// Outcome of the startup self-test, reported by the health endpoint
+#[derive(Debug, Clone, Serialize)]
+pub struct SelfTestReport {
+    pub passed: bool,
+    pub ran_at: DateTime<Utc>,
+    pub steps: Vec<SelfTestStep>,
+}
+
+impl SelfTestReport {
+    fn record<T>(&mut self, name: &'static str, result: Result<T, String>) -> Option<T> {
+        let (passed, detail, value) = match result {
+            Ok(value) => (true, "ok".to_string(), Some(value)),
+            Err(detail) => (false, detail, None),
+        };
+        if passed {
+            info!(step = name, "self-test step passed");
+        } else {
+            error!(step = name, %detail, "self-test step failed");
+        }
+        self.passed &= passed;
+        self.steps.push(SelfTestStep {
+            name,
+            passed,
+            detail,
+        });
+        value
+    }
+}
+
+/// Run the self-test against the configured ACS (in-process or remote) and state store
+pub async fn run(
+    state: Arc<Box<dyn StateStore>>,
+    settings: &Settings,
+    caches: web::Data<AppCaches>,
+    card_statuses: web::Data<CardStatusList>,
+    acs_client: &AcsClient,
+) -> SelfTestReport {
+    info!("running startup self-test");
+    let mut report = SelfTestReport {
+        passed: true,
+        ran_at: Utc::now(),
+        steps: Vec::new(),
+    };
+
+    let Some(sdk_keys) = report.record(
+        "sdk_ephemeral_keys",
+        generate_ephemeral_key_pair().map_err(|e| e.to_string()),
+    ) else {
+        return report;
+    };
+
+    let three_ds_server_trans_id = Uuid::new_v4();
+    let areq = synthetic_areq(three_ds_server_trans_id, SELF_TEST_CARD, Some(&sdk_keys));
+    let body = web::Bytes::from(areq.to_string());
+    let state = web::Data::new(state);
+    let ares = acs_client
+        .authenticate(
+            body,
+            state.clone(),
+            web::Data::new(settings.clone()),
+            caches,
+            card_statuses,
+        )
+        .await;
+    let ares = match ares {
+        Ok(response) => {
+            let status = response.status();
+            let body = to_bytes(response.into_body())
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|bytes| {
+                    serde_json::from_slice::<Value>(&bytes).map_err(|e| e.to_string())
+                });
+            match body {
+                Ok(body) if status.is_success() => check_ares(&body),
+                Ok(body) => Err(format!("HTTP {}: {}", status, body)),
+                Err(e) => Err(format!("HTTP {}: {}", status, e)),
+            }
+        }
+        Err(e) => Err(e.to_string()),
+    };
+    if report.record("areq", ares).is_none() {
+        return report;
+    }
+
+    let stored = state
+        .get(&three_ds_server_trans_id)
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|data| data.ok_or_else(|| "transaction was not stored".to_string()));
+    if let Some(transaction) = report.record("state_store", stored) {
+        report.record(
+            "jwe_round_trip",
+            jwe_round_trip(&transaction, &sdk_keys, settings).await,
+        );
+    }
+
+    if let Err(e) = state.delete(&three_ds_server_trans_id).await {
+        error!(%three_ds_server_trans_id, error = %e, "failed to remove the self-test transaction");
+    }
+
+    if report.passed {
+        info!("self-test passed");
+    } else {
+        error!("self-test failed, see the steps above or /health");
+    }
+    report
+}
+
+/// AReq for a synthetic cardholder: an app AReq carrying the SDK's ephemeral public key
+/// when SDK keys are given, a browser AReq otherwise
+pub(crate) fn synthetic_areq(
+    three_ds_server_trans_id: Uuid,
+    card_number: &str,
+    sdk_keys: Option<&EphemeralKeyPair>,
+) -> Value {
+    let mut areq = json!({
+        "threeDsServerTransId": three_ds_server_trans_id,
+        "deviceChannel": if sdk_keys.is_some() { "01" } else { "02" },
+        "messageCategory": "01",
+        "preferredProtocolVersion": "2.2.0",
+        "enforcePreferredProtocolVersion": false,
+        "threeDsCompInd": "U",
+        "threeDsRequestor": {
+            "threeDsRequestorAuthenticationInd": "01",
+            "threeDsRequestorAuthenticationInfo": {
+                "threeDsReqAuthMethod": "01",
+                "threeDsReqAuthTimestamp": Utc::now().format("%Y%m%d%H%M").to_string()
+            },
+            "threeDsRequestorChallengeInd": "01"
+        },
+        "cardholderAccount": {
+            "acctType": "02",
+            "cardExpiryDate": "3012",
+            "schemeId": "VISA",
+            "acctNumber": card_number,
+            "cardSecurityCode": "123"
+        },
+        "cardholder": {
+            "addrMatch": "Y",
+            "billAddrCity": "Self Test",
+            "billAddrCountry": "978",
+            "billAddrLine1": "1 Test Street",
+            "billAddrLine2": "",
+            "billAddrLine3": "",
+            "billAddrPostCode": "00000",
+            "email": "self-test@example.com",
+            "homePhone": { "cc": "1", "subscriber": "5550100" },
+            "mobilePhone": { "cc": "1", "subscriber": "5550100" },
+            "workPhone": { "cc": "1", "subscriber": "5550100" },
+            "cardholderName": "Self Test",
+            "shipAddrCity": "Self Test",
+            "shipAddrCountry": "978",
+            "shipAddrLine1": "1 Test Street",
+            "shipAddrLine2": "",
+            "shipAddrLine3": "",
+            "shipAddrPostCode": "00000"
+        },
+        "purchase": {
+            "purchaseAmount": 100,
+            "purchaseCurrency": "978",
+            "purchaseExponent": 2,
+            "purchaseDate": Utc::now().format("%Y%m%d%H%M%S").to_string(),
+            "transType": "01"
+        },
+        "acquirer": {
+            "acquirerBin": "400000",
+            "acquirerMerchantId": "self-test"
+        },
+        "merchant": {
+            "mcc": "5999",
+            "merchantCountryCode": "978",
+            "threeDsRequestorId": "self-test",
+            "threeDsRequestorName": "self-test",
+            "merchantName": "Self Test",
+            "resultsResponseNotificationUrl": "http://localhost/self-test/results",
+            "notificationUrl": "http://localhost/self-test/notify"
+        },
+        "deviceRenderOptions": {
+            "sdkInterface": "03",
+            "sdkUiType": ["01", "02", "03", "04", "05"],
+            "sdkAuthenticationType": ["02"]
+        }
+    });
+
+    if let Some(sdk_keys) = sdk_keys {
+        areq["sdkTransId"] = json!(Uuid::new_v4());
+        areq["sdkEphemeralPublicKey"] = json!({
+            "kty": sdk_keys.public_key.kty,
+            "crv": sdk_keys.public_key.crv,
+            "x": sdk_keys.public_key.x,
+            "y": sdk_keys.public_key.y
+        });
+    }
+    areq
+}
+
+/// The ARes must be a challenge with signed content for the SDK
+fn check_ares(body: &Value) -> Result<(), String> {
+    let ares = &body["authenticationResponse"];
+    if ares["transStatus"] != "C" {
+        return Err(format!(
+            "expected transStatus C, got {}",
+            ares["transStatus"]
+        ));
+    }
+    if !ares["acsSignedContent"].is_string() {
+        return Err("ARes has no acsSignedContent".to_string());
+    }
+    Ok(())
+}
+
+/// Derive the CReq key on both sides and decrypt an SDK-encrypted CReq as the ACS would
+async fn jwe_round_trip(
+    transaction: &TransactionData,
+    sdk_keys: &EphemeralKeyPair,
+    settings: &Settings,
+) -> Result<(), String> {
+    let acs_keys = transaction
+        .ephemeral_keys
+        .as_ref()
+        .ok_or("transaction has no ACS ephemeral keys")?;
+    let jwk = |keys: &EphemeralKeyPair| {
+        json!({
+            "kty": keys.public_key.kty,
+            "crv": keys.public_key.crv,
+            "x": keys.public_key.x,
+            "y": keys.public_key.y
+        })
+        .to_string()
+    };
+
+    let sdk_reference_number = settings
+        .acs
+        .sdk_reference_number(
+            "android",
+            transaction
+                .authenticate_request
+                .sdk_reference_number
+                .as_deref(),
+        )
+        .unwrap_or_default();
+    let sdk_key =
+        calculate_derived_key(&jwk(acs_keys), &sdk_keys.private_key, sdk_reference_number)
+            .map_err(|e| e.to_string())?;
+    let acs_key =
+        calculate_derived_key(&jwk(sdk_keys), &acs_keys.private_key, sdk_reference_number)
+            .map_err(|e| e.to_string())?;
+
+    let creq = json!({
+        "messageType": "CReq",
+        "messageVersion": "2.2.0",
+        "acsTransID": transaction.acs_trans_id,
+        "sdkCounterStoA": "000"
+    });
+    let jwe = encrypt_challenge_response(
+        &creq,
+        &transaction.acs_trans_id.to_string(),
+        &sdk_key,
+        "android",
+    )
+    .await
+    .map_err(|e| e.to_string())?;
+    let decrypted = decrypt_challenge_request(&jwe, &acs_key)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if decrypted != creq {
+        return Err("decrypted CReq does not match".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{AuthenticateRequest, DeviceChannel};
+
+    #[test]
+    fn test_synthetic_areq_is_a_valid_mobile_areq() {
+        let sdk_keys = generate_ephemeral_key_pair().unwrap();
+        let areq = synthetic_areq(Uuid::new_v4(), SELF_TEST_CARD, Some(&sdk_keys));
+        let areq: AuthenticateRequest = serde_json::from_value(areq).unwrap();
+
+        assert_eq!(areq.device_channel, DeviceChannel::App);
+        assert!(areq.sdk_trans_id.is_some());
+        assert_eq!(areq.sdk_ephemeral_key().unwrap().x, sdk_keys.public_key.x);
+        assert!(crate::validation::validate_authenticate_request(&areq).is_ok());
+    }
+
+    #[test]
+    fn test_synthetic_areq_without_sdk_keys_is_a_browser_areq() {
+        let areq = synthetic_areq(Uuid::new_v4(), SELF_TEST_CARD, None);
+        let areq: AuthenticateRequest = serde_json::from_value(areq).unwrap();
+
+        assert_eq!(areq.device_channel, DeviceChannel::Browser);
+        assert!(areq.sdk_trans_id.is_none());
+        assert!(areq.sdk_ephemeral_key().is_none());
+    }
+}
@@ -2,11 +2,20 @@ use async_trait::async_trait;
 use uuid::Uuid;
 use serde::{Serialize, Deserialize};
 use deadpool_redis::{Config, Pool, Runtime};
+use tracing::{debug, info, warn};
+use moka::sync::Cache;
+use sqlx::any::AnyPoolOptions;
+use sqlx::AnyPool;
+use sqlx::Row;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use crate::config::Settings;
-use crate::models::{AuthenticateRequest, ResultsRequest};
+use crate::challenge::InitialUi;
+use crate::config::{Protocol23Fields, Settings, SuccessLiability, ValueFormat};
+use crate::models::{AuthenticateRequest, ChallengeUi, FrictionlessOutcome, MethodDeviceData, ResultsRequest};
 use crate::crypto::EphemeralKeyPair;
+use crate::pan::MaskedPan;
+use crate::recording::RecordedMessage;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TransactionData {
@@ -18,6 +27,53 @@ pub struct TransactionData {
     pub ephemeral_keys: Option<EphemeralKeyPair>,
     pub redirect_url: Option<String>,
     pub sdk_ephemeral_public_key: Option<String>, // SDK's public key for ECDH shared secret derivation
+    #[serde(default)]
+    pub authentication_type: Option<String>, // Challenge method negotiated at AReq time
+    #[serde(default)]
+    pub interaction_count: u32, // Cardholder challenge interactions so far
+    #[serde(default)]
+    pub challenge_ui: Option<ChallengeUi>, // UI last presented to the cardholder in a CRes
+    #[serde(default)]
+    pub spc_challenge: Option<String>, // Challenge the SPC assertion must sign (transStatus = S)
+    #[serde(default)]
+    pub three_ds_method_data: Option<MethodDeviceData>, // Browser device data from the 3DS Method
+    #[serde(default)]
+    pub frictionless_outcome: Option<FrictionlessOutcome>, // ARes outcome when no challenge followed
+    #[serde(default)]
+    pub message_version: Option<String>, // messageVersion negotiated for the AReq
+    #[serde(default)]
+    pub three_ds_method_completed: bool, // 3DS Method device fingerprinting finished before the AReq
+    #[serde(default)]
+    pub otp_attempts: u32, // Wrong OTPs entered in the challenge so far
+    #[serde(default)]
+    pub otp: Option<String>, // OTP the challenge accepts; None falls back to acs.otp.value
+    #[serde(default)]
+    pub oob_started_at_ms: Option<i64>, // When the OOB screen was first presented (Unix ms)
+    #[serde(default)]
+    pub oob_approved: Option<bool>, // Issuer decision on the OOB challenge from the admin API
+    #[serde(default)]
+    pub whitelisting_choice: Option<String>, // CReq whitelistingDataEntry: Y trusts the merchant, N doesn't
+    #[serde(default)]
+    pub request_id: Option<String>, // X-Request-ID of the AReq that created the transaction
+    #[serde(default)]
+    pub masked_pan: Option<MaskedPan>, // The stored AReq's acctNumber is masked the same way
+    #[serde(default)]
+    pub pan_hash: Option<String>, // SHA-256 of the full PAN, which is never stored
+    #[serde(default)]
+    pub initial_ui: Option<InitialUi>, // App challenge UI decided from the full PAN at AReq time
+    #[serde(default)]
+    pub protocol_2_3: Option<Protocol23Fields>, // 2.3 status fields decided at AReq time, for the RReq
+    #[serde(default)]
+    pub three_ds_session_data: Option<String>, // Browser CReq threeDSSessionData, posted back with the final CRes
+    #[serde(default)]
+    pub success_liability: Option<SuccessLiability>, // What a verified challenge reports, decided from the full PAN
+}
+
+impl TransactionData {
+    /// messageVersion the ACS answers this transaction's messages in
+    pub fn message_version(&self) -> &str {
+        self.message_version.as_deref().unwrap_or(crate::protocol::DEFAULT_MESSAGE_VERSION)
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -26,12 +82,64 @@ pub enum StateError {
     NotFound,
     #[error("Serialization error: {0}")]
     Serialization(#[from] serde_json::Error),
+    #[error("MessagePack encode error: {0}")]
+    MsgpackEncode(#[from] rmp_serde::encode::Error),
+    #[error("MessagePack decode error: {0}")]
+    MsgpackDecode(#[from] rmp_serde::decode::Error),
     #[error("Redis error: {0}")]
     Redis(#[from] deadpool_redis::redis::RedisError),
     #[error("Pool error: {0}")]
     Pool(#[from] deadpool_redis::PoolError),
+    #[error("SQL error: {0}")]
+    Sql(#[from] sqlx::Error),
     #[error("Connection error: {0}")]
     Connection(String),
+    #[error("Decode task failed: {0}")]
+    Decode(String),
+}
+
+// Leading byte of MessagePack values. 0xC1 is never emitted by MessagePack and cannot
+// start a JSON document, so unmarked values are read as the original JSON format.
+const MSGPACK_MARKER: u8 = 0xC1;
+
+fn encode_transaction(data: &TransactionData, format: ValueFormat) -> Result<Vec<u8>, StateError> {
+    match format {
+        ValueFormat::Json => Ok(serde_json::to_vec(data)?),
+        ValueFormat::Msgpack => {
+            let mut encoded = vec![MSGPACK_MARKER];
+            // Named fields, so #[serde(default)] fields keep working as the model grows
+            rmp_serde::encode::write_named(&mut encoded, data)?;
+            Ok(encoded)
+        }
+    }
+}
+
+fn decode_transaction(value: &[u8]) -> Result<TransactionData, StateError> {
+    match value.split_first() {
+        Some((&MSGPACK_MARKER, encoded)) => Ok(rmp_serde::from_slice(encoded)?),
+        _ => Ok(serde_json::from_slice(value)?),
+    }
+}
+
+// Outside the "{prefix}:*" pattern the fallback scan walks
+fn acs_index_key(key_prefix: &str, acs_trans_id: &Uuid) -> String {
+    format!("{}-acs:{}", key_prefix, acs_trans_id)
+}
+
+// A batch of scanned values being decoded on the blocking pool
+type DecodeTask = tokio::task::JoinHandle<Option<(Uuid, TransactionData)>>;
+
+// Decode one MGET batch and pick out the transaction with this acsTransID, keyed by the
+// threeDSServerTransID taken from its Redis key. Expired keys come back as None.
+fn find_in_batch(key_prefix: &str, keys: Vec<String>, values: Vec<Option<Vec<u8>>>, acs_trans_id: &Uuid) -> Option<(Uuid, TransactionData)> {
+    keys.into_iter().zip(values).find_map(|(key, value)| {
+        let transaction_data = decode_transaction(&value?).ok()?;
+        if transaction_data.acs_trans_id != *acs_trans_id {
+            return None;
+        }
+        let three_ds_server_trans_id = Uuid::parse_str(key.strip_prefix(key_prefix)?).ok()?;
+        Some((three_ds_server_trans_id, transaction_data))
+    })
 }
 
 #[async_trait]
@@ -41,51 +149,291 @@ pub trait StateStore: Send + Sync {
     async fn update(&self, key: &Uuid, data: TransactionData) -> Result<(), StateError>;
     async fn delete(&self, key: &Uuid) -> Result<(), StateError>;
     async fn find_by_acs_trans_id(&self, acs_trans_id: &Uuid) -> Result<Option<(Uuid, TransactionData)>, StateError>;
+    // 3DS Method device data arrives before the AReq creates the transaction, so it is kept apart
+    async fn insert_method_data(&self, key: Uuid, data: MethodDeviceData) -> Result<(), StateError>;
+    async fn get_method_data(&self, key: &Uuid) -> Result<Option<MethodDeviceData>, StateError>;
+    // Completion of a 3DS Method run through /3ds/method, which collects no browser device data
+    async fn mark_method_completed(&self, key: Uuid) -> Result<(), StateError>;
+    async fn method_completed(&self, key: &Uuid) -> Result<bool, StateError>;
+
+    // Message log per transaction, oldest first (see recording.rs)
+    async fn append_message(&self, key: Uuid, message: RecordedMessage) -> Result<(), StateError>;
+    async fn messages(&self, key: &Uuid) -> Result<Vec<RecordedMessage>, StateError>;
+
+    // Dependency name reported by deep health checks, and a single round trip to it
+    fn backend(&self) -> &'static str;
+    async fn ping(&self) -> Result<(), StateError>;
 }
 
 // Redis implementation with connection pooling (Redis-only state store)
 pub struct RedisStore {
     pool: Pool,
+    // Replica pool for transaction lookups; writes always use `pool`
+    read_pool: Option<Pool>,
+    replica_miss_delay: Duration,
     ttl_seconds: u64,
     key_prefix: String,
+    value_format: ValueFormat,
+    scan_batch_size: usize,
 }
 
 impl RedisStore {
     pub async fn new(settings: &Settings) -> Result<Self, StateError> {
         // Configure connection pool
-        let cfg = Config::from_url(&settings.redis.url);
-        let pool = cfg
-            .builder()
-            .map_err(|e| StateError::Connection(format!("Failed to create pool builder: {}", e)))?
-            .max_size(settings.redis.pool.max_size as usize)
-            .runtime(Runtime::Tokio1)
-            .build()
-            .map_err(|e| StateError::Connection(format!("Failed to create connection pool: {}", e)))?;
+        let pool = Self::build_pool(&settings.redis.url, settings.redis.pool.max_size)?;
         
-        // Test the connection pool
-        let mut conn = pool.get().await?;
-        
-        // Simple ping test
-        let _: String = deadpool_redis::redis::cmd("PING")
-            .query_async(&mut *conn)
-            .await?;
+        // Pre-establish min_idle connections (this also tests the pool) so the first
+        // burst of traffic after startup doesn't pay connection-setup latency
+        let min_idle = (settings.redis.pool.min_idle as usize).max(1);
+        let warmed = Self::warm_pool(&pool, min_idle).await?;
+
+        info!(
+            url = %settings.redis.url,
+            pool_size = settings.redis.pool.max_size,
+            min_idle = settings.redis.pool.min_idle,
+            warmed,
+            ttl_seconds = settings.redis.ttl_seconds,
+            key_prefix = %settings.redis.key_prefix,
+            value_format = ?settings.redis.value_format,
+            "Redis connection pool established"
+        );
 
-        println!("✅ Redis connection pool established: {}", settings.redis.url);
-        println!("📊 Pool size: {} (min idle: {})", settings.redis.pool.max_size, settings.redis.pool.min_idle);
-        println!("📝 Transaction TTL: {} seconds", settings.redis.ttl_seconds);
-        println!("🔑 Key prefix: {}", settings.redis.key_prefix);
+        // Keep the idle connections warm, re-opening any the pool has dropped
+        let keepalive_interval = Duration::from_secs((settings.redis.pool.idle_timeout_seconds / 2).max(1));
+        tokio::spawn(Self::keep_warm(pool.clone(), min_idle, keepalive_interval));
+
+        // Lookups go to the read replica when one is configured, with the same pool sizing
+        let replica = &settings.redis.replica;
+        let read_pool = match &replica.url {
+            Some(url) => {
+                let read_pool = Self::build_pool(url, settings.redis.pool.max_size)?;
+                let warmed = Self::warm_pool(&read_pool, min_idle).await?;
+                info!(url = %url, warmed, miss_retry_delay_ms = replica.miss_retry_delay_ms, "Redis read replica connected");
+                tokio::spawn(Self::keep_warm(read_pool.clone(), min_idle, keepalive_interval));
+                Some(read_pool)
+            }
+            None => None,
+        };
 
         Ok(Self {
             pool,
+            read_pool,
+            replica_miss_delay: Duration::from_millis(replica.miss_retry_delay_ms),
             ttl_seconds: settings.redis.ttl_seconds,
             key_prefix: settings.redis.key_prefix.clone(),
+            value_format: settings.redis.value_format,
+            scan_batch_size: settings.redis.scan_batch_size,
         })
     }
 
+    fn build_pool(url: &str, max_size: u32) -> Result<Pool, StateError> {
+        Config::from_url(url)
+            .builder()
+            .map_err(|e| StateError::Connection(format!("Failed to create pool builder: {}", e)))?
+            .max_size(max_size as usize)
+            .runtime(Runtime::Tokio1)
+            .build()
+            .map_err(|e| StateError::Connection(format!("Failed to create connection pool: {}", e)))
+    }
+
+    // Check out `min_idle` connections at once, forcing the pool to open them, and PING
+    // each before releasing them back as idle connections
+    async fn warm_pool(pool: &Pool, min_idle: usize) -> Result<usize, StateError> {
+        let mut conns = Vec::with_capacity(min_idle);
+        for _ in 0..min_idle {
+            let mut conn = pool.get().await?;
+            let _: String = deadpool_redis::redis::cmd("PING")
+                .query_async(&mut *conn)
+                .await?;
+            conns.push(conn);
+        }
+        Ok(conns.len())
+    }
+
+    // Background task topping the pool back up to `min_idle` connections. Checking
+    // connections out also runs deadpool's recycle check, so stale ones get replaced.
+    async fn keep_warm(pool: Pool, min_idle: usize, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await; // first tick completes immediately, warm_pool just ran
+
+        loop {
+            ticker.tick().await;
+
+            let status = pool.status();
+            // Connections currently in use are warm already - only top up the idle ones
+            let in_use = status.size.saturating_sub(status.available);
+            let wanted = min_idle.min(status.max_size.saturating_sub(in_use));
+            if wanted == 0 {
+                continue;
+            }
+
+            match Self::warm_pool(&pool, wanted).await {
+                Ok(_) => debug!(idle_connections = wanted, "Redis pool kept warm"),
+                Err(e) => warn!(error = %e, "Redis pool warmup failed"),
+            }
+        }
+    }
+
     fn make_key(&self, key: &Uuid) -> String {
         format!("{}:{}", self.key_prefix, key)
     }
 
+    // acsTransID -> threeDSServerTransID entry, kept next to the transaction with the same TTL
+    fn make_acs_index_key(&self, acs_trans_id: &Uuid) -> String {
+        acs_index_key(&self.key_prefix, acs_trans_id)
+    }
+
+    // Outside the "{prefix}:*" pattern scanned by find_by_acs_trans_id
+    fn make_method_key(&self, key: &Uuid) -> String {
+        format!("{}-method:{}", self.key_prefix, key)
+    }
+
+    fn make_method_completed_key(&self, key: &Uuid) -> String {
+        format!("{}-method-completed:{}", self.key_prefix, key)
+    }
+
+    fn make_messages_key(&self, key: &Uuid) -> String {
+        format!("{}-messages:{}", self.key_prefix, key)
+    }
+
+    // Lookups read the replica when one is configured. A miss there may only be replication
+    // lag, so it is re-read from the primary after a short delay; replica errors also fall
+    // back to the primary.
+    async fn read<'a, F, Fut, R>(&'a self, lookup: F) -> Result<Option<R>, StateError>
+    where
+        F: Fn(&'a Pool) -> Fut,
+        Fut: std::future::Future<Output = Result<Option<R>, StateError>>,
+    {
+        if let Some(read_pool) = &self.read_pool {
+            match lookup(read_pool).await {
+                Ok(Some(found)) => return Ok(Some(found)),
+                Ok(None) => {
+                    debug!("Redis replica miss, re-reading from the primary");
+                    tokio::time::sleep(self.replica_miss_delay).await;
+                }
+                Err(e) => warn!(error = %e, "Redis replica read failed, using the primary"),
+            }
+        }
+
+        lookup(&self.pool).await
+    }
+
+    // GET a transaction from one of the pools
+    async fn get_from(&self, pool: &Pool, redis_key: &str) -> Result<Option<TransactionData>, StateError> {
+        self.with_retry(|| async {
+            let mut conn = pool.get().await?;
+            
+            let result: Option<Vec<u8>> = deadpool_redis::redis::cmd("GET")
+                .arg(redis_key)
+                .query_async(&mut *conn)
+                .await?;
+            
+            match result {
+                Some(value) => {
+                    let data = decode_transaction(&value)?;
+                    Ok(Some(data))
+                }
+                None => Ok(None),
+            }
+        }).await
+    }
+
+    // Look a transaction up in one of the pools through its acsTransID index entry. An entry
+    // can outlive a deleted transaction, so the transaction must still carry the acsTransID.
+    async fn find_indexed_in(&self, pool: &Pool, acs_trans_id: &Uuid) -> Result<Option<(Uuid, TransactionData)>, StateError> {
+        let index_key = self.make_acs_index_key(acs_trans_id);
+        let indexed: Option<String> = self.with_retry(|| async {
+            let mut conn = pool.get().await?;
+            Ok(deadpool_redis::redis::cmd("GET")
+                .arg(&index_key)
+                .query_async(&mut *conn)
+                .await?)
+        }).await?;
+        let Some(three_ds_server_trans_id) = indexed.and_then(|id| Uuid::parse_str(&id).ok()) else {
+            return Ok(None);
+        };
+
+        let found = self
+            .get_from(pool, &self.make_key(&three_ds_server_trans_id))
+            .await?
+            .filter(|data| data.acs_trans_id == *acs_trans_id)
+            .map(|data| (three_ds_server_trans_id, data));
+        if found.is_some() {
+            debug!(%three_ds_server_trans_id, %acs_trans_id, "found transaction through the acsTransID index");
+        }
+        Ok(found)
+    }
+
+    // Fallback for transactions without an index entry: scan one of the pools for the
+    // transaction with this acsTransID. Keys are walked with SCAN
+    // and fetched with one MGET per batch; each batch is decoded on the blocking pool while the
+    // next one is fetched.
+    async fn find_in(&self, pool: &Pool, acs_trans_id: &Uuid) -> Result<Option<(Uuid, TransactionData)>, StateError> {
+        debug!(%acs_trans_id, key_prefix = %self.key_prefix, batch_size = self.scan_batch_size, "scanning Redis for transaction by acsTransID");
+        
+        self.with_retry(|| async {
+            let mut conn = pool.get().await?;
+            let pattern = format!("{}:*", self.key_prefix);
+            let mut cursor: u64 = 0;
+            let mut scanned = 0;
+            let mut decoding: Option<DecodeTask> = None;
+            
+            loop {
+                let (next_cursor, keys): (u64, Vec<String>) = deadpool_redis::redis::cmd("SCAN")
+                    .arg(cursor)
+                    .arg("MATCH")
+                    .arg(&pattern)
+                    .arg("COUNT")
+                    .arg(self.scan_batch_size)
+                    .query_async(&mut *conn)
+                    .await?;
+                
+                // SCAN may return an empty page while the cursor is still running
+                let fetched = if keys.is_empty() {
+                    None
+                } else {
+                    let values: Vec<Option<Vec<u8>>> = deadpool_redis::redis::cmd("MGET")
+                        .arg(&keys)
+                        .query_async(&mut *conn)
+                        .await?;
+                    scanned += keys.len();
+                    let key_prefix = format!("{}:", self.key_prefix);
+                    let acs_trans_id = *acs_trans_id;
+                    Some(tokio::task::spawn_blocking(move || find_in_batch(&key_prefix, keys, values, &acs_trans_id)))
+                };
+                
+                // The previous batch was decoding while this one was fetched
+                if let Some(found) = Self::finish_decoding(decoding.take()).await? {
+                    return Ok(Some(found));
+                }
+                decoding = fetched;
+                
+                cursor = next_cursor;
+                if cursor == 0 {
+                    break;
+                }
+            }
+            
+            if let Some(found) = Self::finish_decoding(decoding).await? {
+                return Ok(Some(found));
+            }
+            
+            debug!(%acs_trans_id, scanned, "no transaction found with acsTransID");
+            Ok(None)
+        }).await
+    }
+
+    async fn finish_decoding(decoding: Option<DecodeTask>) -> Result<Option<(Uuid, TransactionData)>, StateError> {
+        let Some(task) = decoding else {
+            return Ok(None);
+        };
+        let found = task.await.map_err(|e| StateError::Decode(e.to_string()))?;
+        if let Some((three_ds_server_trans_id, transaction_data)) = &found {
+            debug!(%three_ds_server_trans_id, acs_trans_id = %transaction_data.acs_trans_id, "found transaction by acsTransID");
+        }
+        Ok(found)
+    }
+
     // Simple retry mechanism for Redis operations
     async fn with_retry<F, Fut, R>(&self, operation: F) -> Result<R, StateError>
     where
@@ -116,18 +464,21 @@ impl StateStore for RedisStore {
         let redis_key = self.make_key(&key);
         let ttl_seconds = self.ttl_seconds;
         
+        let index_key = self.make_acs_index_key(&data.acs_trans_id);
+        
         self.with_retry(|| async {
             let mut conn = self.pool.get().await?;
-            let serialized_data = serde_json::to_string(&data)?;
+            let serialized_data = encode_transaction(&data, self.value_format)?;
             
-            deadpool_redis::redis::cmd("SETEX")
-                .arg(&redis_key)
-                .arg(ttl_seconds)
-                .arg(&serialized_data)
+            // The transaction and its acsTransID index entry are written together
+            deadpool_redis::redis::pipe()
+                .atomic()
+                .cmd("SETEX").arg(&redis_key).arg(ttl_seconds).arg(&serialized_data).ignore()
+                .cmd("SETEX").arg(&index_key).arg(ttl_seconds).arg(key.to_string()).ignore()
                 .query_async::<_, ()>(&mut *conn)
                 .await?;
             
-            println!("📦 Transaction stored in Redis: {} (TTL: {} seconds)", key, ttl_seconds);
+            debug!(three_ds_server_trans_id = %key, ttl_seconds, "transaction stored in Redis");
             
             Ok(())
         }).await
@@ -135,27 +486,12 @@ impl StateStore for RedisStore {
 
     async fn get(&self, key: &Uuid) -> Result<Option<TransactionData>, StateError> {
         let redis_key = self.make_key(key);
-        
-        self.with_retry(|| async {
-            let mut conn = self.pool.get().await?;
-            
-            let result: Option<String> = deadpool_redis::redis::cmd("GET")
-                .arg(&redis_key)
-                .query_async(&mut *conn)
-                .await?;
-            
-            match result {
-                Some(data_str) => {
-                    let data: TransactionData = serde_json::from_str(&data_str)?;
-                    Ok(Some(data))
-                }
-                None => Ok(None),
-            }
-        }).await
+        self.read(|pool| self.get_from(pool, &redis_key)).await
     }
 
     async fn update(&self, key: &Uuid, data: TransactionData) -> Result<(), StateError> {
         let redis_key = self.make_key(key);
+        let index_key = self.make_acs_index_key(&data.acs_trans_id);
         let ttl_seconds = self.ttl_seconds;
         
         self.with_retry(|| async {
@@ -171,12 +507,14 @@ impl StateStore for RedisStore {
                 return Err(StateError::NotFound);
             }
             
-            let serialized_data = serde_json::to_string(&data)?;
+            let serialized_data = encode_transaction(&data, self.value_format)?;
             
-            deadpool_redis::redis::cmd("SETEX")
-                .arg(&redis_key)
-                .arg(ttl_seconds)
-                .arg(&serialized_data)
+            // Re-writing the index entry keeps its TTL in step, and indexes transactions
+            // stored before the index existed
+            deadpool_redis::redis::pipe()
+                .atomic()
+                .cmd("SETEX").arg(&redis_key).arg(ttl_seconds).arg(&serialized_data).ignore()
+                .cmd("SETEX").arg(&index_key).arg(ttl_seconds).arg(key.to_string()).ignore()
                 .query_async::<_, ()>(&mut *conn)
                 .await?;
             
@@ -186,12 +524,18 @@ impl StateStore for RedisStore {
 
     async fn delete(&self, key: &Uuid) -> Result<(), StateError> {
         let redis_key = self.make_key(key);
+        // The index entry is named by the acsTransID, which only the stored value knows
+        let index_key = self
+            .get_from(&self.pool, &redis_key)
+            .await?
+            .map(|data| self.make_acs_index_key(&data.acs_trans_id));
         
         self.with_retry(|| async {
             let mut conn = self.pool.get().await?;
             
             deadpool_redis::redis::cmd("DEL")
                 .arg(&redis_key)
+                .arg(index_key.as_slice())
                 .query_async::<_, ()>(&mut *conn)
                 .await?;
             
@@ -200,51 +544,811 @@ impl StateStore for RedisStore {
     }
 
     async fn find_by_acs_trans_id(&self, acs_trans_id: &Uuid) -> Result<Option<(Uuid, TransactionData)>, StateError> {
-        println!("🔍 Searching Redis for transaction by acsTransID: {}", acs_trans_id);
-        println!("  📊 Scanning keys with pattern: {}:*", self.key_prefix);
-        
+        if let Some(found) = self.read(|pool| self.find_indexed_in(pool, acs_trans_id)).await? {
+            return Ok(Some(found));
+        }
+        // Only transactions stored before the index existed lack an entry; the scan for them
+        // runs once, on the primary, which holds everything the replica does
+        self.find_in(&self.pool, acs_trans_id).await
+    }
+
+    async fn insert_method_data(&self, key: Uuid, data: MethodDeviceData) -> Result<(), StateError> {
+        let redis_key = self.make_method_key(&key);
+        let ttl_seconds = self.ttl_seconds;
+
         self.with_retry(|| async {
             let mut conn = self.pool.get().await?;
-            
-            // Get all keys matching our pattern
-            let pattern = format!("{}:*", self.key_prefix);
-            let keys: Vec<String> = deadpool_redis::redis::cmd("KEYS")
-                .arg(&pattern)
+            let serialized_data = serde_json::to_vec(&data)?;
+
+            deadpool_redis::redis::cmd("SETEX")
+                .arg(&redis_key)
+                .arg(ttl_seconds)
+                .arg(&serialized_data)
+                .query_async::<_, ()>(&mut *conn)
+                .await?;
+
+            debug!(three_ds_server_trans_id = %key, "3DS Method device data stored in Redis");
+            Ok(())
+        }).await
+    }
+
+    async fn get_method_data(&self, key: &Uuid) -> Result<Option<MethodDeviceData>, StateError> {
+        let redis_key = self.make_method_key(key);
+
+        self.with_retry(|| async {
+            let mut conn = self.pool.get().await?;
+
+            let result: Option<Vec<u8>> = deadpool_redis::redis::cmd("GET")
+                .arg(&redis_key)
                 .query_async(&mut *conn)
                 .await?;
-            
-            println!("  📋 Found {} total keys to check", keys.len());
-            
-            // Search through all transactions
-            for key in keys {
-                let result: Option<String> = deadpool_redis::redis::cmd("GET")
-                    .arg(&key)
-                    .query_async(&mut *conn)
-                    .await?;
-                
-                if let Some(data_str) = result {
-                    if let Ok(transaction_data) = serde_json::from_str::<TransactionData>(&data_str) {
-                        if transaction_data.acs_trans_id == *acs_trans_id {
-                            // Extract the threeDSServerTransID from the key
-                            if let Some(uuid_str) = key.strip_prefix(&format!("{}:", self.key_prefix)) {
-                                if let Ok(three_ds_server_trans_id) = Uuid::parse_str(uuid_str) {
-                                    println!("  ✅ Found matching transaction: threeDSServerTransID={}, acsTransID={}", 
-                                             three_ds_server_trans_id, acs_trans_id);
-                                    return Ok(Some((three_ds_server_trans_id, transaction_data)));
-                                }
-                            }
-                        }
-                    }
-                }
+
+            match result {
+                Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+                None => Ok(None),
             }
-            
-            println!("  ❌ No transaction found with acsTransID: {}", acs_trans_id);
-            Ok(None)
         }).await
     }
+
+    async fn mark_method_completed(&self, key: Uuid) -> Result<(), StateError> {
+        let redis_key = self.make_method_completed_key(&key);
+        let ttl_seconds = self.ttl_seconds;
+
+        self.with_retry(|| async {
+            let mut conn = self.pool.get().await?;
+
+            deadpool_redis::redis::cmd("SETEX")
+                .arg(&redis_key)
+                .arg(ttl_seconds)
+                .arg("1")
+                .query_async::<_, ()>(&mut *conn)
+                .await?;
+
+            debug!(three_ds_server_trans_id = %key, "3DS Method completion stored in Redis");
+            Ok(())
+        }).await
+    }
+
+    async fn method_completed(&self, key: &Uuid) -> Result<bool, StateError> {
+        let redis_key = self.make_method_completed_key(key);
+
+        self.with_retry(|| async {
+            let mut conn = self.pool.get().await?;
+
+            let exists: bool = deadpool_redis::redis::cmd("EXISTS")
+                .arg(&redis_key)
+                .query_async(&mut *conn)
+                .await?;
+
+            Ok(exists)
+        }).await
+    }
+
+    async fn append_message(&self, key: Uuid, message: RecordedMessage) -> Result<(), StateError> {
+        let redis_key = self.make_messages_key(&key);
+        let ttl_seconds = self.ttl_seconds;
+        let serialized_message = serde_json::to_vec(&message)?;
+
+        self.with_retry(|| async {
+            let mut conn = self.pool.get().await?;
+
+            // The log expires along with the transaction
+            deadpool_redis::redis::pipe()
+                .cmd("RPUSH").arg(&redis_key).arg(&serialized_message).ignore()
+                .cmd("EXPIRE").arg(&redis_key).arg(ttl_seconds).ignore()
+                .query_async::<_, ()>(&mut *conn)
+                .await?;
+
+            Ok(())
+        }).await
+    }
+
+    async fn messages(&self, key: &Uuid) -> Result<Vec<RecordedMessage>, StateError> {
+        let redis_key = self.make_messages_key(key);
+
+        self.with_retry(|| async {
+            let mut conn = self.pool.get().await?;
+
+            let values: Vec<Vec<u8>> = deadpool_redis::redis::cmd("LRANGE")
+                .arg(&redis_key)
+                .arg(0)
+                .arg(-1)
+                .query_async(&mut *conn)
+                .await?;
+
+            values.iter().map(|value| Ok(serde_json::from_slice(value)?)).collect()
+        }).await
+    }
+
+    fn backend(&self) -> &'static str {
+        "redis"
+    }
+
+    // No retries: a health check should see the primary as it is right now
+    async fn ping(&self) -> Result<(), StateError> {
+        let mut conn = self.pool.get().await?;
+        deadpool_redis::redis::cmd("PING").query_async::<_, ()>(&mut *conn).await?;
+        Ok(())
+    }
+}
+
+// In-process implementation for local runs without Redis. Entries expire after
+// `state.memory_ttl_seconds` (the Redis TTL by default), the least used are evicted past
+// `state.memory_max_transactions`, and all are lost when the process exits.
+pub struct InMemoryStore {
+    transactions: Cache<Uuid, TransactionData>,
+    method_data: Cache<Uuid, MethodDeviceData>,
+    method_completions: Cache<Uuid, ()>,
+    messages: Cache<Uuid, Arc<Mutex<Vec<RecordedMessage>>>>,
+}
+
+impl InMemoryStore {
+    pub fn new(settings: &Settings) -> Self {
+        let ttl_seconds = settings
+            .state
+            .memory_ttl_seconds
+            .unwrap_or(settings.redis.ttl_seconds);
+        let ttl = Duration::from_secs(ttl_seconds);
+        let capacity = settings.state.memory_max_transactions;
+
+        info!(
+            ttl_seconds,
+            max_transactions = capacity,
+            "in-memory state store (transactions are lost on restart)"
+        );
+
+        // moka drops expired entries and evicts past the capacity on its own, without a sweeper
+        Self {
+            transactions: Cache::builder().max_capacity(capacity).time_to_live(ttl).build(),
+            method_data: Cache::builder().max_capacity(capacity).time_to_live(ttl).build(),
+            method_completions: Cache::builder().max_capacity(capacity).time_to_live(ttl).build(),
+            messages: Cache::builder().max_capacity(capacity).time_to_live(ttl).build(),
+        }
+    }
+}
+
+#[async_trait]
+impl StateStore for InMemoryStore {
+    async fn insert(&self, key: Uuid, data: TransactionData) -> Result<(), StateError> {
+        self.transactions.insert(key, data);
+        debug!(three_ds_server_trans_id = %key, "transaction stored in memory");
+        Ok(())
+    }
+
+    async fn get(&self, key: &Uuid) -> Result<Option<TransactionData>, StateError> {
+        Ok(self.transactions.get(key))
+    }
+
+    async fn update(&self, key: &Uuid, data: TransactionData) -> Result<(), StateError> {
+        if !self.transactions.contains_key(key) {
+            return Err(StateError::NotFound);
+        }
+        self.transactions.insert(*key, data);
+        Ok(())
+    }
+
+    async fn delete(&self, key: &Uuid) -> Result<(), StateError> {
+        self.transactions.invalidate(key);
+        Ok(())
+    }
+
+    async fn find_by_acs_trans_id(&self, acs_trans_id: &Uuid) -> Result<Option<(Uuid, TransactionData)>, StateError> {
+        Ok(self
+            .transactions
+            .iter()
+            .find(|(_, data)| data.acs_trans_id == *acs_trans_id)
+            .map(|(key, data)| (*key, data)))
+    }
+
+    async fn insert_method_data(&self, key: Uuid, data: MethodDeviceData) -> Result<(), StateError> {
+        self.method_data.insert(key, data);
+        Ok(())
+    }
+
+    async fn get_method_data(&self, key: &Uuid) -> Result<Option<MethodDeviceData>, StateError> {
+        Ok(self.method_data.get(key))
+    }
+
+    async fn mark_method_completed(&self, key: Uuid) -> Result<(), StateError> {
+        self.method_completions.insert(key, ());
+        Ok(())
+    }
+
+    async fn method_completed(&self, key: &Uuid) -> Result<bool, StateError> {
+        Ok(self.method_completions.contains_key(key))
+    }
+
+    async fn append_message(&self, key: Uuid, message: RecordedMessage) -> Result<(), StateError> {
+        self.messages.get_with(key, Default::default).lock().expect("message log lock poisoned").push(message);
+        Ok(())
+    }
+
+    async fn messages(&self, key: &Uuid) -> Result<Vec<RecordedMessage>, StateError> {
+        Ok(self.messages.get(key).map(|log| log.lock().expect("message log lock poisoned").clone()).unwrap_or_default())
+    }
+
+    fn backend(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn ping(&self) -> Result<(), StateError> {
+        Ok(())
+    }
+}
+
+// Postgres or SQLite implementation for runs that keep transactions across restarts.
+// The schema is migrated on startup; rows are kept until deleted rather than expiring.
+pub struct SqlStore {
+    pool: AnyPool,
+    value_format: ValueFormat,
+}
+
+// Schema versions, applied in order and recorded in schema_migrations. `{blob}` and `{serial}`
+// stand for the database's binary column type and auto-incrementing key (see column_types).
+const SQL_MIGRATIONS: &[(i64, &[&str])] = &[
+    (1, &[
+        "CREATE TABLE transactions (three_ds_server_trans_id TEXT PRIMARY KEY, acs_trans_id TEXT NOT NULL, data {blob} NOT NULL, created_at BIGINT NOT NULL, updated_at BIGINT NOT NULL)",
+        "CREATE INDEX idx_transactions_acs_trans_id ON transactions (acs_trans_id)",
+        "CREATE TABLE method_data (three_ds_server_trans_id TEXT PRIMARY KEY, data {blob} NOT NULL, created_at BIGINT NOT NULL)",
+        "CREATE TABLE method_completions (three_ds_server_trans_id TEXT PRIMARY KEY, completed_at BIGINT NOT NULL)",
+    ]),
+    (2, &[
+        "CREATE TABLE transaction_messages (id {serial}, three_ds_server_trans_id TEXT NOT NULL, data {blob} NOT NULL, recorded_at BIGINT NOT NULL)",
+        "CREATE INDEX idx_transaction_messages_trans_id ON transaction_messages (three_ds_server_trans_id)",
+    ]),
+];
+
+// Replacements for the column type placeholders in SQL_MIGRATIONS
+fn column_types(url: &str) -> [(&'static str, &'static str); 2] {
+    if url.starts_with("sqlite:") {
+        [("{blob}", "BLOB"), ("{serial}", "INTEGER PRIMARY KEY AUTOINCREMENT")]
+    } else {
+        [("{blob}", "BYTEA"), ("{serial}", "BIGSERIAL PRIMARY KEY")]
+    }
+}
+
+impl SqlStore {
+    pub async fn new(settings: &Settings) -> Result<Self, StateError> {
+        let sql = &settings.state.sql;
+        sqlx::any::install_default_drivers();
+
+        let pool = AnyPoolOptions::new()
+            .max_connections(sql.max_connections)
+            .acquire_timeout(Duration::from_secs(sql.connection_timeout_seconds))
+            .connect(&sql.url)
+            .await?;
+
+        let applied = Self::migrate(&pool, &column_types(&sql.url)).await?;
+
+        info!(
+            url = %sql.url,
+            pool_size = sql.max_connections,
+            migrations_applied = applied,
+            value_format = ?settings.redis.value_format,
+            "SQL state store connected"
+        );
+
+        Ok(Self {
+            pool,
+            value_format: settings.redis.value_format,
+        })
+    }
+
+    // Apply the migrations not yet recorded, each in its own database transaction
+    async fn migrate(pool: &AnyPool, column_types: &[(&str, &str)]) -> Result<usize, StateError> {
+        sqlx::query("CREATE TABLE IF NOT EXISTS schema_migrations (version BIGINT PRIMARY KEY, applied_at BIGINT NOT NULL)")
+            .execute(pool)
+            .await?;
+
+        let applied = sqlx::query("SELECT version FROM schema_migrations")
+            .fetch_all(pool)
+            .await?
+            .iter()
+            .map(|row| row.try_get::<i64, _>(0))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut count = 0;
+        for (version, statements) in SQL_MIGRATIONS.iter().filter(|(version, _)| !applied.contains(version)) {
+            let mut tx = pool.begin().await?;
+            for statement in statements.iter() {
+                let statement = column_types
+                    .iter()
+                    .fold(statement.to_string(), |statement, (placeholder, column_type)| statement.replace(placeholder, column_type));
+                sqlx::query(&statement).execute(&mut *tx).await?;
+            }
+            sqlx::query("INSERT INTO schema_migrations (version, applied_at) VALUES ($1, $2)")
+                .bind(*version)
+                .bind(chrono::Utc::now().timestamp())
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+
+            debug!(version, "SQL schema migration applied");
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+#[async_trait]
+impl StateStore for SqlStore {
+    async fn insert(&self, key: Uuid, data: TransactionData) -> Result<(), StateError> {
+        let value = encode_transaction(&data, self.value_format)?;
+        let now = chrono::Utc::now().timestamp();
+
+        sqlx::query(
+            "INSERT INTO transactions (three_ds_server_trans_id, acs_trans_id, data, created_at, updated_at) VALUES ($1, $2, $3, $4, $5) \
+             ON CONFLICT (three_ds_server_trans_id) DO UPDATE SET acs_trans_id = excluded.acs_trans_id, data = excluded.data, updated_at = excluded.updated_at",
+        )
+        .bind(key.to_string())
+        .bind(data.acs_trans_id.to_string())
+        .bind(value)
+        .bind(now)
+        .bind(now)
+        .execute(&self.pool)
+        .await?;
+
+        debug!(three_ds_server_trans_id = %key, "transaction stored in SQL");
+        Ok(())
+    }
+
+    async fn get(&self, key: &Uuid) -> Result<Option<TransactionData>, StateError> {
+        let row = sqlx::query("SELECT data FROM transactions WHERE three_ds_server_trans_id = $1")
+            .bind(key.to_string())
+            .fetch_optional(&self.pool)
+            .await?;
+
+        match row {
+            Some(row) => Ok(Some(decode_transaction(&row.try_get::<Vec<u8>, _>("data")?)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn update(&self, key: &Uuid, data: TransactionData) -> Result<(), StateError> {
+        let value = encode_transaction(&data, self.value_format)?;
+
+        let result = sqlx::query("UPDATE transactions SET acs_trans_id = $1, data = $2, updated_at = $3 WHERE three_ds_server_trans_id = $4")
+            .bind(data.acs_trans_id.to_string())
+            .bind(value)
+            .bind(chrono::Utc::now().timestamp())
+            .bind(key.to_string())
+            .execute(&self.pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(StateError::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, key: &Uuid) -> Result<(), StateError> {
+        // The 3DS Method rows and recorded messages go with the transaction, all or nothing
+        let mut tx = self.pool.begin().await?;
+        for table in ["transactions", "method_data", "method_completions", "transaction_messages"] {
+            sqlx::query(&format!("DELETE FROM {table} WHERE three_ds_server_trans_id = $1"))
+                .bind(key.to_string())
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn find_by_acs_trans_id(&self, acs_trans_id: &Uuid) -> Result<Option<(Uuid, TransactionData)>, StateError> {
+        // Served by idx_transactions_acs_trans_id rather than a scan of every transaction
+        let row = sqlx::query("SELECT three_ds_server_trans_id, data FROM transactions WHERE acs_trans_id = $1 LIMIT 1")
+            .bind(acs_trans_id.to_string())
+            .fetch_optional(&self.pool)
+            .await?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let key = Uuid::parse_str(&row.try_get::<String, _>("three_ds_server_trans_id")?)
+            .map_err(|e| StateError::Decode(e.to_string()))?;
+        Ok(Some((key, decode_transaction(&row.try_get::<Vec<u8>, _>("data")?)?)))
+    }
+
+    async fn insert_method_data(&self, key: Uuid, data: MethodDeviceData) -> Result<(), StateError> {
+        sqlx::query(
+            "INSERT INTO method_data (three_ds_server_trans_id, data, created_at) VALUES ($1, $2, $3) \
+             ON CONFLICT (three_ds_server_trans_id) DO UPDATE SET data = excluded.data",
+        )
+        .bind(key.to_string())
+        .bind(serde_json::to_vec(&data)?)
+        .bind(chrono::Utc::now().timestamp())
+        .execute(&self.pool)
+        .await?;
+
+        debug!(three_ds_server_trans_id = %key, "3DS Method device data stored in SQL");
+        Ok(())
+    }
+
+    async fn get_method_data(&self, key: &Uuid) -> Result<Option<MethodDeviceData>, StateError> {
+        let row = sqlx::query("SELECT data FROM method_data WHERE three_ds_server_trans_id = $1")
+            .bind(key.to_string())
+            .fetch_optional(&self.pool)
+            .await?;
+
+        match row {
+            Some(row) => Ok(Some(serde_json::from_slice(&row.try_get::<Vec<u8>, _>("data")?)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn mark_method_completed(&self, key: Uuid) -> Result<(), StateError> {
+        sqlx::query("INSERT INTO method_completions (three_ds_server_trans_id, completed_at) VALUES ($1, $2) ON CONFLICT (three_ds_server_trans_id) DO NOTHING")
+            .bind(key.to_string())
+            .bind(chrono::Utc::now().timestamp())
+            .execute(&self.pool)
+            .await?;
+
+        debug!(three_ds_server_trans_id = %key, "3DS Method completion stored in SQL");
+        Ok(())
+    }
+
+    async fn method_completed(&self, key: &Uuid) -> Result<bool, StateError> {
+        let row = sqlx::query("SELECT 1 FROM method_completions WHERE three_ds_server_trans_id = $1")
+            .bind(key.to_string())
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.is_some())
+    }
+
+    async fn append_message(&self, key: Uuid, message: RecordedMessage) -> Result<(), StateError> {
+        sqlx::query("INSERT INTO transaction_messages (three_ds_server_trans_id, data, recorded_at) VALUES ($1, $2, $3)")
+            .bind(key.to_string())
+            .bind(serde_json::to_vec(&message)?)
+            .bind(message.recorded_at.timestamp())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn messages(&self, key: &Uuid) -> Result<Vec<RecordedMessage>, StateError> {
+        sqlx::query("SELECT data FROM transaction_messages WHERE three_ds_server_trans_id = $1 ORDER BY id")
+            .bind(key.to_string())
+            .fetch_all(&self.pool)
+            .await?
+            .iter()
+            .map(|row| Ok(serde_json::from_slice::<RecordedMessage>(&row.try_get::<Vec<u8>, _>("data")?)?))
+            .collect()
+    }
+
+    fn backend(&self) -> &'static str {
+        "sql"
+    }
+
+    async fn ping(&self) -> Result<(), StateError> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
 }
 
 // Factory function to create Redis store from settings
 pub async fn create_redis_store(settings: &Settings) -> Result<RedisStore, StateError> {
     RedisStore::new(settings).await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::test_settings;
+    use crate::models::tests::sample_authenticate_request;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    fn sample_transaction() -> TransactionData {
+        TransactionData {
+            authenticate_request: sample_authenticate_request(),
+            acs_trans_id: Uuid::new_v4(),
+            ds_trans_id: Uuid::new_v4(),
+            sdk_trans_id: None,
+            results_request: None,
+            ephemeral_keys: None,
+            redirect_url: Some("https://example.com/return".to_string()),
+            sdk_ephemeral_public_key: None,
+            authentication_type: Some("02".to_string()),
+            interaction_count: 1,
+            challenge_ui: None,
+            spc_challenge: None,
+            three_ds_method_data: None,
+            frictionless_outcome: None,
+            message_version: None,
+            three_ds_method_completed: false,
+            otp_attempts: 0,
+            otp: None,
+            oob_started_at_ms: None,
+            oob_approved: None,
+            whitelisting_choice: None,
+            request_id: None,
+            masked_pan: None,
+            pan_hash: None,
+            initial_ui: None,
+            protocol_2_3: None,
+            three_ds_session_data: None,
+            success_liability: None,
+        }
+    }
+
+    #[test]
+    fn test_transaction_round_trips_in_both_formats() {
+        let data = sample_transaction();
+
+        for format in [ValueFormat::Json, ValueFormat::Msgpack] {
+            let encoded = encode_transaction(&data, format).unwrap();
+            assert_eq!(encoded[0] == MSGPACK_MARKER, format == ValueFormat::Msgpack);
+
+            let decoded = decode_transaction(&encoded).unwrap();
+            assert_eq!(decoded.acs_trans_id, data.acs_trans_id);
+            assert_eq!(decoded.redirect_url, data.redirect_url);
+            assert_eq!(decoded.interaction_count, 1);
+            assert_eq!(
+                decoded.authenticate_request.purchase.purchase_amount,
+                data.authenticate_request.purchase.purchase_amount
+            );
+        }
+    }
+
+    // A stand-in Redis answering PING (echoed, as deadpool's recycle check expects) and GET
+    // from a seeded map, and every other command with OK
+    struct FakeRedis {
+        url: String,
+        connections: AtomicUsize,
+        gets: AtomicUsize,
+        values: Mutex<HashMap<Vec<u8>, Vec<u8>>>,
+    }
+
+    impl FakeRedis {
+        async fn start() -> Arc<Self> {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let redis = Arc::new(Self {
+                url: format!("redis://{}", listener.local_addr().unwrap()),
+                connections: Default::default(),
+                gets: Default::default(),
+                values: Default::default(),
+            });
+            let server = redis.clone();
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    server.connections.fetch_add(1, Ordering::SeqCst);
+                    tokio::spawn(server.clone().serve(stream));
+                }
+            });
+            redis
+        }
+
+        fn seed(&self, key: String, value: Vec<u8>) {
+            self.values.lock().unwrap().insert(key.into_bytes(), value);
+        }
+
+        fn count(counter: &AtomicUsize) -> usize {
+            counter.load(Ordering::SeqCst)
+        }
+
+        async fn serve(self: Arc<Self>, stream: tokio::net::TcpStream) {
+            use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+            let bulk = |data: &[u8]| {
+                let mut reply = format!("${}\r\n", data.len()).into_bytes();
+                reply.extend_from_slice(data);
+                reply.extend_from_slice(b"\r\n");
+                reply
+            };
+            let mut stream = BufReader::new(stream);
+            let mut line = String::new();
+            // Each command is an array of bulk strings: *<n>, then $<len> and the data
+            while stream.read_line(&mut line).await.unwrap_or(0) > 0 {
+                let count: usize = line.trim_end().trim_start_matches('*').parse().unwrap_or(0);
+                let mut args = Vec::with_capacity(count);
+                for _ in 0..count {
+                    line.clear();
+                    stream.read_line(&mut line).await.unwrap();
+                    let len: usize = line.trim_end().trim_start_matches('$').parse().unwrap();
+                    let mut data = vec![0u8; len + 2];
+                    stream.read_exact(&mut data).await.unwrap();
+                    data.truncate(len);
+                    args.push(data);
+                }
+                let reply = match args.as_slice() {
+                    [cmd] if cmd.eq_ignore_ascii_case(b"PING") => b"+PONG\r\n".to_vec(),
+                    [cmd, msg] if cmd.eq_ignore_ascii_case(b"PING") => bulk(msg),
+                    [cmd, key] if cmd.eq_ignore_ascii_case(b"GET") => {
+                        self.gets.fetch_add(1, Ordering::SeqCst);
+                        match self.values.lock().unwrap().get(key) {
+                            Some(value) => bulk(value),
+                            None => b"$-1\r\n".to_vec(),
+                        }
+                    }
+                    _ => b"+OK\r\n".to_vec(),
+                };
+                if stream.get_mut().write_all(&reply).await.is_err() {
+                    break;
+                }
+                line.clear();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_warm_pool_opens_min_idle_connections() {
+        let redis = FakeRedis::start().await;
+        let mut settings = test_settings();
+        settings.redis.url = redis.url.clone();
+        settings.redis.pool.min_idle = 3;
+        let store = RedisStore::new(&settings).await.unwrap();
+
+        // Released back to the pool as idle connections, ready for the first requests
+        let status = store.pool.status();
+        assert_eq!((status.size, status.available), (3, 3));
+        assert_eq!(FakeRedis::count(&redis.connections), 3);
+
+        // Warming again reuses them rather than opening more
+        assert_eq!(RedisStore::warm_pool(&store.pool, 3).await.unwrap(), 3);
+        assert_eq!(FakeRedis::count(&redis.connections), 3);
+    }
+
+    #[tokio::test]
+    async fn test_reads_use_the_replica_and_fall_back_to_the_primary() {
+        let (primary, replica) = (FakeRedis::start().await, FakeRedis::start().await);
+        let mut settings = test_settings();
+        settings.redis.url = primary.url.clone();
+        settings.redis.replica.url = Some(replica.url.clone());
+        let store = RedisStore::new(&settings).await.unwrap();
+        let encoded = |data: &TransactionData| encode_transaction(data, ValueFormat::Json).unwrap();
+
+        // Replicated: served by the replica alone
+        let (replicated_id, replicated) = (Uuid::new_v4(), sample_transaction());
+        primary.seed(store.make_key(&replicated_id), encoded(&replicated));
+        replica.seed(store.make_key(&replicated_id), encoded(&replicated));
+        let found = store.get(&replicated_id).await.unwrap().unwrap();
+        assert_eq!(found.acs_trans_id, replicated.acs_trans_id);
+        assert_eq!((FakeRedis::count(&replica.gets), FakeRedis::count(&primary.gets)), (1, 0));
+
+        // Not replicated yet: the replica miss is re-read from the primary
+        let (lagging_id, lagging) = (Uuid::new_v4(), sample_transaction());
+        primary.seed(store.make_key(&lagging_id), encoded(&lagging));
+        let found = store.get(&lagging_id).await.unwrap().unwrap();
+        assert_eq!(found.acs_trans_id, lagging.acs_trans_id);
+        assert_eq!((FakeRedis::count(&replica.gets), FakeRedis::count(&primary.gets)), (2, 1));
+
+        // Unknown everywhere
+        assert!(store.get(&Uuid::new_v4()).await.unwrap().is_none());
+        assert_eq!((FakeRedis::count(&replica.gets), FakeRedis::count(&primary.gets)), (3, 2));
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_store_finds_by_acs_trans_id() {
+        let store = InMemoryStore {
+            transactions: Cache::builder().build(),
+            method_data: Cache::builder().build(),
+            method_completions: Cache::builder().build(),
+            messages: Cache::builder().build(),
+        };
+        let key = Uuid::new_v4();
+        let data = sample_transaction();
+        let acs_trans_id = data.acs_trans_id;
+
+        assert!(matches!(store.update(&key, data.clone()).await, Err(StateError::NotFound)));
+        store.insert(key, data).await.unwrap();
+
+        let (found_key, found) = store.find_by_acs_trans_id(&acs_trans_id).await.unwrap().unwrap();
+        assert_eq!(found_key, key);
+        assert_eq!(found.acs_trans_id, acs_trans_id);
+        assert!(store.find_by_acs_trans_id(&Uuid::new_v4()).await.unwrap().is_none());
+
+        store.delete(&key).await.unwrap();
+        assert!(store.get(&key).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_sql_store_round_trips_through_sqlite() {
+        let mut settings = Settings::default();
+        // One connection, so every query sees the same in-memory database
+        settings.state.sql.url = "sqlite::memory:".to_string();
+        settings.state.sql.max_connections = 1;
+        let store = SqlStore::new(&settings).await.unwrap();
+        assert_eq!(SqlStore::migrate(&store.pool, &column_types("sqlite::memory:")).await.unwrap(), 0);
+
+        let key = Uuid::new_v4();
+        let mut data = sample_transaction();
+        let acs_trans_id = data.acs_trans_id;
+
+        assert!(matches!(store.update(&key, data.clone()).await, Err(StateError::NotFound)));
+        store.insert(key, data.clone()).await.unwrap();
+        data.interaction_count = 2;
+        store.update(&key, data).await.unwrap();
+
+        let (found_key, found) = store.find_by_acs_trans_id(&acs_trans_id).await.unwrap().unwrap();
+        assert_eq!(found_key, key);
+        assert_eq!(found.interaction_count, 2);
+        assert!(store.find_by_acs_trans_id(&Uuid::new_v4()).await.unwrap().is_none());
+
+        assert!(!store.method_completed(&key).await.unwrap());
+        store.mark_method_completed(key).await.unwrap();
+        store.mark_method_completed(key).await.unwrap();
+        assert!(store.method_completed(&key).await.unwrap());
+
+        let message = RecordedMessage {
+            direction: crate::recording::Direction::Inbound,
+            message_type: "AReq".to_string(),
+            recorded_at: chrono::Utc::now(),
+            body: serde_json::json!({ "deviceChannel": "02" }),
+        };
+        store.append_message(key, message.clone()).await.unwrap();
+        store.append_message(key, RecordedMessage { message_type: "ARes".to_string(), ..message }).await.unwrap();
+        let messages = store.messages(&key).await.unwrap();
+        assert_eq!(messages.iter().map(|m| m.message_type.as_str()).collect::<Vec<_>>(), ["AReq", "ARes"]);
+
+        let device_data = MethodDeviceData {
+            browser_user_agent: "Mozilla/5.0".to_string(),
+            browser_language: "en-GB".to_string(),
+            browser_color_depth: "24".to_string(),
+            browser_screen_height: 1080,
+            browser_screen_width: 1920,
+            browser_tz: 0,
+            browser_java_enabled: false,
+            platform: None,
+        };
+        store.insert_method_data(key, device_data).await.unwrap();
+        assert!(store.get_method_data(&key).await.unwrap().is_some());
+
+        // Deleting the transaction takes its 3DS Method rows and message log with it
+        store.delete(&key).await.unwrap();
+        assert!(store.get(&key).await.unwrap().is_none());
+        assert!(store.get_method_data(&key).await.unwrap().is_none());
+        assert!(!store.method_completed(&key).await.unwrap());
+        assert!(store.messages(&key).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_acs_index_key_is_outside_the_scanned_pattern() {
+        let acs_trans_id = Uuid::new_v4();
+        let index_key = acs_index_key("3ds_transaction", &acs_trans_id);
+        assert_eq!(index_key, format!("3ds_transaction-acs:{}", acs_trans_id));
+        assert!(!index_key.starts_with("3ds_transaction:"));
+    }
+
+    #[test]
+    fn test_find_in_batch_skips_expired_and_other_values() {
+        let key = Uuid::new_v4();
+        let data = sample_transaction();
+        let other = sample_transaction();
+        let keys = vec![
+            format!("3ds_transaction:{}", Uuid::new_v4()),
+            format!("3ds_transaction:{}", Uuid::new_v4()),
+            format!("3ds_transaction:{}", key),
+        ];
+        let values = vec![
+            None,
+            Some(encode_transaction(&other, ValueFormat::Json).unwrap()),
+            Some(encode_transaction(&data, ValueFormat::Msgpack).unwrap()),
+        ];
+
+        let (found_key, found) = find_in_batch("3ds_transaction:", keys.clone(), values.clone(), &data.acs_trans_id).unwrap();
+        assert_eq!(found_key, key);
+        assert_eq!(found.acs_trans_id, data.acs_trans_id);
+        assert!(find_in_batch("3ds_transaction:", keys, values, &Uuid::new_v4()).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_store_expires_and_evicts_transactions() {
+        let mut settings = test_settings();
+        settings.state.memory_ttl_seconds = Some(1);
+        settings.state.memory_max_transactions = 10;
+        let store = InMemoryStore::new(&settings);
+
+        let key = Uuid::new_v4();
+        store.insert(key, sample_transaction()).await.unwrap();
+        assert!(store.get(&key).await.unwrap().is_some());
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        assert!(store.get(&key).await.unwrap().is_none());
+
+        for _ in 0..50 {
+            store.insert(Uuid::new_v4(), sample_transaction()).await.unwrap();
+        }
+        store.transactions.run_pending_tasks();
+        assert!(store.transactions.entry_count() <= 10);
+    }
+}
@@ -0,0 +1,607 @@
+//! In-process test server for payment-service test suites, behind the `test-support`
+//! feature: the mock on a free port with the in-memory store and seeded ACS ephemeral keys,
+//! plus helpers that run the 3DS Server calls and return typed responses.
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use uuid::Uuid;
+
+use crate::config::{Settings, StateBackend};
+use crate::crypto::{derive_ephemeral_key_pair, generate_self_signed_certificate, CryptoError};
+use crate::self_test::synthetic_areq;
+use crate::{MockThreeDsServer, StartError};
+
+pub use crate::crypto::{AcsEphemPubKey, EphemeralKeyPair};
+pub use crate::models::{Eci, TransStatus};
+
+/// Seed the test server derives ACS ephemeral keys (and the helpers' SDK keys) from
+pub const TEST_KEY_SEED: &str = "mock-three-ds-test-support";
+
+/// Cards with a fixed outcome under the built-in card scenarios
+pub mod cards {
+    pub const FRICTIONLESS: &str = "4000000000004000";
+    pub const CHALLENGE: &str = "4000000000004001";
+    pub const NOT_AUTHENTICATED: &str = "4000000000004009";
+    pub const UNAVAILABLE: &str = "4000000000004010";
+    pub const REJECTED: &str = "4000000000004011";
+    pub const ATTEMPTED: &str = "4000000000004012";
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TestServerError {
+    #[error(transparent)]
+    Start(#[from] StartError),
+    #[error("request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("unexpected response body: {0}")]
+    Body(#[from] serde_json::Error),
+    #[error(transparent)]
+    Crypto(#[from] CryptoError),
+    /// The mock answered with an error status, usually with an Erro body
+    #[error("HTTP {status}: {body}")]
+    Status { status: u16, body: Value },
+}
+
+/// /3ds/version answer
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Version {
+    pub three_ds_server_trans_id: Uuid,
+    pub card_ranges: Vec<CardRange>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CardRange {
+    pub start_range: String,
+    pub end_range: String,
+    pub acs_start_protocol_version: String,
+    pub acs_end_protocol_version: String,
+    #[serde(default, rename = "threeDSMethodURL")]
+    pub three_ds_method_url: Option<String>,
+}
+
+/// /3ds/authenticate answer, with the ARes elements tests usually assert on
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Authentication {
+    pub three_ds_server_trans_id: Uuid,
+    pub trans_status: TransStatus,
+    pub acs_challenge_mandated: String,
+    #[serde(default)]
+    pub acs_url: Option<String>,
+    #[serde(default)]
+    pub base64_encoded_challenge_request: Option<String>,
+    pub authentication_response: AuthenticationResponse,
+}
+
+impl Authentication {
+    pub fn is_challenge(&self) -> bool {
+        self.trans_status == TransStatus::ChallengeRequired
+    }
+}
+
+/// The ARes inside an [`Authentication`]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticationResponse {
+    pub message_version: String,
+    pub trans_status: TransStatus,
+    #[serde(default)]
+    pub trans_status_reason: Option<String>,
+    /// Absent while a challenge is pending
+    #[serde(default)]
+    pub eci: Option<Eci>,
+    #[serde(default)]
+    pub authentication_value: Option<String>,
+    pub acs_trans_id: Uuid,
+    pub ds_trans_id: Uuid,
+    #[serde(default)]
+    pub acs_signed_content: Option<String>,
+}
+
+/// An app-channel authentication and the ephemeral keys of both sides
+#[derive(Debug, Clone)]
+pub struct AppAuthentication {
+    pub authentication: Authentication,
+    /// The keys whose public half was sent as sdkEphemPubKey
+    pub sdk_keys: EphemeralKeyPair,
+    /// The ACS's keys, for challenged transactions
+    pub acs_keys: Option<EphemeralKeyPair>,
+}
+
+// ACS signing certificate and key for app challenges, generated once per test process, so
+// parallel tests never see a half-written pair
+fn signing_certificate() -> &'static (PathBuf, PathBuf) {
+    static PATHS: OnceLock<(PathBuf, PathBuf)> = OnceLock::new();
+    PATHS.get_or_init(|| {
+        let dir = std::env::temp_dir().join(format!("mock-three-ds-{}", std::process::id()));
+        let paths = (dir.join("acs-cert.pem"), dir.join("acs-private-key.pem"));
+        // On failure app challenges are answered with an Erro naming the missing files
+        if let Err(e) = generate_self_signed_certificate(&paths.0, &paths.1) {
+            tracing::warn!(error = %e, "test server has no ACS signing certificate");
+        }
+        paths
+    })
+}
+
+/// Settings the test server starts with: built-in defaults, the in-memory store, a free
+/// port, [`TEST_KEY_SEED`] and a generated ACS signing certificate
+pub fn test_settings() -> Settings {
+    let mut settings = Settings::default();
+    settings.server.port = 0;
+    settings.state.backend = StateBackend::Memory;
+    settings.acs.ephemeral_key_seed = Some(TEST_KEY_SEED.to_string());
+    let (cert_path, key_path) = signing_certificate();
+    settings.acs.signing.cert_path = cert_path.to_string_lossy().into_owned();
+    settings.acs.signing.private_key_path = key_path.to_string_lossy().into_owned();
+    settings
+}
+
+/// The mock, started in-process; stop it with [`TestServer::stop`] at the end of the test
+pub struct TestServer {
+    server: MockThreeDsServer,
+    client: reqwest::Client,
+}
+
+impl TestServer {
+    /// Start with [`test_settings`]. Must run on an actix runtime, e.g. in an
+    /// `#[actix_web::test]`.
+    pub async fn start() -> Result<Self, TestServerError> {
+        Self::start_with(test_settings()).await
+    }
+
+    /// Start with these settings, e.g. [`test_settings`] with a card registry file
+    pub async fn start_with(settings: Settings) -> Result<Self, TestServerError> {
+        let server = MockThreeDsServer::builder()
+            .settings(settings)
+            .start()
+            .await?;
+        Ok(Self {
+            server,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    pub fn server(&self) -> &MockThreeDsServer {
+        &self.server
+    }
+
+    pub fn settings(&self) -> &Settings {
+        self.server.settings()
+    }
+
+    /// URL of a route on the main listener, e.g. `url("/3ds/final")`
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.server.base_url(), path)
+    }
+
+    /// POST a JSON body and decode the answer, failing on a non-2xx status
+    pub async fn post<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &Value,
+    ) -> Result<T, TestServerError> {
+        let response = self
+            .client
+            .post(self.url(path))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.to_string())
+            .send()
+            .await?;
+        let status = response.status();
+        let bytes = response.bytes().await?;
+        if !status.is_success() {
+            return Err(TestServerError::Status {
+                status: status.as_u16(),
+                body: serde_json::from_slice(&bytes)
+                    .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into())),
+            });
+        }
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    /// POST a form, as the browser challenge pages do, and return the HTML answer, failing
+    /// on a non-2xx status
+    pub async fn post_form(
+        &self,
+        path: &str,
+        form: &[(&str, &str)],
+    ) -> Result<String, TestServerError> {
+        let response = self.client.post(self.url(path)).form(form).send().await?;
+        let status = response.status();
+        let text = response.text().await?;
+        if !status.is_success() {
+            return Err(TestServerError::Status {
+                status: status.as_u16(),
+                body: serde_json::from_str(&text).unwrap_or(Value::String(text)),
+            });
+        }
+        Ok(text)
+    }
+
+    pub async fn version(&self, card: &str) -> Result<Version, TestServerError> {
+        self.post("/3ds/version", &json!({ "cardNumber": card }))
+            .await
+    }
+
+    /// Version call, then a browser AReq for the card
+    pub async fn authenticate(&self, card: &str) -> Result<Authentication, TestServerError> {
+        self.authenticate_with(card, |_| {}).await
+    }
+
+    /// [`authenticate`](Self::authenticate) with the AReq JSON adjusted before it is sent,
+    /// e.g. to set threeDSRequestorChallengeInd
+    pub async fn authenticate_with(
+        &self,
+        card: &str,
+        edit: impl FnOnce(&mut Value),
+    ) -> Result<Authentication, TestServerError> {
+        let version = self.version(card).await?;
+        let mut areq = synthetic_areq(version.three_ds_server_trans_id, card, None);
+        edit(&mut areq);
+        self.post("/3ds/authenticate", &areq).await
+    }
+
+    /// Version call, then an app AReq for the card with SDK keys derived from the
+    /// transaction ID
+    pub async fn authenticate_app(&self, card: &str) -> Result<AppAuthentication, TestServerError> {
+        let version = self.version(card).await?;
+        let three_ds_server_trans_id = version.three_ds_server_trans_id;
+        let sdk_keys = derive_ephemeral_key_pair(TEST_KEY_SEED, three_ds_server_trans_id)?;
+        let areq = synthetic_areq(three_ds_server_trans_id, card, Some(&sdk_keys));
+        let authentication: Authentication = self.post("/3ds/authenticate", &areq).await?;
+
+        let acs_keys = match (
+            &self.settings().acs.ephemeral_key_seed,
+            authentication.is_challenge(),
+        ) {
+            (Some(seed), true) => {
+                derive_ephemeral_key_pair(seed, authentication.authentication_response.acs_trans_id)
+                    .ok()
+            }
+            _ => None,
+        };
+        Ok(AppAuthentication {
+            authentication,
+            sdk_keys,
+            acs_keys,
+        })
+    }
+
+    /// /3ds/final for a transaction, as JSON
+    pub async fn final_outcome(
+        &self,
+        three_ds_server_trans_id: Uuid,
+    ) -> Result<Value, TestServerError> {
+        self.post(
+            "/3ds/final",
+            &json!({ "threeDsServerTransId": three_ds_server_trans_id }),
+        )
+        .await
+    }
+
+    /// Stop the listeners without waiting for open connections
+    pub async fn stop(self) -> std::io::Result<()> {
+        self.server.stop(false).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SuccessLiability;
+    use base64::{engine::general_purpose, Engine as _};
+
+    #[actix_web::test]
+    async fn test_typed_frictionless_and_app_challenge_flows() {
+        let server = TestServer::start().await.unwrap();
+
+        let frictionless = server.authenticate(cards::FRICTIONLESS).await.unwrap();
+        assert_eq!(frictionless.trans_status, TransStatus::Authenticated);
+        assert!(frictionless
+            .authentication_response
+            .authentication_value
+            .is_some());
+
+        let app = server.authenticate_app(cards::CHALLENGE).await.unwrap();
+        assert!(app.authentication.is_challenge());
+        let acs_keys = app.acs_keys.unwrap();
+        let signed_content = app
+            .authentication
+            .authentication_response
+            .acs_signed_content
+            .unwrap();
+
+        // The seeded ACS key is the one acsSignedContent carries
+        let payload = signed_content.split('.').nth(1).unwrap();
+        let payload: Value =
+            serde_json::from_slice(&general_purpose::URL_SAFE_NO_PAD.decode(payload).unwrap())
+                .unwrap();
+        assert_eq!(payload["acsEphemPubKey"]["x"], acs_keys.public_key.x);
+        assert_eq!(payload["acsEphemPubKey"]["y"], acs_keys.public_key.y);
+
+        server.stop().await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_recurring_set_up_is_authenticated_like_a_payment() {
+        let server = TestServer::start().await.unwrap();
+
+        let recurring = server
+            .authenticate_with(cards::FRICTIONLESS, |areq| {
+                areq["threeDsRequestor"]["threeDsRequestorAuthenticationInd"] = json!("02");
+                areq["purchase"]["recurringExpiry"] = json!("20991231");
+                areq["purchase"]["recurringFrequency"] = json!(30);
+            })
+            .await
+            .unwrap();
+        let ares = &recurring.authentication_response;
+        assert_eq!(ares.trans_status, TransStatus::Authenticated);
+        assert_eq!(ares.eci, Some(Eci::VisaAuthenticated));
+        assert!(ares.authentication_value.is_some());
+
+        // An instalment set-up without its instalment count is refused on that element
+        let missing = server
+            .authenticate_with(cards::FRICTIONLESS, |areq| {
+                areq["threeDsRequestor"]["threeDsRequestorAuthenticationInd"] = json!("03");
+                areq["purchase"]["recurringExpiry"] = json!("20991231");
+                areq["purchase"]["recurringFrequency"] = json!(30);
+            })
+            .await
+            .unwrap_err();
+        match missing {
+            TestServerError::Status { status, body } => {
+                assert_eq!(status, 400);
+                assert_eq!(body["errorDetail"], "purchaseInstalData");
+            }
+            e => panic!("expected an Erro, got {}", e),
+        }
+
+        server.stop().await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_failed_outcomes_carry_no_authentication_value() {
+        let server = TestServer::start().await.unwrap();
+
+        for card in [
+            cards::NOT_AUTHENTICATED,
+            cards::UNAVAILABLE,
+            cards::REJECTED,
+        ] {
+            let authentication = server.authenticate(card).await.unwrap();
+            let ares = &authentication.authentication_response;
+            assert_eq!(ares.eci, Some(Eci::VisaNotAuthenticated));
+            assert!(ares.authentication_value.is_none());
+            let outcome = server
+                .final_outcome(authentication.three_ds_server_trans_id)
+                .await
+                .unwrap();
+            assert!(outcome.get("authenticationValue").is_none());
+        }
+
+        // A challenge locked out after wrong OTPs ends with an RReq N and no value either
+        let challenge = server.authenticate(cards::CHALLENGE).await.unwrap();
+        let three_ds_server_trans_id = challenge.three_ds_server_trans_id.to_string();
+        server
+            .post_form("/acs/creq", &[("creq", &browser_creq(&challenge))])
+            .await
+            .unwrap();
+        for _ in 0..server.settings().acs.otp.max_attempts {
+            server
+                .post_form(
+                    "/processor/mock/acs/verify-otp",
+                    &[
+                        ("otp", "0000"),
+                        ("threeDSServerTransID", &three_ds_server_trans_id),
+                    ],
+                )
+                .await
+                .unwrap();
+        }
+        let outcome = server
+            .final_outcome(challenge.three_ds_server_trans_id)
+            .await
+            .unwrap();
+        assert_eq!(outcome["transStatus"], "N");
+        assert!(outcome.get("authenticationValue").is_none());
+        assert!(outcome["resultsRequest"]
+            .get("authenticationValue")
+            .is_none());
+
+        server.stop().await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_challenge_reports_the_configured_liability_once_verified() {
+        let mut settings = test_settings();
+        settings.scenarios.success_eci.payment = SuccessLiability::Attempted;
+        let server = TestServer::start_with(settings).await.unwrap();
+
+        // The ARes of a challenge has no outcome to report yet
+        let challenge = server.authenticate(cards::CHALLENGE).await.unwrap();
+        assert!(challenge.is_challenge());
+        let ares = &challenge.authentication_response;
+        assert_eq!(ares.eci, None);
+        assert_eq!(ares.authentication_value, None);
+
+        server
+            .post_form("/acs/creq", &[("creq", &browser_creq(&challenge))])
+            .await
+            .unwrap();
+        server
+            .post_form(
+                "/processor/mock/acs/verify-otp",
+                &[
+                    ("otp", "1234"),
+                    (
+                        "threeDSServerTransID",
+                        &challenge.three_ds_server_trans_id.to_string(),
+                    ),
+                ],
+            )
+            .await
+            .unwrap();
+        let outcome = server
+            .final_outcome(challenge.three_ds_server_trans_id)
+            .await
+            .unwrap();
+        assert_eq!(outcome["transStatus"], "A");
+        assert_eq!(outcome["eci"], "06");
+        assert!(outcome["authenticationValue"].is_string());
+
+        server.stop().await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_admin_routes_need_the_api_token() {
+        let get = |url: String, token: Option<&'static str>| async move {
+            let mut request = reqwest::Client::new().get(url);
+            if let Some(token) = token {
+                request = request.bearer_auth(token);
+            }
+            request.send().await.unwrap().status().as_u16()
+        };
+
+        // Without a configured token the admin API is closed; health stays open for probes
+        let server = TestServer::start().await.unwrap();
+        assert_eq!(get(server.url("/admin/snapshot"), None).await, 401);
+        assert_eq!(
+            get(server.url("/admin/snapshot"), Some("secret")).await,
+            401
+        );
+        assert_eq!(get(server.url("/health/live"), None).await, 200);
+        server.stop().await.unwrap();
+
+        let mut settings = test_settings();
+        settings.debug.api_token = Some("secret".to_string());
+        let server = TestServer::start_with(settings).await.unwrap();
+        assert_eq!(get(server.url("/admin/snapshot"), None).await, 401);
+        assert_eq!(get(server.url("/admin/snapshot"), Some("other")).await, 401);
+        assert_eq!(
+            get(server.url("/admin/snapshot"), Some("secret")).await,
+            200
+        );
+        server.stop().await.unwrap();
+    }
+
+    // The browser CReq as a 3DS Server form-posts it: base64url, unpadded
+    fn browser_creq(authentication: &Authentication) -> String {
+        let creq = general_purpose::STANDARD
+            .decode(
+                authentication
+                    .base64_encoded_challenge_request
+                    .as_ref()
+                    .unwrap(),
+            )
+            .unwrap();
+        general_purpose::URL_SAFE_NO_PAD.encode(creq)
+    }
+
+    #[actix_web::test]
+    async fn test_trigger_otp_refuses_unknown_and_mismatched_transactions() {
+        let server = TestServer::start().await.unwrap();
+        let challenge = server.authenticate(cards::CHALLENGE).await.unwrap();
+        let creq: Value = serde_json::from_slice(
+            &general_purpose::STANDARD
+                .decode(challenge.base64_encoded_challenge_request.as_ref().unwrap())
+                .unwrap(),
+        )
+        .unwrap();
+        let edited = |edit: fn(&mut Value)| {
+            let mut creq = creq.clone();
+            edit(&mut creq);
+            general_purpose::URL_SAFE_NO_PAD.encode(creq.to_string())
+        };
+        let path = "/processor/mock/acs/trigger-otp";
+
+        server
+            .post_form(path, &[("creq", &edited(|_| {}))])
+            .await
+            .unwrap();
+
+        let unknown = edited(|creq| creq["threeDsServerTransId"] = json!(Uuid::new_v4()));
+        match server
+            .post_form(path, &[("creq", &unknown)])
+            .await
+            .unwrap_err()
+        {
+            TestServerError::Status { status, .. } => assert_eq!(status, 404),
+            e => panic!("expected an Erro, got {}", e),
+        }
+
+        let mismatched = edited(|creq| creq["acsTransId"] = json!(Uuid::new_v4()));
+        match server
+            .post_form(path, &[("creq", &mismatched)])
+            .await
+            .unwrap_err()
+        {
+            TestServerError::Status { status, body } => {
+                assert_eq!(status, 400);
+                assert_eq!(body["errorCode"], "301");
+                assert_eq!(body["errorDetail"], "acsTransID");
+            }
+            e => panic!("expected an Erro, got {}", e),
+        }
+
+        server.stop().await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_session_data_is_posted_back_with_the_final_cres() {
+        let server = TestServer::start().await.unwrap();
+        let challenge = server.authenticate(cards::CHALLENGE).await.unwrap();
+        let three_ds_server_trans_id = challenge.three_ds_server_trans_id.to_string();
+
+        server
+            .post_form(
+                "/acs/creq",
+                &[
+                    ("creq", &browser_creq(&challenge)),
+                    ("threeDSSessionData", "c2Vzc2lvbi0xMjM"),
+                ],
+            )
+            .await
+            .unwrap();
+        let page = server
+            .post_form(
+                "/processor/mock/acs/verify-otp",
+                &[
+                    ("otp", "1234"),
+                    ("threeDSServerTransID", &three_ds_server_trans_id),
+                ],
+            )
+            .await
+            .unwrap();
+
+        assert!(page.contains(r#"<input type="hidden" name="threeDSSessionData" />"#));
+        assert!(page.contains(r#"const threeDSSessionData = "c2Vzc2lvbi0xMjM";"#));
+
+        // Without threeDSSessionData in the CReq, none is posted back
+        let challenge = server.authenticate(cards::CHALLENGE).await.unwrap();
+        server
+            .post_form("/acs/creq", &[("creq", &browser_creq(&challenge))])
+            .await
+            .unwrap();
+        let page = server
+            .post_form(
+                "/processor/mock/acs/verify-otp",
+                &[
+                    ("otp", "1234"),
+                    (
+                        "threeDSServerTransID",
+                        &challenge.three_ds_server_trans_id.to_string(),
+                    ),
+                ],
+            )
+            .await
+            .unwrap();
+        assert!(page.contains("const threeDSSessionData = null;"));
+
+        server.stop().await.unwrap();
+    }
+}
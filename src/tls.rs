@@ -0,0 +1,191 @@
+//! rustls server configuration for `[server.tls]`, so SDKs that insist on an https acsURL
+//! can talk to the mock without a reverse proxy in front of it, plus the client certificate
+//! checks for mutual TLS: the chain is verified during the handshake, and the per-route
+//! requirement and subject allowlist are applied to each request.
+
+use actix_tls::accept::rustls_0_21::TlsStream;
+use actix_web::dev::Extensions;
+use actix_web::rt::net::TcpStream;
+use rustls::server::{AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient};
+use rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
+use rustls_pemfile::Item;
+use std::any::Any;
+use std::fs::File;
+use std::io::BufReader;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::config::{ClientAuthScope, TlsConfig};
+
+#[derive(Debug, thiserror::Error)]
+pub enum TlsError {
+    #[error("{path}: {source}")]
+    Read {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("{0}: no PEM certificates found")]
+    NoCertificates(String),
+    #[error("{0}: no PEM private key found")]
+    NoPrivateKey(String),
+    #[error("{path}: invalid client CA certificate: {detail}")]
+    ClientCa { path: String, detail: String },
+    #[error("TLS configuration rejected: {0}")]
+    Rustls(#[from] rustls::Error),
+}
+
+fn read_pem(path: &str) -> Result<Vec<Item>, TlsError> {
+    let read_error = |source| TlsError::Read {
+        path: path.to_string(),
+        source,
+    };
+    let file = File::open(path).map_err(read_error)?;
+    rustls_pemfile::read_all(&mut BufReader::new(file)).map_err(read_error)
+}
+
+fn load_certificates(path: &str) -> Result<Vec<Certificate>, TlsError> {
+    let certificates: Vec<Certificate> = read_pem(path)?
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::X509Certificate(der) => Some(Certificate(der)),
+            _ => None,
+        })
+        .collect();
+    if certificates.is_empty() {
+        return Err(TlsError::NoCertificates(path.to_string()));
+    }
+    Ok(certificates)
+}
+
+fn load_private_key(path: &str) -> Result<PrivateKey, TlsError> {
+    read_pem(path)?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(der) | Item::RSAKey(der) | Item::ECKey(der) => Some(PrivateKey(der)),
+            _ => None,
+        })
+        .ok_or_else(|| TlsError::NoPrivateKey(path.to_string()))
+}
+
+/// Build the listeners' TLS configuration. With a client CA, presented client certificates
+/// must chain to it (mutual TLS); for `client_auth = "all"` one must be presented.
+pub fn server_config(tls: &TlsConfig) -> Result<ServerConfig, TlsError> {
+    let certificates = load_certificates(&tls.cert_path)?;
+    let key = load_private_key(&tls.key_path)?;
+
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match &tls.client_ca_path {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for certificate in load_certificates(path)? {
+                roots.add(&certificate).map_err(|e| TlsError::ClientCa {
+                    path: path.clone(),
+                    detail: e.to_string(),
+                })?;
+            }
+            match tls.client_auth {
+                ClientAuthScope::All => builder
+                    .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed()),
+                // Required per route by client_certificate_rejection instead
+                ClientAuthScope::ServerRoutes => builder.with_client_cert_verifier(
+                    AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed(),
+                ),
+            }
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    Ok(builder.with_single_cert(certificates, key)?)
+}
+
+/// Certificates a client presented on its TLS connection, leaf first
+#[derive(Debug, Clone)]
+pub struct PeerCertificates(pub Vec<Certificate>);
+
+/// `on_connect` hook keeping the client's verified certificates with the connection, where
+/// requests read them with `conn_data`
+pub fn capture_peer_certificates(connection: &dyn Any, data: &mut Extensions) {
+    if let Some(stream) = connection.downcast_ref::<TlsStream<TcpStream>>() {
+        let (_, session) = stream.get_ref();
+        if let Some(certificates) = session.peer_certificates() {
+            data.insert(PeerCertificates(certificates.to_vec()));
+        }
+    }
+}
+
+/// Why a request to `path` (with any route prefix removed) is refused for its client
+/// certificate, or None when it may proceed
+pub fn client_certificate_rejection(
+    tls: &TlsConfig,
+    path: &str,
+    peer: Option<&PeerCertificates>,
+) -> Option<String> {
+    if !tls.enabled || tls.client_ca_path.is_none() {
+        return None;
+    }
+    if tls.client_auth == ClientAuthScope::ServerRoutes
+        && !path.starts_with("/3ds/")
+        && !path.starts_with("/ds/")
+    {
+        return None;
+    }
+    let Some(leaf) = peer.and_then(|peer| peer.0.first()) else {
+        return Some("a client certificate is required".to_string());
+    };
+    if tls.allowed_client_subjects.is_empty() {
+        return None;
+    }
+
+    let certificate = match X509Certificate::from_der(&leaf.0) {
+        Ok((_, certificate)) => certificate,
+        Err(e) => return Some(format!("unreadable client certificate: {}", e)),
+    };
+    let subject = certificate.subject();
+    let common_name = subject
+        .iter_common_name()
+        .next()
+        .and_then(|name| name.as_str().ok());
+    let subject = subject.to_string();
+    let allowed = tls
+        .allowed_client_subjects
+        .iter()
+        .any(|allowed| *allowed == subject || Some(allowed.as_str()) == common_name);
+    (!allowed).then(|| format!("client certificate subject {} is not allowed", subject))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_files_are_reported_with_their_path() {
+        let tls = TlsConfig {
+            enabled: true,
+            cert_path: "certs/does-not-exist.pem".to_string(),
+            ..TlsConfig::default()
+        };
+        let error = server_config(&tls).unwrap_err();
+        assert!(matches!(error, TlsError::Read { .. }));
+        assert!(error.to_string().starts_with("certs/does-not-exist.pem: "));
+    }
+
+    #[test]
+    fn test_server_routes_need_a_client_certificate() {
+        let tls = TlsConfig {
+            enabled: true,
+            client_ca_path: Some("certs/client-ca.pem".to_string()),
+            client_auth: ClientAuthScope::ServerRoutes,
+            ..TlsConfig::default()
+        };
+
+        assert!(client_certificate_rejection(&tls, "/3ds/authenticate", None).is_some());
+        assert!(client_certificate_rejection(&tls, "/ds/authenticate", None).is_some());
+        assert!(client_certificate_rejection(&tls, "/challenge", None).is_none());
+        assert!(client_certificate_rejection(&tls, "/dsx", None).is_none());
+
+        let unverified = TlsConfig {
+            client_ca_path: None,
+            ..tls.clone()
+        };
+        assert!(client_certificate_rejection(&unverified, "/3ds/authenticate", None).is_none());
+    }
+}
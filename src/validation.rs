@@ -0,0 +1,919 @@
+use base64::{engine::general_purpose, Engine as _};
+use chrono::{NaiveDate, Utc};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::fmt;
+use uuid::Uuid;
+
+use crate::models::{
+    AuthenticateRequest, ChallengeRequest, DeviceChannel, Eci, ErrorMessage, MessageExtension,
+    PreparationRequest, ResultsRequest, ThreeDsMethodData, TransStatus,
+};
+use crate::protocol;
+
+/// Data elements defined for the app-based CReq (EMV 3DS 2.2 / 2.3)
+const CREQ_FIELDS: &[&str] = &[
+    "threeDSServerTransID",
+    "acsTransID",
+    "sdkTransID",
+    "messageType",
+    "messageVersion",
+    "messageExtension",
+    "sdkCounterStoA",
+    "challengeWindowSize",
+    "challengeCancel",
+    "challengeDataEntry",
+    "challengeHTMLDataEntry",
+    "challengeNoEntry",
+    "resendChallenge",
+    "oobContinue",
+    "threeDSRequestorAppURL",
+    "whitelistingDataEntry",
+    "trustListDataEntry",
+];
+
+/// Prefix of the serde error message produced for a rejected protocol code
+const DATA_ELEMENT_PREFIX: &str = "data element ";
+
+/// Field-level AReq validation failure, rendered to the client as an Erro message
+#[derive(Debug, Clone)]
+pub struct FieldError {
+    pub error_code: &'static str,
+    pub field: Cow<'static, str>,
+    pub description: String,
+}
+
+impl FieldError {
+    pub fn missing(field: &'static str) -> Self {
+        Self {
+            error_code: "201",
+            field: Cow::Borrowed(field),
+            description: "Required data element missing".to_string(),
+        }
+    }
+
+    pub fn invalid(field: &'static str, description: &str) -> Self {
+        Self {
+            error_code: "203",
+            field: Cow::Borrowed(field),
+            description: description.to_string(),
+        }
+    }
+
+    /// A protocol version the ACS does not answer in
+    pub fn unsupported_version(field: &'static str, description: &str) -> Self {
+        Self {
+            error_code: "102",
+            field: Cow::Borrowed(field),
+            description: description.to_string(),
+        }
+    }
+
+    /// A transaction ID that differs from the one issued for the transaction
+    pub fn unrecognized_transaction_id(field: &'static str) -> Self {
+        Self {
+            error_code: "301",
+            field: Cow::Borrowed(field),
+            description: "Transaction ID not recognized".to_string(),
+        }
+    }
+
+    /// A second RReq for a transaction whose results were already received
+    pub fn duplicate_results() -> Self {
+        Self {
+            error_code: "305",
+            field: Cow::Borrowed("threeDSServerTransID"),
+            description: "Transaction data not valid - results already received".to_string(),
+        }
+    }
+
+    /// Critical message extensions the ACS does not recognise, listed by ID
+    pub fn unrecognized_critical_extensions(ids: &[&str]) -> Self {
+        Self {
+            error_code: "202",
+            field: Cow::Owned(ids.join(",")),
+            description: "Critical message extension not recognised".to_string(),
+        }
+    }
+
+    /// Elements a strict-mode message carried that its model does not define
+    pub fn unrecognized(fields: &[String]) -> Self {
+        Self {
+            error_code: "203",
+            field: Cow::Owned(fields.join(",")),
+            description: "Unrecognized data element".to_string(),
+        }
+    }
+
+    /// Recover the field-level error behind a failed JSON extraction: a missing required
+    /// element, or a protocol code rejected while deserializing. Other failures yield None.
+    pub fn from_json_error(error: &serde_json::Error) -> Option<Self> {
+        if !error.is_data() {
+            return None;
+        }
+        Self::from_serde_message(None, &error.to_string())
+    }
+
+    /// Like `from_json_error` for an error tracked to the element it was raised at, so a
+    /// value of the wrong type is also reported against that element
+    pub fn from_json_path_error(
+        error: &serde_path_to_error::Error<serde_json::Error>,
+    ) -> Option<Self> {
+        if !error.inner().is_data() {
+            return None;
+        }
+        let path = error.path().to_string();
+        Self::from_serde_message(Some(&path), &error.inner().to_string())
+    }
+
+    /// Recover the field-level error from a serde error message, qualifying missing
+    /// elements with the path of the object they were missing from when it is known
+    pub fn from_serde_message(path: Option<&str>, message: &str) -> Option<Self> {
+        // serde_json appends the position, e.g. " at line 1 column 42"
+        let message = message.split(" at line ").next().unwrap_or_default();
+        let path = path.filter(|path| !path.is_empty() && *path != ".");
+
+        if let Some(field) = message.strip_prefix("missing field `") {
+            let field = field.trim_end_matches('`');
+            return Some(Self {
+                error_code: "201",
+                field: Cow::Owned(match path {
+                    Some(path) => format!("{}.{}", path, field),
+                    None => field.to_string(),
+                }),
+                description: "Required data element missing".to_string(),
+            });
+        }
+
+        if let Some((field, description)) = message
+            .strip_prefix(DATA_ELEMENT_PREFIX)
+            .and_then(|rest| rest.split_once(": "))
+        {
+            return Some(Self {
+                error_code: "203",
+                field: Cow::Owned(field.to_string()),
+                description: description.to_string(),
+            });
+        }
+
+        // e.g. "invalid type: string \"abc\", expected u32", naming the expected type
+        const TYPE_ERRORS: &[&str] = &[
+            "invalid type",
+            "invalid value",
+            "invalid length",
+            "unknown variant",
+        ];
+        let path = path?;
+        TYPE_ERRORS
+            .iter()
+            .any(|kind| message.starts_with(kind))
+            .then(|| Self {
+                error_code: "203",
+                field: Cow::Owned(path.to_string()),
+                description: message.to_string(),
+            })
+    }
+
+    pub fn to_error_message(
+        &self,
+        message_type: &str,
+        three_ds_server_trans_id: Uuid,
+    ) -> ErrorMessage {
+        ErrorMessage::new(self.error_code, &self.description, &self.field)
+            .for_message(message_type, three_ds_server_trans_id)
+    }
+}
+
+/// Rendered as the serde error when a protocol code is rejected during deserialization,
+/// in the form `FieldError::from_json_error` parses back
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}: {}",
+            DATA_ELEMENT_PREFIX, self.field, self.description
+        )
+    }
+}
+
+/// Paths of the elements in a received message that its parsed model (re-serialized) does not
+/// carry. Keys compare case-insensitively since the models accept differently cased aliases
+/// (e.g. threeDSRequestorSpcSupport), and explicit nulls are ignored.
+pub fn unknown_fields(received: &Value, parsed: &Value) -> Vec<String> {
+    let mut unknown = Vec::new();
+    collect_unknown_fields(received, parsed, "", &mut unknown);
+    unknown
+}
+
+fn collect_unknown_fields(received: &Value, parsed: &Value, path: &str, unknown: &mut Vec<String>) {
+    match (received, parsed) {
+        (Value::Object(received), Value::Object(parsed)) => {
+            for (key, value) in received {
+                if value.is_null() {
+                    continue;
+                }
+                let field_path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                match parsed
+                    .iter()
+                    .find(|(known, _)| known.eq_ignore_ascii_case(key))
+                {
+                    Some((_, parsed_value)) => {
+                        collect_unknown_fields(value, parsed_value, &field_path, unknown)
+                    }
+                    None => unknown.push(field_path),
+                }
+            }
+        }
+        (Value::Array(received), Value::Array(parsed)) => {
+            for (index, (value, parsed_value)) in received.iter().zip(parsed).enumerate() {
+                collect_unknown_fields(
+                    value,
+                    parsed_value,
+                    &format!("{}[{}]", path, index),
+                    unknown,
+                );
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Top-level elements of a decrypted app-based CReq that the spec does not define
+pub fn unknown_creq_fields(creq: &Value) -> Vec<String> {
+    creq.as_object()
+        .map(|fields| {
+            fields
+                .keys()
+                .filter(|key| !CREQ_FIELDS.contains(&key.as_str()))
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Protocol versions a browser CReq may carry
+const SUPPORTED_MESSAGE_VERSIONS: &[&str] = &["2.1.0", "2.2.0", "2.3.1"];
+
+/// Decode the `creq` value of a browser CReq form post: base64url over the CReq JSON.
+/// Padding is not part of the encoding but is tolerated.
+pub fn decode_browser_creq(creq: &str) -> Result<Vec<u8>, FieldError> {
+    decode_base64url("creq", creq)
+}
+
+/// Decode the `creq` value posted to trigger-otp: base64url as 3DS Servers send it, or the
+/// CReq JSON itself as older clients post it (told apart by the leading `{`)
+pub fn decode_trigger_otp_creq(creq: &str) -> Result<Cow<'_, [u8]>, FieldError> {
+    if creq.trim_start().starts_with('{') {
+        Ok(Cow::Borrowed(creq.as_bytes()))
+    } else {
+        decode_browser_creq(creq).map(Cow::Owned)
+    }
+}
+
+/// Decode the threeDSMethodData posted to the threeDSMethodURL (base64url JSON)
+pub fn decode_three_ds_method_data(value: &str) -> Result<ThreeDsMethodData, FieldError> {
+    let json = decode_base64url("threeDSMethodData", value)?;
+    let data: ThreeDsMethodData = serde_json::from_slice(&json)
+        .map_err(|e| FieldError::invalid("threeDSMethodData", &e.to_string()))?;
+
+    let url = &data.three_ds_method_notification_url;
+    if !url.starts_with("https://") && !url.starts_with("http://") {
+        return Err(FieldError::invalid(
+            "threeDSMethodNotificationURL",
+            "Must be an http(s) URL",
+        ));
+    }
+
+    Ok(data)
+}
+
+/// threeDSMethodData posted to the threeDSMethodNotificationURL once the method completes:
+/// only the transaction ID, base64url-encoded like the request
+pub fn encode_method_notification_data(three_ds_server_trans_id: Uuid) -> String {
+    general_purpose::URL_SAFE_NO_PAD
+        .encode(serde_json::json!({ "threeDSServerTransID": three_ds_server_trans_id }).to_string())
+}
+
+fn decode_base64url(field: &'static str, value: &str) -> Result<Vec<u8>, FieldError> {
+    general_purpose::URL_SAFE_NO_PAD
+        .decode(value.trim().trim_end_matches('='))
+        .map_err(|_| FieldError::invalid(field, "Not base64url encoded"))
+}
+
+/// Validate the header elements of a PReq; the PRes answers in its messageVersion
+pub fn validate_preparation_request(preq: &PreparationRequest) -> Result<(), FieldError> {
+    if preq.message_type != "PReq" {
+        return Err(FieldError {
+            error_code: "101",
+            field: Cow::Borrowed("messageType"),
+            description: "Message received invalid".to_string(),
+        });
+    }
+
+    if !protocol::ACS_PROTOCOL_VERSIONS.contains(&preq.message_version.as_str()) {
+        return Err(FieldError::unsupported_version(
+            "messageVersion",
+            "Message Version Number Not Supported",
+        ));
+    }
+
+    if preq
+        .serial_num
+        .as_ref()
+        .is_some_and(|serial_num| serial_num.is_empty() || serial_num.len() > 20)
+    {
+        return Err(FieldError::invalid(
+            "serialNum",
+            "serialNum must be 1 to 20 characters",
+        ));
+    }
+
+    Ok(())
+}
+
+/// challengeWindowSize values a browser CReq may carry (01-04 sized, 05 full screen)
+pub fn is_challenge_window_size(value: &str) -> bool {
+    matches!(value, "01" | "02" | "03" | "04" | "05")
+}
+
+/// Validate the elements of a browser CReq the ACS acts on
+pub fn validate_browser_creq(creq: &ChallengeRequest) -> Result<(), FieldError> {
+    if creq.message_type != "CReq" {
+        return Err(FieldError {
+            error_code: "101",
+            field: Cow::Borrowed("messageType"),
+            description: "Message received invalid".to_string(),
+        });
+    }
+
+    if !SUPPORTED_MESSAGE_VERSIONS.contains(&creq.message_version.as_str()) {
+        return Err(FieldError {
+            error_code: "102",
+            field: Cow::Borrowed("messageVersion"),
+            description: "Message Version Number Not Supported".to_string(),
+        });
+    }
+
+    if !is_challenge_window_size(&creq.challenge_window_size) {
+        return Err(FieldError::invalid(
+            "challengeWindowSize",
+            "Must be 01, 02, 03, 04 or 05",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Most messageExtension entries a message may carry
+pub const MAX_MESSAGE_EXTENSIONS: usize = 10;
+
+/// Check a message's extensions: at most ten, and each critical one among the `recognized`
+/// IDs. Non-critical extensions the ACS does not know are accepted as they are.
+pub fn validate_message_extensions(
+    extensions: Option<&[MessageExtension]>,
+    recognized: &[String],
+) -> Result<(), FieldError> {
+    let extensions = extensions.unwrap_or_default();
+    if extensions.len() > MAX_MESSAGE_EXTENSIONS {
+        return Err(FieldError::invalid(
+            "messageExtension",
+            &format!(
+                "messageExtension must have at most {} entries",
+                MAX_MESSAGE_EXTENSIONS
+            ),
+        ));
+    }
+
+    let unrecognized: Vec<&str> = extensions
+        .iter()
+        .filter(|extension| extension.criticality_indicator && !recognized.contains(&extension.id))
+        .map(|extension| extension.id.as_str())
+        .collect();
+    if !unrecognized.is_empty() {
+        return Err(FieldError::unrecognized_critical_extensions(&unrecognized));
+    }
+
+    Ok(())
+}
+
+/// threeDSRequestorAuthenticationInd values that set up a series of future payments
+/// (02 = recurring, 03 = instalment)
+pub fn is_recurring_or_instalment(authentication_ind: &str) -> bool {
+    matches!(authentication_ind, "02" | "03")
+}
+
+/// Check an RReq against the transaction it reports on: acsTransID and dsTransID must be the
+/// ones issued for it, and transStatus, eci and authenticationValue must describe one outcome
+pub fn validate_results_request(
+    rreq: &ResultsRequest,
+    acs_trans_id: Uuid,
+    ds_trans_id: Uuid,
+) -> Result<(), FieldError> {
+    if rreq.acs_trans_id != acs_trans_id {
+        return Err(FieldError::unrecognized_transaction_id("acsTransID"));
+    }
+    if rreq.ds_trans_id != ds_trans_id {
+        return Err(FieldError::unrecognized_transaction_id("dsTransID"));
+    }
+
+    let eci_matches = match rreq.trans_status {
+        TransStatus::Authenticated => matches!(
+            rreq.eci,
+            Eci::VisaAuthenticated | Eci::MastercardAuthenticated
+        ),
+        TransStatus::Attempted => matches!(rreq.eci, Eci::VisaAttempted | Eci::MastercardAttempted),
+        TransStatus::NotAuthenticated | TransStatus::Unavailable | TransStatus::Rejected => {
+            matches!(
+                rreq.eci,
+                Eci::VisaNotAuthenticated | Eci::MastercardNotAuthenticated
+            )
+        }
+        _ => {
+            return Err(FieldError::invalid(
+                TransStatus::FIELD,
+                &format!(
+                    "transStatus {} is not a final RReq outcome",
+                    rreq.trans_status
+                ),
+            ))
+        }
+    };
+    if !eci_matches {
+        return Err(FieldError::invalid(
+            Eci::FIELD,
+            &format!(
+                "eci {} does not match transStatus {}",
+                rreq.eci, rreq.trans_status
+            ),
+        ));
+    }
+
+    // A successful or attempted authentication carries the CAVV/AAV, padded or not; the
+    // other outcomes carry none
+    let authentic = matches!(
+        rreq.trans_status,
+        TransStatus::Authenticated | TransStatus::Attempted
+    );
+    match rreq.authentication_value.as_deref() {
+        Some(value) if authentic => {
+            let value = value.trim_end_matches('=');
+            if value.is_empty() || general_purpose::STANDARD_NO_PAD.decode(value).is_err() {
+                return Err(FieldError::invalid(
+                    "authenticationValue",
+                    &format!(
+                        "transStatus {} requires a base64 encoded authenticationValue",
+                        rreq.trans_status
+                    ),
+                ));
+            }
+        }
+        None if authentic => return Err(FieldError::missing("authenticationValue")),
+        Some(_) => {
+            return Err(FieldError::invalid(
+                "authenticationValue",
+                &format!(
+                    "transStatus {} has no authenticationValue",
+                    rreq.trans_status
+                ),
+            ))
+        }
+        None => {}
+    }
+    Ok(())
+}
+
+/// Validate AReq fields whose presence or format depends on other fields
+pub fn validate_authenticate_request(req: &AuthenticateRequest) -> Result<(), FieldError> {
+    validate_channel_fields(req)?;
+    validate_recurring_fields(req)?;
+    validate_instalment_data(req)?;
+    Ok(())
+}
+
+/// threeDSRequestorAuthenticationInd values accepted on 3RI AReqs
+pub const THREE_RI_AUTHENTICATION_INDS: &[&str] = &["85", "86"];
+
+/// Elements required, or forbidden, by the deviceChannel: app AReqs need their SDK render
+/// options, browser AReqs a threeDSCompInd, and 3RI AReqs carry neither browser nor SDK data
+fn validate_channel_fields(req: &AuthenticateRequest) -> Result<(), FieldError> {
+    match req.device_channel {
+        DeviceChannel::App if req.device_render_options.is_none() => {
+            Err(FieldError::missing("deviceRenderOptions"))
+        }
+        DeviceChannel::Browser if req.three_ds_comp_ind.is_empty() => {
+            Err(FieldError::missing("threeDSCompInd"))
+        }
+        DeviceChannel::ThreeRi => validate_three_ri_fields(req),
+        _ => Ok(()),
+    }
+}
+
+fn validate_three_ri_fields(req: &AuthenticateRequest) -> Result<(), FieldError> {
+    let present = [
+        ("browserInformation", req.browser_information.is_some()),
+        ("deviceRenderOptions", req.device_render_options.is_some()),
+        ("sdkTransID", req.sdk_trans_id.is_some()),
+        ("sdkEncData", req.sdk_enc_data.is_some()),
+        ("sdkReferenceNumber", req.sdk_reference_number.is_some()),
+        ("sdkEphemeralPublicKey", req.sdk_ephemeral_key().is_some()),
+    ];
+    if let Some((field, _)) = present.into_iter().find(|(_, present)| *present) {
+        return Err(FieldError::invalid(
+            field,
+            &format!("{} must not be present for 3RI (deviceChannel 03)", field),
+        ));
+    }
+
+    let authentication_ind = req
+        .three_ds_requestor
+        .three_ds_requestor_authentication_ind
+        .as_str();
+    if !THREE_RI_AUTHENTICATION_INDS.contains(&authentication_ind) {
+        return Err(FieldError::invalid(
+            "threeDSRequestorAuthenticationInd",
+            &format!(
+                "threeDSRequestorAuthenticationInd must be one of {} for 3RI",
+                THREE_RI_AUTHENTICATION_INDS.join(", ")
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+fn validate_recurring_fields(req: &AuthenticateRequest) -> Result<(), FieldError> {
+    let authentication_ind = req
+        .three_ds_requestor
+        .three_ds_requestor_authentication_ind
+        .as_str();
+
+    if !is_recurring_or_instalment(authentication_ind) {
+        return Ok(());
+    }
+
+    let purchase = &req.purchase;
+
+    // recurringExpiry: YYYYMMDD, required for recurring and instalment, must not be in the past
+    let recurring_expiry = purchase
+        .recurring_expiry
+        .as_deref()
+        .ok_or_else(|| FieldError::missing("recurringExpiry"))?;
+    let expiry_date = parse_yyyymmdd(recurring_expiry).ok_or_else(|| {
+        FieldError::invalid(
+            "recurringExpiry",
+            "recurringExpiry must be a valid date in YYYYMMDD format",
+        )
+    })?;
+    if expiry_date < Utc::now().date_naive() {
+        return Err(FieldError::invalid(
+            "recurringExpiry",
+            "recurringExpiry must not be in the past",
+        ));
+    }
+
+    // recurringFrequency: minimum number of days between authorisations (max 4 digits)
+    match purchase.recurring_frequency {
+        None => return Err(FieldError::missing("recurringFrequency")),
+        Some(0) => {
+            return Err(FieldError::invalid(
+                "recurringFrequency",
+                "recurringFrequency must be at least 1 day",
+            ))
+        }
+        Some(frequency) if frequency > 9999 => {
+            return Err(FieldError::invalid(
+                "recurringFrequency",
+                "recurringFrequency must be at most 4 digits",
+            ))
+        }
+        Some(_) => {}
+    }
+
+    Ok(())
+}
+
+fn validate_instalment_data(req: &AuthenticateRequest) -> Result<(), FieldError> {
+    let authentication_ind = req
+        .three_ds_requestor
+        .three_ds_requestor_authentication_ind
+        .as_str();
+
+    match req.purchase.purchase_instal_data {
+        // Required when the cardholder agreed to instalments
+        None if authentication_ind == "03" => Err(FieldError::missing("purchaseInstalData")),
+        None => Ok(()),
+        Some(count) if !(1..=999).contains(&count) => Err(FieldError::invalid(
+            "purchaseInstalData",
+            "purchaseInstalData must be between 1 and 999",
+        )),
+        Some(_) => Ok(()),
+    }
+}
+
+/// Whether an instalment AReq asks for more instalments than the issuer allows without a challenge
+pub fn exceeds_instalment_threshold(req: &AuthenticateRequest, threshold: Option<u32>) -> bool {
+    let is_instalment = req.three_ds_requestor.three_ds_requestor_authentication_ind == "03";
+
+    match (threshold, req.purchase.purchase_instal_data) {
+        (Some(threshold), Some(count)) => is_instalment && count > threshold,
+        _ => false,
+    }
+}
+
+fn parse_yyyymmdd(value: &str) -> Option<NaiveDate> {
+    if value.len() != 8 || !value.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    NaiveDate::parse_from_str(value, "%Y%m%d").ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_yyyymmdd() {
+        assert!(parse_yyyymmdd("20301231").is_some());
+        assert!(parse_yyyymmdd("20301331").is_none());
+        assert!(parse_yyyymmdd("2030123").is_none());
+        assert!(parse_yyyymmdd("+2030123").is_none());
+    }
+
+    #[test]
+    fn test_field_error_from_json_error() {
+        let missing = serde_json::from_str::<crate::models::FinalRequest>("{}").unwrap_err();
+        let error = FieldError::from_json_error(&missing).expect("missing field");
+        assert_eq!(error.error_code, "201");
+        assert_eq!(error.field, "threeDsServerTransId");
+
+        let rejected = serde_json::from_str::<crate::models::DeviceChannel>("\"09\"").unwrap_err();
+        let error = FieldError::from_json_error(&rejected).expect("rejected code");
+        assert_eq!(error.error_code, "203");
+        assert_eq!(error.field, "deviceChannel");
+
+        let syntax = serde_json::from_str::<crate::models::FinalRequest>("{").unwrap_err();
+        assert!(FieldError::from_json_error(&syntax).is_none());
+    }
+
+    #[test]
+    fn test_field_error_from_json_path_error() {
+        let mut areq = crate::self_test::synthetic_areq(Uuid::new_v4(), "4000000000001000", None);
+        areq["purchase"]["purchaseAmount"] = serde_json::json!("abc");
+        let body = areq.to_string();
+        let error = serde_path_to_error::deserialize::<_, AuthenticateRequest>(
+            &mut serde_json::Deserializer::from_str(&body),
+        )
+        .unwrap_err();
+        let error = FieldError::from_json_path_error(&error).expect("wrong type");
+        assert_eq!(error.error_code, "203");
+        assert_eq!(error.field, "purchase.purchaseAmount");
+        assert!(error.description.contains("expected u64"));
+
+        areq["purchase"]
+            .as_object_mut()
+            .unwrap()
+            .remove("purchaseAmount");
+        let error = serde_path_to_error::deserialize::<_, AuthenticateRequest>(&areq).unwrap_err();
+        let error = FieldError::from_json_path_error(&error).expect("missing field");
+        assert_eq!(error.error_code, "201");
+        assert_eq!(error.field, "purchase.purchaseAmount");
+    }
+
+    #[test]
+    fn test_unknown_fields() {
+        let received = serde_json::json!({
+            "threeDSRequestorSpcSupport": "Y",
+            "purchase": { "purchaseAmount": "100", "purchaseColour": "blue" },
+            "sdkTransId": null,
+            "extra": 1
+        });
+        let parsed = serde_json::json!({
+            "threeDsRequestorSpcSupport": "Y",
+            "purchase": { "purchaseAmount": 100 }
+        });
+
+        let mut unknown = unknown_fields(&received, &parsed);
+        unknown.sort();
+        assert_eq!(unknown, vec!["extra", "purchase.purchaseColour"]);
+        assert!(unknown_fields(&parsed, &parsed).is_empty());
+
+        let creq = serde_json::json!({ "messageType": "CReq", "challengeDataEntry": "1234", "debug": true });
+        assert_eq!(unknown_creq_fields(&creq), vec!["debug".to_string()]);
+    }
+
+    #[test]
+    fn test_results_request_consistency() {
+        let (acs_trans_id, ds_trans_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let rreq = |trans_status: &str, eci: &str, authentication_value: Option<&str>| {
+            serde_json::from_value::<ResultsRequest>(serde_json::json!({
+                "acsTransId": acs_trans_id,
+                "messageCategory": "01",
+                "eci": eci,
+                "messageType": "RReq",
+                "acsRenderingType": { "acsUiTemplate": "01", "acsInterface": "01" },
+                "dsTransId": ds_trans_id,
+                "authenticationMethod": "02",
+                "authenticationType": "02",
+                "messageVersion": "2.2.0",
+                "interactionCounter": "01",
+                "authenticationValue": authentication_value,
+                "transStatus": trans_status,
+                "threeDsServerTransId": Uuid::new_v4()
+            }))
+            .unwrap()
+        };
+        let cavv = general_purpose::STANDARD.encode([2u8; 20]);
+        let check =
+            |rreq: &ResultsRequest| validate_results_request(rreq, acs_trans_id, ds_trans_id);
+
+        let cavv = Some(cavv.as_str());
+
+        assert!(check(&rreq("Y", "05", cavv)).is_ok());
+        assert!(check(&rreq("A", "01", cavv)).is_ok());
+        assert!(check(&rreq("N", "07", None)).is_ok());
+
+        assert_eq!(check(&rreq("Y", "07", cavv)).unwrap_err().field, "eci");
+        assert_eq!(
+            check(&rreq("C", "05", cavv)).unwrap_err().field,
+            "transStatus"
+        );
+        assert_eq!(
+            check(&rreq("Y", "05", Some(""))).unwrap_err().field,
+            "authenticationValue"
+        );
+        assert_eq!(
+            check(&rreq("Y", "05", None)).unwrap_err().field,
+            "authenticationValue"
+        );
+        assert_eq!(
+            check(&rreq("N", "07", Some("AAAAAAAAAAAAAAAAAAAAAA==")))
+                .unwrap_err()
+                .field,
+            "authenticationValue"
+        );
+
+        let mut foreign = rreq("Y", "05", cavv);
+        foreign.ds_trans_id = Uuid::new_v4();
+        let error = check(&foreign).unwrap_err();
+        assert_eq!(error.error_code, "301");
+        assert_eq!(error.field, "dsTransID");
+    }
+
+    #[test]
+    fn test_three_ri_channel_fields() {
+        let mut req = crate::models::tests::sample_authenticate_request();
+        req.device_channel = DeviceChannel::ThreeRi;
+        assert_eq!(
+            validate_authenticate_request(&req).unwrap_err().field,
+            "browserInformation"
+        );
+
+        req.browser_information = None;
+        req.device_render_options = None;
+        assert_eq!(
+            validate_authenticate_request(&req).unwrap_err().field,
+            "threeDSRequestorAuthenticationInd"
+        );
+
+        req.three_ds_requestor.three_ds_requestor_authentication_ind = "85".to_string();
+        req.three_ds_comp_ind = String::new();
+        assert!(validate_authenticate_request(&req).is_ok());
+
+        req.device_channel = DeviceChannel::Browser;
+        assert_eq!(
+            validate_authenticate_request(&req).unwrap_err().field,
+            "threeDSCompInd"
+        );
+    }
+
+    #[test]
+    fn test_recurring_indicator() {
+        assert!(is_recurring_or_instalment("02"));
+        assert!(is_recurring_or_instalment("03"));
+        assert!(!is_recurring_or_instalment("01"));
+    }
+
+    #[test]
+    fn test_browser_creq_decoding_and_validation() {
+        let json = r#"{"threeDSServerTransID":"8a880dc0-d2d2-4067-bcb1-b08d1690b26e","acsTransID":"d7c1ee99-9478-44a6-b1f2-391e29c6b340","messageType":"CReq","messageVersion":"2.2.0","challengeWindowSize":"05"}"#;
+        let encoded = general_purpose::URL_SAFE_NO_PAD.encode(json);
+
+        let decoded = decode_browser_creq(&encoded).expect("base64url creq");
+        let mut creq: ChallengeRequest = serde_json::from_slice(&decoded).expect("CReq JSON");
+        assert!(validate_browser_creq(&creq).is_ok());
+        // Padded input decodes too
+        assert!(decode_browser_creq(&general_purpose::URL_SAFE.encode(json)).is_ok());
+        assert_eq!(
+            decode_browser_creq("not base64!").unwrap_err().field,
+            "creq"
+        );
+
+        // trigger-otp takes either encoding
+        assert_eq!(decode_trigger_otp_creq(&encoded).unwrap(), json.as_bytes());
+        assert_eq!(decode_trigger_otp_creq(json).unwrap(), json.as_bytes());
+        assert!(decode_trigger_otp_creq("not base64!").is_err());
+
+        creq.challenge_window_size = "06".to_string();
+        assert_eq!(
+            validate_browser_creq(&creq).unwrap_err().field,
+            "challengeWindowSize"
+        );
+
+        creq.message_version = "1.0.2".to_string();
+        assert_eq!(validate_browser_creq(&creq).unwrap_err().error_code, "102");
+    }
+
+    #[test]
+    fn test_message_extension_validation() {
+        let extension = |id: &str, critical: bool| MessageExtension {
+            name: "Issuer data".to_string(),
+            id: id.to_string(),
+            criticality_indicator: critical,
+            data: serde_json::json!({ "key": "value" }),
+        };
+        let recognized = vec!["A000000004-ext".to_string()];
+
+        assert!(validate_message_extensions(None, &recognized).is_ok());
+        assert!(validate_message_extensions(
+            Some(&[
+                extension("unknown-ext", false),
+                extension("A000000004-ext", true)
+            ]),
+            &recognized
+        )
+        .is_ok());
+
+        let error = validate_message_extensions(
+            Some(&[extension("unknown-ext", true), extension("other-ext", true)]),
+            &recognized,
+        )
+        .unwrap_err();
+        assert_eq!(error.error_code, "202");
+        assert_eq!(error.field, "unknown-ext,other-ext");
+
+        let too_many = vec![extension("unknown-ext", false); MAX_MESSAGE_EXTENSIONS + 1];
+        assert_eq!(
+            validate_message_extensions(Some(&too_many), &recognized)
+                .unwrap_err()
+                .error_code,
+            "203"
+        );
+    }
+
+    #[test]
+    fn test_preparation_request_validation() {
+        let mut preq: PreparationRequest = serde_json::from_value(serde_json::json!({
+            "messageType": "PReq",
+            "messageVersion": "2.1.0",
+            "threeDSServerTransID": "8a880dc0-d2d2-4067-bcb1-b08d1690b26e",
+            "threeDSServerRefNumber": "3DS_LOA_SER_PPFU_020100_00008"
+        }))
+        .unwrap();
+        assert!(validate_preparation_request(&preq).is_ok());
+
+        preq.serial_num = Some("x".repeat(21));
+        assert_eq!(
+            validate_preparation_request(&preq).unwrap_err().field,
+            "serialNum"
+        );
+
+        preq.message_version = "2.3.1".to_string();
+        assert_eq!(
+            validate_preparation_request(&preq).unwrap_err().error_code,
+            "102"
+        );
+
+        preq.message_type = "AReq".to_string();
+        assert_eq!(
+            validate_preparation_request(&preq).unwrap_err().error_code,
+            "101"
+        );
+    }
+
+    #[test]
+    fn test_three_ds_method_data_decoding() {
+        let encode = |json: &str| general_purpose::URL_SAFE_NO_PAD.encode(json);
+
+        let data = decode_three_ds_method_data(&encode(
+            r#"{"threeDSServerTransID":"8a880dc0-d2d2-4067-bcb1-b08d1690b26e","threeDSMethodNotificationURL":"https://merchant.example.com/3ds-method-notify"}"#,
+        ))
+        .expect("valid threeDSMethodData");
+        assert_eq!(
+            data.three_ds_method_notification_url,
+            "https://merchant.example.com/3ds-method-notify"
+        );
+
+        let error = decode_three_ds_method_data(&encode(
+            r#"{"threeDSServerTransID":"8a880dc0-d2d2-4067-bcb1-b08d1690b26e","threeDSMethodNotificationURL":"javascript:alert(1)"}"#,
+        ))
+        .unwrap_err();
+        assert_eq!(error.field, "threeDSMethodNotificationURL");
+
+        let error = decode_three_ds_method_data(&encode("{}")).unwrap_err();
+        assert_eq!(error.field, "threeDSMethodData");
+    }
+}
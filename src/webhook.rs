@@ -0,0 +1,197 @@
+//! Final-outcome webhook: once a transaction reaches its outcome (frictionlessly or through
+//! a challenge), a short summary is POSTed to the AReq notificationURL, so merchant callback
+//! handlers can be exercised without following the browser redirect.
+
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::config::WebhookConfig;
+use crate::models::{Eci, TransStatus};
+
+/// Unix timestamp the signature covers, in seconds
+pub const TIMESTAMP_HEADER: &str = "X-Webhook-Timestamp";
+/// `v1=` followed by the hex HMAC-SHA256 of `{timestamp}.{body}` under `webhook.secret`
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+
+/// How the transaction reached its outcome
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum OutcomeFlow {
+    Frictionless,
+    Browser,
+    App,
+    Spc,
+}
+
+/// The payload POSTed to the notificationURL
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FinalOutcome {
+    #[serde(rename = "threeDSServerTransID")]
+    pub three_ds_server_trans_id: Uuid,
+    pub trans_status: TransStatus,
+    pub eci: Eci,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authentication_value: Option<String>,
+    pub flow: OutcomeFlow,
+}
+
+/// Send the outcome in the background; the cardholder-facing response never waits on the
+/// merchant. Does nothing while `webhook.enabled` is off.
+pub fn notify(config: &WebhookConfig, notification_url: &str, outcome: FinalOutcome) {
+    if !config.enabled {
+        return;
+    }
+    let config = config.clone();
+    let url = notification_url.to_string();
+    tokio::spawn(async move { deliver(&config, &url, &outcome).await });
+}
+
+/// Shared HTTP client for calls to merchant endpoints
+pub(crate) fn client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(reqwest::Client::new)
+}
+
+/// POST the outcome; see `post_with_retries`
+async fn deliver(config: &WebhookConfig, url: &str, outcome: &FinalOutcome) -> bool {
+    let body = match serde_json::to_vec(outcome) {
+        Ok(body) => body,
+        Err(e) => {
+            warn!(error = %e, "final-outcome webhook not sent");
+            return false;
+        }
+    };
+    post_with_retries(
+        config,
+        url,
+        body,
+        "final_outcome",
+        outcome.three_ds_server_trans_id,
+    )
+    .await
+}
+
+/// POST a JSON body (signed when `config.secret` is set), retrying connection failures,
+/// timeouts and 5xx/429 answers with a doubling delay. Returns whether the merchant accepted
+/// it; `delivery` names what was sent in the log.
+pub(crate) async fn post_with_retries(
+    config: &WebhookConfig,
+    url: &str,
+    body: Vec<u8>,
+    delivery: &'static str,
+    three_ds_server_trans_id: Uuid,
+) -> bool {
+    let mut delay = Duration::from_millis(config.retry_delay_ms);
+
+    for attempt in 1..=config.max_attempts {
+        let mut request = client()
+            .post(url)
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .header(reqwest::header::CONTENT_TYPE, "application/json");
+        if let Some(secret) = &config.secret {
+            let timestamp = chrono::Utc::now().timestamp().to_string();
+            request = request
+                .header(SIGNATURE_HEADER, sign(secret, &timestamp, &body))
+                .header(TIMESTAMP_HEADER, timestamp);
+        }
+
+        let retryable = match request.body(body.clone()).send().await {
+            Ok(response) if response.status().is_success() => {
+                info!(
+                    delivery,
+                    %three_ds_server_trans_id,
+                    url,
+                    attempt,
+                    "delivered"
+                );
+                return true;
+            }
+            Ok(response) => {
+                let status = response.status();
+                warn!(
+                    delivery,
+                    %three_ds_server_trans_id,
+                    url,
+                    status = status.as_u16(),
+                    attempt,
+                    max_attempts = config.max_attempts,
+                    "delivery refused"
+                );
+                status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            }
+            Err(e) => {
+                warn!(
+                    delivery,
+                    %three_ds_server_trans_id,
+                    url,
+                    error = %e,
+                    attempt,
+                    max_attempts = config.max_attempts,
+                    "delivery failed"
+                );
+                true
+            }
+        };
+
+        if !retryable {
+            break;
+        }
+        if attempt < config.max_attempts {
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+    warn!(
+        delivery,
+        %three_ds_server_trans_id,
+        url,
+        "giving up on delivery"
+    );
+    false
+}
+
+/// Signature header value for a body sent at `timestamp`
+pub fn sign(secret: &str, timestamp: &str, body: &[u8]) -> String {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("v1={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload_and_signature() {
+        let outcome = FinalOutcome {
+            three_ds_server_trans_id: Uuid::nil(),
+            trans_status: TransStatus::Authenticated,
+            eci: Eci::VisaAuthenticated,
+            authentication_value: None,
+            flow: OutcomeFlow::Frictionless,
+        };
+        let body = serde_json::to_value(&outcome).unwrap();
+        assert_eq!(
+            body["threeDSServerTransID"],
+            "00000000-0000-0000-0000-000000000000"
+        );
+        assert_eq!(body["transStatus"], "Y");
+        assert_eq!(body["flow"], "frictionless");
+        assert!(body.get("authenticationValue").is_none());
+
+        let signature = sign("secret", "1700000000", b"{}");
+        assert!(signature.starts_with("v1="));
+        assert_eq!(signature.len(), 3 + 64);
+        assert_ne!(signature, sign("other", "1700000000", b"{}"));
+        assert_ne!(signature, sign("secret", "1700000001", b"{}"));
+    }
+}